@@ -0,0 +1,60 @@
+//! Throughput of the base64 and hex engines over one large buffer. Build with `--features simd`
+//! to compare the vectorized engines against the portable ones.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use ptrs::codec::{base64, hex};
+
+const LEN: usize = 1 << 20;
+
+fn input() -> Vec<u8> {
+    (0..LEN).map(|i| (i * 31 + i / 7) as u8).collect()
+}
+
+fn bench_base64<E: base64::Engine>(c: &mut Criterion, name: &str, engine: E) {
+    let input = input();
+    let mut encoded = vec![0; LEN.div_ceil(3) * 4];
+    let mut decoded = vec![0; LEN.div_ceil(3) * 3];
+
+    let mut group = c.benchmark_group("base64");
+    group.throughput(Throughput::Bytes(LEN as u64));
+    group.bench_function(BenchmarkId::new("encode", name), |b| {
+        b.iter(|| engine.encode(&input, &mut encoded).unwrap())
+    });
+    group.bench_function(BenchmarkId::new("decode", name), |b| {
+        b.iter(|| engine.decode(&encoded, &mut decoded).unwrap())
+    });
+    group.finish();
+}
+
+fn bench_hex<E: hex::Engine>(c: &mut Criterion, name: &str, engine: E) {
+    let input = input();
+    let mut encoded = vec![0; LEN * 2];
+    let mut decoded = vec![0; LEN];
+
+    let mut group = c.benchmark_group("hex");
+    group.throughput(Throughput::Bytes(LEN as u64));
+    group.bench_function(BenchmarkId::new("encode", name), |b| {
+        b.iter(|| engine.encode(&input, &mut encoded, false))
+    });
+    group.bench_function(BenchmarkId::new("decode", name), |b| {
+        b.iter(|| engine.decode(&encoded, &mut decoded).unwrap())
+    });
+    group.finish();
+}
+
+fn portable(c: &mut Criterion) {
+    bench_base64(c, "portable", base64::Portable);
+    bench_hex(c, "portable", hex::Portable);
+}
+
+#[cfg(feature = "simd")]
+fn simd(c: &mut Criterion) {
+    bench_base64(c, "simd", base64::Simd);
+    bench_hex(c, "simd", hex::Simd);
+}
+
+#[cfg(not(feature = "simd"))]
+fn simd(_c: &mut Criterion) {}
+
+criterion_group!(benches, portable, simd);
+criterion_main!(benches);
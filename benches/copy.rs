@@ -0,0 +1,109 @@
+//! Throughput of the duplex copy loop, for bulk transfers and for interactive traffic made
+//! of many small writes, and of a chunked transform over a bulk transfer.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::future::poll_fn;
+use ptrs::codec::xor::Xor;
+use ptrs::copy::{DuplexCopy, HalfClosePolicy, TransferState};
+use ptrs::transform::{BufferTransform, Chunked};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio::runtime::Runtime;
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+const BULK: usize = 32 << 20;
+
+fn transfer<A, B>(
+    cx: &mut Context<'_>,
+    state: &mut TransferState,
+    r: &mut A,
+    w: &mut B,
+) -> Poll<std::io::Result<u64>>
+where
+    A: AsyncRead + Unpin,
+    B: AsyncWrite + Unpin,
+{
+    match state {
+        TransferState::Running(buf) => buf.poll_copy(cx, Pin::new(r), Pin::new(w)),
+        TransferState::ShuttingDown(n) | TransferState::Done(n) => Poll::Ready(Ok(*n)),
+    }
+}
+
+/// Push `writes` of `size` bytes from one end of a proxied pair to the other.
+async fn run(writes: usize, size: usize, pipe: usize) {
+    let (mut client, mut a) = tokio::io::duplex(pipe);
+    let (mut b, mut server) = tokio::io::duplex(pipe);
+    let proxy = tokio::spawn(async move {
+        let mut copy = DuplexCopy::new(HalfClosePolicy::CloseBoth);
+        poll_fn(|cx| copy.poll_copy(cx, &mut a, &mut b, transfer, transfer)).await
+    });
+    let sink = tokio::spawn(async move {
+        let mut buf = vec![0u8; 64 << 10];
+        let mut total = 0;
+        while total < writes * size {
+            total += server.read(&mut buf).await.unwrap();
+        }
+        server
+    });
+
+    let chunk = vec![0x5au8; size];
+    for _ in 0..writes {
+        client.write_all(&chunk).await.unwrap();
+    }
+    let _server: DuplexStream = sink.await.unwrap();
+    drop(client);
+    proxy.await.unwrap().unwrap();
+}
+
+fn bulk(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("copy/bulk");
+    group.throughput(Throughput::Bytes(BULK as u64));
+    for pipe in [64 << 10, 1 << 20] {
+        group.bench_with_input(BenchmarkId::from_parameter(pipe), &pipe, |bench, &pipe| {
+            bench
+                .to_async(&rt)
+                .iter(|| run(BULK / (64 << 10), 64 << 10, pipe))
+        });
+    }
+    group.finish();
+}
+
+fn interactive(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("copy/interactive");
+    group.throughput(Throughput::Bytes(10_000 * 64));
+    group.bench_function("64B", |bench| {
+        bench.to_async(&rt).iter(|| run(10_000, 64, 4096))
+    });
+    group.finish();
+}
+
+/// Push [`BULK`] bytes through a chunked XOR transform into a sink.
+async fn run_transform() {
+    let (mut client, mut a) = tokio::io::duplex(1 << 20);
+    let writer = tokio::spawn(async move {
+        let chunk = vec![0x5au8; 64 << 10];
+        for _ in 0..BULK / chunk.len() {
+            client.write_all(&chunk).await.unwrap();
+        }
+    });
+    let mut sink = tokio::io::sink();
+    let mut t = Chunked::new(Xor::new(b"key").unwrap());
+    poll_fn(|cx| t.poll_copy(cx, Pin::new(&mut a), Pin::new(&mut sink)))
+        .await
+        .unwrap();
+    writer.await.unwrap();
+}
+
+fn transform(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("transform/bulk");
+    group.throughput(Throughput::Bytes(BULK as u64));
+    group.bench_function("xor", |bench| bench.to_async(&rt).iter(run_transform));
+    group.finish();
+}
+
+criterion_group!(benches, bulk, interactive, transform);
+criterion_main!(benches);
@@ -0,0 +1,62 @@
+//! The ROT13 transport from `ptrs::tutorial`, end to end over TCP: a server that reveals and
+//! echoes, and a client that seals, with a look at what actually crosses the wire.
+//!
+//! ```sh
+//! cargo run --example custom_transport --features tutorial
+//! ```
+
+use ptrs::prelude::*;
+use ptrs::tutorial::{smethod_line, Rot13Transport};
+use ptrs::Result;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    // the registry knows the transport by name, as the proxy binary's `-t rot13` does
+    let _: Transports = "rot13".parse()?;
+    let builder = Rot13Transport::new();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    println!("{}", smethod_line(&builder, addr));
+
+    let server = builder.server()?;
+    tokio::spawn(async move {
+        let (conn, _) = listener.accept().await?;
+        let mut conn = server.wrap(conn)?;
+        let mut buf = [0u8; 1024];
+        loop {
+            let n = conn.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            conn.write_all(&buf[..n]).await?;
+            conn.flush().await?;
+        }
+        Result::Ok(())
+    });
+
+    // peek at the wire by wrapping a borrowed stream, then use the same connection unwrapped
+    let mut conn = TcpStream::connect(addr).await?;
+    let message = b"Hello from a custom transport";
+    {
+        let mut wrapped = builder.client()?.wrap(&mut conn)?;
+        wrapped.write_all(message).await?;
+        wrapped.flush().await?;
+    }
+    let mut on_the_wire = vec![0u8; message.len()];
+    conn.read_exact(&mut on_the_wire).await?;
+    println!("on the wire: {}", String::from_utf8_lossy(&on_the_wire));
+    assert_eq!(&on_the_wire, b"Uryyb sebz n phfgbz genafcbeg");
+
+    let mut client = builder.client()?.wrap(conn)?;
+    client.write_all(message).await?;
+    client.flush().await?;
+    let mut echoed = vec![0u8; message.len()];
+    client.read_exact(&mut echoed).await?;
+    println!("echoed back: {}", String::from_utf8_lossy(&echoed));
+    assert_eq!(&echoed, message);
+    Ok(())
+}
@@ -0,0 +1,269 @@
+//! # Source address filtering
+//!
+//! Allow and block lists of CIDR ranges, checked against the peer address as soon as a
+//! connection is accepted and before any transport handshake runs, so abusive sources cost a
+//! bridge no more than an `accept`. Unlike a [`ConnPolicy`](crate::policy::ConnPolicy), the
+//! filter never reads from the connection.
+//!
+//! ```
+//! use ptrs::acl::IpFilter;
+//!
+//! let filter: IpFilter = "allow=10.0.0.0/8,fd00::/8;block=10.66.0.0/16".parse().unwrap();
+//! assert!(filter.check("10.1.2.3".parse().unwrap()).is_ok());
+//! assert!(filter.check("10.66.0.1".parse().unwrap()).is_err());
+//! assert!(filter.check("192.0.2.1".parse().unwrap()).is_err());
+//! assert_eq!(filter.stats().rejected(), 2);
+//! ```
+
+use crate::safelog::sensitive;
+use crate::{Error, Result};
+
+use tracing::info;
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// A range of addresses, e.g. `10.0.0.0/8` or `2001:db8::/32`. A bare address is a range of
+/// one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Bits past `prefix` in `addr` are ignored.
+    pub fn new(addr: IpAddr, prefix: u8) -> Result<Self> {
+        let max = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix > max {
+            return Err(Error::Config(
+                format!("prefix length {prefix} is too long for {addr}").into(),
+            ));
+        }
+        Ok(Self {
+            addr: mask(addr, prefix),
+            prefix,
+        })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        ip.is_ipv4() == self.addr.is_ipv4() && mask(ip, self.prefix) == self.addr
+    }
+}
+
+fn mask(addr: IpAddr, prefix: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(a) => {
+            let m = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            IpAddr::V4((u32::from(a) & m).into())
+        }
+        IpAddr::V6(a) => {
+            let m = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            IpAddr::V6((u128::from(a) & m).into())
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let bad = |e: &dyn fmt::Display| Error::Config(format!("bad CIDR {s:?}: {e}").into());
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|e| bad(&e))?;
+        let prefix = match prefix {
+            Some(p) => p.parse().map_err(|e| bad(&e))?,
+            None if addr.is_ipv4() => 32,
+            None => 128,
+        };
+        Cidr::new(addr, prefix)
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Counts of the connections an [`IpFilter`] has checked. Clones of a filter share them.
+#[derive(Debug, Default)]
+pub struct FilterStats {
+    allowed: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl FilterStats {
+    pub fn allowed(&self) -> u64 {
+        self.allowed.load(Ordering::Relaxed)
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+}
+
+/// Why an [`IpFilter`] turned a peer away.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rejection {
+    /// The peer is in this blocked range.
+    Blocked(Cidr),
+    /// There is an allow list and the peer is not on it.
+    NotAllowed,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::Blocked(cidr) => write!(f, "blocked by {cidr}"),
+            Rejection::NotAllowed => write!(f, "not in the allow list"),
+        }
+    }
+}
+
+/// Source address filter. Blocked ranges win over allowed ones; with no allow list, every
+/// address that isn't blocked is allowed.
+///
+/// Parses from `allow=<cidr>,..;block=<cidr>,..`, where either part may be left out.
+#[derive(Clone, Debug, Default)]
+pub struct IpFilter {
+    allow: Vec<Cidr>,
+    block: Vec<Cidr>,
+    stats: Arc<FilterStats>,
+}
+
+impl IpFilter {
+    pub fn new(allow: Vec<Cidr>, block: Vec<Cidr>) -> Self {
+        Self {
+            allow,
+            block,
+            stats: Arc::default(),
+        }
+    }
+
+    /// True if the filter lets every address through.
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.block.is_empty()
+    }
+
+    pub fn stats(&self) -> &Arc<FilterStats> {
+        &self.stats
+    }
+
+    /// Check `ip`, counting the result and logging rejections.
+    pub fn check(&self, ip: IpAddr) -> std::result::Result<(), Rejection> {
+        let result = self.evaluate(ip);
+        match &result {
+            Ok(()) => self.stats.allowed.fetch_add(1, Ordering::Relaxed),
+            Err(reason) => {
+                info!(peer = %sensitive(ip), "connection rejected by address filter: {reason}");
+                self.stats.rejected.fetch_add(1, Ordering::Relaxed)
+            }
+        };
+        result
+    }
+
+    fn evaluate(&self, ip: IpAddr) -> std::result::Result<(), Rejection> {
+        if let Some(cidr) = self.block.iter().find(|c| c.contains(ip)) {
+            return Err(Rejection::Blocked(*cidr));
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(|c| c.contains(ip)) {
+            return Err(Rejection::NotAllowed);
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for IpFilter {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut allow = vec![];
+        let mut block = vec![];
+        for part in s.split(';').map(str::trim).filter(|p| !p.is_empty()) {
+            let (list, ranges) = match part.split_once('=') {
+                Some(("allow", ranges)) => (&mut allow, ranges),
+                Some(("block", ranges)) => (&mut block, ranges),
+                _ => {
+                    return Err(Error::Config(
+                        format!("expected allow=.. or block=.., got {part:?}").into(),
+                    ))
+                }
+            };
+            for range in ranges.split(',').filter(|r| !r.trim().is_empty()) {
+                list.push(range.parse()?);
+            }
+        }
+        Ok(Self::new(allow, block))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_cidrs() -> Result<()> {
+        let c: Cidr = "10.1.2.3/8".parse()?;
+        assert_eq!(c.to_string(), "10.0.0.0/8");
+        assert!(c.contains("10.255.0.1".parse().unwrap()));
+        assert!(!c.contains("11.0.0.1".parse().unwrap()));
+
+        let c: Cidr = "2001:db8::1".parse()?;
+        assert_eq!(c.to_string(), "2001:db8::1/128");
+        assert!(!c.contains("2001:db8::2".parse().unwrap()));
+
+        let any: Cidr = "0.0.0.0/0".parse()?;
+        assert!(any.contains("203.0.113.9".parse().unwrap()));
+        assert!(!any.contains("::1".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("10.0.0/8".parse::<Cidr>().is_err());
+        assert!("::/x".parse::<Cidr>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn mapped_addresses_match_v4_ranges() -> Result<()> {
+        let filter: IpFilter = "block=192.0.2.0/24".parse()?;
+        assert_eq!(
+            filter.check("::ffff:192.0.2.7".parse().unwrap()),
+            Err(Rejection::Blocked("192.0.2.0/24".parse()?))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn block_wins_and_counts() -> Result<()> {
+        let filter: IpFilter = " allow=10.0.0.0/8 ; block=10.66.0.0/16, 10.67.0.1 ".parse()?;
+        assert!(filter.check("10.0.0.1".parse().unwrap()).is_ok());
+        assert!(filter.check("10.66.1.1".parse().unwrap()).is_err());
+        assert!(filter.check("10.67.0.1".parse().unwrap()).is_err());
+        assert_eq!(
+            filter.check("172.16.0.1".parse().unwrap()),
+            Err(Rejection::NotAllowed)
+        );
+
+        let shared = filter.clone();
+        assert_eq!(shared.stats().allowed(), 1);
+        assert_eq!(shared.stats().rejected(), 3);
+
+        let open: IpFilter = "".parse()?;
+        assert!(open.is_empty());
+        assert!(open.check("198.51.100.1".parse().unwrap()).is_ok());
+
+        assert!("deny=10.0.0.0/8".parse::<IpFilter>().is_err());
+        Ok(())
+    }
+}
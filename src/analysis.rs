@@ -0,0 +1,311 @@
+//! # Analysis
+//!
+//! Rough measures of how a transport's output looks to a classifier: byte entropy, the share
+//! of printable ASCII, and the distribution of write ("packet") lengths. Put a [`Capture`]
+//! between a transport and the network, run some traffic through it, then look at the
+//! [`Trace`] it collected.
+//!
+//! These are the first-order features passive censors are known to use. Good numbers here
+//! don't make a transport undetectable, but bad ones (e.g. every record the same length, or
+//! uniformly random bytes where the cover protocol is text) show it is easy to pick out.
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use std::collections::BTreeMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+
+/// How often each byte value occurs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ByteHistogram {
+    counts: [u64; 256],
+    total: u64,
+}
+
+impl Default for ByteHistogram {
+    fn default() -> Self {
+        Self {
+            counts: [0; 256],
+            total: 0,
+        }
+    }
+}
+
+impl ByteHistogram {
+    pub fn new(data: &[u8]) -> Self {
+        let mut h = Self::default();
+        h.add(data);
+        h
+    }
+
+    pub fn add(&mut self, data: &[u8]) {
+        for &b in data {
+            self.counts[b as usize] += 1;
+        }
+        self.total += data.len() as u64;
+    }
+
+    pub fn count(&self, byte: u8) -> u64 {
+        self.counts[byte as usize]
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Shannon entropy in bits per byte, from 0 (one repeated value) to 8 (uniformly random).
+    pub fn entropy(&self) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        let total = self.total as f64;
+        self.counts
+            .iter()
+            .filter(|&&c| c > 0)
+            .map(|&c| {
+                let p = c as f64 / total;
+                -p * p.log2()
+            })
+            .sum()
+    }
+
+    /// Share of bytes that are printable ASCII or common whitespace, from 0 to 1.
+    pub fn printable_ratio(&self) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        let printable: u64 = (0..=255u8)
+            .filter(|&b| b.is_ascii_graphic() || matches!(b, b' ' | b'\t' | b'\r' | b'\n'))
+            .map(|b| self.count(b))
+            .sum();
+        printable as f64 / self.total as f64
+    }
+}
+
+/// Shannon entropy of `data` in bits per byte.
+pub fn entropy(data: &[u8]) -> f64 {
+    ByteHistogram::new(data).entropy()
+}
+
+/// Share of `data` that is printable ASCII.
+pub fn printable_ratio(data: &[u8]) -> f64 {
+    ByteHistogram::new(data).printable_ratio()
+}
+
+/// How often each write length occurs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LengthDistribution {
+    counts: BTreeMap<usize, u64>,
+}
+
+impl LengthDistribution {
+    pub fn add(&mut self, len: usize) {
+        *self.counts.entry(len).or_default() += 1;
+    }
+
+    /// Number of lengths recorded.
+    pub fn len(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    pub fn mean(&self) -> f64 {
+        let n = self.len();
+        if n == 0 {
+            return 0.0;
+        }
+        let sum: u64 = self.counts.iter().map(|(&l, &c)| l as u64 * c).sum();
+        sum as f64 / n as f64
+    }
+
+    /// Number of distinct lengths seen. One, with many writes, is a strong fingerprint.
+    pub fn distinct(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// The `q` quantile (0 to 1) of the recorded lengths.
+    pub fn quantile(&self, q: f64) -> Option<usize> {
+        let n = self.len();
+        if n == 0 {
+            return None;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * n as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (&len, &c) in &self.counts {
+            seen += c;
+            if seen >= rank {
+                return Some(len);
+            }
+        }
+        self.counts.keys().next_back().copied()
+    }
+
+    /// `(length, count)` pairs in increasing order of length.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        self.counts.iter().map(|(&l, &c)| (l, c))
+    }
+
+    /// Lengths grouped into buckets of `width`, keyed by the start of each bucket.
+    pub fn buckets(&self, width: usize) -> BTreeMap<usize, u64> {
+        let width = width.max(1);
+        let mut out = BTreeMap::new();
+        for (&len, &c) in &self.counts {
+            *out.entry(len / width * width).or_default() += c;
+        }
+        out
+    }
+}
+
+/// What a [`Capture`] saw in one direction.
+#[derive(Clone, Debug, Default)]
+pub struct Trace {
+    pub bytes: ByteHistogram,
+    pub lengths: LengthDistribution,
+}
+
+impl Trace {
+    fn record(&mut self, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        self.bytes.add(data);
+        self.lengths.add(data.len());
+    }
+}
+
+/// Stream that records everything written to and read from it. Each write (or read) counts
+/// as one packet for the length distribution, so capture between a transport and the socket
+/// to see the lengths the transport hands the network.
+pub struct Capture<S> {
+    inner: S,
+    written: Arc<Mutex<Trace>>,
+    read: Arc<Mutex<Trace>>,
+}
+
+crate::stream::forward_addr_info!(Capture, inner);
+
+impl<S> Capture<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            written: Arc::default(),
+            read: Arc::default(),
+        }
+    }
+
+    /// What has been written through the stream so far. The handle stays valid after the
+    /// stream is wrapped or dropped.
+    pub fn written(&self) -> Arc<Mutex<Trace>> {
+        self.written.clone()
+    }
+
+    /// What has been read through the stream so far.
+    pub fn read(&self) -> Arc<Mutex<Trace>> {
+        self.read.clone()
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Capture<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        self.read.lock().unwrap().record(&buf.filled()[before..]);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Capture<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let n = ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        self.written.lock().unwrap().record(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rand::Rng;
+
+    use rand::RngCore;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn entropy_and_printable() {
+        assert_eq!(entropy(&[]), 0.0);
+        assert_eq!(entropy(&[7; 100]), 0.0);
+        assert!((entropy(b"abab") - 1.0).abs() < 1e-9);
+        let all: Vec<u8> = (0..=255).collect();
+        assert!((entropy(&all) - 8.0).abs() < 1e-9);
+
+        let mut random = vec![0u8; 1 << 16];
+        Rng::from_seed(1).fill_bytes(&mut random);
+        assert!(entropy(&random) > 7.99);
+        assert!(printable_ratio(&random) < 0.5);
+        assert_eq!(printable_ratio(b"GET / HTTP/1.1\r\n"), 1.0);
+    }
+
+    #[test]
+    fn length_distribution() {
+        let mut d = LengthDistribution::default();
+        assert_eq!(d.quantile(0.5), None);
+        for len in [10, 10, 20, 1500] {
+            d.add(len);
+        }
+        assert_eq!(d.len(), 4);
+        assert_eq!(d.distinct(), 3);
+        assert_eq!(d.mean(), 385.0);
+        assert_eq!(d.quantile(0.0), Some(10));
+        assert_eq!(d.quantile(0.5), Some(10));
+        assert_eq!(d.quantile(0.75), Some(20));
+        assert_eq!(d.quantile(1.0), Some(1500));
+        assert_eq!(
+            d.buckets(100).into_iter().collect::<Vec<_>>(),
+            [(0, 3), (1500, 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn captures_both_directions() -> std::io::Result<()> {
+        let (a, mut b) = tokio::io::duplex(1024);
+        let mut s = Capture::new(a);
+        let (written, read) = (s.written(), s.read());
+
+        s.write_all(b"hello").await?;
+        s.write_all(b", world").await?;
+        b.write_all(b"\x00\x01").await?;
+        let mut buf = [0u8; 2];
+        s.read_exact(&mut buf).await?;
+
+        let w = written.lock().unwrap();
+        assert_eq!(w.bytes.total(), 12);
+        assert_eq!(w.lengths.iter().collect::<Vec<_>>(), [(5, 1), (7, 1)]);
+        assert_eq!(w.bytes.printable_ratio(), 1.0);
+        assert_eq!(read.lock().unwrap().bytes.count(1), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,324 @@
+//! Forward revealed connections to a pool of upstream addresses (e.g. several tor ORPorts),
+//! spreading load across them and ejecting backends that stop accepting connections.
+
+use ptrs::reconnect::{Backoff, ReconnectingDialer};
+use ptrs::{sockopt::SocketOpts, Error, Result};
+
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+pub const DEFAULT_HEALTH_INTERVAL: Duration = Duration::from_secs(10);
+pub const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+/// Longest a single dial of a backend may take, the same as a health check's.
+pub const CONNECT_TIMEOUT: Duration = HEALTH_CHECK_TIMEOUT;
+
+/// The default dial [`Backoff`], with few attempts: the other backends can take the
+/// connection once this one is ejected.
+fn default_backoff() -> Backoff {
+    Backoff {
+        max_attempts: Some(3),
+        ..Backoff::default()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LbPolicy {
+    /// Cycle through backends, visiting each in proportion to its weight.
+    #[default]
+    RoundRobin,
+    /// Pick the backend with the fewest active connections relative to its weight.
+    LeastConnections,
+}
+
+impl FromStr for LbPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "round-robin" => Ok(LbPolicy::RoundRobin),
+            "least-connections" => Ok(LbPolicy::LeastConnections),
+            _ => Err(Error::Other(
+                format!("unknown balancing policy \"{s}\"").into(),
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Backend {
+    addr: SocketAddr,
+    dialer: ReconnectingDialer,
+    weight: usize,
+    healthy: AtomicBool,
+    active: AtomicUsize,
+}
+
+/// A weighted set of upstream addresses.
+#[derive(Debug)]
+pub struct BackendPool {
+    backends: Vec<Backend>,
+    policy: LbPolicy,
+    /// Round robin schedule, each backend index repeated according to its weight.
+    schedule: Vec<usize>,
+    next: AtomicUsize,
+}
+
+/// Marks a connection as active on a backend until it is dropped.
+pub struct Lease {
+    pool: Arc<BackendPool>,
+    idx: usize,
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.pool.backends[self.idx]
+            .active
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl BackendPool {
+    /// Parse a comma separated list of `addr[*weight]`, e.g.
+    /// `127.0.0.1:9001*3,127.0.0.1:9002`.
+    pub fn parse(spec: &str, policy: LbPolicy) -> Result<Self> {
+        let mut backends = vec![];
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (addr, weight) = match entry.split_once('*') {
+                Some((addr, weight)) => (addr, weight.parse().map_err(Error::new)?),
+                None => (entry, 1),
+            };
+            if weight == 0 {
+                return Err(Error::Other(
+                    format!("backend {addr} has zero weight").into(),
+                ));
+            }
+            let addr = addr.parse().map_err(Error::new)?;
+            backends.push(Backend {
+                addr,
+                dialer: ReconnectingDialer::new(addr)
+                    .with_backoff(default_backoff())
+                    .with_connect_timeout(CONNECT_TIMEOUT),
+                weight,
+                healthy: AtomicBool::new(true),
+                active: AtomicUsize::new(0),
+            });
+        }
+        if backends.is_empty() {
+            return Err(Error::Other("no backends configured".into()));
+        }
+
+        // Interleave so that heavy backends are not hit in long runs.
+        let max_weight = backends.iter().map(|b| b.weight).max().unwrap_or(1);
+        let schedule = (0..max_weight)
+            .flat_map(|round| {
+                backends
+                    .iter()
+                    .enumerate()
+                    .filter(move |(_, b)| b.weight > round)
+                    .map(|(i, _)| i)
+            })
+            .collect();
+
+        Ok(Self {
+            backends,
+            policy,
+            schedule,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// Dial backends with `opts`.
+    pub fn with_socket_opts(mut self, opts: SocketOpts) -> Self {
+        for b in &mut self.backends {
+            b.dialer = b.dialer.clone().with_socket_opts(opts.clone());
+        }
+        self
+    }
+
+    /// Choose a healthy backend according to the pool's policy.
+    fn pick(&self) -> Option<usize> {
+        match self.policy {
+            LbPolicy::RoundRobin => {
+                for _ in 0..self.schedule.len() {
+                    let n = self.next.fetch_add(1, Ordering::Relaxed);
+                    let idx = self.schedule[n % self.schedule.len()];
+                    if self.backends[idx].healthy.load(Ordering::Relaxed) {
+                        return Some(idx);
+                    }
+                }
+                None
+            }
+            LbPolicy::LeastConnections => self
+                .backends
+                .iter()
+                .enumerate()
+                .filter(|(_, b)| b.healthy.load(Ordering::Relaxed))
+                // compare active/weight without dividing: a/w < b/v  <=>  a*v < b*w
+                .min_by(|(_, a), (_, b)| {
+                    let load_a = a.active.load(Ordering::Relaxed) * b.weight;
+                    let load_b = b.active.load(Ordering::Relaxed) * a.weight;
+                    load_a.cmp(&load_b)
+                })
+                .map(|(i, _)| i),
+        }
+    }
+
+    /// Connect to a healthy backend, ejecting any that still fail to accept after the dial
+    /// [`Backoff`] gives up.
+    pub async fn connect(self: &Arc<Self>) -> Result<(TcpStream, Lease)> {
+        while let Some(idx) = self.pick() {
+            let backend = &self.backends[idx];
+            match backend.dialer.connect().await {
+                Ok(stream) => {
+                    backend.active.fetch_add(1, Ordering::Relaxed);
+                    let lease = Lease {
+                        pool: self.clone(),
+                        idx,
+                    };
+                    return Ok((stream, lease));
+                }
+                // A failure that would repeat on every backend (e.g. bad socket options)
+                // should not take the whole pool down with it.
+                Err(e) if !e.is_retriable() => return Err(e),
+                Err(e) => {
+                    warn!("ejecting backend {}: {e}", backend.addr);
+                    backend.healthy.store(false, Ordering::Relaxed);
+                }
+            }
+        }
+        Err(Error::Other("no healthy backends".into()))
+    }
+
+    /// Dial every backend once, updating its health.
+    pub async fn check_health(&self) {
+        for backend in &self.backends {
+            let dial = tokio::time::timeout(HEALTH_CHECK_TIMEOUT, TcpStream::connect(backend.addr));
+            let healthy = matches!(dial.await, Ok(Ok(_)));
+            let was = backend.healthy.swap(healthy, Ordering::Relaxed);
+            if was != healthy {
+                info!("backend {} healthy={healthy}", backend.addr);
+            }
+        }
+    }
+
+    /// Periodically check the health of every backend until `close` is cancelled.
+    pub fn spawn_health_checks(self: Arc<Self>, interval: Duration, close: CancellationToken) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => self.check_health().await,
+                    _ = close.cancelled() => break,
+                }
+            }
+            debug!("backend health checks stopped");
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// `spec` with backends retried `attempts` times, a few milliseconds apart.
+    fn fast_pool(spec: &str, attempts: u32) -> Result<Arc<BackendPool>> {
+        let mut pool = BackendPool::parse(spec, LbPolicy::RoundRobin)?;
+        for b in &mut pool.backends {
+            b.dialer = b.dialer.clone().with_backoff(Backoff {
+                initial: Duration::from_millis(5),
+                max: Duration::from_millis(5),
+                max_attempts: Some(attempts),
+            });
+        }
+        Ok(Arc::new(pool))
+    }
+
+    async fn listener() -> (TcpListener, SocketAddr) {
+        let l = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = l.local_addr().unwrap();
+        (l, addr)
+    }
+
+    #[test]
+    fn weighted_round_robin() -> Result<()> {
+        let pool = BackendPool::parse("127.0.0.1:1*3, 127.0.0.1:2", LbPolicy::RoundRobin)?;
+        let picks: Vec<_> = (0..8).map(|_| pool.pick().unwrap()).collect();
+        assert_eq!(picks, [0, 1, 0, 0, 0, 1, 0, 0]);
+
+        assert!(BackendPool::parse("", LbPolicy::RoundRobin).is_err());
+        assert!(BackendPool::parse("127.0.0.1:1*0", LbPolicy::RoundRobin).is_err());
+        assert!(BackendPool::parse("nope", LbPolicy::RoundRobin).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn least_connections() -> Result<()> {
+        let (_l1, a1) = listener().await;
+        let (_l2, a2) = listener().await;
+        let pool = Arc::new(BackendPool::parse(
+            &format!("{a1}*2,{a2}"),
+            LbPolicy::LeastConnections,
+        )?);
+
+        let (_, first) = pool.connect().await?;
+        let (_, second) = pool.connect().await?;
+        let (_, third) = pool.connect().await?;
+        // a1 has twice the weight so it takes two of the first three connections.
+        let idxs = [first.idx, second.idx, third.idx];
+        assert_eq!(idxs.iter().filter(|i| **i == 0).count(), 2);
+
+        drop(first);
+        drop(third);
+        assert_eq!(pool.pick(), Some(0));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ejects_and_restores() -> Result<()> {
+        let (_l1, good) = listener().await;
+        let (l2, flaky) = listener().await;
+        drop(l2);
+
+        let pool = fast_pool(&format!("{flaky},{good}"), 2)?;
+        for _ in 0..3 {
+            let (s, _) = pool.connect().await?;
+            assert_eq!(s.peer_addr()?, good);
+        }
+        assert!(!pool.backends[0].healthy.load(Ordering::Relaxed));
+
+        let _l2 = TcpListener::bind(flaky).await?;
+        pool.check_health().await;
+        assert!(pool.backends[0].healthy.load(Ordering::Relaxed));
+
+        drop(_l1);
+        pool.check_health().await;
+        let (s, _) = pool.connect().await?;
+        assert_eq!(s.peer_addr()?, flaky);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retries_before_ejecting() -> Result<()> {
+        let (l, restarting) = listener().await;
+        drop(l);
+        let pool = fast_pool(&restarting.to_string(), 100)?;
+
+        let backend = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let l = TcpListener::bind(restarting).await.unwrap();
+            l.accept().await.unwrap()
+        });
+        let (s, _) = pool.connect().await?;
+        assert_eq!(s.local_addr()?, backend.await.unwrap().1);
+        assert!(pool.backends[0].healthy.load(Ordering::Relaxed));
+        Ok(())
+    }
+}
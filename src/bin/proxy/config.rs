@@ -1,20 +1,43 @@
 use crate::{
+    backends::{BackendPool, DEFAULT_HEALTH_INTERVAL},
+    daemon::Daemon,
     handler::{EchoHandler, Handler},
+    listener::{ListenAddr, Listener},
+    prewarm::{WarmPool, DEFAULT_MAX_IDLE},
+    proxy_protocol,
     pt::get_transport,
+    selftest::{SelftestConfig, DEFAULT_BYTES, DEFAULT_MAX_WRITE},
+};
+use ptrs::acl::IpFilter;
+use ptrs::bridge_stats::{self, BridgeStats};
+use ptrs::copy::{copy_bidirectional_tcp, HalfClosePolicy};
+use ptrs::logging::{self, LogFormat};
+use ptrs::policy::{self, AllowAll, ConnMeta, ConnPolicy, Decision};
+use ptrs::rand::Rng;
+use ptrs::reconnect::ReconnectingDialer;
+use ptrs::retry::Retrying;
+use ptrs::safelog::{self, sensitive};
+use ptrs::status::{Stage, StatusReporter};
+use ptrs::stream::{Addressed, AnyStream, InstrumentedStream};
+use ptrs::transports::identity::Identity;
+use ptrs::{sockopt::SocketOpts, Capabilities, DynTransport, Role, TransportBuilder};
+
+use std::{
+    convert::TryFrom,
+    default::Default,
+    net,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
 };
-use ptrs::{Role, Transport, TransportBuilder};
-
-use std::{convert::TryFrom, default::Default, net, str::FromStr};
 
 use anyhow::anyhow;
 use clap::{Args, CommandFactory, Parser, Subcommand};
-use tokio::{
-    io::copy_bidirectional,
-    net::{TcpListener, TcpStream},
-    sync::mpsc::Sender,
-};
+use rand::RngCore;
+use tokio::sync::mpsc::Sender;
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, error, info, trace, Level};
+use tracing::{debug, error, info, trace, Instrument, Level};
 
 pub const DEFAULT_LISTEN_ADDRESS: &str = "127.0.0.1:9000";
 pub const DEFAULT_SERVER_ADDRESS: &str = "127.0.0.1:9001";
@@ -24,6 +47,7 @@ pub const DEFAULT_LOG_LEVEL: Level = Level::INFO;
 pub enum ProxyConfig {
     Entrance(EntranceConfig),
     Exit(ExitConfig),
+    Selftest(SelftestConfig),
 }
 
 impl ProxyConfig {
@@ -35,6 +59,15 @@ impl ProxyConfig {
         match self {
             ProxyConfig::Entrance(config) => config.run(close, wait).await,
             ProxyConfig::Exit(config) => config.run(close, wait).await,
+            ProxyConfig::Selftest(config) => config.run(close, wait).await,
+        }
+    }
+
+    pub fn daemon_mut(&mut self) -> Option<&mut Daemon> {
+        match self {
+            ProxyConfig::Entrance(config) => Some(&mut config.daemon),
+            ProxyConfig::Exit(config) => Some(&mut config.daemon),
+            ProxyConfig::Selftest(_) => None,
         }
     }
 }
@@ -45,8 +78,14 @@ pub struct EntranceConfig {
     role: Role,
     builder: Option<Box<dyn TransportBuilder>>,
 
-    listen_address: net::SocketAddr,
+    listen_address: ListenAddr,
     remote_address: net::SocketAddr,
+    socket_opts: SocketOpts,
+    half_close: HalfClosePolicy,
+    /// Handshaked connections to the remote to keep ready ahead of clients, 0 for none.
+    prewarm: usize,
+    prewarm_idle: std::time::Duration,
+    daemon: Daemon,
 
     level: Level,
 }
@@ -57,41 +96,128 @@ impl EntranceConfig {
         close: CancellationToken,
         _wait: Sender<()>,
     ) -> Result<(), anyhow::Error> {
-        let listener = TcpListener::bind(self.listen_address).await.unwrap();
+        let listener = Listener::bind(&self.listen_address, &self.socket_opts)
+            .await
+            .map_err(|e| anyhow!("failed to listen on {}: {:?}", self.listen_address, e))?;
         info!("started proxy client on {}", self.listen_address);
+        self.daemon.drop_privileges()?;
 
         let builder = self.builder.as_ref().unwrap();
         let t_name = builder.name();
+        let passthrough = builder.capabilities().contains(Capabilities::PASSTHROUGH);
+        let half_close = self.half_close;
+        // the remote may restart under us; retry rather than take down the accept loop
+        let dialer =
+            ReconnectingDialer::new(self.remote_address).with_socket_opts(self.socket_opts.clone());
+        // progress of each connection for the parent, when run as a managed transport
+        let status = StatusReporter::from_env(t_name);
+        // a passthrough transport has nothing to wrap, and leaving the socket bare lets the
+        // copy splice it in-kernel
+        let client_transport = || -> Result<Option<Box<dyn DynTransport>>, anyhow::Error> {
+            match passthrough {
+                true => Ok(None),
+                false => {
+                    Ok(Some(Box::new(builder.client().map_err(|e| {
+                        anyhow!("failed to build transport: {:?}", e)
+                    })?)))
+                }
+            }
+        };
+        let pool = match self.prewarm {
+            0 => None,
+            size => {
+                let pool = Arc::new(WarmPool::new(
+                    dialer.clone(),
+                    client_transport()?,
+                    size,
+                    self.prewarm_idle,
+                ));
+                pool.spawn_replenish(close.clone());
+                Some(pool)
+            }
+        };
+        // without a pool each connection dials the remote itself; a handshake that fails for
+        // reasons that may pass is retried over a new connection, as a failed dial is
+        let retrying = match (&pool, passthrough) {
+            (None, false) => {
+                Some(Arc::new(Retrying::new(builder.client().map_err(|e| {
+                    anyhow!("failed to build transport: {:?}", e)
+                })?)))
+            }
+            _ => None,
+        };
 
         loop {
-            let (in_stream, socket_addr) = listener.accept().await?;
-            trace!("new tcp connection {socket_addr}");
-
-            let mut out_stream = TcpStream::connect(self.remote_address)
+            let (mut in_stream, socket_addr) = listener
+                .accept()
                 .await
-                .map_err(|e| anyhow!("failed to connect to remote: {}", e))?;
-            let transport = builder
-                .build(&self.role)
-                .map_err(|e| anyhow!("failed to build transport: {:?}", e))?;
+                .map_err(|e| anyhow!("failed to accept: {:?}", e))?;
+            let client = sensitive(socket_addr);
+            trace!("new connection {client}");
 
             let close_c = close.clone();
-            tokio::spawn(async move {
-                let mut in_stream = match transport.wrap(Box::new(in_stream)) {
+            let dialer = dialer.clone();
+            let socket_opts = self.socket_opts.clone();
+            let status = status.clone();
+            let pool = pool.clone();
+            let retrying = retrying.clone();
+            let span = logging::conn_span(t_name);
+            let task = async move {
+                let remote = dialer.addr();
+                // the transport runs over the connection to the bridge; pooled connections
+                // have been through the handshake already
+                let wrapped = match &pool {
+                    Some(pool) => {
+                        let taken = pool.take(&close_c).await;
+                        status.report(remote, Stage::Connect, taken.as_ref().map(|_| ()));
+                        taken
+                    }
+                    None => match &retrying {
+                        Some(retrying) => retrying
+                            .connect_until(&close_c, || async {
+                                let connected = socket_opts.connect(remote).await;
+                                status.report(
+                                    remote,
+                                    Stage::Connect,
+                                    connected.as_ref().map(|_| ()),
+                                );
+                                connected
+                            })
+                            .await
+                            .map(|s| Box::new(s) as Box<dyn AnyStream>),
+                        // a passthrough transport has no handshake to retry
+                        None => {
+                            let connected = dialer.connect_until(&close_c).await;
+                            status.report(remote, Stage::Connect, connected.as_ref().map(|_| ()));
+                            connected.map(|s| Box::new(s) as Box<dyn AnyStream>)
+                        }
+                    },
+                };
+                status.report(remote, Stage::Handshake, wrapped.as_ref().map(|_| ()));
+                let mut out_stream = match wrapped {
                     Ok(s) => s,
                     Err(e) => {
-                        error!("failed to wrap in_stream ->({socket_addr}): {:?}", e);
+                        error!("failed to connect to remote {}: {:?}", sensitive(remote), e);
                         return;
                     }
                 };
 
-                debug!("connection sealer established ->{t_name}-[{socket_addr}]");
+                debug!("connection sealer established ->{t_name}-[{client}]");
+                let identity = Identity::new();
                 tokio::select! {
-                    _ = copy_bidirectional(&mut in_stream, &mut out_stream) => {}
+                    r = copy_bidirectional_tcp(&identity, &mut in_stream, &mut out_stream, half_close) => {
+                        status.report(remote, Stage::Done, r.as_ref().map(|_| ()));
+                        match r {
+                            Ok((up, down)) => info!(up, down, "connection closed [{client}]"),
+                            Err(e) => debug!("connection errored [{client}]: {e}"),
+                        }
+                    },
                     _ = close_c.cancelled() => {
-                        debug!("shutting down proxy thread for {socket_addr}");
+                        debug!("shutting down proxy thread for {client}");
                     }
                 }
-            });
+            };
+            tokio::spawn(task.instrument(span));
         }
     }
 }
@@ -104,8 +230,13 @@ impl Default for EntranceConfig {
             builder: None,
             role: Role::Sealer,
 
-            listen_address: DEFAULT_LISTEN_ADDRESS.parse().unwrap(),
+            listen_address: ListenAddr::Tcp(DEFAULT_LISTEN_ADDRESS.parse().unwrap()),
             remote_address: DEFAULT_REMOTE_ADDRESS.parse().unwrap(),
+            socket_opts: SocketOpts::default(),
+            half_close: HalfClosePolicy::default(),
+            prewarm: 0,
+            prewarm_idle: DEFAULT_MAX_IDLE,
+            daemon: Daemon::default(),
             level: DEFAULT_LOG_LEVEL,
         }
     }
@@ -117,8 +248,21 @@ pub struct ExitConfig {
     handler: Handler,
     role: Role,
     builder: Option<Box<dyn TransportBuilder>>,
-
-    listen_address: net::SocketAddr,
+    policy: Arc<dyn ConnPolicy>,
+    /// Source addresses to refuse before the transport handshake.
+    filter: IpFilter,
+    /// Aggregate usage statistics, saved to `stats_dir` every `stats_interval`.
+    stats: Option<Arc<BridgeStats>>,
+    stats_dir: Option<PathBuf>,
+    stats_interval: Duration,
+
+    listen_address: ListenAddr,
+    /// Expect a PROXY protocol header at the start of each accepted connection.
+    proxy_protocol: bool,
+    /// How long to wait for that header before dropping the connection.
+    proxy_protocol_timeout: Duration,
+    socket_opts: SocketOpts,
+    daemon: Daemon,
 
     level: Level,
 }
@@ -129,29 +273,130 @@ impl ExitConfig {
         close: CancellationToken,
         _wait: Sender<()>,
     ) -> Result<(), anyhow::Error> {
-        let listener = TcpListener::bind(self.listen_address).await.unwrap();
+        let listener = Listener::bind(&self.listen_address, &self.socket_opts)
+            .await
+            .map_err(|e| anyhow!("failed to listen on {}: {:?}", self.listen_address, e))?;
+        let local_addr = listener
+            .local_addr()
+            .map_err(|e| anyhow!("failed to get local address: {:?}", e))?;
         info!("started server listening on {}", self.listen_address);
+        self.daemon.drop_privileges()?;
+
+        if let Handler::Forward(pool, ..) = &self.handler {
+            pool.clone()
+                .spawn_health_checks(DEFAULT_HEALTH_INTERVAL, close.clone());
+        }
+
+        if let Some(stats) = &self.stats {
+            let stats = stats.clone();
+            let dir = self.stats_dir.clone();
+            let interval = self.stats_interval;
+            let close = close.clone();
+            tokio::spawn(async move { stats.run(interval, dir, close).await });
+        }
 
         let builder = self.builder.as_ref().unwrap();
         let t_name = builder.name();
         loop {
-            let (stream, socket_addr) = listener.accept().await?;
-            trace!("new tcp connection {socket_addr}");
-
-            let transport = builder
-                .build(&self.role)
-                .map_err(|e| anyhow!("failed to build transport: {:?}", e))?;
+            let (stream, socket_addr) = listener
+                .accept()
+                .await
+                .map_err(|e| anyhow!("failed to accept: {:?}", e))?;
+            let client = sensitive(socket_addr.clone());
+            trace!("new connection {client}");
+
+            let transport: Box<dyn DynTransport> = Box::new(
+                builder
+                    .server()
+                    .map_err(|e| anyhow!("failed to build transport: {:?}", e))?,
+            );
             let close_c = close.clone();
-            let handler = self.handler;
-            let stream = match transport.wrap(Box::new(stream)) {
-                Ok(s) => s,
-                Err(e) => {
-                    error!("failed to wrap in_stream ->({socket_addr}): {:?}", e);
-                    continue;
+            let handler = self.handler.clone();
+            let policy = self.policy.clone();
+            let filter = self.filter.clone();
+            let stats = self.stats.clone();
+            let proxy_protocol = self.proxy_protocol;
+            let header_timeout = self.proxy_protocol_timeout;
+            let span = logging::conn_span(t_name);
+            let task = async move {
+                let mut stream = stream;
+                let mut meta = ConnMeta {
+                    peer_addr: socket_addr.socket_addr(),
+                    local_addr,
+                    transport: t_name,
+                    accepted_at: Instant::now(),
+                };
+                // a Unix socket peer has no address to filter or count it by, unless a PROXY
+                // header supplies the real source
+                let mut source = socket_addr.ip();
+                if proxy_protocol {
+                    match proxy_protocol::read_header_within(&mut stream, header_timeout).await {
+                        Ok(Some(header)) => {
+                            trace!("proxy protocol [{client}]: {header:?}");
+                            meta.peer_addr = header.src;
+                            meta.local_addr = header.dst;
+                            source = Some(header.src.ip());
+                        }
+                        Ok(None) => {}
+                        Err(e) => {
+                            error!("failed to read proxy header [{client}]: {:?}", e);
+                            return;
+                        }
+                    }
+                }
+                let peer = meta.peer_addr;
+                let client = sensitive(peer);
+                if source.is_some_and(|ip| filter.check(ip).is_err()) {
+                    return;
+                }
+
+                let stream = match transport
+                    .wrap_boxed_with_cancel(stream, close_c.clone())
+                    .await
+                {
+                    Ok(s) => s,
+                    Err(e) => {
+                        error!("failed to wrap in_stream ->({client}): {:?}", e);
+                        return;
+                    }
+                };
+                debug!("connection successfully revealed ->{t_name}-[{client}]");
+                // the handler reads the client's addresses from the stream; a Unix socket peer
+                // without a PROXY header has none
+                let stream = Addressed::new(
+                    stream,
+                    source.map(|_| meta.peer_addr),
+                    Some(meta.local_addr),
+                );
+
+                let stream = match policy::apply(policy.as_ref(), &meta, stream).await {
+                    Ok((Decision::Allow, s)) => s,
+                    Ok((Decision::Tag(tags), s)) => {
+                        debug!("connection tagged [{client}]: {tags:?}");
+                        s
+                    }
+                    Ok((Decision::Reject(reason), _)) => {
+                        info!("connection rejected by policy [{client}]: {reason}");
+                        return;
+                    }
+                    Err(e) => {
+                        error!("failed to apply policy [{client}]: {:?}", e);
+                        return;
+                    }
+                };
+                if let Some(stats) = &stats {
+                    stats.record_connection(t_name, source);
+                }
+                let stream = InstrumentedStream::new(stream);
+                let counts = stream.as_stats();
+                if let Err(e) = handler.handle(stream, close_c).await {
+                    error!("handler failed [{client}]: {:?}", e);
+                }
+                if let Some(stats) = &stats {
+                    stats.record_bytes(t_name, counts.bytes_read(), counts.bytes_written());
                 }
             };
-            debug!("connection successfully revealed ->{t_name}-[{socket_addr}]");
-            tokio::spawn(handler.handle(stream, close_c));
+            tokio::spawn(task.instrument(span));
         }
     }
 }
@@ -163,9 +408,18 @@ impl Default for ExitConfig {
             pt_args: vec![],
             builder: None,
             role: Role::Revealer,
-            listen_address: DEFAULT_SERVER_ADDRESS.parse().unwrap(),
+            listen_address: ListenAddr::Tcp(DEFAULT_SERVER_ADDRESS.parse().unwrap()),
+            proxy_protocol: false,
+            proxy_protocol_timeout: proxy_protocol::DEFAULT_HEADER_TIMEOUT,
+            socket_opts: SocketOpts::default(),
+            daemon: Daemon::default(),
             level: DEFAULT_LOG_LEVEL,
             handler: Handler::Echo(EchoHandler),
+            policy: Arc::new(AllowAll),
+            filter: IpFilter::default(),
+            stats: None,
+            stats_dir: None,
+            stats_interval: bridge_stats::DEFAULT_INTERVAL,
         }
     }
 }
@@ -176,28 +430,15 @@ impl TryFrom<Cli> for ProxyConfig {
     fn try_from(cli: Cli) -> Result<Self, Self::Error> {
         match cli.command {
             Some(Commands::Server(args)) => {
-                let mut config = ExitConfig::default();
-                if args.debug {
-                    config.level = Level::DEBUG;
-                } else if args.trace {
-                    config.level = Level::TRACE;
-                }
-                tracing_subscriber::fmt()
-                    .with_max_level(config.level)
-                    .init();
-                trace!("{:?}", args);
-
-                config.pt = "".to_string();
-                config.pt_args = vec![];
-                let builder = get_transport(&config.pt, &config.role)
-                    .map_err(|e| anyhow!("failed to get transport: {:?}", e))?;
-                config.builder = Some(builder);
-
-                config.listen_address = args.listen_addr.parse()?;
-
-                config.handler = Handler::from_str(&args.backend)
-                    .map_err(|e| anyhow!("failed to parse backend: {:?}", e))?;
-
+                let config = ExitConfig::from_args(&args)?;
+                let format: LogFormat = args
+                    .log_format
+                    .parse()
+                    .map_err(|e| anyhow!("failed to parse log format: {:?}", e))?;
+                logging::init(format, config.level)
+                    .map_err(|e| anyhow!("failed to set up logging: {:?}", e))?;
+                safelog::set_unsafe_logging(args.unsafe_logging);
+                trace!("{:?}", sensitive(&args));
                 Ok(ProxyConfig::Exit(config))
             }
             Some(Commands::Client(args)) => {
@@ -207,15 +448,31 @@ impl TryFrom<Cli> for ProxyConfig {
                 } else if args.trace {
                     config.level = Level::TRACE;
                 }
-                tracing_subscriber::fmt()
-                    .with_max_level(config.level)
-                    .init();
-                trace!("{:?}", args);
+                let format: LogFormat = args
+                    .log_format
+                    .parse()
+                    .map_err(|e| anyhow!("failed to parse log format: {:?}", e))?;
+                logging::init(format, config.level)
+                    .map_err(|e| anyhow!("failed to set up logging: {:?}", e))?;
+                safelog::set_unsafe_logging(args.unsafe_logging);
+                trace!("{:?}", sensitive(&args));
 
                 config.remote_address = args.remote.parse()?;
-                config.listen_address = args.listen_addr.parse()?;
-
-                config.pt = "".to_string();
+                config.listen_address = args
+                    .listen_addr
+                    .parse()
+                    .map_err(|e| anyhow!("failed to parse listen address: {:?}", e))?;
+                config.socket_opts = args.socket.into();
+                config.daemon = args.daemon.into();
+                config.daemon.allow_listener(&config.listen_address);
+                config.half_close = args
+                    .half_close
+                    .parse()
+                    .map_err(|e| anyhow!("failed to parse half-close policy: {:?}", e))?;
+                config.prewarm = args.prewarm;
+                config.prewarm_idle = std::time::Duration::from_secs(args.prewarm_idle);
+
+                config.pt = args.transport.clone();
                 config.pt_args = vec![];
                 let builder = get_transport(&config.pt, &config.role)
                     .map_err(|e| anyhow!("failed to get transport: {:?}", e))?;
@@ -223,6 +480,17 @@ impl TryFrom<Cli> for ProxyConfig {
 
                 Ok(ProxyConfig::Entrance(config))
             }
+            Some(Commands::Selftest(args)) => {
+                let level = if args.debug {
+                    Level::DEBUG
+                } else {
+                    Level::WARN
+                };
+                logging::init(LogFormat::Text, level)
+                    .map_err(|e| anyhow!("failed to set up logging: {:?}", e))?;
+                trace!("{:?}", args);
+                Ok(ProxyConfig::Selftest(SelftestConfig::from_args(&args)?))
+            }
             None => {
                 Cli::command().print_help()?;
                 std::process::exit(1);
@@ -231,6 +499,94 @@ impl TryFrom<Cli> for ProxyConfig {
     }
 }
 
+impl ExitConfig {
+    fn from_args(args: &ServerArgs) -> Result<Self, anyhow::Error> {
+        let mut config = ExitConfig::default();
+        if args.debug {
+            config.level = Level::DEBUG;
+        } else if args.trace {
+            config.level = Level::TRACE;
+        }
+        config.pt = args.transport.clone();
+        config.pt_args = vec![];
+        let builder = get_transport(&config.pt, &config.role)
+            .map_err(|e| anyhow!("failed to get transport: {:?}", e))?;
+        config.builder = Some(builder);
+
+        config.listen_address = args
+            .listen_addr
+            .parse()
+            .map_err(|e| anyhow!("failed to parse listen address: {:?}", e))?;
+        config.proxy_protocol = args.proxy_protocol;
+        config.proxy_protocol_timeout = Duration::from_secs(args.proxy_protocol_timeout);
+        if let Some(spec) = &args.filter {
+            config.filter = spec
+                .parse()
+                .map_err(|e| anyhow!("failed to parse address filter: {:?}", e))?;
+        }
+        if args.stats || args.stats_dir.is_some() {
+            config.stats = Some(Arc::new(BridgeStats::new()));
+            config.stats_interval = Duration::from_secs(args.stats_interval);
+            config.stats_dir = args.stats_dir.clone();
+        }
+        #[cfg(feature = "geoip")]
+        if let Some(path) = &args.geoip {
+            let tagger = ptrs::geoip::GeoTagger::open(path)
+                .map_err(|e| anyhow!("failed to load geoip database: {:?}", e))?;
+            config.policy = Arc::new(tagger);
+        }
+        config.socket_opts = args.socket.clone().into();
+        config.daemon = args.daemon.clone().into();
+        config.daemon.allow_listener(&config.listen_address);
+        if let Some(dir) = &config.stats_dir {
+            config.daemon.allow_write(dir);
+        }
+
+        config.handler = match args.backend.strip_prefix("forward:") {
+            Some(spec) => {
+                let policy = args
+                    .lb_policy
+                    .parse()
+                    .map_err(|e| anyhow!("failed to parse lb policy: {:?}", e))?;
+                let pool = BackendPool::parse(spec, policy)
+                    .map_err(|e| anyhow!("failed to parse backends: {:?}", e))?
+                    .with_socket_opts(config.socket_opts.clone());
+                let send_header = args
+                    .backend_proxy_protocol
+                    .as_deref()
+                    .map(proxy_protocol::Version::from_str)
+                    .transpose()
+                    .map_err(|e| anyhow!("failed to parse proxy protocol: {:?}", e))?;
+                let half_close = args
+                    .half_close
+                    .parse()
+                    .map_err(|e| anyhow!("failed to parse half-close policy: {:?}", e))?;
+                Handler::Forward(Arc::new(pool), send_header, half_close)
+            }
+            None => Handler::from_str(&args.backend)
+                .map_err(|e| anyhow!("failed to parse backend: {:?}", e))?,
+        };
+
+        Ok(config)
+    }
+}
+
+impl SelftestConfig {
+    fn from_args(args: &SelftestArgs) -> Result<Self, anyhow::Error> {
+        if args.max_write == 0 {
+            return Err(anyhow!("--max-write must be at least 1"));
+        }
+        let builder = get_transport(&args.transport, &Role::Sealer)
+            .map_err(|e| anyhow!("failed to get transport: {:?}", e))?;
+        Ok(SelftestConfig {
+            builder,
+            bytes: args.bytes,
+            max_write: args.max_write,
+            seed: args.seed.unwrap_or_else(|| Rng::from_entropy().next_u64()),
+        })
+    }
+}
+
 #[derive(Parser, Debug)]
 #[command(author, version, about="Proof of Concept proxy system for pluggable transports (PTRS)", long_about = None)]
 #[command(propagate_version = true)]
@@ -246,21 +602,80 @@ enum Commands {
 
     /// Run the binary as the client-side proxy
     Client(ClientArgs),
+
+    /// Check a transport by echoing traffic through its client and server over loopback
+    Selftest(SelftestArgs),
 }
 
 #[derive(Args, Debug)]
 struct ServerArgs {
-    /// Address to listen for incoming client connections
+    /// Address to listen for incoming client connections ["ip:port", "unix:/path", "systemd[:N]"]
     listen_addr: String,
 
     /// pluggable transport by name
     #[arg(short, long, default_value_t = String::from("plain"))]
     transport: String,
 
-    /// The backend handler to use ["echo", "socks5"]
+    /// The backend handler to use ["echo", "socks5", "forward:addr[*weight],..."]
     #[arg(short, long, default_value_t = String::from("echo"))]
     backend: String,
 
+    /// How "forward" spreads connections across backends ["round-robin", "least-connections"]
+    #[arg(long, default_value_t = String::from("round-robin"))]
+    lb_policy: String,
+
+    /// Expect a PROXY protocol (v1 or v2) header on accepted connections
+    #[arg(long, default_value_t = false)]
+    proxy_protocol: bool,
+
+    /// Seconds a client has to send its PROXY protocol header
+    #[arg(long, default_value_t = proxy_protocol::DEFAULT_HEADER_TIMEOUT.as_secs())]
+    proxy_protocol_timeout: u64,
+
+    /// Refuse clients by source address before the handshake ["allow=cidr,..;block=cidr,.."].
+    /// Unix socket clients are only checked if a PROXY header gives their source
+    #[arg(long)]
+    filter: Option<String>,
+
+    /// Collect aggregate usage statistics, reported as STATUS lines when managed
+    #[arg(long, default_value_t = false)]
+    stats: bool,
+
+    /// Directory to save usage statistics to, implies --stats
+    #[arg(long)]
+    stats_dir: Option<PathBuf>,
+
+    /// Seconds per usage statistics report
+    #[arg(long, default_value_t = bridge_stats::DEFAULT_INTERVAL.as_secs())]
+    stats_interval: u64,
+
+    /// MaxMind format country database, for tagging connections by country
+    #[cfg(feature = "geoip")]
+    #[arg(long)]
+    geoip: Option<std::path::PathBuf>,
+
+    /// Send a PROXY protocol header to "forward" backends ["v1", "v2"]
+    #[arg(long)]
+    backend_proxy_protocol: Option<String>,
+
+    /// What "forward" does when one side closes ["half-close", "close-both", "linger:SECS"]
+    #[arg(long, default_value_t = String::from("half-close"))]
+    half_close: String,
+
+    #[command(flatten)]
+    socket: SocketArgs,
+
+    #[command(flatten)]
+    daemon: DaemonArgs,
+
+    /// Log output format ["text", "json"]
+    #[arg(long, default_value_t = String::from("text"))]
+    log_format: String,
+
+    /// Log client addresses, bridge addresses and transport arguments instead of scrubbing them
+    #[arg(long, default_value_t = false)]
+    unsafe_logging: bool,
+
     /// Optional argument enabling debug logging
     #[arg(long, default_value_t = false, conflicts_with = "trace")]
     debug: bool,
@@ -279,7 +694,7 @@ struct ClientArgs {
     /// Optional argument specifying the client_type, default to be Runner
     remote: String,
 
-    /// Address to listen for incoming client connections
+    /// Address to listen for incoming client connections ["ip:port", "unix:/path", "systemd[:N]"]
     #[arg(short, long, default_value_t=String::from(DEFAULT_LISTEN_ADDRESS))]
     listen_addr: String,
 
@@ -287,6 +702,32 @@ struct ClientArgs {
     #[arg(short, long, default_value_t = String::from("plain"))]
     transport: String,
 
+    /// What to do when one side closes ["half-close", "close-both", "linger:SECS"]
+    #[arg(long, default_value_t = String::from("half-close"))]
+    half_close: String,
+
+    /// Handshaked connections to the remote to keep ready ahead of incoming clients
+    #[arg(long, default_value_t = 0)]
+    prewarm: usize,
+
+    /// Seconds a prewarmed connection may sit unused before it is replaced
+    #[arg(long, default_value_t = DEFAULT_MAX_IDLE.as_secs())]
+    prewarm_idle: u64,
+
+    #[command(flatten)]
+    socket: SocketArgs,
+
+    #[command(flatten)]
+    daemon: DaemonArgs,
+
+    /// Log output format ["text", "json"]
+    #[arg(long, default_value_t = String::from("text"))]
+    log_format: String,
+
+    /// Log client addresses, bridge addresses and transport arguments instead of scrubbing them
+    #[arg(long, default_value_t = false)]
+    unsafe_logging: bool,
+
     /// Optional argument enabling debug logging
     #[arg(long, default_value_t = false, conflicts_with = "trace")]
     debug: bool,
@@ -299,3 +740,220 @@ struct ClientArgs {
     #[arg(name="PT_ARGS", num_args = 1.., trailing_var_arg = true, allow_hyphen_values = true)]
     trailing: Vec<String>,
 }
+
+#[derive(Args, Debug)]
+struct SelftestArgs {
+    /// pluggable transport by name
+    #[arg(short, long, default_value_t = String::from("plain"))]
+    transport: String,
+
+    /// Bytes of traffic to echo through the transport
+    #[arg(long, default_value_t = DEFAULT_BYTES)]
+    bytes: usize,
+
+    /// Largest single write; write sizes are random up to this
+    #[arg(long, default_value_t = DEFAULT_MAX_WRITE)]
+    max_write: usize,
+
+    /// Seed for the traffic and write sizes, to reproduce a failure
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Optional argument enabling debug logging
+    #[arg(long, default_value_t = false)]
+    debug: bool,
+}
+
+/// TCP options applied to listeners and outbound connections.
+#[derive(Args, Clone, Debug)]
+struct SocketArgs {
+    /// Disable Nagle's algorithm on TCP connections
+    #[arg(long, default_value_t = false)]
+    nodelay: bool,
+
+    /// Seconds of idle time before sending TCP keepalive probes
+    #[arg(long)]
+    keepalive: Option<u64>,
+
+    /// Set SO_REUSEPORT on the listener so several processes can share the address
+    #[arg(long, default_value_t = false)]
+    reuse_port: bool,
+
+    /// Accept only IPv6 connections on IPv6 listeners
+    #[arg(long, default_value_t = false)]
+    only_v6: bool,
+
+    /// Socket send buffer size in bytes
+    #[arg(long)]
+    send_buffer: Option<usize>,
+
+    /// Socket receive buffer size in bytes
+    #[arg(long)]
+    recv_buffer: Option<usize>,
+
+    /// DSCP value (0-63) to mark outgoing packets with (linux only)
+    #[arg(long)]
+    dscp: Option<u8>,
+
+    /// SO_MARK firewall mark for policy routing, needs CAP_NET_ADMIN (linux only)
+    #[arg(long)]
+    mark: Option<u32>,
+}
+
+impl From<SocketArgs> for SocketOpts {
+    fn from(args: SocketArgs) -> Self {
+        SocketOpts {
+            nodelay: args.nodelay.then_some(true),
+            keepalive: args.keepalive.map(std::time::Duration::from_secs),
+            #[cfg(unix)]
+            reuse_port: args.reuse_port,
+            only_v6: args.only_v6.then_some(true),
+            send_buffer: args.send_buffer,
+            recv_buffer: args.recv_buffer,
+            dscp: args.dscp,
+            mark: args.mark,
+        }
+    }
+}
+
+/// Options for running as a daemon from an init script.
+#[derive(Args, Clone, Debug)]
+struct DaemonArgs {
+    /// Fork into the background, detached from the terminal (unix only)
+    #[arg(long, default_value_t = false)]
+    daemon: bool,
+
+    /// Write the process id to this file
+    #[arg(long)]
+    pid_file: Option<PathBuf>,
+
+    /// Switch to this user once listening, e.g. after binding a privileged port (unix only)
+    #[arg(long)]
+    user: Option<String>,
+
+    #[cfg(feature = "sandbox")]
+    #[command(flatten)]
+    sandbox: SandboxArgs,
+}
+
+/// Hardening applied before the proxy starts listening (linux only).
+#[cfg(feature = "sandbox")]
+#[derive(Args, Clone, Debug)]
+struct SandboxArgs {
+    /// Refuse syscalls the proxy never needs and limit file access with landlock
+    #[arg(long, default_value_t = false)]
+    sandbox: bool,
+
+    /// Chroot into this directory first, which needs root
+    #[arg(long)]
+    chroot: Option<PathBuf>,
+
+    /// Extra path the sandbox may read, may be repeated
+    #[arg(long)]
+    sandbox_read: Vec<PathBuf>,
+
+    /// Extra path the sandbox may read and write, may be repeated
+    #[arg(long)]
+    sandbox_write: Vec<PathBuf>,
+}
+
+impl From<DaemonArgs> for Daemon {
+    fn from(args: DaemonArgs) -> Self {
+        Daemon {
+            detach: args.daemon,
+            pid_file: args.pid_file,
+            user: args.user,
+            #[cfg(feature = "sandbox")]
+            sandbox: ptrs::sandbox::Sandbox {
+                chroot: args.sandbox.chroot,
+                landlock: args.sandbox.sandbox,
+                read_paths: args.sandbox.sandbox_read,
+                write_paths: args.sandbox.sandbox_write,
+                seccomp: args.sandbox.sandbox,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::UnixStream;
+
+    fn parse(args: &[&str]) -> Commands {
+        let cli = Cli::try_parse_from([&["proxy"], args].concat()).unwrap();
+        cli.command.unwrap()
+    }
+
+    #[test]
+    fn selftest_needs_a_known_transport() {
+        let Commands::Selftest(args) = parse(&["selftest", "--transport", "no-such-pt"]) else {
+            unreachable!()
+        };
+        let e = SelftestConfig::from_args(&args).err().unwrap();
+        assert!(e.to_string().contains("unknown transport"), "{e}");
+
+        let Commands::Selftest(args) = parse(&["selftest", "--transport", "plain"]) else {
+            unreachable!()
+        };
+        assert_eq!(
+            SelftestConfig::from_args(&args).unwrap().builder.name(),
+            "identity"
+        );
+    }
+
+    #[cfg(feature = "sandbox")]
+    #[test]
+    fn stats_dir_is_writable_in_the_sandbox() {
+        let Commands::Server(args) =
+            parse(&["server", "127.0.0.1:0", "--stats-dir", "/var/lib/ptrs"])
+        else {
+            unreachable!()
+        };
+        let config = ExitConfig::from_args(&args).unwrap();
+        assert!(config
+            .daemon
+            .sandbox
+            .write_paths
+            .contains(&PathBuf::from("/var/lib/ptrs")));
+    }
+
+    #[tokio::test]
+    async fn unix_peers_skip_address_checks() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("exit.sock");
+        let stats = Arc::new(BridgeStats::new());
+        let config = ExitConfig {
+            builder: Some(Box::new(Identity::new())),
+            listen_address: ListenAddr::Unix(path.clone()),
+            // would refuse everyone if a Unix peer were checked as 0.0.0.0
+            filter: "allow=10.0.0.0/8".parse().map_err(|e| anyhow!("{e}"))?,
+            stats: Some(stats.clone()),
+            ..ExitConfig::default()
+        };
+
+        let (wait, _) = tokio::sync::mpsc::channel(1);
+        let client = async {
+            while !path.exists() {
+                tokio::task::yield_now().await;
+            }
+            let mut client = UnixStream::connect(&path).await?;
+            client.write_all(b"hi").await?;
+            let mut buf = [0_u8; 2];
+            client.read_exact(&mut buf).await?;
+            assert_eq!(&buf, b"hi");
+            Ok::<_, anyhow::Error>(())
+        };
+        tokio::select! {
+            r = config.run(CancellationToken::new(), wait) => r?,
+            r = client => r?,
+        }
+
+        let usage = &stats.rotate().transports["identity"];
+        assert_eq!(usage.unique_ips, 0);
+        assert_eq!(usage.connections, bridge_stats::BIN_SIZE);
+        Ok(())
+    }
+}
@@ -0,0 +1,229 @@
+//! Running as a traditional daemon, so init scripts can start the proxy directly: detaching
+//! from the terminal, writing a pid file, sandboxing (with the `sandbox` feature) and
+//! dropping root once listeners are bound.
+
+use crate::listener::ListenAddr;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use tracing::{debug, info};
+
+#[derive(Clone, Debug, Default)]
+pub struct Daemon {
+    /// Fork into the background, detached from the terminal.
+    pub detach: bool,
+    pub pid_file: Option<PathBuf>,
+    /// User to switch to once listening.
+    pub user: Option<String>,
+    #[cfg(feature = "sandbox")]
+    pub sandbox: ptrs::sandbox::Sandbox,
+}
+
+impl Daemon {
+    /// Detach if asked to, write the pid file, then enter the sandbox. Must be called before the runtime (or
+    /// any other thread) starts, since only the calling thread survives the fork. When
+    /// detaching, the original process exits once the pid file has been written, so whoever
+    /// started it can read the file straight away.
+    pub fn start(&mut self) -> anyhow::Result<()> {
+        // detaching moves to `/`
+        if let Some(path) = &mut self.pid_file {
+            *path = std::path::absolute(&*path)?;
+        }
+        let ready = match self.detach {
+            true => Some(imp::detach().context("failed to detach")?),
+            false => None,
+        };
+        if let Some(path) = &self.pid_file {
+            fs::write(path, format!("{}\n", std::process::id()))
+                .with_context(|| format!("failed to write pid file {}", path.display()))?;
+        }
+        #[cfg(feature = "sandbox")]
+        self.sandbox
+            .apply()
+            .map_err(|e| anyhow::anyhow!("failed to enter the sandbox: {e}"))?;
+        if let Some(ready) = ready {
+            imp::signal_ready(ready)?;
+        }
+        Ok(())
+    }
+
+    /// Switch to the configured user, if any. Call once the listeners are bound, so ports
+    /// below 1024 and root owned unix socket paths can still be used.
+    pub fn drop_privileges(&self) -> anyhow::Result<()> {
+        let Some(user) = &self.user else {
+            return Ok(());
+        };
+        imp::switch_user(user).with_context(|| format!("failed to switch to user {user}"))?;
+        info!("switched to user {user}");
+        Ok(())
+    }
+
+    /// Let the sandbox create the listener's unix socket.
+    pub fn allow_listener(&mut self, _addr: &ListenAddr) {
+        #[cfg(all(feature = "sandbox", unix))]
+        if let ListenAddr::Unix(path) = _addr {
+            self.allow_write(path.parent().unwrap_or(Path::new(".")));
+        }
+    }
+
+    /// Let the sandbox write below `dir`.
+    pub fn allow_write(&mut self, _dir: &Path) {
+        #[cfg(feature = "sandbox")]
+        self.sandbox.write_paths.push(_dir.to_path_buf());
+    }
+
+    /// Best effort, as the directory may not be writable once privileges are dropped.
+    pub fn remove_pid_file(&self) {
+        if let Some(path) = &self.pid_file {
+            if let Err(e) = fs::remove_file(path) {
+                debug!("failed to remove pid file {}: {e}", path.display());
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::ffi::{CStr, CString};
+    use std::fs::File;
+    use std::io::{self, Read, Write};
+    use std::os::fd::{AsRawFd, FromRawFd};
+
+    fn check(ret: libc::c_int) -> io::Result<libc::c_int> {
+        match ret {
+            -1 => Err(io::Error::last_os_error()),
+            ret => Ok(ret),
+        }
+    }
+
+    /// Double fork into a new session with stdio on `/dev/null`. The original process waits
+    /// until the daemon writes to the returned pipe, exiting with success, or with failure if
+    /// the daemon exits first.
+    pub fn detach() -> io::Result<File> {
+        let mut fds = [0; 2];
+        check(unsafe { libc::pipe(fds.as_mut_ptr()) })?;
+        let (mut r, w) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+        if check(unsafe { libc::fork() })? > 0 {
+            drop(w);
+            let mut buf = [0u8; 1];
+            let started = matches!(r.read(&mut buf), Ok(1));
+            unsafe { libc::_exit(if started { 0 } else { 1 }) };
+        }
+        drop(r);
+        check(unsafe { libc::setsid() })?;
+        // leave the session so the daemon can never acquire a controlling terminal
+        if check(unsafe { libc::fork() })? > 0 {
+            unsafe { libc::_exit(0) };
+        }
+
+        std::env::set_current_dir("/")?;
+        let null = File::options().read(true).write(true).open("/dev/null")?;
+        for fd in 0..=2 {
+            check(unsafe { libc::dup2(null.as_raw_fd(), fd) })?;
+        }
+        Ok(w)
+    }
+
+    pub fn signal_ready(mut ready: File) -> anyhow::Result<()> {
+        ready.write_all(b"1")?;
+        Ok(())
+    }
+
+    /// Look up the uid and primary gid of `name`.
+    pub fn lookup_user(name: &str) -> io::Result<(libc::uid_t, libc::gid_t)> {
+        let c_name =
+            CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut buf = vec![0 as libc::c_char; 16 * 1024];
+        let mut found = std::ptr::null_mut();
+        let ret = unsafe {
+            libc::getpwnam_r(
+                c_name.as_ptr(),
+                &mut pwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut found,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::from_raw_os_error(ret));
+        }
+        if found.is_null() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no such user"));
+        }
+        debug_assert_eq!(unsafe { CStr::from_ptr(pwd.pw_name) }, c_name.as_c_str());
+        Ok((pwd.pw_uid, pwd.pw_gid))
+    }
+
+    /// Takes on the user's groups and ids. libc applies the changes to every thread, not
+    /// just the calling one.
+    pub fn switch_user(name: &str) -> io::Result<()> {
+        let (uid, gid) = lookup_user(name)?;
+        let c_name = CString::new(name).expect("checked by lookup_user");
+        // groups first, as changing the uid takes away the right to
+        check(unsafe { libc::initgroups(c_name.as_ptr(), gid as _) })?;
+        check(unsafe { libc::setgid(gid) })?;
+        check(unsafe { libc::setuid(uid) })?;
+        if unsafe { libc::setuid(0) } == 0 && uid != 0 {
+            return Err(io::Error::other("regained root after dropping it"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    use anyhow::anyhow;
+
+    use std::io;
+
+    pub struct Ready;
+
+    pub fn detach() -> anyhow::Result<Ready> {
+        Err(anyhow!("--daemon is only supported on unix"))
+    }
+
+    pub fn signal_ready(_: Ready) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    pub fn switch_user(_: &str) -> io::Result<()> {
+        Err(io::Error::other("--user is only supported on unix"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn writes_and_removes_pid_file() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut daemon = Daemon {
+            pid_file: Some(dir.path().join("proxy.pid")),
+            ..Daemon::default()
+        };
+        daemon.start()?;
+        let path = daemon.pid_file.clone().unwrap();
+        assert_eq!(
+            fs::read_to_string(&path)?,
+            format!("{}\n", std::process::id())
+        );
+        // nobody to switch to
+        daemon.drop_privileges()?;
+
+        daemon.remove_pid_file();
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn looks_up_users() {
+        assert_eq!(imp::lookup_user("root").unwrap(), (0, 0));
+        assert!(imp::lookup_user("no-such-user-4906").is_err());
+    }
+}
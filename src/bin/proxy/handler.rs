@@ -1,32 +1,58 @@
 #![allow(dead_code)]
+use crate::backends::BackendPool;
+use crate::proxy_protocol::{self, Header};
 use crate::socks5;
-use ptrs::{Error, Result};
+use ptrs::copy::{copy_bidirectional_tcp, HalfClosePolicy};
+use ptrs::transports::identity::Identity;
+use ptrs::{stream::AddrInfo, Error, Result};
 use tor_rtcompat::PreferredRuntime;
 
 use async_compat::CompatExt;
+use std::net::{IpAddr, Ipv4Addr};
 use std::str::FromStr;
+use std::sync::Arc;
 
 use tokio::{
     self,
-    io::{copy, split, AsyncRead, AsyncWrite},
+    io::{copy, split, AsyncRead, AsyncWrite, AsyncWriteExt},
 };
 use tokio_util::sync::CancellationToken;
 use tracing::trace;
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Debug)]
 pub enum Handler {
     Socks5,
     Echo(EchoHandler),
+    /// Forward to a backend from the pool, optionally prefixed with a PROXY protocol header
+    /// carrying the original client address.
+    Forward(
+        Arc<BackendPool>,
+        Option<proxy_protocol::Version>,
+        HalfClosePolicy,
+    ),
 }
 
 impl Handler {
     pub async fn handle<RW>(self, stream: RW, close_c: CancellationToken) -> Result<()>
     where
-        RW: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+        RW: AsyncRead + AsyncWrite + AddrInfo + Unpin + Send + Sync + 'static,
     {
         match self {
-            Handler::Socks5 => Socks5Handler::handle(stream.compat(), close_c).await,
+            Handler::Socks5 => {
+                let local_ip = stream
+                    .local_addr()
+                    .map_or(Ipv4Addr::UNSPECIFIED.into(), |a| a.ip());
+                Socks5Handler::handle(stream.compat(), local_ip, close_c).await
+            }
             Handler::Echo(h) => h.handle(stream, close_c).await,
+            Handler::Forward(pool, send_header, half_close) => {
+                // without a client address there is nothing useful to tell the backend
+                let header = match (send_header, stream.peer_addr(), stream.local_addr()) {
+                    (Some(v), Some(src), Some(dst)) => Some(Header { src, dst }.encode(v)),
+                    _ => None,
+                };
+                forward(&pool, stream, header, half_close, close_c).await
+            }
         }
     }
 }
@@ -43,17 +69,45 @@ impl FromStr for Handler {
     }
 }
 
+/// Copy between `stream` and a backend chosen from `pool` until either side closes. `header`
+/// is sent to the backend before anything else.
+async fn forward<RW>(
+    pool: &Arc<BackendPool>,
+    mut stream: RW,
+    header: Option<Vec<u8>>,
+    half_close: HalfClosePolicy,
+    close_c: CancellationToken,
+) -> Result<()>
+where
+    RW: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+{
+    // the lease keeps the connection counted against its backend until the copy finishes
+    let (mut backend, _lease) = pool.connect().await?;
+    if let Some(header) = header {
+        backend.write_all(&header).await?;
+    }
+    let identity = Identity::new();
+    tokio::select! {
+        r = copy_bidirectional_tcp(&identity, &mut stream, &mut backend, half_close) => match r {
+            Ok((up, down)) => tracing::info!(up, down, "forward finished"),
+            Err(e) => tracing::error!("forward errored: {}", e),
+        },
+        _ = close_c.cancelled() => {}
+    }
+    Ok(())
+}
+
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct Socks5Handler;
 
 impl Socks5Handler {
-    pub async fn handle<RW>(stream: RW, close_c: CancellationToken) -> Result<()>
+    pub async fn handle<RW>(stream: RW, local_ip: IpAddr, close_c: CancellationToken) -> Result<()>
     where
         RW: futures::AsyncRead + futures::AsyncWrite + Unpin + Send + Sync + 'static,
     {
         let rt = PreferredRuntime::current()?;
         tokio::select! {
-            r = socks5::handle_socks_conn(rt, stream) => {
+            r = socks5::handle_socks_conn(rt, stream, local_ip) => {
                 if let Err(e) = r {
                     tracing::error!("socks connection errored: {}", e);
                 }
@@ -90,12 +144,10 @@ impl EchoHandler {
     {
         let (mut reader, mut writer) = split(stream);
         tokio::select! {
-            r = copy(&mut reader, &mut writer) => {
-                if let Err(e) = r {
-                    tracing::error!("echo errored: {}", e);
-                }
-                trace!("echo finished")
-            }
+            r = copy(&mut reader, &mut writer) => match r {
+                Ok(n) => trace!(bytes = n, "echo finished"),
+                Err(e) => tracing::error!("echo errored: {}", e),
+            },
             _ = close_c.cancelled() => {}
         }
         Ok(())
@@ -0,0 +1,256 @@
+//! Listeners the proxy can accept connections on: TCP addresses, Unix domain sockets
+//! (`unix:/path`), and sockets inherited from systemd socket activation (`systemd[:N]`).
+
+use ptrs::{sockopt::SocketOpts, stream::AnyStream, Error, Result};
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+/// First file descriptor passed by systemd (`SD_LISTEN_FDS_START`).
+#[cfg(unix)]
+const LISTEN_FDS_START: i32 = 3;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+    /// Index into the sockets passed in by systemd.
+    #[cfg(unix)]
+    Systemd(usize),
+}
+
+impl FromStr for ListenAddr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        #[cfg(unix)]
+        {
+            if let Some(path) = s.strip_prefix("unix:") {
+                return Ok(ListenAddr::Unix(path.into()));
+            }
+            if s == "systemd" {
+                return Ok(ListenAddr::Systemd(0));
+            }
+            if let Some(idx) = s.strip_prefix("systemd:") {
+                return Ok(ListenAddr::Systemd(idx.parse().map_err(Error::new)?));
+            }
+        }
+        Ok(ListenAddr::Tcp(s.parse().map_err(Error::new)?))
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "{addr}"),
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+            #[cfg(unix)]
+            ListenAddr::Systemd(idx) => write!(f, "systemd:{idx}"),
+        }
+    }
+}
+
+/// Where an accepted connection came from.
+#[derive(Clone, Debug)]
+pub enum PeerAddr {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix,
+}
+
+impl PeerAddr {
+    /// The peer's IP address. Unix socket peers have none, so they can't be filtered or
+    /// counted by address unless a PROXY protocol header names the real source.
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            PeerAddr::Tcp(addr) => Some(addr.ip()),
+            #[cfg(unix)]
+            PeerAddr::Unix => None,
+        }
+    }
+
+    /// The peer's socket address, or the unspecified address for Unix socket peers. Use
+    /// [`PeerAddr::ip`] to make decisions on the address.
+    pub fn socket_addr(&self) -> SocketAddr {
+        match self {
+            PeerAddr::Tcp(addr) => *addr,
+            #[cfg(unix)]
+            PeerAddr::Unix => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        }
+    }
+}
+
+impl fmt::Display for PeerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerAddr::Tcp(addr) => write!(f, "{addr}"),
+            #[cfg(unix)]
+            PeerAddr::Unix => write!(f, "unix"),
+        }
+    }
+}
+
+pub enum Listener {
+    /// A TCP listener and the options to apply to the connections it accepts.
+    Tcp(TcpListener, SocketOpts),
+    /// A Unix socket listener, along with the path to remove when it is dropped if we created
+    /// it ourselves.
+    #[cfg(unix)]
+    Unix(UnixListener, Option<PathBuf>),
+}
+
+impl Listener {
+    /// Bind `addr`. `opts` only applies to TCP listeners, inherited sockets keep the options
+    /// systemd created them with.
+    pub async fn bind(addr: &ListenAddr, opts: &SocketOpts) -> Result<Self> {
+        match addr {
+            ListenAddr::Tcp(addr) => Ok(Listener::Tcp(opts.bind(*addr)?, opts.clone())),
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => {
+                remove_stale_socket(path)?;
+                Ok(Listener::Unix(
+                    UnixListener::bind(path)?,
+                    Some(path.clone()),
+                ))
+            }
+            #[cfg(unix)]
+            ListenAddr::Systemd(idx) => from_systemd(*idx, opts),
+        }
+    }
+
+    /// The local address for TCP listeners, or the unspecified address for Unix sockets.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        match self {
+            Listener::Tcp(l, _) => Ok(l.local_addr()?),
+            #[cfg(unix)]
+            Listener::Unix(..) => Ok((Ipv4Addr::UNSPECIFIED, 0).into()),
+        }
+    }
+
+    pub async fn accept(&self) -> Result<(Box<dyn AnyStream>, PeerAddr)> {
+        match self {
+            Listener::Tcp(l, opts) => {
+                let (s, addr) = l.accept().await?;
+                opts.apply(&s)?;
+                Ok((Box::new(s), PeerAddr::Tcp(addr)))
+            }
+            #[cfg(unix)]
+            Listener::Unix(l, _) => {
+                let (s, _) = l.accept().await?;
+                Ok((Box::new(s), PeerAddr::Unix))
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, Some(path)) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Remove a socket file left behind by a previous run. Anything that is not a socket is left
+/// alone so that binding fails instead of clobbering it.
+#[cfg(unix)]
+fn remove_stale_socket(path: &std::path::Path) -> Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(m) if m.file_type().is_socket() => Ok(std::fs::remove_file(path)?),
+        _ => Ok(()),
+    }
+}
+
+/// Take over the `idx`th socket passed in by systemd (see sd_listen_fds(3)).
+#[cfg(unix)]
+fn from_systemd(idx: usize, opts: &SocketOpts) -> Result<Listener> {
+    use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+
+    let pid: u32 = std::env::var("LISTEN_PID")
+        .map_err(|_| Error::Other("LISTEN_PID is not set, not socket activated".into()))?
+        .parse()
+        .map_err(Error::new)?;
+    if pid != std::process::id() {
+        return Err(Error::Other("LISTEN_PID is for another process".into()));
+    }
+    let count: usize = std::env::var("LISTEN_FDS")
+        .map_err(|_| Error::Other("LISTEN_FDS is not set".into()))?
+        .parse()
+        .map_err(Error::new)?;
+    if idx >= count {
+        return Err(Error::Other(
+            format!("systemd passed {count} sockets, wanted index {idx}").into(),
+        ));
+    }
+
+    let fd = LISTEN_FDS_START + idx as RawFd;
+    // SAFETY: systemd hands ownership of fds LISTEN_FDS_START..LISTEN_FDS_START+count to us,
+    // and each index is only taken once per listener the proxy binds.
+    let l = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+    l.set_nonblocking(true)?;
+    // A Unix socket has no inet address, which is how the two are told apart.
+    if l.local_addr().is_ok() {
+        return Ok(Listener::Tcp(TcpListener::from_std(l)?, opts.clone()));
+    }
+    let l = unsafe { std::os::unix::net::UnixListener::from_raw_fd(l.into_raw_fd()) };
+    Ok(Listener::Unix(UnixListener::from_std(l)?, None))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::UnixStream;
+
+    #[test]
+    fn parse_listen_addr() -> Result<()> {
+        assert_eq!(
+            "127.0.0.1:9000".parse::<ListenAddr>()?,
+            ListenAddr::Tcp("127.0.0.1:9000".parse().unwrap())
+        );
+        assert_eq!(
+            "unix:/run/ptrs.sock".parse::<ListenAddr>()?,
+            ListenAddr::Unix("/run/ptrs.sock".into())
+        );
+        assert_eq!("systemd".parse::<ListenAddr>()?, ListenAddr::Systemd(0));
+        assert_eq!("systemd:2".parse::<ListenAddr>()?, ListenAddr::Systemd(2));
+        assert!("nope".parse::<ListenAddr>().is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unix_listener() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("proxy.sock");
+        // a stale socket from an earlier run is replaced
+        drop(std::os::unix::net::UnixListener::bind(&path)?);
+
+        let listener =
+            Listener::bind(&ListenAddr::Unix(path.clone()), &SocketOpts::default()).await?;
+        let mut client = UnixStream::connect(&path).await?;
+        let (mut conn, peer) = listener.accept().await?;
+        assert_eq!(peer.to_string(), "unix");
+        assert_eq!(peer.ip(), None);
+
+        client.write_all(b"hi").await?;
+        let mut buf = [0_u8; 2];
+        conn.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"hi");
+
+        drop(listener);
+        assert!(!path.exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+//! Keep connections to the remote open and handshaked ahead of time, so clients of a far away
+//! (or slow to connect to) bridge don't wait on the dial or on the transport's handshake.
+
+use ptrs::reconnect::ReconnectingDialer;
+use ptrs::safelog::sensitive;
+use ptrs::stream::AnyStream;
+use ptrs::{DynTransport, Result};
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, trace};
+
+pub const DEFAULT_MAX_IDLE: Duration = Duration::from_secs(30);
+/// Pause before refilling again once the dialer has given up.
+const RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// Wrap a connection to the bridge in `transport` and drive its handshake, which wrapped
+/// streams otherwise only start on first use. Without a transport (a passthrough one) the
+/// connection is left bare, so the copy can splice it.
+pub async fn handshake(
+    transport: Option<&dyn DynTransport>,
+    stream: TcpStream,
+    cancel: &CancellationToken,
+) -> Result<Box<dyn AnyStream>> {
+    let Some(transport) = transport else {
+        return Ok(Box::new(stream));
+    };
+    let mut wrapped = transport
+        .wrap_boxed_with_cancel(Box::new(stream), cancel.clone())
+        .await?;
+    wrapped.flush().await?;
+    Ok(Box::new(wrapped))
+}
+
+/// Holds up to `size` connected and handshaked streams. Connections idle for longer than
+/// `max_idle` are closed, since middleboxes and the remote tend to drop them, and replaced.
+pub struct WarmPool {
+    dialer: ReconnectingDialer,
+    transport: Option<Box<dyn DynTransport>>,
+    size: usize,
+    max_idle: Duration,
+    idle: Mutex<VecDeque<(Instant, Box<dyn AnyStream>)>>,
+    taken: Notify,
+}
+
+impl WarmPool {
+    /// Pool connections from `dialer`, wrapped in `transport` as [`handshake`] does.
+    pub fn new(
+        dialer: ReconnectingDialer,
+        transport: Option<Box<dyn DynTransport>>,
+        size: usize,
+        max_idle: Duration,
+    ) -> Self {
+        Self {
+            dialer,
+            transport,
+            size,
+            max_idle,
+            idle: Mutex::new(VecDeque::with_capacity(size)),
+            taken: Notify::new(),
+        }
+    }
+
+    /// Fill the pool and keep it full until `close` is cancelled.
+    pub fn spawn_replenish(self: &Arc<Self>, close: CancellationToken) {
+        let pool = self.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = pool.replenish(&close) => {}
+                _ = close.cancelled() => {}
+            }
+        });
+    }
+
+    /// A pooled connection if one is ready, otherwise a new one.
+    pub async fn take(&self, cancel: &CancellationToken) -> Result<Box<dyn AnyStream>> {
+        let pooled = {
+            let mut idle = self.idle.lock().unwrap();
+            self.evict(&mut idle);
+            let pooled = idle.pop_front();
+            trace!("{} warm connections left", idle.len());
+            pooled
+        };
+        match pooled {
+            Some((_, stream)) => {
+                self.taken.notify_one();
+                Ok(stream)
+            }
+            None => {
+                trace!("warm pool empty, dialing {}", sensitive(self.dialer.addr()));
+                let stream = self.dialer.connect_until(cancel).await?;
+                handshake(self.transport.as_deref(), stream, cancel).await
+            }
+        }
+    }
+
+    async fn replenish(&self, close: &CancellationToken) {
+        loop {
+            let (missing, next_expiry) = {
+                let mut idle = self.idle.lock().unwrap();
+                self.evict(&mut idle);
+                let expiry = idle.front().map(|(at, _)| *at + self.max_idle);
+                (self.size - idle.len(), expiry)
+            };
+            for _ in 0..missing {
+                // the dialer backs off between failures, and gives up eventually
+                let warmed = match self.dialer.connect().await {
+                    Ok(stream) => handshake(self.transport.as_deref(), stream, close).await,
+                    Err(e) => Err(e),
+                };
+                match warmed {
+                    Ok(stream) => self
+                        .idle
+                        .lock()
+                        .unwrap()
+                        .push_back((Instant::now(), stream)),
+                    Err(e) => {
+                        debug!(
+                            "warm pool failed to connect to {}: {e}",
+                            sensitive(self.dialer.addr())
+                        );
+                        tokio::time::sleep(RETRY_AFTER).await;
+                        break;
+                    }
+                }
+            }
+            if missing > 0 {
+                continue;
+            }
+            let wake = next_expiry.unwrap_or_else(|| Instant::now() + self.max_idle);
+            tokio::select! {
+                _ = self.taken.notified() => {}
+                _ = tokio::time::sleep_until(wake) => {}
+            }
+        }
+    }
+
+    /// Drop connections that have idled too long, oldest first.
+    fn evict(&self, idle: &mut VecDeque<(Instant, Box<dyn AnyStream>)>) {
+        while idle
+            .front()
+            .is_some_and(|(at, _)| at.elapsed() >= self.max_idle)
+        {
+            idle.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    async fn wait_for(pool: &WarmPool, n: usize) {
+        while pool.idle.lock().unwrap().len() != n {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    fn tcp(stream: &dyn AnyStream) -> &TcpStream {
+        stream
+            .downcast_ref()
+            .expect("passthrough connections are left bare")
+    }
+
+    #[tokio::test]
+    async fn keeps_connections_ready() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let dialer = ReconnectingDialer::new(listener.local_addr()?);
+        let pool = Arc::new(WarmPool::new(dialer, None, 3, DEFAULT_MAX_IDLE));
+        let close = CancellationToken::new();
+        pool.spawn_replenish(close.clone());
+        wait_for(&pool, 3).await;
+
+        let a = pool.take(&close).await?;
+        let b = pool.take(&close).await?;
+        assert_eq!(tcp(&*a).peer_addr()?, listener.local_addr()?);
+        assert_ne!(tcp(&*a).local_addr()?, tcp(&*b).local_addr()?);
+        wait_for(&pool, 3).await;
+        close.cancel();
+        Ok(())
+    }
+
+    #[cfg(feature = "codecs")]
+    #[tokio::test]
+    async fn pools_wrapped_connections() -> Result<()> {
+        use ptrs::transports::base64::Base64Builder;
+        use ptrs::Role;
+        use tokio::io::AsyncReadExt;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let dialer = ReconnectingDialer::new(listener.local_addr()?);
+        let transport = ptrs::dyn_from_wrapping(Box::new(Base64Builder::default()), Role::Sealer);
+        let pool = Arc::new(WarmPool::new(dialer, Some(transport), 1, DEFAULT_MAX_IDLE));
+        let close = CancellationToken::new();
+        pool.spawn_replenish(close.clone());
+        wait_for(&pool, 1).await;
+
+        let (mut bridge, _) = listener.accept().await?;
+        let mut client = pool.take(&close).await?;
+        client.write_all(b"hi").await?;
+        client.flush().await?;
+        let mut wire = [0u8; 3];
+        bridge.read_exact(&mut wire).await?;
+        assert_eq!(&wire, b"aGk");
+        close.cancel();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn evicts_idle_connections() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let dialer = ReconnectingDialer::new(listener.local_addr()?);
+        let pool = Arc::new(WarmPool::new(dialer, None, 1, Duration::from_millis(50)));
+        let close = CancellationToken::new();
+        pool.spawn_replenish(close.clone());
+        wait_for(&pool, 1).await;
+        let first = tcp(&*pool.idle.lock().unwrap()[0].1).local_addr()?;
+
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        wait_for(&pool, 1).await;
+        let fresh = pool.take(&close).await?;
+        assert_ne!(tcp(&*fresh).local_addr()?, first);
+        close.cancel();
+        Ok(())
+    }
+}
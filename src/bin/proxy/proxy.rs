@@ -1,6 +1,12 @@
+mod backends;
 mod config;
+mod daemon;
 mod handler;
+mod listener;
+mod prewarm;
+mod proxy_protocol;
 mod pt;
+mod selftest;
 mod socks5;
 
 use config::{Cli, ProxyConfig};
@@ -9,30 +15,51 @@ extern crate tracing_subscriber;
 
 use anyhow::Result;
 use clap::Parser;
-use tokio::{self, signal, sync::mpsc::channel};
-use tokio_util::sync::CancellationToken;
+use tokio::{self, sync::mpsc::channel};
 use tracing::{debug, error};
 
-#[tokio::main]
-async fn main() -> std::result::Result<(), anyhow::Error> {
+fn main() -> std::result::Result<(), anyhow::Error> {
+    let mut config = parse_config()?;
+    // forking has to happen before the runtime starts its threads
+    let daemon = match config.daemon_mut() {
+        Some(daemon) => {
+            daemon.start()?;
+            Some(daemon.clone())
+        }
+        None => None,
+    };
+
+    let out = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(run(config));
+    if let Some(daemon) = daemon {
+        daemon.remove_pid_file();
+    }
+    out
+}
+
+async fn run(config: ProxyConfig) -> std::result::Result<(), anyhow::Error> {
     // send recv channel so that we know when all tasks have closed cleanly
     let (send, mut recv) = channel(1);
-    // shutdown signal to indicate to all active thread processes that they should close
-    let shutdown_signal = CancellationToken::new();
+    // shutdown signal to indicate to all active thread processes that they should close,
+    // tripped by SIGINT / SIGTERM
+    let shutdown_signal = ptrs::shutdown::listen()
+        .map_err(|e| anyhow::anyhow!("failed to install signal handlers: {:?}", e))?;
+    // when run as a managed transport, the parent may ask us to exit once our stdin closes
+    ptrs::exit_on_stdin_close(shutdown_signal.clone());
 
     tokio::select! {
         // launch proxy runner based on the parsed config. If config parsing fails we fail and
         // return the parse error.
-        out = parse_config()?.run(shutdown_signal.clone(), send.clone()) => {
+        out = config.run(shutdown_signal.clone(), send.clone()) => {
             if let Err(e) = out {
                 error!("encountered error:{:?}", e);
                 panic!("\tshutting down");
             }
         },
-        _ = signal::ctrl_c() => {
-            // ctrl-c was pressed, so we'll set the shutdown signal
-            debug!("ctrl-c pressed, shutting down");
-            shutdown_signal.cancel();
+        _ = shutdown_signal.cancelled() => {
+            debug!("shutting down");
         },
     };
 
@@ -0,0 +1,290 @@
+//! HAProxy PROXY protocol v1 and v2 headers, so that the real client address survives a load
+//! balancer in front of the proxy and reaches backends behind it.
+//!
+//! See <https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt>.
+
+use ptrs::{Error, Result};
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V1_PREFIX: &[u8] = b"PROXY";
+/// Longest possible v1 header, including the CRLF.
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_VERSION_PROXY: u8 = 0x21;
+const V2_VERSION_LOCAL: u8 = 0x20;
+const V2_TCP4: u8 = 0x11;
+const V2_TCP6: u8 = 0x21;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Version {
+    V1,
+    V2,
+}
+
+impl FromStr for Version {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "v1" => Ok(Version::V1),
+            "v2" => Ok(Version::V2),
+            _ => Err(Error::Other(
+                format!("unknown proxy protocol version \"{s}\"").into(),
+            )),
+        }
+    }
+}
+
+/// The original endpoints of a proxied connection.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Header {
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+}
+
+impl Header {
+    pub fn encode(&self, version: Version) -> Vec<u8> {
+        match version {
+            Version::V1 => self.encode_v1(),
+            Version::V2 => self.encode_v2(),
+        }
+    }
+
+    fn encode_v1(&self) -> Vec<u8> {
+        let (src, dst) = unmap(self.src, self.dst);
+        let family = if src.is_ipv4() { "TCP4" } else { "TCP6" };
+        format!(
+            "PROXY {family} {} {} {} {}\r\n",
+            src.ip(),
+            dst.ip(),
+            src.port(),
+            dst.port()
+        )
+        .into_bytes()
+    }
+
+    fn encode_v2(&self) -> Vec<u8> {
+        let (src, dst) = unmap(self.src, self.dst);
+        let mut out = V2_SIGNATURE.to_vec();
+        out.push(V2_VERSION_PROXY);
+        match (src.ip(), dst.ip()) {
+            (IpAddr::V4(s), IpAddr::V4(d)) => {
+                out.push(V2_TCP4);
+                out.extend_from_slice(&12_u16.to_be_bytes());
+                out.extend_from_slice(&s.octets());
+                out.extend_from_slice(&d.octets());
+            }
+            (s, d) => {
+                out.push(V2_TCP6);
+                out.extend_from_slice(&36_u16.to_be_bytes());
+                out.extend_from_slice(&to_v6(s).octets());
+                out.extend_from_slice(&to_v6(d).octets());
+            }
+        }
+        out.extend_from_slice(&src.port().to_be_bytes());
+        out.extend_from_slice(&dst.port().to_be_bytes());
+        out
+    }
+}
+
+/// Use IPv4 for both ends when both can be written that way, since the header carries a
+/// single address family.
+fn unmap(src: SocketAddr, dst: SocketAddr) -> (SocketAddr, SocketAddr) {
+    let v4 = |a: SocketAddr| match a.ip() {
+        IpAddr::V4(ip) => Some(ip),
+        IpAddr::V6(ip) => ip.to_ipv4_mapped(),
+    };
+    match (v4(src), v4(dst)) {
+        (Some(s), Some(d)) => ((s, src.port()).into(), (d, dst.port()).into()),
+        _ => (
+            (to_v6(src.ip()), src.port()).into(),
+            (to_v6(dst.ip()), dst.port()).into(),
+        ),
+    }
+}
+
+fn to_v6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+/// How long a client gets to send its header unless configured otherwise.
+pub const DEFAULT_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// [`read_header`], failing with [`Error::Timeout`] if the header hasn't arrived within
+/// `limit`, so a client that connects and sends nothing doesn't hold the connection open.
+pub async fn read_header_within<S>(stream: &mut S, limit: Duration) -> Result<Option<Header>>
+where
+    S: AsyncRead + Unpin,
+{
+    tokio::time::timeout(limit, read_header(stream))
+        .await
+        .map_err(|_| Error::Timeout)?
+}
+
+/// Read a v1 or v2 header from the start of `stream`, consuming nothing past it. Returns
+/// `None` for headers that carry no address (v1 `UNKNOWN`, v2 `LOCAL` or non-TCP families).
+pub async fn read_header<S>(stream: &mut S) -> Result<Option<Header>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut start = [0_u8; 5];
+    stream.read_exact(&mut start).await?;
+    if start == V1_PREFIX {
+        read_v1(stream).await
+    } else if start == V2_SIGNATURE[..5] {
+        read_v2(stream, &start).await
+    } else {
+        Err(Error::Other("missing proxy protocol header".into()))
+    }
+}
+
+async fn read_v1<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Option<Header>> {
+    let mut line = V1_PREFIX.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LEN {
+            return Err(Error::Other("proxy protocol v1 header too long".into()));
+        }
+        line.push(stream.read_u8().await?);
+    }
+    let bad = || Error::Other("malformed proxy protocol v1 header".into());
+
+    let line = std::str::from_utf8(&line[..line.len() - 2]).map_err(|_| bad())?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields[..] {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", src, dst, sport, dport] => {
+            let src: IpAddr = src.parse().map_err(|_| bad())?;
+            let dst: IpAddr = dst.parse().map_err(|_| bad())?;
+            let sport: u16 = sport.parse().map_err(|_| bad())?;
+            let dport: u16 = dport.parse().map_err(|_| bad())?;
+            Ok(Some(Header {
+                src: (src, sport).into(),
+                dst: (dst, dport).into(),
+            }))
+        }
+        _ => Err(bad()),
+    }
+}
+
+async fn read_v2<S: AsyncRead + Unpin>(stream: &mut S, start: &[u8]) -> Result<Option<Header>> {
+    let mut fixed = [0_u8; 16];
+    fixed[..start.len()].copy_from_slice(start);
+    stream.read_exact(&mut fixed[start.len()..]).await?;
+    if fixed[..12] != V2_SIGNATURE[..] {
+        return Err(Error::Other("bad proxy protocol v2 signature".into()));
+    }
+    let len = u16::from_be_bytes([fixed[14], fixed[15]]) as usize;
+    let mut body = vec![0_u8; len];
+    stream.read_exact(&mut body).await?;
+
+    match fixed[12] {
+        V2_VERSION_LOCAL => return Ok(None),
+        V2_VERSION_PROXY => {}
+        v => {
+            return Err(Error::Other(
+                format!("unsupported proxy protocol v2 command {v:#04x}").into(),
+            ))
+        }
+    }
+    let short = || Error::Other("truncated proxy protocol v2 address".into());
+    // Anything after the addresses is TLVs, which are not used here.
+    match fixed[13] {
+        V2_TCP4 => {
+            let a = body.get(..12).ok_or_else(short)?;
+            let ip = |b: &[u8]| Ipv4Addr::new(b[0], b[1], b[2], b[3]);
+            Ok(Some(Header {
+                src: (ip(&a[0..4]), u16::from_be_bytes([a[8], a[9]])).into(),
+                dst: (ip(&a[4..8]), u16::from_be_bytes([a[10], a[11]])).into(),
+            }))
+        }
+        V2_TCP6 => {
+            let a = body.get(..36).ok_or_else(short)?;
+            let ip = |b: &[u8]| Ipv6Addr::from(<[u8; 16]>::try_from(b).unwrap());
+            Ok(Some(Header {
+                src: (ip(&a[0..16]), u16::from_be_bytes([a[32], a[33]])).into(),
+                dst: (ip(&a[16..32]), u16::from_be_bytes([a[34], a[35]])).into(),
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn header(src: &str, dst: &str) -> Header {
+        Header {
+            src: src.parse().unwrap(),
+            dst: dst.parse().unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn round_trip() -> Result<()> {
+        for h in [
+            header("192.0.2.1:5555", "198.51.100.2:443"),
+            header("[2001:db8::1]:5555", "[2001:db8::2]:443"),
+        ] {
+            for version in [Version::V1, Version::V2] {
+                let mut wire = h.encode(version);
+                wire.extend_from_slice(b"payload");
+                let mut r = &wire[..];
+                assert_eq!(read_header(&mut r).await?, Some(h));
+                assert_eq!(r, b"payload");
+            }
+        }
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn silent_client_times_out() -> Result<()> {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let r = read_header_within(&mut server, DEFAULT_HEADER_TIMEOUT).await;
+        assert!(matches!(r, Err(Error::Timeout)));
+
+        let wire = header("192.0.2.1:5555", "198.51.100.2:443").encode(Version::V2);
+        tokio::io::AsyncWriteExt::write_all(&mut client, &wire).await?;
+        assert!(read_header_within(&mut server, DEFAULT_HEADER_TIMEOUT)
+            .await?
+            .is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn v1_text() -> Result<()> {
+        let h = header("192.0.2.1:5555", "[::ffff:198.51.100.2]:443");
+        assert_eq!(
+            h.encode(Version::V1),
+            b"PROXY TCP4 192.0.2.1 198.51.100.2 5555 443\r\n"
+        );
+
+        let mut r = &b"PROXY UNKNOWN\r\nrest"[..];
+        assert_eq!(read_header(&mut r).await?, None);
+        assert_eq!(r, b"rest");
+
+        let mut r = &b"GET / HTTP/1.1\r\n"[..];
+        assert!(read_header(&mut r).await.is_err());
+        let long = format!("PROXY {}\r\n", "x".repeat(200));
+        assert!(read_header(&mut long.as_bytes()).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn v2_local() -> Result<()> {
+        let mut wire = V2_SIGNATURE.to_vec();
+        wire.extend_from_slice(&[V2_VERSION_LOCAL, 0, 0, 0]);
+        let mut r = &wire[..];
+        assert_eq!(read_header(&mut r).await?, None);
+        assert!(r.is_empty());
+        Ok(())
+    }
+}
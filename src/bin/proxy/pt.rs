@@ -1,11 +1,17 @@
 use ptrs::transports::identity::Identity;
-use ptrs::{Result, Role, TransportBuilder};
+use ptrs::{Error, Result, Role, TransportBuilder};
 
-// use std::str::FromStr;
-
-pub fn get_transport(_name: &str, _role: &Role) -> Result<Box<dyn TransportBuilder + Send + Sync>> {
-    // Transports::from_str(name)?.as_transport()
-    Ok(Box::new(Identity::new()))
+/// Builder for the transport called `name`. Names that aren't known, or whose transport
+/// wasn't built into this binary, are an error rather than quietly running something else.
+pub fn get_transport(name: &str, _role: &Role) -> Result<Box<dyn TransportBuilder + Send + Sync>> {
+    match name {
+        "plain" | "identity" => Ok(Box::new(Identity::new())),
+        #[cfg(feature = "tutorial")]
+        ptrs::tutorial::NAME => Ok(Box::new(ptrs::tutorial::Rot13Transport::new())),
+        _ => Err(Error::Config(
+            format!("unknown transport \"{name}\", or not enabled in this build").into(),
+        )),
+    }
 }
 
 #[cfg(test)]
@@ -24,14 +30,10 @@ mod test {
 
         let (c, s) = UnixStream::pair()?;
 
-        let mut wrapped_c = transport.build(&Role::Sealer)?.wrap(Box::new(c))?;
+        let mut wrapped_c = transport.client()?.wrap(Box::new(c))?;
 
         tokio::spawn(async move {
-            let wrapped_s = transport
-                .build(&Role::Revealer)
-                .unwrap()
-                .wrap(Box::new(s))
-                .unwrap();
+            let wrapped_s = transport.server().unwrap().wrap(Box::new(s)).unwrap();
             let (mut r, mut w) = split(wrapped_s);
             tokio::io::copy(&mut r, &mut w).await.unwrap();
         });
@@ -45,4 +47,18 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn unknown_transports_fail() {
+        assert_eq!(
+            get_transport("plain", &Role::Sealer).unwrap().name(),
+            "identity"
+        );
+        assert!(matches!(
+            get_transport("obfs4", &Role::Sealer),
+            Err(Error::Config(_))
+        ));
+        #[cfg(not(feature = "tutorial"))]
+        assert!(get_transport("rot13", &Role::Sealer).is_err());
+    }
 }
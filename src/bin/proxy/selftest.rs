@@ -0,0 +1,249 @@
+//! `proxy selftest`: run a transport's client and server against each other over loopback,
+//! echo a corpus of mixed traffic through them in randomly sized writes and check it comes
+//! back intact, to validate a build or transport config before deploying it.
+
+use ptrs::rand::Rng;
+use ptrs::stream::{InstrumentedStream, Stats};
+use ptrs::{DynTransport, TransportBuilder};
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail};
+use rand::RngCore;
+use tokio::io::{split, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::Sender;
+use tokio_util::sync::CancellationToken;
+
+pub const DEFAULT_BYTES: usize = 1 << 20;
+pub const DEFAULT_MAX_WRITE: usize = 4096;
+
+const TEXT: &[u8] = b"the quick brown fox jumps over the lazy dog\n";
+
+pub struct SelftestConfig {
+    pub(crate) builder: Box<dyn TransportBuilder>,
+    /// Bytes echoed through the transport.
+    pub(crate) bytes: usize,
+    /// Largest single write; each write is between one byte and this.
+    pub(crate) max_write: usize,
+    pub(crate) seed: u64,
+}
+
+impl SelftestConfig {
+    pub async fn run(
+        self,
+        close: CancellationToken,
+        _wait: Sender<()>,
+    ) -> Result<(), anyhow::Error> {
+        tokio::select! {
+            r = self.check() => println!("{}", r?),
+            _ = close.cancelled() => {}
+        }
+        Ok(())
+    }
+
+    async fn check(&self) -> Result<Report, anyhow::Error> {
+        let name = self.builder.name();
+        let mut rng = Rng::from_seed(self.seed);
+        let corpus = corpus(&mut rng, self.bytes);
+        let writes = write_sizes(&mut rng, self.bytes, self.max_write);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let server = self
+            .builder
+            .server()
+            .map_err(|e| anyhow!("failed to build {name} server: {:?}", e))?;
+        let wire = Arc::new(Mutex::new(None));
+        let server = tokio::spawn(echo(listener, server, wire.clone()));
+
+        let start = Instant::now();
+        let client = self
+            .builder
+            .client()
+            .map_err(|e| anyhow!("failed to build {name} client: {:?}", e))?;
+        let stream = client
+            .wrap_boxed(Box::new(TcpStream::connect(addr).await?))
+            .await
+            .map_err(|e| anyhow!("{name} client failed to wrap: {:?}", e))?;
+        let handshake = start.elapsed();
+
+        let (mut r, mut w) = split(stream);
+        let send = async {
+            let mut rest = &corpus[..];
+            for &n in &writes {
+                let (chunk, tail) = rest.split_at(n);
+                w.write_all(chunk).await?;
+                w.flush().await?;
+                rest = tail;
+            }
+            w.shutdown().await
+        };
+        let mut echoed = Vec::with_capacity(corpus.len());
+        tokio::try_join!(send, r.read_to_end(&mut echoed))?;
+        server
+            .await?
+            .map_err(|e| anyhow!("{name} server failed: {:?}", e))?;
+        let elapsed = start.elapsed();
+        let stats = wire.lock().unwrap().take().expect("server accepted");
+
+        if let Some(i) = (0..corpus.len().min(echoed.len())).find(|&i| corpus[i] != echoed[i]) {
+            bail!(
+                "{name} corrupted the stream at byte {i} (seed {})",
+                self.seed
+            );
+        }
+        if echoed.len() != corpus.len() {
+            bail!(
+                "{name} echoed {} of {} bytes (seed {})",
+                echoed.len(),
+                corpus.len(),
+                self.seed
+            );
+        }
+
+        Ok(Report {
+            transport: name,
+            seed: self.seed,
+            bytes: self.bytes,
+            writes: writes.len(),
+            max_write: self.max_write,
+            handshake,
+            elapsed,
+            up: stats.bytes_read(),
+            down: stats.bytes_written(),
+        })
+    }
+}
+
+/// Accept one connection, reveal it and echo everything back. The wire byte counts for the
+/// connection are left in `wire`.
+async fn echo(
+    listener: TcpListener,
+    server: ptrs::ServerTransport,
+    wire: Arc<Mutex<Option<Arc<Stats>>>>,
+) -> ptrs::Result<()> {
+    let (stream, _) = listener.accept().await?;
+    let stream = InstrumentedStream::new(stream);
+    *wire.lock().unwrap() = Some(stream.as_stats());
+    let stream = server.wrap_boxed(Box::new(stream)).await?;
+    let (mut r, mut w) = split(stream);
+    tokio::io::copy(&mut r, &mut w).await?;
+    w.shutdown().await?;
+    Ok(())
+}
+
+/// Mixed traffic: runs of random bytes, zeros, text and byte ramps, so transports that
+/// compress, encode or pad see their best and worst cases.
+fn corpus(rng: &mut Rng, len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(len);
+    while out.len() < len {
+        let n = (rng.next_u32() as usize % 4096 + 1).min(len - out.len());
+        let start = out.len();
+        match rng.next_u32() % 4 {
+            0 => {
+                out.resize(start + n, 0);
+                rng.fill_bytes(&mut out[start..]);
+            }
+            1 => out.resize(start + n, 0),
+            2 => out.extend(TEXT.iter().cycle().take(n)),
+            _ => out.extend((0..=255u8).cycle().take(n)),
+        }
+    }
+    out
+}
+
+/// Random write sizes in `1..=max` adding up to `len`.
+fn write_sizes(rng: &mut Rng, mut len: usize, max: usize) -> Vec<usize> {
+    let mut sizes = vec![];
+    while len > 0 {
+        let n = (rng.next_u32() as usize % max + 1).min(len);
+        sizes.push(n);
+        len -= n;
+    }
+    sizes
+}
+
+#[derive(Debug)]
+struct Report {
+    transport: &'static str,
+    seed: u64,
+    bytes: usize,
+    writes: usize,
+    max_write: usize,
+    handshake: Duration,
+    elapsed: Duration,
+    /// Wire bytes from client to server.
+    up: u64,
+    /// Wire bytes from server to client.
+    down: u64,
+}
+
+impl Report {
+    /// Wire bytes beyond the payload, as a fraction of it.
+    fn overhead(&self) -> f64 {
+        let payload = 2 * self.bytes as u64;
+        (self.up + self.down) as f64 / payload.max(1) as f64 - 1.0
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mib_s = 2.0 * self.bytes as f64 / self.elapsed.as_secs_f64() / (1 << 20) as f64;
+        writeln!(
+            f,
+            "selftest passed: {} (seed {})",
+            self.transport, self.seed
+        )?;
+        writeln!(
+            f,
+            "  payload    {} bytes each way in {} writes of 1..={} bytes",
+            self.bytes, self.writes, self.max_write
+        )?;
+        writeln!(f, "  handshake  {:?}", self.handshake)?;
+        writeln!(f, "  echo       {:?} ({mib_s:.1} MiB/s)", self.elapsed)?;
+        write!(
+            f,
+            "  wire       {} bytes up, {} down ({:+.1}% overhead)",
+            self.up,
+            self.down,
+            self.overhead() * 100.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ptrs::transports::identity::Identity;
+
+    #[tokio::test]
+    async fn echoes_through_identity() -> Result<(), anyhow::Error> {
+        let config = SelftestConfig {
+            builder: Box::new(Identity::new()),
+            bytes: 100_000,
+            max_write: 700,
+            seed: 4898,
+        };
+        let report = config.check().await?;
+        assert_eq!(report.transport, "identity");
+        assert!(report.writes >= 100_000 / 700);
+        assert_eq!((report.up, report.down), (100_000, 100_000));
+        assert_eq!(report.overhead(), 0.0);
+        assert!(report.to_string().starts_with("selftest passed: identity"));
+        Ok(())
+    }
+
+    #[test]
+    fn corpus_is_reproducible() {
+        let a = corpus(&mut Rng::from_seed(1), 50_000);
+        assert_eq!(a.len(), 50_000);
+        assert_eq!(a, corpus(&mut Rng::from_seed(1), 50_000));
+
+        let sizes = write_sizes(&mut Rng::from_seed(1), 50_000, 10);
+        assert_eq!(sizes.iter().sum::<usize>(), 50_000);
+        assert!(sizes.iter().all(|n| (1..=10).contains(n)));
+    }
+}
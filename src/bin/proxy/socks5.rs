@@ -2,15 +2,27 @@ use anyhow::{anyhow, Context, Result};
 use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use futures::task::SpawnExt;
 use futures::FutureExt;
-use safelog::sensitive;
+use ptrs::safelog::sensitive;
 use std::io::Result as IoResult;
-use std::net::IpAddr;
 use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::str::FromStr;
-use tracing::{debug, warn};
+use std::time::Duration;
+use tracing::{debug, trace, warn};
 
-use tor_rtcompat::Runtime;
-use tor_socksproto::{SocksAuth, SocksCmd};
+use tor_rtcompat::{Runtime, TcpListener, UdpSocket};
+use tor_socksproto::{SocksCmd, SocksStatus};
+
+const SOCKS5_VERSION: u8 = 5;
+const SOCKS5_CMD_BIND: u8 = 2;
+const SOCKS5_CMD_UDP_ASSOCIATE: u8 = 3;
+const SOCKS5_ATYP_IPV4: u8 = 1;
+const SOCKS5_ATYP_DOMAIN: u8 = 3;
+const SOCKS5_ATYP_IPV6: u8 = 4;
+/// Largest UDP payload, and so the largest datagram the relay can receive.
+const MAX_DATAGRAM: usize = 65535;
+/// How long a BIND request waits for the inbound connection.
+const BIND_ACCEPT_TIMEOUT: Duration = Duration::from_secs(120);
 
 /// Given a just-received TCP connection `S` on a SOCKS port, handle the
 /// SOCKS handshake and relay the connection over the Tor network.
@@ -18,7 +30,14 @@ use tor_socksproto::{SocksAuth, SocksCmd};
 /// Uses `isolation_info` to decide which circuits this connection
 /// may use.  Requires that `isolation_info` is a pair listing the listener
 /// id and the source address for the socks request.
-pub(crate) async fn handle_socks_conn<R, S>(runtime: R, socks_stream: S) -> Result<()>
+///
+/// `local_ip` is the address the connection was accepted on. UDP relay sockets are bound
+/// to it so that clients can reach them the same way they reached the proxy.
+pub(crate) async fn handle_socks_conn<R, S>(
+    runtime: R,
+    socks_stream: S,
+    local_ip: IpAddr,
+) -> Result<()>
 where
     R: Runtime,
     S: AsyncRead + AsyncWrite + Send + Sync + Unpin + 'static,
@@ -55,13 +74,29 @@ where
         // try to advance the handshake to the next state.
         let action = match handshake.handshake(&inbuf[..n_read]) {
             Err(_) => continue, // Message truncated.
+            Ok(Err(tor_socksproto::Error::NotImplemented(_))) => {
+                // tor_socksproto only knows the commands tor itself supports, handle the
+                // others here.
+                match parse_request(&inbuf[..n_read]) {
+                    Some((SOCKS5_CMD_BIND, peer)) => {
+                        return bind(runtime, socks_r, socks_w, peer, local_ip).await;
+                    }
+                    Some((SOCKS5_CMD_UDP_ASSOCIATE, client)) => {
+                        return udp_associate(runtime, socks_r, socks_w, client, local_ip).await;
+                    }
+                    _ => {}
+                }
+                let reply = socks5_reply(SocksStatus::COMMAND_NOT_SUPPORTED, None);
+                write_all_and_close(&mut socks_w, &reply).await?;
+                return Err(anyhow!("unsupported socks request"));
+            }
             Ok(Err(e)) => {
                 if let tor_socksproto::Error::BadProtocol(version) = e {
                     // check for HTTP methods: CONNECT, DELETE, GET, HEAD, OPTION, PUT, POST, PATCH and
                     // TRACE.
                     // To do so, check the first byte of the connection, which happen to be placed
                     // where SOCKs version field is.
-                    if [b'C', b'D', b'G', b'H', b'O', b'P', b'T'].contains(&version) {
+                    if b"CDGHOPT".contains(&version) {
                         write_all_and_close(&mut socks_w, WRONG_PROTOCOL_PAYLOAD).await?;
                     }
                 }
@@ -136,6 +171,252 @@ where
     Ok(())
 }
 
+/// Address in a SOCKS5 request or UDP datagram header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum TargetAddr {
+    Ip(SocketAddr),
+    Domain(String, u16),
+}
+
+impl TargetAddr {
+    async fn resolve(&self) -> IoResult<SocketAddr> {
+        match self {
+            TargetAddr::Ip(addr) => Ok(*addr),
+            TargetAddr::Domain(host, port) => tokio::net::lookup_host((host.as_str(), *port))
+                .await?
+                .next()
+                .ok_or_else(|| std::io::Error::other(format!("no addresses for {host}"))),
+        }
+    }
+}
+
+/// Parse `ATYP ADDR PORT`, returning the address and the number of bytes it took.
+fn parse_addr(buf: &[u8]) -> Option<(TargetAddr, usize)> {
+    let port = |at: usize| Some(u16::from_be_bytes(buf.get(at..at + 2)?.try_into().ok()?));
+    match *buf.first()? {
+        SOCKS5_ATYP_IPV4 => {
+            let ip: [u8; 4] = buf.get(1..5)?.try_into().ok()?;
+            let addr = SocketAddr::new(Ipv4Addr::from(ip).into(), port(5)?);
+            Some((TargetAddr::Ip(addr), 7))
+        }
+        SOCKS5_ATYP_IPV6 => {
+            let ip: [u8; 16] = buf.get(1..17)?.try_into().ok()?;
+            let addr = SocketAddr::new(Ipv6Addr::from(ip).into(), port(17)?);
+            Some((TargetAddr::Ip(addr), 19))
+        }
+        SOCKS5_ATYP_DOMAIN => {
+            let len = *buf.get(1)? as usize;
+            let host = std::str::from_utf8(buf.get(2..2 + len)?).ok()?;
+            Some((
+                TargetAddr::Domain(host.to_string(), port(2 + len)?),
+                4 + len,
+            ))
+        }
+        _ => None,
+    }
+}
+
+fn encode_addr(addr: &SocketAddr, out: &mut Vec<u8>) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            out.push(SOCKS5_ATYP_IPV4);
+            out.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            out.push(SOCKS5_ATYP_IPV6);
+            out.extend_from_slice(&ip.octets());
+        }
+    }
+    out.extend_from_slice(&addr.port().to_be_bytes());
+}
+
+/// Parse a SOCKS5 request `VER CMD RSV ATYP DST.ADDR DST.PORT` into its command and address.
+fn parse_request(buf: &[u8]) -> Option<(u8, TargetAddr)> {
+    match buf {
+        [SOCKS5_VERSION, cmd, 0, rest @ ..] => Some((*cmd, parse_addr(rest)?.0)),
+        _ => None,
+    }
+}
+
+/// Encode a SOCKS5 reply carrying `bound` as BND.ADDR and BND.PORT.
+fn socks5_reply(status: SocksStatus, bound: Option<SocketAddr>) -> Vec<u8> {
+    let mut out = vec![SOCKS5_VERSION, status.into(), 0];
+    encode_addr(
+        &bound.unwrap_or((Ipv4Addr::UNSPECIFIED, 0).into()),
+        &mut out,
+    );
+    out
+}
+
+/// Prefix `data` with the RFC 1928 UDP request header for `from`.
+fn encode_datagram(from: &SocketAddr, data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0, 0, 0];
+    encode_addr(from, &mut out);
+    out.extend_from_slice(data);
+    out
+}
+
+/// Split a client datagram into its destination and payload. Fragments are not supported.
+fn decode_datagram(buf: &[u8]) -> Option<(TargetAddr, &[u8])> {
+    match buf {
+        [0, 0, 0, rest @ ..] => {
+            let (addr, n) = parse_addr(rest)?;
+            Some((addr, &rest[n..]))
+        }
+        _ => None,
+    }
+}
+
+/// The wildcard address of the same family as `ip`. Used when `ip` cannot be bound, e.g. when
+/// the local address came from a PROXY header and belongs to a load balancer.
+fn unspecified(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    }
+}
+
+/// Serve a BIND request: listen on an ephemeral port, send its address in the first reply,
+/// accept a single connection, send the peer's address in the second reply, then relay.
+///
+/// `peer` is the address the client expects the connection from. When it names an IP, a
+/// connection from anywhere else fails the request.
+async fn bind<RT, R, W>(
+    runtime: RT,
+    socks_r: R,
+    mut socks_w: W,
+    peer: TargetAddr,
+    local_ip: IpAddr,
+) -> Result<()>
+where
+    RT: Runtime,
+    R: AsyncRead + Send + Unpin + 'static,
+    W: AsyncWrite + Send + Unpin + 'static,
+{
+    let listener = match runtime.listen(&SocketAddr::new(local_ip, 0)).await {
+        Ok(l) => l,
+        Err(_) => {
+            runtime
+                .listen(&SocketAddr::new(unspecified(local_ip), 0))
+                .await?
+        }
+    };
+    let bound = listener.local_addr()?;
+    let reply = socks5_reply(SocksStatus::SUCCEEDED, Some(bound));
+    write_all_and_flush(&mut socks_w, &reply).await?;
+    debug!("socks bind listening on {bound}");
+
+    let (inbound, from) = match tokio::time::timeout(BIND_ACCEPT_TIMEOUT, listener.accept()).await {
+        Ok(r) => r?,
+        Err(_) => {
+            let reply = socks5_reply(SocksStatus::TTL_EXPIRED, None);
+            write_all_and_close(&mut socks_w, &reply).await?;
+            return Err(anyhow!("no connection to socks bind on {bound}"));
+        }
+    };
+    drop(listener);
+    if let TargetAddr::Ip(expected) = peer {
+        if !expected.ip().is_unspecified() && expected.ip() != from.ip() {
+            let reply = socks5_reply(SocksStatus::NOT_ALLOWED, None);
+            write_all_and_close(&mut socks_w, &reply).await?;
+            return Err(anyhow!("socks bind connection from unexpected peer"));
+        }
+    }
+    let reply = socks5_reply(SocksStatus::SUCCEEDED, Some(from));
+    write_all_and_flush(&mut socks_w, &reply).await?;
+    debug!("socks bind accepted {}", sensitive(&from));
+
+    let (in_r, in_w) = inbound.split();
+    runtime.spawn(copy_interactive(socks_r, in_w).map(|_| ()))?;
+    runtime.spawn(copy_interactive(in_r, socks_w).map(|_| ()))?;
+    Ok(())
+}
+
+/// Serve a UDP ASSOCIATE request: bind a relay socket, tell the client where it is, and relay
+/// datagrams until the control connection closes.
+///
+/// `client` is the address the client said it will send from. Unspecified parts match any
+/// sender, in which case the first datagram decides which address the client is.
+async fn udp_associate<RT, R, W>(
+    runtime: RT,
+    mut control_r: R,
+    mut control_w: W,
+    client: TargetAddr,
+    local_ip: IpAddr,
+) -> Result<()>
+where
+    RT: Runtime,
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let relay = match runtime.bind(&SocketAddr::new(local_ip, 0)).await {
+        Ok(relay) => relay,
+        Err(_) => {
+            runtime
+                .bind(&SocketAddr::new(unspecified(local_ip), 0))
+                .await?
+        }
+    };
+    let bound = relay.local_addr()?;
+    let reply = socks5_reply(SocksStatus::SUCCEEDED, Some(bound));
+    write_all_and_flush(&mut control_w, &reply).await?;
+    debug!("udp relay bound on {bound}");
+
+    let expected = match client {
+        TargetAddr::Ip(addr) => addr,
+        TargetAddr::Domain(..) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+    };
+    let is_expected = |from: &SocketAddr| {
+        (expected.ip().is_unspecified() || expected.ip() == from.ip())
+            && (expected.port() == 0 || expected.port() == from.port())
+    };
+
+    let relay_loop = async {
+        let mut client_addr: Option<SocketAddr> = None;
+        let mut buf = vec![0_u8; MAX_DATAGRAM];
+        loop {
+            let (n, from) = relay.recv(&mut buf).await?;
+            let from_client = match client_addr {
+                Some(c) => c == from,
+                None => is_expected(&from),
+            };
+            if from_client {
+                client_addr = Some(from);
+                let Some((dest, data)) = decode_datagram(&buf[..n]) else {
+                    trace!("dropping malformed or fragmented datagram");
+                    continue;
+                };
+                match dest.resolve().await {
+                    Ok(dest) => {
+                        relay.send(data, &dest).await?;
+                    }
+                    Err(e) => debug!("dropping datagram: {e}"),
+                }
+            } else if let Some(c) = client_addr {
+                relay.send(&encode_datagram(&from, &buf[..n]), &c).await?;
+            }
+        }
+    };
+
+    // The association lasts as long as the TCP connection that requested it.
+    let control_closed = async {
+        let mut b = [0_u8; 64];
+        while control_r.read(&mut b).await? != 0 {}
+        Ok::<_, std::io::Error>(())
+    };
+
+    tokio::select! {
+        r = relay_loop => {
+            let r: IoResult<()> = r;
+            r.context("udp relay failed")
+        }
+        r = control_closed => {
+            trace!("udp association closed");
+            r.context("error while reading SOCKS control connection")
+        }
+    }
+}
+
 /// write_all the data to the writer & flush the writer if write_all is successful.
 async fn write_all_and_flush<W>(writer: &mut W, buf: &[u8]) -> Result<()>
 where
@@ -225,35 +506,6 @@ where
     loop_result.or(flush_result)
 }
 
-/// Type alias for the isolation information associated with a given SOCKS
-/// connection _before_ SOCKS is negotiated.
-///
-/// Currently this is an index for which listener accepted the connection, plus
-/// the address of the client that connected to the Socks port.
-type ConnIsolation = (usize, IpAddr);
-
-/// A Key used to isolate connections.
-///
-/// Composed of an usize (representing which listener socket accepted
-/// the connection, the source IpAddr of the client, and the
-/// authentication string provided by the client).
-#[derive(Debug, Clone, PartialEq, Eq)]
-struct SocksIsolationKey(ConnIsolation, SocksAuth);
-
-impl arti_client::isolation::IsolationHelper for SocksIsolationKey {
-    fn compatible_same_type(&self, other: &Self) -> bool {
-        self == other
-    }
-
-    fn join_same_type(&self, other: &Self) -> Option<Self> {
-        if self == other {
-            Some(self.clone())
-        } else {
-            None
-        }
-    }
-}
-
 /// Payload to return when an HTTP connection arrive on a Socks port
 const WRONG_PROTOCOL_PAYLOAD: &[u8] = br#"HTTP/1.0 501 Tor is not an HTTP Proxy
 Content-Type: text/html; charset=utf-8
@@ -279,3 +531,154 @@ See <a href="https://gitlab.torproject.org/tpo/core/arti/#todo-need-to-change-wh
 </p>
 </body>
 </html>"#;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use async_compat::CompatExt;
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+    use tokio::net::UdpSocket as TokioUdpSocket;
+    use tor_rtcompat::PreferredRuntime;
+
+    #[test]
+    fn datagram_codec() {
+        let from: SocketAddr = "[2001:db8::1]:53".parse().unwrap();
+        let wire = encode_datagram(&from, b"answer");
+        assert_eq!(
+            decode_datagram(&wire),
+            Some((TargetAddr::Ip(from), &b"answer"[..]))
+        );
+
+        let mut wire = vec![0, 0, 0, SOCKS5_ATYP_DOMAIN, 11];
+        wire.extend_from_slice(b"example.com");
+        wire.extend_from_slice(&53_u16.to_be_bytes());
+        wire.extend_from_slice(b"query");
+        assert_eq!(
+            decode_datagram(&wire),
+            Some((TargetAddr::Domain("example.com".into(), 53), &b"query"[..]))
+        );
+
+        // fragments and truncated headers are dropped
+        assert_eq!(
+            decode_datagram(&[0, 0, 1, SOCKS5_ATYP_IPV4, 1, 2, 3, 4, 0, 53]),
+            None
+        );
+        assert_eq!(decode_datagram(&[0, 0, 0, SOCKS5_ATYP_IPV4, 1, 2]), None);
+    }
+
+    /// Greet with no auth and send a request for `cmd` with the IPv4 address `addr`.
+    async fn request(client: &mut tokio::net::UnixStream, cmd: u8, addr: SocketAddr) -> Result<()> {
+        client.write_all(&[5, 1, 0]).await?;
+        let mut method = [0_u8; 2];
+        client.read_exact(&mut method).await?;
+        assert_eq!(method, [5, 0]);
+        let mut req = vec![5, cmd, 0];
+        encode_addr(&addr, &mut req);
+        client.write_all(&req).await?;
+        Ok(())
+    }
+
+    /// Read a reply with an IPv4 address, returning its status and address.
+    async fn reply(client: &mut tokio::net::UnixStream) -> Result<(u8, SocketAddr)> {
+        let mut reply = [0_u8; 10];
+        client.read_exact(&mut reply).await?;
+        match parse_addr(&reply[3..]) {
+            Some((TargetAddr::Ip(addr), _)) => Ok((reply[1], addr)),
+            r => panic!("unexpected bound address {r:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn bind_accepts_one_peer() -> Result<()> {
+        let (mut client, server) = tokio::net::UnixStream::pair()?;
+        let rt = PreferredRuntime::current()?;
+        tokio::spawn(handle_socks_conn(
+            rt,
+            server.compat(),
+            Ipv4Addr::LOCALHOST.into(),
+        ));
+
+        request(&mut client, SOCKS5_CMD_BIND, "127.0.0.1:0".parse().unwrap()).await?;
+        let (status, bound) = reply(&mut client).await?;
+        assert_eq!(status, 0);
+
+        let mut peer = tokio::net::TcpStream::connect(bound).await?;
+        let (status, from) = reply(&mut client).await?;
+        assert_eq!(status, 0);
+        assert_eq!(from, peer.local_addr()?);
+        // only one connection is accepted
+        assert!(tokio::net::TcpStream::connect(bound).await.is_err());
+
+        peer.write_all(b"220 ready").await?;
+        let mut buf = [0_u8; 9];
+        client.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"220 ready");
+        client.write_all(b"QUIT").await?;
+        let mut buf = [0_u8; 4];
+        peer.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"QUIT");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bind_rejects_unexpected_peer() -> Result<()> {
+        let (mut client, server) = tokio::net::UnixStream::pair()?;
+        let rt = PreferredRuntime::current()?;
+        let srv = tokio::spawn(handle_socks_conn(
+            rt,
+            server.compat(),
+            Ipv4Addr::LOCALHOST.into(),
+        ));
+
+        request(
+            &mut client,
+            SOCKS5_CMD_BIND,
+            "192.0.2.1:21".parse().unwrap(),
+        )
+        .await?;
+        let (_, bound) = reply(&mut client).await?;
+        let _peer = tokio::net::TcpStream::connect(bound).await?;
+        let (status, _) = reply(&mut client).await?;
+        assert_eq!(status, u8::from(SocksStatus::NOT_ALLOWED));
+        assert!(srv.await.unwrap().is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn udp_associate_relays() -> Result<()> {
+        let (mut client, server) = tokio::net::UnixStream::pair()?;
+        let rt = PreferredRuntime::current()?;
+        let local_ip = Ipv4Addr::LOCALHOST.into();
+        let srv = tokio::spawn(handle_socks_conn(rt, server.compat(), local_ip));
+
+        // UDP ASSOCIATE from any address
+        let any = "0.0.0.0:0".parse().unwrap();
+        request(&mut client, SOCKS5_CMD_UDP_ASSOCIATE, any).await?;
+        let (status, relay) = reply(&mut client).await?;
+        assert_eq!(status, 0);
+
+        let echo = TokioUdpSocket::bind("127.0.0.1:0").await?;
+        let echo_addr = echo.local_addr()?;
+        tokio::spawn(async move {
+            let mut buf = [0_u8; 64];
+            let (n, from) = echo.recv_from(&mut buf).await.unwrap();
+            echo.send_to(&buf[..n], from).await.unwrap();
+        });
+
+        let udp = TokioUdpSocket::bind("127.0.0.1:0").await?;
+        udp.send_to(&encode_datagram(&echo_addr, b"ping"), relay)
+            .await?;
+        let mut buf = [0_u8; 64];
+        let n = udp.recv(&mut buf).await?;
+        assert_eq!(
+            decode_datagram(&buf[..n]),
+            Some((TargetAddr::Ip(echo_addr), &b"ping"[..]))
+        );
+
+        // closing the control connection ends the association
+        drop(client);
+        srv.await.unwrap()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+use crate::{Error, Result};
+
+use bitflags::bitflags;
+
+bitflags! {
+    /// Properties of the stream a transport produces, used by composition helpers and the
+    /// transport registry to validate a configuration when it is built rather than when it
+    /// first fails at runtime.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub struct Capabilities: u32 {
+        /// Delivered bytes are never lost.
+        const RELIABLE = 1 << 0;
+        /// Delivered bytes arrive in the order they were sent.
+        const ORDERED = 1 << 1;
+        /// Message boundaries are preserved (datagram rather than byte stream semantics).
+        const DATAGRAM = 1 << 2;
+        /// Application data can be sent before the handshake completes.
+        const ZERO_RTT = 1 << 3;
+        /// Bytes are copied through unmodified.
+        const PASSTHROUGH = 1 << 4;
+        /// The transport exchanges messages with the peer before application data flows.
+        const NEEDS_HANDSHAKE = 1 << 5;
+    }
+}
+
+impl Capabilities {
+    /// A reliable, ordered byte stream such as TCP. This is what a transport that wraps a
+    /// [`Stream`](crate::Stream) without changing its semantics provides.
+    pub const STREAM: Self = Self::RELIABLE.union(Self::ORDERED);
+
+    /// Returns an error naming any capabilities in `required` that `self` lacks.
+    pub fn require(self, required: Capabilities) -> Result<()> {
+        let missing = required.difference(self);
+        if missing.is_empty() {
+            return Ok(());
+        }
+        Err(Error::Other(
+            format!("missing required transport capabilities: {missing:?}").into(),
+        ))
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self::STREAM
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transports::{identity::Identity, Transports};
+    use crate::{Role, Transport, TransportBuilder};
+
+    use tokio::net::TcpStream;
+
+    #[test]
+    fn identity_capabilities() -> Result<()> {
+        let caps = TransportBuilder::capabilities(&Identity::new());
+        assert!(caps.contains(Capabilities::STREAM | Capabilities::PASSTHROUGH));
+        caps.require(Capabilities::RELIABLE)?;
+        assert!(caps.require(Capabilities::DATAGRAM).is_err());
+
+        let t = Transports::Identity.build::<TcpStream>(&Role::Sealer);
+        assert_eq!(t.capabilities(), caps);
+        assert_eq!(Transports::Identity.capabilities(), caps);
+        Ok(())
+    }
+
+    #[test]
+    fn require_names_missing() {
+        let err = Capabilities::STREAM
+            .require(Capabilities::ZERO_RTT | Capabilities::ORDERED)
+            .unwrap_err();
+        assert!(err.to_string().contains("ZERO_RTT"));
+        assert!(!err.to_string().contains("ORDERED"));
+    }
+}
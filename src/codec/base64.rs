@@ -0,0 +1,157 @@
+//! Streaming base64 encoding.
+
+use super::{ChunkTransform, Error, Result};
+
+use alloc::vec::Vec;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+
+/// Whole buffer base64 with the standard alphabet and padding, which [`Encode`] and
+/// [`Decode`] are built on.
+pub trait Engine {
+    /// Encode `input` into `out`, which is exactly as long as the padded encoding.
+    fn encode(&self, input: &[u8], out: &mut [u8]) -> Result<()>;
+
+    /// Decode `input`, whole 4 character groups with padding only in the last one, into
+    /// `out`, returning the number of bytes written.
+    fn decode(&self, input: &[u8], out: &mut [u8]) -> Result<usize>;
+}
+
+/// The `base64` crate's scalar implementation.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Portable;
+
+impl Engine for Portable {
+    fn encode(&self, input: &[u8], out: &mut [u8]) -> Result<()> {
+        STANDARD
+            .encode_slice(input, out)
+            .map_err(|_| Error::InvalidData("base64 output size"))?;
+        Ok(())
+    }
+
+    fn decode(&self, input: &[u8], out: &mut [u8]) -> Result<usize> {
+        STANDARD
+            .decode_slice(input, out)
+            .map_err(|_| Error::InvalidData("base64"))
+    }
+}
+
+/// `base64-simd`, which picks SSE, AVX2 or NEON at runtime where the CPU has them.
+#[cfg(feature = "simd")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Simd;
+
+#[cfg(feature = "simd")]
+impl Engine for Simd {
+    fn encode(&self, input: &[u8], out: &mut [u8]) -> Result<()> {
+        use base64_simd::AsOut;
+        let _ = base64_simd::STANDARD.encode(input, out.as_out());
+        Ok(())
+    }
+
+    fn decode(&self, input: &[u8], out: &mut [u8]) -> Result<usize> {
+        use base64_simd::AsOut;
+        base64_simd::STANDARD
+            .decode(input, out.as_out())
+            .map(|decoded| decoded.len())
+            .map_err(|_| Error::InvalidData("base64"))
+    }
+}
+
+/// The engine [`Encode`] and [`Decode`] use: [`Simd`] with the `simd` feature, otherwise
+/// [`Portable`].
+#[cfg(feature = "simd")]
+pub const ENGINE: Simd = Simd;
+#[cfg(not(feature = "simd"))]
+pub const ENGINE: Portable = Portable;
+
+/// Encodes with the standard alphabet. Every chunk is padded so that it can be decoded as
+/// soon as it arrives rather than waiting for a complete 3 byte group.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Encode;
+
+impl ChunkTransform for Encode {
+    fn transform(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        let start = out.len();
+        out.resize(start + input.len().div_ceil(3) * 4, 0);
+        ENGINE.encode(input, &mut out[start..])
+    }
+}
+
+/// Decodes whole 4 character groups as they arrive. Padding may appear between chunks, so
+/// the input is split after each padded group and decoded a run at a time. A partial group
+/// is held until the next chunk.
+#[derive(Clone, Debug, Default)]
+pub struct Decode {
+    pending: Vec<u8>,
+}
+
+impl ChunkTransform for Decode {
+    fn transform(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        self.pending.extend_from_slice(input);
+        let whole = self.pending.len() - self.pending.len() % 4;
+        let mut rest = &self.pending[..whole];
+        while !rest.is_empty() {
+            let end = rest
+                .chunks(4)
+                .position(|group| group[3] == b'=')
+                .map_or(rest.len(), |i| (i + 1) * 4);
+            let start = out.len();
+            out.resize(start + end / 4 * 3, 0);
+            let n = ENGINE.decode(&rest[..end], &mut out[start..])?;
+            out.truncate(start + n);
+            rest = &rest[end..];
+        }
+        self.pending.drain(..whole);
+        Ok(())
+    }
+
+    fn finish(&mut self, _out: &mut Vec<u8>) -> Result<()> {
+        match self.pending.is_empty() {
+            true => Ok(()),
+            false => Err(Error::Truncated("base64 group")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn chunks_decode_independently() -> Result<()> {
+        let mut enc = Encode;
+        let mut wire = vec![];
+        enc.transform(b"a", &mut wire)?;
+        enc.transform(b"bcd", &mut wire)?;
+        assert_eq!(wire, b"YQ==YmNk");
+
+        let mut dec = Decode::default();
+        let mut out = vec![];
+        for b in wire.chunks(3) {
+            dec.transform(b, &mut out)?;
+        }
+        dec.finish(&mut out)?;
+        assert_eq!(out, b"abcd");
+        assert!(crate::codec::apply(&mut Decode::default(), b"YQ=a").is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "simd")]
+    #[test]
+    fn engines_agree() -> Result<()> {
+        for len in [0usize, 1, 2, 3, 31, 32, 33, 1000] {
+            let input: Vec<u8> = (0..len).map(|i| (i * 7) as u8).collect();
+            let mut a = vec![0; len.div_ceil(3) * 4];
+            let mut b = a.clone();
+            Portable.encode(&input, &mut a)?;
+            Simd.encode(&input, &mut b)?;
+            assert_eq!(a, b);
+
+            let mut decoded = vec![0; len.div_ceil(3) * 3];
+            let n = Simd.decode(&a, &mut decoded)?;
+            assert_eq!(decoded[..n], input);
+        }
+        assert!(Simd.decode(b"YQ=a", &mut [0; 3]).is_err());
+        Ok(())
+    }
+}
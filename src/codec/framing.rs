@@ -0,0 +1,81 @@
+//! Length-prefixed framing: each frame is a big-endian `u16` length followed by that many
+//! bytes of payload.
+
+use super::{ChunkTransform, Error, Result};
+
+use alloc::vec::Vec;
+
+/// Largest payload a single frame can carry.
+pub const MAX_PAYLOAD: usize = u16::MAX as usize;
+
+/// Wraps each chunk in one or more frames.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Encode;
+
+impl ChunkTransform for Encode {
+    fn transform(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        for payload in input.chunks(MAX_PAYLOAD) {
+            out.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+            out.extend_from_slice(payload);
+        }
+        Ok(())
+    }
+}
+
+/// Strips frames, emitting each payload once it has fully arrived.
+#[derive(Clone, Debug, Default)]
+pub struct Decode {
+    pending: Vec<u8>,
+}
+
+impl ChunkTransform for Decode {
+    fn transform(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        self.pending.extend_from_slice(input);
+        let mut pos = 0;
+        while let Some(header) = self.pending.get(pos..pos + 2) {
+            let len = u16::from_be_bytes([header[0], header[1]]) as usize;
+            let Some(payload) = self.pending.get(pos + 2..pos + 2 + len) else {
+                break;
+            };
+            out.extend_from_slice(payload);
+            pos += 2 + len;
+        }
+        self.pending.drain(..pos);
+        Ok(())
+    }
+
+    fn finish(&mut self, _out: &mut Vec<u8>) -> Result<()> {
+        match self.pending.is_empty() {
+            true => Ok(()),
+            false => Err(Error::Truncated("frame")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::codec::apply;
+
+    #[test]
+    fn frames_reassemble() -> Result<()> {
+        let mut enc = Encode;
+        let mut wire = vec![];
+        enc.transform(b"hello", &mut wire)?;
+        enc.transform(b"", &mut wire)?;
+        enc.transform(&[7_u8; MAX_PAYLOAD + 1], &mut wire)?;
+        assert_eq!(&wire[..7], b"\x00\x05hello");
+
+        let mut dec = Decode::default();
+        let mut out = vec![];
+        for b in wire.chunks(1000) {
+            dec.transform(b, &mut out)?;
+        }
+        dec.finish(&mut out)?;
+        assert_eq!(&out[..5], b"hello");
+        assert_eq!(out.len(), 5 + MAX_PAYLOAD + 1);
+
+        assert!(apply(&mut Decode::default(), b"\x00\x05hel").is_err());
+        Ok(())
+    }
+}
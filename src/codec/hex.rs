@@ -0,0 +1,170 @@
+//! Streaming hex encoding.
+
+use super::{ChunkTransform, Error, Result};
+
+use alloc::vec::Vec;
+
+const UPPER: &[u8; 16] = b"0123456789ABCDEF";
+const LOWER: &[u8; 16] = b"0123456789abcdef";
+
+/// Whole buffer hex, which [`Encode`] and [`Decode`] are built on.
+pub trait Engine {
+    /// Encode `input` into `out`, which is exactly twice as long.
+    fn encode(&self, input: &[u8], out: &mut [u8], upper: bool);
+
+    /// Decode `input`, pairs of digits in either case, into `out`, which is exactly half as
+    /// long.
+    fn decode(&self, input: &[u8], out: &mut [u8]) -> Result<()>;
+}
+
+/// A byte at a time lookup.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Portable;
+
+impl Engine for Portable {
+    fn encode(&self, input: &[u8], out: &mut [u8], upper: bool) {
+        let digits = if upper { UPPER } else { LOWER };
+        for (b, pair) in input.iter().zip(out.chunks_exact_mut(2)) {
+            pair[0] = digits[(b >> 4) as usize];
+            pair[1] = digits[(b & 0xf) as usize];
+        }
+    }
+
+    fn decode(&self, input: &[u8], out: &mut [u8]) -> Result<()> {
+        for (pair, b) in input.chunks_exact(2).zip(out) {
+            *b = nibble(pair[0])? << 4 | nibble(pair[1])?;
+        }
+        Ok(())
+    }
+}
+
+/// `faster-hex`, which picks SSE4.1 or AVX2 at runtime where the CPU has them.
+#[cfg(feature = "simd")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Simd;
+
+#[cfg(feature = "simd")]
+impl Engine for Simd {
+    fn encode(&self, input: &[u8], out: &mut [u8], upper: bool) {
+        // only fails when `out` is too short
+        let _ = match upper {
+            true => faster_hex::hex_encode_upper(input, out),
+            false => faster_hex::hex_encode(input, out),
+        };
+    }
+
+    fn decode(&self, input: &[u8], out: &mut [u8]) -> Result<()> {
+        faster_hex::hex_decode(input, out).map_err(|_| Error::InvalidData("hex digit"))
+    }
+}
+
+/// The engine [`Encode`] and [`Decode`] use: [`Simd`] with the `simd` feature, otherwise
+/// [`Portable`].
+#[cfg(feature = "simd")]
+pub const ENGINE: Simd = Simd;
+#[cfg(not(feature = "simd"))]
+pub const ENGINE: Portable = Portable;
+
+/// Encodes each byte as two hex digits.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Encode {
+    pub upper: bool,
+}
+
+impl ChunkTransform for Encode {
+    fn transform(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        let start = out.len();
+        out.resize(start + input.len() * 2, 0);
+        ENGINE.encode(input, &mut out[start..], self.upper);
+        Ok(())
+    }
+}
+
+/// Decodes pairs of hex digits in either case. A trailing half byte is held until the next
+/// chunk.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Decode {
+    pending: Option<u8>,
+}
+
+fn nibble(c: u8) -> Result<u8> {
+    match c {
+        b'0'..=b'9' => Ok(c - b'0'),
+        b'a'..=b'f' => Ok(c - b'a' + 10),
+        b'A'..=b'F' => Ok(c - b'A' + 10),
+        _ => Err(Error::InvalidData("hex digit")),
+    }
+}
+
+impl ChunkTransform for Decode {
+    fn transform(&mut self, mut input: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        if let (Some(hi), Some((&c, rest))) = (self.pending, input.split_first()) {
+            out.push(hi << 4 | nibble(c)?);
+            self.pending = None;
+            input = rest;
+        }
+        let whole = input.len() - input.len() % 2;
+        let start = out.len();
+        out.resize(start + whole / 2, 0);
+        if let Err(e) = ENGINE.decode(&input[..whole], &mut out[start..]) {
+            out.truncate(start);
+            return Err(e);
+        }
+        if whole < input.len() {
+            self.pending = Some(nibble(input[whole])?);
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, _out: &mut Vec<u8>) -> Result<()> {
+        match self.pending {
+            Some(_) => Err(Error::Truncated("hex stream")),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::codec::apply;
+
+    #[test]
+    fn round_trip_split() -> Result<()> {
+        let enc = apply(&mut Encode { upper: true }, b"\x00\xffhi")?;
+        assert_eq!(enc, b"00FF6869");
+
+        let mut dec = Decode::default();
+        let mut out = vec![];
+        for c in enc.chunks(3) {
+            dec.transform(c, &mut out)?;
+        }
+        dec.finish(&mut out)?;
+        assert_eq!(out, b"\x00\xffhi");
+
+        assert!(apply(&mut Decode::default(), b"abc").is_err());
+        assert!(apply(&mut Decode::default(), b"zz").is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "simd")]
+    #[test]
+    fn engines_agree() -> Result<()> {
+        for len in [0usize, 1, 15, 16, 17, 64, 1000] {
+            let input: Vec<u8> = (0..len).map(|i| (i * 7) as u8).collect();
+            for upper in [false, true] {
+                let mut a = vec![0; len * 2];
+                let mut b = a.clone();
+                Portable.encode(&input, &mut a, upper);
+                Simd.encode(&input, &mut b, upper);
+                assert_eq!(a, b);
+
+                let mut decoded = vec![0; len];
+                Simd.decode(&a, &mut decoded)?;
+                assert_eq!(decoded, input);
+            }
+        }
+        assert!(Simd.decode(&[b'g'; 32], &mut [0; 16]).is_err());
+        Ok(())
+    }
+}
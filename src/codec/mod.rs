@@ -0,0 +1,70 @@
+//! # Codecs
+//!
+//! Pure byte-level transforms, e.g. encodings and framing, that don't depend on an async
+//! runtime or on `std`. Everything here uses only `core` and `alloc` so that it can be lifted
+//! into embedded or mobile builds; [`Chunked`](crate::pt::transform::Chunked) adapts any
+//! [`ChunkTransform`] into a stream transform for the rest of the crate.
+
+#![deny(clippy::std_instead_of_core, clippy::std_instead_of_alloc)]
+
+use alloc::vec::Vec;
+use core::fmt;
+
+#[cfg(feature = "codecs")]
+pub mod base64;
+pub mod framing;
+pub mod hex;
+pub mod xor;
+
+/// Why a codec rejected its input.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The input can't be decoded.
+    InvalidData(&'static str),
+    /// The input ended part way through a unit of output, e.g. half a hex byte.
+    Truncated(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidData(what) => write!(f, "invalid {what}"),
+            Error::Truncated(what) => write!(f, "{what} ended part way through"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// A transform that maps whatever has been read so far to output, with no need to wait on
+/// either side.
+pub trait ChunkTransform {
+    /// Append the output for `input` to `out`. Input that can't be transformed yet, e.g. part
+    /// of a multi-byte group, should be held until the next call.
+    fn transform(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<()>;
+
+    /// Append any trailing output once the input has ended.
+    fn finish(&mut self, _out: &mut Vec<u8>) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<C: ChunkTransform + ?Sized> ChunkTransform for alloc::boxed::Box<C> {
+    fn transform(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        (**self).transform(input, out)
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) -> Result<()> {
+        (**self).finish(out)
+    }
+}
+
+/// Run `t` over all of `input` at once, e.g. to encode a whole message.
+pub fn apply<C: ChunkTransform + ?Sized>(t: &mut C, input: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    t.transform(input, &mut out)?;
+    t.finish(&mut out)?;
+    Ok(out)
+}
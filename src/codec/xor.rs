@@ -0,0 +1,60 @@
+//! Repeating-key XOR. Not encryption; useful for tests and for breaking up fixed byte
+//! patterns.
+
+use super::{ChunkTransform, Error, Result};
+
+use alloc::vec::Vec;
+
+/// XORs the stream with `key` repeated. Applying it twice with the same key from the start
+/// of the stream gives back the input, so the same transform both seals and reveals.
+#[derive(Clone, Debug)]
+pub struct Xor {
+    key: Vec<u8>,
+    offset: usize,
+}
+
+impl Xor {
+    /// Fails with [`Error::InvalidData`] if `key` is empty.
+    pub fn new(key: &[u8]) -> Result<Self> {
+        if key.is_empty() {
+            return Err(Error::InvalidData("xor key (empty)"));
+        }
+        Ok(Xor {
+            key: key.to_vec(),
+            offset: 0,
+        })
+    }
+}
+
+impl ChunkTransform for Xor {
+    fn transform(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        out.extend(input.iter().map(|b| {
+            let k = self.key[self.offset];
+            self.offset = (self.offset + 1) % self.key.len();
+            b ^ k
+        }));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::codec::apply;
+
+    #[test]
+    fn involution_across_chunks() -> Result<()> {
+        let mut x = Xor::new(b"key")?;
+        let mut sealed = vec![];
+        x.transform(b"hel", &mut sealed)?;
+        x.transform(b"lo world", &mut sealed)?;
+        assert_ne!(sealed, b"hello world");
+        assert_eq!(apply(&mut Xor::new(b"key")?, &sealed)?, b"hello world");
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_empty_keys() {
+        assert!(matches!(Xor::new(b""), Err(Error::InvalidData(_))));
+    }
+}
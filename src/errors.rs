@@ -11,6 +11,28 @@ pub enum Error {
     IOError(std::io::Error),
     EncodeError(Box<dyn std::error::Error>),
     NullTransport,
+    /// The peer sent something the transport could not make sense of.
+    Protocol(Box<dyn std::error::Error>),
+    /// The transport was given options it cannot run with.
+    Config(Box<dyn std::error::Error>),
+    /// The peer completed the exchange but refused the handshake.
+    HandshakeRejected(Box<dyn std::error::Error>),
+    Timeout,
+    Cancelled,
+}
+
+/// Broad classification of an [`Error`], for making retry and failover decisions without
+/// matching on error strings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    Io,
+    Protocol,
+    Config,
+    HandshakeRejected,
+    Timeout,
+    Cancelled,
+    Other,
 }
 
 impl Display for Error {
@@ -20,6 +42,11 @@ impl Display for Error {
             Error::IOError(e) => write!(f, "{}", e),
             Error::EncodeError(e) => write!(f, "{}", e),
             Error::NullTransport => write!(f, "NullTransport"),
+            Error::Protocol(e) => write!(f, "protocol error: {}", e),
+            Error::Config(e) => write!(f, "bad config: {}", e),
+            Error::HandshakeRejected(e) => write!(f, "handshake rejected: {}", e),
+            Error::Timeout => write!(f, "timed out"),
+            Error::Cancelled => write!(f, "cancelled"),
         }
     }
 }
@@ -30,6 +57,45 @@ impl Error {
     pub fn new<T: Into<Box<dyn std::error::Error>>>(e: T) -> Self {
         Error::Other(e.into())
     }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::IOError(e) if e.kind() == std::io::ErrorKind::TimedOut => ErrorKind::Timeout,
+            Error::IOError(_) => ErrorKind::Io,
+            Error::EncodeError(_) | Error::Protocol(_) => ErrorKind::Protocol,
+            Error::NullTransport | Error::Config(_) => ErrorKind::Config,
+            Error::HandshakeRejected(_) => ErrorKind::HandshakeRejected,
+            Error::Timeout => ErrorKind::Timeout,
+            Error::Cancelled => ErrorKind::Cancelled,
+            Error::Other(_) => ErrorKind::Other,
+        }
+    }
+
+    /// Whether the same operation might succeed if attempted again, e.g. against another
+    /// backend or after a backoff. Misconfiguration, malformed or rejected handshakes and
+    /// cancellation will fail the same way every time.
+    pub fn is_retriable(&self) -> bool {
+        use std::io::ErrorKind as Io;
+        match self {
+            Error::IOError(e) => matches!(
+                e.kind(),
+                Io::ConnectionRefused
+                    | Io::ConnectionReset
+                    | Io::ConnectionAborted
+                    | Io::NotConnected
+                    | Io::BrokenPipe
+                    | Io::TimedOut
+                    | Io::Interrupted
+                    | Io::WouldBlock
+                    | Io::UnexpectedEof
+                    | Io::AddrNotAvailable
+                    | Io::HostUnreachable
+                    | Io::NetworkUnreachable
+                    | Io::NetworkDown
+            ),
+            e => e.kind() == ErrorKind::Timeout,
+        }
+    }
 }
 
 impl FromStr for Error {
@@ -42,7 +108,28 @@ impl FromStr for Error {
 
 impl From<std::io::Error> for Error {
     fn from(e: std::io::Error) -> Self {
-        Error::IOError(e)
+        match e.get_ref().is_some_and(|inner| inner.is::<Cancelled>()) {
+            true => Error::Cancelled,
+            false => Error::IOError(e),
+        }
+    }
+}
+
+/// Carries [`Error::Cancelled`] through `std::io::Error`, which needs a `Sync` payload.
+#[derive(Debug)]
+pub(crate) struct Cancelled;
+
+impl Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+impl From<Cancelled> for std::io::Error {
+    fn from(e: Cancelled) -> Self {
+        std::io::Error::other(e)
     }
 }
 
@@ -76,10 +163,7 @@ mod tests {
 
     #[test]
     fn test_display_io_error() {
-        let err = Error::IOError(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "some io error",
-        ));
+        let err = Error::IOError(std::io::Error::other("some io error"));
         assert_eq!(format!("{}", err), "some io error");
     }
 
@@ -98,9 +182,47 @@ mod tests {
         assert_eq!(format!("{}", err), "NullTransport");
     }
 
+    #[test]
+    fn test_kind_and_retriable() {
+        let refused = Error::from(std::io::Error::from(std::io::ErrorKind::ConnectionRefused));
+        assert_eq!(refused.kind(), ErrorKind::Io);
+        assert!(refused.is_retriable());
+
+        let denied = Error::from(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+        assert_eq!(denied.kind(), ErrorKind::Io);
+        assert!(!denied.is_retriable());
+
+        let timed_out = Error::from(std::io::Error::from(std::io::ErrorKind::TimedOut));
+        assert_eq!(timed_out.kind(), ErrorKind::Timeout);
+        assert!(timed_out.is_retriable());
+        assert!(Error::Timeout.is_retriable());
+
+        for e in [
+            Error::Protocol("bad frame".into()),
+            Error::Config("no cert".into()),
+            Error::HandshakeRejected("bad mac".into()),
+            Error::NullTransport,
+            Error::Cancelled,
+            Error::new("unknown"),
+        ] {
+            assert!(!e.is_retriable(), "{e}");
+        }
+        assert_eq!(Error::NullTransport.kind(), ErrorKind::Config);
+        assert_eq!(
+            Error::from(FromHexError::OddLength).kind(),
+            ErrorKind::Protocol
+        );
+    }
+
+    #[test]
+    fn test_cancelled_through_io_error() {
+        let err = Error::from(std::io::Error::from(Cancelled));
+        assert!(matches!(err, Error::Cancelled));
+    }
+
     #[test]
     fn test_from_io_error() {
-        let io_err = std::io::Error::new(std::io::ErrorKind::Other, "some io error");
+        let io_err = std::io::Error::other("some io error");
         let err = Error::from(io_err);
         assert_eq!(format!("{}", err), "some io error");
     }
@@ -114,10 +236,7 @@ mod tests {
 
     #[test]
     fn test_from_other_error() {
-        let other_err = Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "some other error",
-        ));
+        let other_err = Box::new(std::io::Error::other("some other error"));
         let err = Error::from(other_err);
         assert_eq!(format!("{}", err), "some other error");
     }
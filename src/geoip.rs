@@ -0,0 +1,143 @@
+//! # Country tagging
+//!
+//! A [`ConnPolicy`] that tags each connection with the country its peer address maps to in a
+//! MaxMind format database (e.g. GeoLite2-Country), and keeps per-country totals for the
+//! aggregate usage reports bridge operators are asked for. Only the country code is ever
+//! looked up, and the totals are rounded up so that single clients don't stand out.
+
+use crate::policy::{ConnMeta, ConnPolicy, Decision};
+use crate::{Error, Result};
+
+use async_trait::async_trait;
+use maxminddb::{geoip2, Reader};
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Per-country totals are rounded up to a multiple of this.
+pub const ROUND_TO: u64 = 8;
+
+/// Code used for addresses the database has no country for.
+pub const UNKNOWN: &str = "??";
+
+/// Maps an address to an ISO 3166-1 alpha-2 country code.
+pub trait CountryLookup: Send + Sync {
+    fn country(&self, ip: IpAddr) -> Option<String>;
+}
+
+impl<S: AsRef<[u8]> + Send + Sync> CountryLookup for Reader<S> {
+    fn country(&self, ip: IpAddr) -> Option<String> {
+        let found: geoip2::Country = self.lookup(ip.to_canonical()).ok()?;
+        found.country?.iso_code.map(str::to_owned)
+    }
+}
+
+/// Tags connections with `country=<code>` and counts them by country. Never rejects.
+pub struct GeoTagger<L = Reader<Vec<u8>>> {
+    lookup: L,
+    counts: Mutex<HashMap<String, u64>>,
+}
+
+impl GeoTagger {
+    /// Load a MaxMind format database into memory.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let reader = Reader::open_readfile(path).map_err(|e| {
+            Error::Config(format!("failed to load geoip database {}: {e}", path.display()).into())
+        })?;
+        Ok(Self::new(reader))
+    }
+}
+
+impl<L: CountryLookup> GeoTagger<L> {
+    pub fn new(lookup: L) -> Self {
+        Self {
+            lookup,
+            counts: Mutex::default(),
+        }
+    }
+
+    /// Country code for `ip`, or [`UNKNOWN`].
+    pub fn country(&self, ip: IpAddr) -> String {
+        self.lookup.country(ip).unwrap_or_else(|| UNKNOWN.into())
+    }
+
+    /// Connections seen per country so far, each rounded up to a multiple of [`ROUND_TO`].
+    pub fn counts(&self) -> BTreeMap<String, u64> {
+        self.counts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(cc, n)| (cc.clone(), n.div_ceil(ROUND_TO) * ROUND_TO))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl<L: CountryLookup> ConnPolicy for GeoTagger<L> {
+    async fn check(&self, meta: &ConnMeta, _preview: &[u8]) -> Decision {
+        let cc = self.country(meta.peer_addr.ip());
+        *self.counts.lock().unwrap().entry(cc.clone()).or_default() += 1;
+        Decision::Tag(vec![format!("country={cc}")])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::Instant;
+
+    /// Documentation ranges stand in for countries.
+    struct Fixed;
+
+    impl CountryLookup for Fixed {
+        fn country(&self, ip: IpAddr) -> Option<String> {
+            match ip {
+                IpAddr::V4(a) if a.octets()[..3] == [192, 0, 2] => Some("AQ".into()),
+                IpAddr::V4(a) if a.octets()[..3] == [198, 51, 100] => Some("TV".into()),
+                _ => None,
+            }
+        }
+    }
+
+    fn meta(peer: &str) -> ConnMeta {
+        ConnMeta {
+            peer_addr: peer.parse().unwrap(),
+            local_addr: "127.0.0.1:9001".parse().unwrap(),
+            transport: "identity",
+            accepted_at: Instant::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn tags_and_rounds_counts() {
+        let tagger = GeoTagger::new(Fixed);
+        assert_eq!(
+            tagger.check(&meta("192.0.2.1:1234"), &[]).await,
+            Decision::Tag(vec!["country=AQ".into()])
+        );
+        for _ in 0..8 {
+            tagger.check(&meta("198.51.100.7:1234"), &[]).await;
+        }
+        assert_eq!(
+            tagger.check(&meta("[2001:db8::1]:1234"), &[]).await,
+            Decision::Tag(vec!["country=??".into()])
+        );
+
+        let counts = tagger.counts();
+        assert_eq!(counts["AQ"], 8);
+        assert_eq!(counts["TV"], 8);
+        assert_eq!(counts[UNKNOWN], 8);
+    }
+
+    #[test]
+    fn bad_database_is_a_config_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("country.mmdb");
+        std::fs::write(&path, b"not a database").unwrap();
+        assert!(matches!(GeoTagger::open(&path), Err(Error::Config(_))));
+    }
+}
@@ -1,24 +1,63 @@
-#![feature(trait_alias)]
 #![doc = include_str!("../README.md")]
 
+extern crate alloc;
+
+mod capabilities;
 mod errors;
 mod other_copy;
+mod overhead;
 
-pub use errors::{Error, Result};
+pub use capabilities::Capabilities;
+pub use errors::{Error, ErrorKind, Result};
+pub use overhead::OverheadEstimate;
 
+pub mod acl;
+#[cfg(feature = "experimental")]
+#[doc(hidden)]
+pub mod analysis;
+pub mod codec;
+#[cfg(feature = "geoip")]
+pub mod geoip;
+pub mod logging;
+pub mod policy;
+pub mod prelude;
+#[cfg(feature = "python")]
+mod python;
+pub mod rand;
+#[cfg(feature = "experimental")]
+#[doc(hidden)]
+pub mod registration;
+pub mod safelog;
+#[cfg(feature = "sandbox")]
+pub mod sandbox;
+pub mod sockopt;
 pub mod stream;
 pub mod sync;
 pub mod transports;
+#[cfg(feature = "tutorial")]
+pub mod tutorial;
 
 mod pt;
-pub use pt::*;
+#[cfg(feature = "crypto")]
+pub use pt::crypto;
+#[cfg(feature = "fte")]
+pub use pt::fte;
+pub use pt::{
+    bridge_stats, conversion, copy, exit_on_stdin_close, manager, parser, proxy_dialer, reconnect,
+    retry, shutdown, status, transform, wrap,
+};
 pub use pt::{copy::DuplexTransform, transform::BufferTransform, wrap::WrapTransport};
+#[cfg(feature = "experimental")]
+#[doc(hidden)]
+pub use pt::{cover, replay, rotation, session, state};
 pub use stream::Stream;
 
 #[cfg(test)]
 pub(crate) mod test_utils;
 
+use futures::future::BoxFuture;
 use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::sync::CancellationToken;
 
 pub trait Named {
     fn name(&self) -> &'static str;
@@ -30,6 +69,7 @@ pub trait Configurable {
         Self: Sized;
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Role {
     /// Plaintext -> Ciphertext transformation
     Sealer,
@@ -39,7 +79,36 @@ pub enum Role {
 }
 
 pub trait TransportBuilder: Named + Configurable {
+    /// Build a transport instance for the given role.
+    ///
+    /// Prefer [`TransportBuilder::client`] and [`TransportBuilder::server`], which make the role
+    /// part of the returned type so the two sides can't be mixed up.
     fn build(&self, r: &Role) -> Result<TransportInstance>;
+
+    /// Build the client side of the transport, which seals plaintext for the wire.
+    fn client(&self) -> Result<ClientTransport> {
+        Ok(ClientTransport {
+            inner: self.build(&Role::Sealer)?,
+        })
+    }
+
+    /// Build the server side of the transport, which reveals plaintext from the wire.
+    fn server(&self) -> Result<ServerTransport> {
+        Ok(ServerTransport {
+            inner: self.build(&Role::Revealer)?,
+        })
+    }
+
+    /// Capabilities of the transports this builder produces.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::STREAM
+    }
+
+    /// What the transports this builder produces add to a payload of `payload_len` bytes.
+    /// Defaults to nothing.
+    fn overhead(&self, payload_len: usize) -> OverheadEstimate {
+        OverheadEstimate::passthrough(payload_len)
+    }
 }
 
 /// Copies data in both directions between `a` and `b`, encoding/decoding as it goes.
@@ -74,13 +143,128 @@ where
     A: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'a,
 {
     fn wrap(&self, a: A) -> Result<Box<dyn Stream + 'a>>;
+
+    /// [`wrap`](Transport::wrap) `a`, keeping its socket addresses on the returned stream.
+    /// They are read before wrapping, since most transports split `a` and box the halves.
+    fn wrap_addressed(&self, a: A) -> Result<stream::Addressed<Box<dyn Stream + 'a>>>
+    where
+        A: stream::AddrInfo,
+    {
+        let (peer, local) = (a.peer_addr(), a.local_addr());
+        Ok(stream::Addressed::new(self.wrap(a)?, peer, local))
+    }
+
+    /// Wrap `a` in a stream that gives up once `token` is cancelled: any handshake still in
+    /// progress, and all I/O after it, fails with [`Error::Cancelled`] rather than waiting on
+    /// a peer that may never answer.
+    fn wrap_with_cancel(&self, a: A, token: CancellationToken) -> Result<Box<dyn Stream + 'a>> {
+        if token.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        Ok(Box::new(stream::Cancellable::new(self.wrap(a)?, token)))
+    }
+
+    /// Wrap `a`, sending `early` to the peer ahead of anything written to the returned stream.
+    /// Transports that can carry data in their handshake (see [`Capabilities::ZERO_RTT`])
+    /// override this so the peer sees it a round trip sooner; by default it is sent once the
+    /// returned stream is first used.
+    fn wrap_with_early_data(&self, a: A, early: &[u8]) -> Result<Box<dyn Stream + 'a>> {
+        let wrapped = self.wrap(a)?;
+        if early.is_empty() {
+            return Ok(wrapped);
+        }
+        Ok(Box::new(stream::EarlyData::new(wrapped, early)))
+    }
+
+    /// Capabilities of the streams returned by [`Transport::wrap`].
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::STREAM
+    }
+}
+
+/// Object safe form of [`Transport`] over boxed streams, so that transports can be stored and
+/// passed around uniformly as `Box<dyn DynTransport>` without naming the stream type or its
+/// lifetime. Wrapping is asynchronous to leave room for transports that need to talk to the
+/// peer before the stream is usable.
+///
+/// Implemented for every transport that can wrap any boxed stream.
+pub trait DynTransport: Send + Sync {
+    fn wrap_boxed<'a>(
+        &self,
+        stream: Box<dyn Stream + 'a>,
+    ) -> BoxFuture<'a, Result<Box<dyn Stream + 'a>>>;
+
+    /// [`DynTransport::wrap_boxed`], racing the wrap against `token` and tying the returned
+    /// stream to it as [`Transport::wrap_with_cancel`] does.
+    fn wrap_boxed_with_cancel<'a>(
+        &self,
+        stream: Box<dyn Stream + 'a>,
+        token: CancellationToken,
+    ) -> BoxFuture<'a, Result<Box<dyn Stream + 'a>>> {
+        let wrap = self.wrap_boxed(stream);
+        Box::pin(async move {
+            let stream = tokio::select! {
+                r = wrap => r?,
+                _ = token.cancelled() => return Err(Error::Cancelled),
+            };
+            Ok(Box::new(stream::Cancellable::new(stream, token)) as Box<dyn Stream + 'a>)
+        })
+    }
+
+    /// Capabilities of the streams returned by [`DynTransport::wrap_boxed`].
+    fn capabilities(&self) -> Capabilities;
+}
+
+impl<T> DynTransport for T
+where
+    T: for<'a> Transport<'a, Box<dyn Stream + 'a>> + Send + Sync,
+{
+    fn wrap_boxed<'a>(
+        &self,
+        stream: Box<dyn Stream + 'a>,
+    ) -> BoxFuture<'a, Result<Box<dyn Stream + 'a>>> {
+        Box::pin(std::future::ready(self.wrap(stream)))
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Transport::<Box<dyn Stream>>::capabilities(self)
+    }
+}
+
+/// Wrap a stream the caller keeps ownership of. The borrow ends when the wrapped stream is
+/// dropped, so the caller can shut the wrapper down and carry on using (or inspect) the
+/// underlying stream, e.g. to check what a transport wrote to the wire in tests.
+///
+/// Implemented for every transport that can wrap `&mut S`, which includes any transport
+/// generic over its stream type.
+pub trait WrapBorrowed<S>
+where
+    S: Stream,
+{
+    fn wrap_borrowed<'a>(&self, stream: &'a mut S) -> Result<Box<dyn Stream + 'a>>
+    where
+        S: 'a;
+}
+
+impl<T, S> WrapBorrowed<S> for T
+where
+    S: Stream,
+    T: for<'a> Transport<'a, &'a mut S>,
+{
+    fn wrap_borrowed<'a>(&self, stream: &'a mut S) -> Result<Box<dyn Stream + 'a>>
+    where
+        S: 'a,
+    {
+        self.wrap(stream)
+    }
 }
 
 pub struct TransportInstance {
     inner: Box<dyn for<'a> Transport<'a, Box<dyn Stream + 'a>> + Send + Sync>,
 }
 impl TransportInstance {
-    fn new(inner: Box<dyn for<'a> Transport<'a, Box<dyn Stream + 'a>> + Send + Sync>) -> Self {
+    /// Used by [`TransportBuilder::build`] implementations to hand back their transport.
+    pub fn new(inner: Box<dyn for<'a> Transport<'a, Box<dyn Stream + 'a>> + Send + Sync>) -> Self {
         Self { inner }
     }
 }
@@ -92,37 +276,79 @@ where
     fn wrap(&self, a: A) -> Result<Box<dyn Stream + 'a>> {
         self.inner.wrap(Box::new(a))
     }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
 }
 
-/// Copies data in both directions between `a` and `b`, encoding/decoding as it goes.
-///
-/// This function returns a future that will read from both streams,
-/// writing any data read to the opposing stream.
-/// This happens in both directions concurrently.
-///
-/// If an EOF is observed on one stream, [`shutdown()`] will be invoked on
-/// the other, and reading from that stream will stop. Copying of data in
-/// the other direction will continue.
-///
-/// The future will complete successfully once both directions of communication has been shut down.
-/// A direction is shut down when the reader reports EOF,
-/// at which point [`shutdown()`] is called on the corresponding writer. When finished,
-/// it will return a tuple of the number of bytes copied from a to b
-/// and the number of bytes copied from b to a, in that order.
-///
-/// [`shutdown()`]: crate::io::AsyncWriteExt::shutdown
-///
-/// # Errors
-///
-/// The future will immediately return an error if any IO operation on `a`
-/// or `b` returns an error. Some data read from either stream may be lost (not
-/// written to the other stream) in this case.
-///
-/// # Return value
-///
-/// Returns a tuple of bytes copied `a` to `b` and bytes copied `b` to `a`.
+/// The client (sealing) side of a transport, produced by [`TransportBuilder::client`].
+pub struct ClientTransport {
+    inner: TransportInstance,
+}
+
+impl ClientTransport {
+    pub fn role(&self) -> Role {
+        Role::Sealer
+    }
+}
+
+impl<'a, A> Transport<'a, A> for ClientTransport
+where
+    A: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'a,
+{
+    fn wrap(&self, a: A) -> Result<Box<dyn Stream + 'a>> {
+        self.inner.wrap(a)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Transport::<A>::capabilities(&self.inner)
+    }
+}
+
+/// The server (revealing) side of a transport, produced by [`TransportBuilder::server`].
+pub struct ServerTransport {
+    inner: TransportInstance,
+}
+
+impl ServerTransport {
+    pub fn role(&self) -> Role {
+        Role::Revealer
+    }
+}
+
+impl<'a, A> Transport<'a, A> for ServerTransport
+where
+    A: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'a,
+{
+    fn wrap(&self, a: A) -> Result<Box<dyn Stream + 'a>> {
+        self.inner.wrap(a)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Transport::<A>::capabilities(&self.inner)
+    }
+}
+
+/// A named, configurable [`WrapTransport`], so wrap style transports can be selected and set
+/// up at runtime like any other. Implemented for every type that is all three; use
+/// [`dyn_from_wrapping`] to get a [`DynTransport`] for one side.
 pub trait Wrapping: WrapTransport + Named + Configurable {}
 
+impl<T: WrapTransport + Named + Configurable> Wrapping for T {}
+
+/// The object safe transport for the `role` side of `wrapping`: sealing uses its
+/// [`WrapTransport::wrapper`] and revealing its [`WrapTransport::unwrapper`].
+pub fn dyn_from_wrapping(
+    wrapping: Box<dyn Wrapping + Send + Sync>,
+    role: Role,
+) -> Box<dyn DynTransport> {
+    Box::new(conversion::WrapSide {
+        inner: wrapping,
+        role,
+    })
+}
+
 /// Copies data in one direction from `a` to `b`, applying the transform as it goes.
 ///
 /// This function returns a future that will read from both streams,
@@ -186,15 +412,15 @@ where
 /// Returns a count of bytes copied `a` to `b`.
 pub trait Transform<'a, R, W>: BufferTransform<'a, R, W> + Named + Configurable
 where
-    R: AsyncRead + Clone + ?Sized + 'a,
-    W: AsyncWrite + Clone + ?Sized + 'a,
+    R: AsyncRead + 'a,
+    W: AsyncWrite + 'a,
 {
 }
 
 pub fn duplex_from_transform<'a, T, A, B>(transform: T) -> Result<Box<dyn Duplex<A, B>>>
 where
-    A: AsyncRead + AsyncWrite + Unpin + Clone + ?Sized + 'a,
-    B: AsyncRead + AsyncWrite + Unpin + Clone + ?Sized + 'a,
+    A: AsyncRead + AsyncWrite + Unpin + 'a,
+    B: AsyncRead + AsyncWrite + Unpin + 'a,
     T: Transform<'a, A, B> + 'a,
 {
     let _duplex: Box<dyn DuplexTransform<A, B>> =
@@ -204,8 +430,8 @@ where
 
 pub fn wrapping_from_transform<'a, T, R, W>(_transform: T) -> Result<Box<dyn Wrapping>>
 where
-    R: AsyncRead + Clone + ?Sized + 'a,
-    W: AsyncWrite + Clone + ?Sized + 'a,
+    R: AsyncRead + 'a,
+    W: AsyncWrite + 'a,
     T: Transform<'a, R, W>,
 {
     Err(Error::Other("not implemented yet".into()))
@@ -256,6 +482,45 @@ mod tests {
     use tokio::io::{AsyncReadExt, AsyncWriteExt};
     use tokio::net::UnixStream;
 
+    #[tokio::test]
+    async fn dyn_transports_store_uniformly() -> Result<()> {
+        let identity = transports::identity::Identity::new();
+        let ts: Vec<Box<dyn DynTransport>> = vec![
+            Box::new(identity.client()?),
+            Box::new(identity.server()?),
+            Box::new(transports::reverse::Reverse::new()),
+        ];
+        for t in &ts {
+            let (a, b) = UnixStream::pair()?;
+            let mut s = t.wrap_boxed(Box::new(a)).await?;
+            let mut b = t.wrap_boxed(Box::new(b)).await?;
+            s.write_all(b"ok").await?;
+            let mut buf = [0_u8; 2];
+            b.read_exact(&mut buf).await?;
+            assert!(t.capabilities().contains(Capabilities::STREAM));
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wrap_borrowed_returns_stream() -> Result<()> {
+        let (mut a, mut b) = tokio::io::duplex(64);
+        let t = transports::identity::Identity::new();
+        {
+            let mut wrapped = t.wrap_borrowed(&mut a)?;
+            wrapped.write_all(b"wrapped").await?;
+            wrapped.flush().await?;
+        }
+        // the borrow ended with the wrapper, so `a` is usable again
+        a.write_all(b" raw").await?;
+        drop(a);
+
+        let mut out = String::new();
+        b.read_to_string(&mut out).await?;
+        assert_eq!(out, "wrapped raw");
+        Ok(())
+    }
+
     #[tokio::test]
     async fn splits() -> Result<()> {
         let (client, server) = UnixStream::pair()?;
@@ -276,6 +541,20 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn typed_roles() -> Result<()> {
+        let builder = transports::identity::Identity::new();
+        let client = builder.client()?;
+        let server = builder.server()?;
+        assert_eq!(client.role(), Role::Sealer);
+        assert_eq!(server.role(), Role::Revealer);
+
+        let (c, s) = UnixStream::pair()?;
+        let (mut cr, mut cw) = split_stream(client.wrap(c)?)?;
+        let (mut sr, mut sw) = split_stream(server.wrap(s)?)?;
+        test_split_read_write(&mut cr, &mut cw, &mut sr, &mut sw).await
+    }
+
     async fn test_split_read_write<'a, R1, W1, R2, W2>(
         mut cr: R1,
         mut cw: W1,
@@ -0,0 +1,119 @@
+//! # Logging
+//!
+//! Sets up `tracing` output for binaries built on this crate, either as human readable text
+//! or as one JSON object per line for ingestion by log pipelines. Connections should be
+//! handled inside a [`conn_span`] so that every event they log carries the connection id
+//! and transport name.
+
+use crate::{Error, Result};
+
+use tracing::{Level, Span, Subscriber};
+use tracing_subscriber::fmt::MakeWriter;
+
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static NEXT_CONN_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(Error::Other(format!("unknown log format \"{s}\"").into())),
+        }
+    }
+}
+
+/// Build a subscriber writing events at or above `level` to `writer`.
+pub fn subscriber<W>(
+    format: LogFormat,
+    level: Level,
+    writer: W,
+) -> Box<dyn Subscriber + Send + Sync>
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(writer);
+    match format {
+        LogFormat::Text => Box::new(builder.finish()),
+        LogFormat::Json => Box::new(
+            builder
+                .json()
+                .with_current_span(true)
+                .with_span_list(false)
+                .finish(),
+        ),
+    }
+}
+
+/// Install a subscriber writing to stdout as the global default.
+pub fn init(format: LogFormat, level: Level) -> Result<()> {
+    tracing::subscriber::set_global_default(subscriber(format, level, std::io::stdout))
+        .map_err(Error::new)
+}
+
+/// A span for one connection, tagged with a process-unique id and the transport name.
+pub fn conn_span(transport: &'static str) -> Span {
+    let id = NEXT_CONN_ID.fetch_add(1, Ordering::Relaxed);
+    tracing::info_span!("conn", conn_id = id, transport)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Buf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buf {
+        fn write(&mut self, b: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(b);
+            Ok(b.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn json_includes_conn_fields() -> Result<()> {
+        let buf = Buf::default();
+        let out = buf.clone();
+        let sub = subscriber("json".parse()?, Level::INFO, move || buf.clone());
+
+        tracing::subscriber::with_default(sub, || {
+            let span = conn_span("identity");
+            let _e = span.enter();
+            tracing::info!(up = 10, down = 20, "connection closed");
+        });
+
+        let out = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(out.lines().count(), 1);
+        for field in [
+            "\"conn_id\":",
+            "\"transport\":\"identity\"",
+            "\"up\":10",
+            "\"down\":20",
+        ] {
+            assert!(out.contains(field), "{field} missing from {out}");
+        }
+        assert!("xml".parse::<LogFormat>().is_err());
+        Ok(())
+    }
+}
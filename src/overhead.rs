@@ -0,0 +1,78 @@
+/// What a transport adds to a payload sent through it in one write, so layers above (and
+/// capacity planning) can work out the wire size of what they send.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OverheadEstimate {
+    /// Expected wire bytes for the payload.
+    pub wire_len: usize,
+    /// Bytes of that added whatever the payload's size, e.g. headers.
+    pub fixed: usize,
+    /// Wire bytes per payload byte on top of `fixed`, averaged over padding and rounding.
+    pub per_byte: f64,
+}
+
+impl OverheadEstimate {
+    /// The payload goes out as it is.
+    pub fn passthrough(payload_len: usize) -> Self {
+        Self {
+            wire_len: payload_len,
+            fixed: 0,
+            per_byte: 1.0,
+        }
+    }
+
+    /// Wire bytes beyond the payload.
+    pub fn added(&self, payload_len: usize) -> usize {
+        self.wire_len.saturating_sub(payload_len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transports::{identity::Identity, Transports};
+    use crate::TransportBuilder;
+
+    #[test]
+    fn passthrough() {
+        let e = Identity::new().overhead(100);
+        assert_eq!(e, OverheadEstimate::passthrough(100));
+        assert_eq!(e.added(100), 0);
+        assert_eq!(Transports::Reverse.overhead(7).wire_len, 7);
+    }
+
+    #[cfg(feature = "codecs")]
+    #[test]
+    fn matches_encoders() -> crate::codec::Result<()> {
+        use crate::codec::{apply, base64, hex};
+        use crate::transports::hex_encoder::HexEncoder;
+
+        for n in [0, 1, 2, 3, 4, 100, 65536] {
+            let payload = vec![7u8; n];
+            let b64 = Transports::Base64.overhead(n);
+            assert_eq!(b64.wire_len, apply(&mut base64::Encode, &payload)?.len());
+            let hex = HexEncoder::new().overhead(n);
+            let encoded = apply(&mut hex::Encode { upper: false }, &payload)?;
+            assert_eq!(hex.wire_len, encoded.len());
+        }
+        assert_eq!(Transports::Base64.overhead(1).added(1), 3);
+        Ok(())
+    }
+
+    #[cfg(feature = "fte")]
+    #[test]
+    fn fte_strings() -> crate::Result<()> {
+        use crate::codec::apply;
+        use crate::fte::{Encode, Language};
+        use crate::transports::fte::{FteBuilder, DEFAULT_LEN, DEFAULT_REGEX};
+        use crate::WrapTransport;
+
+        let lang = std::sync::Arc::new(Language::new(DEFAULT_REGEX, DEFAULT_LEN)?);
+        for n in [0, 1, 74, 75, 1000] {
+            let e = FteBuilder::default().overhead(n);
+            let wire = apply(&mut Encode::new(lang.clone()), &vec![1u8; n]).unwrap();
+            assert_eq!(e.wire_len, wire.len(), "{n}");
+            assert!(e.per_byte > 1.0);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,229 @@
+//! # Policy
+//!
+//! Hooks for deciding what to do with a connection after the transport has revealed it and
+//! before it is forwarded, e.g. token based access to a private bridge. A [`ConnPolicy`] sees
+//! metadata about the connection and, optionally, a preview of the first revealed bytes.
+//!
+//! `check` is async, so a policy can rate-limit by waiting (e.g. on a semaphore or token
+//! bucket) before it allows the connection.
+
+use crate::Result;
+
+use async_trait::async_trait;
+use pin_project::pin_project;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// How long to wait for the peer to send the preview bytes a policy asked for. Protocols where
+/// the server speaks first never send them, so the policy gets whatever arrived in time.
+pub const PREVIEW_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What is known about a connection when the policy is consulted.
+#[derive(Clone, Debug)]
+pub struct ConnMeta {
+    pub peer_addr: SocketAddr,
+    pub local_addr: SocketAddr,
+    /// Name of the transport that revealed the connection.
+    pub transport: &'static str,
+    pub accepted_at: Instant,
+}
+
+/// The outcome of a policy check.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    /// Allow the connection, attaching labels for logging and metrics.
+    Tag(Vec<String>),
+    /// Close the connection without forwarding it.
+    Reject(String),
+}
+
+#[async_trait]
+pub trait ConnPolicy: Send + Sync {
+    /// Number of revealed bytes the policy wants to see before deciding.
+    fn preview_len(&self) -> usize {
+        0
+    }
+
+    async fn check(&self, meta: &ConnMeta, preview: &[u8]) -> Decision;
+}
+
+/// Policy that allows every connection.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AllowAll;
+
+#[async_trait]
+impl ConnPolicy for AllowAll {
+    async fn check(&self, _meta: &ConnMeta, _preview: &[u8]) -> Decision {
+        Decision::Allow
+    }
+}
+
+/// Read the preview `policy` asks for from `stream` and consult the policy. The returned
+/// stream replays the previewed bytes before reading from `stream` again.
+pub async fn apply<S>(
+    policy: &dyn ConnPolicy,
+    meta: &ConnMeta,
+    mut stream: S,
+) -> Result<(Decision, Prefixed<S>)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut preview = vec![0_u8; policy.preview_len()];
+    let mut n = 0;
+    if !preview.is_empty() {
+        let read = tokio::time::timeout(PREVIEW_TIMEOUT, async {
+            while n < preview.len() {
+                match stream.read(&mut preview[n..]).await? {
+                    0 => break,
+                    nr => n += nr,
+                }
+            }
+            Ok::<_, std::io::Error>(())
+        })
+        .await;
+        if let Ok(r) = read {
+            r?;
+        }
+        preview.truncate(n);
+    }
+
+    let decision = policy.check(meta, &preview).await;
+    Ok((decision, Prefixed::new(preview, stream)))
+}
+
+/// Stream that yields `prefix` before reading from the wrapped stream. Writes go straight
+/// through.
+#[pin_project]
+pub struct Prefixed<S> {
+    prefix: Vec<u8>,
+    pos: usize,
+    #[pin]
+    inner: S,
+}
+
+crate::stream::forward_addr_info!(Prefixed, inner);
+
+impl<S> Prefixed<S> {
+    pub fn new(prefix: Vec<u8>, inner: S) -> Self {
+        Self {
+            prefix,
+            pos: 0,
+            inner,
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for Prefixed<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.project();
+        if *this.pos < this.prefix.len() {
+            let n = std::cmp::min(buf.remaining(), this.prefix.len() - *this.pos);
+            buf.put_slice(&this.prefix[*this.pos..*this.pos + n]);
+            *this.pos += n;
+            return Poll::Ready(Ok(()));
+        }
+        this.inner.poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite> AsyncWrite for Prefixed<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.project().inner.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.project().inner.poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::UnixStream;
+
+    /// Only allows connections that start with a shared token.
+    struct Token(&'static [u8]);
+
+    #[async_trait]
+    impl ConnPolicy for Token {
+        fn preview_len(&self) -> usize {
+            self.0.len()
+        }
+
+        async fn check(&self, _meta: &ConnMeta, preview: &[u8]) -> Decision {
+            if preview == self.0 {
+                Decision::Tag(vec!["token".into()])
+            } else {
+                Decision::Reject("bad token".into())
+            }
+        }
+    }
+
+    fn meta() -> ConnMeta {
+        ConnMeta {
+            peer_addr: "192.0.2.1:5555".parse().unwrap(),
+            local_addr: "127.0.0.1:9001".parse().unwrap(),
+            transport: "identity",
+            accepted_at: Instant::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn preview_is_replayed() -> Result<()> {
+        let (a, mut b) = UnixStream::pair()?;
+        b.write_all(b"s3cret and then the payload").await?;
+        drop(b);
+
+        let (decision, mut s) = apply(&Token(b"s3cret"), &meta(), a).await?;
+        assert_eq!(decision, Decision::Tag(vec!["token".into()]));
+        let mut out = String::new();
+        s.read_to_string(&mut out).await?;
+        assert_eq!(out, "s3cret and then the payload");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn short_preview_is_rejected() -> Result<()> {
+        let (a, mut b) = UnixStream::pair()?;
+        b.write_all(b"s3").await?;
+        drop(b);
+
+        let (decision, _) = apply(&Token(b"s3cret"), &meta(), a).await?;
+        assert_eq!(decision, Decision::Reject("bad token".into()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn allow_all_reads_nothing() -> Result<()> {
+        // Nothing is ever written, so this would hang until PREVIEW_TIMEOUT if AllowAll read.
+        let (a, _b) = UnixStream::pair()?;
+        let start = Instant::now();
+        let (decision, _) = apply(&AllowAll, &meta(), a).await?;
+        assert_eq!(decision, Decision::Allow);
+        assert!(start.elapsed() < PREVIEW_TIMEOUT);
+        Ok(())
+    }
+}
@@ -0,0 +1,16 @@
+//! The traits and types most transports and applications need, for a single glob import:
+//!
+//! ```
+//! use ptrs::prelude::*;
+//! ```
+//!
+//! Only the stable core of the API is exported here; more specialised pieces (copy and
+//! transform machinery, policies, the proxy helpers) stay in their own modules.
+//! [`Result`](crate::Result) is left out so the glob doesn't shadow the standard one.
+
+pub use crate::stream::Stream;
+pub use crate::transports::Transports;
+pub use crate::{
+    Capabilities, ClientTransport, Configurable, DynTransport, Error, ErrorKind, Named, Role,
+    ServerTransport, Transport, TransportBuilder, WrapTransport,
+};
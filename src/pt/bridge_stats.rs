@@ -0,0 +1,341 @@
+//! Aggregate usage statistics for a bridge, modeled on tor's `bridge-stats`: per transport,
+//! the number of unique client addresses and connections, binned so that single clients
+//! don't stand out, and byte totals, over fixed intervals.
+//!
+//! Client addresses are never stored. Each interval they are hashed with a fresh random key
+//! that is thrown away when the interval ends, so the hashes can't be linked across
+//! intervals or reversed by anyone who later reads the process memory.
+//!
+//! ```text
+//! {"start":1700000000,"end":1700086400,"transports":{"obfs4":{"unique_ips":24,"connections":80,"bytes_read":81920,"bytes_written":1048576}}}
+//! STATUS TRANSPORT=obfs4 TYPE=BRIDGE_STATS START=1700000000 END=1700086400 UNIQUE_IPS=24 CONNECTIONS=80 READ=81920 WRITTEN=1048576
+//! ```
+
+use super::status::{quote, MANAGED_TRANSPORT_VER};
+use crate::Result;
+
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write as _;
+use std::hash::BuildHasher;
+use std::io::Write as _;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// File the latest report is written to inside a state directory.
+pub const STATE_FILE: &str = "bridge-stats.json";
+
+/// Address and connection counts are rounded up to a multiple of this.
+pub const BIN_SIZE: u64 = 8;
+
+/// Tor's reporting interval.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Default)]
+struct Counts {
+    ips: HashSet<u64>,
+    connections: u64,
+    read: u64,
+    written: u64,
+}
+
+struct Interval {
+    start: u64,
+    key: RandomState,
+    transports: BTreeMap<String, Counts>,
+}
+
+impl Interval {
+    fn new() -> Self {
+        Self {
+            start: unix_now(),
+            key: RandomState::new(),
+            transports: BTreeMap::new(),
+        }
+    }
+
+    fn counts(&mut self, transport: &str) -> &mut Counts {
+        self.transports.entry(transport.to_string()).or_default()
+    }
+}
+
+/// Collects statistics for the current interval. Shared between connection tasks, which
+/// record into it, and whoever ends each interval with [`BridgeStats::rotate`] (or
+/// [`BridgeStats::run`]).
+pub struct BridgeStats {
+    current: Mutex<Interval>,
+}
+
+impl Default for BridgeStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BridgeStats {
+    pub fn new() -> Self {
+        Self {
+            current: Mutex::new(Interval::new()),
+        }
+    }
+
+    /// Count a client connection over `transport` from `ip`. A connection with no known
+    /// address, e.g. over a Unix socket, counts toward connections but not unique addresses.
+    pub fn record_connection(&self, transport: &str, ip: Option<IpAddr>) {
+        let mut current = self.current.lock().unwrap();
+        let hash = ip.map(|ip| current.key.hash_one(ip.to_canonical()));
+        let counts = current.counts(transport);
+        counts.ips.extend(hash);
+        counts.connections += 1;
+    }
+
+    /// Add bytes moved for a client over `transport`.
+    pub fn record_bytes(&self, transport: &str, read: u64, written: u64) {
+        let mut current = self.current.lock().unwrap();
+        let counts = current.counts(transport);
+        counts.read += read;
+        counts.written += written;
+    }
+
+    /// End the current interval, returning its report, and start the next one.
+    pub fn rotate(&self) -> Report {
+        let ended = std::mem::replace(&mut *self.current.lock().unwrap(), Interval::new());
+        Report {
+            start: ended.start,
+            end: unix_now(),
+            transports: ended
+                .transports
+                .into_iter()
+                .map(|(name, c)| {
+                    let usage = TransportUsage {
+                        unique_ips: bin(c.ips.len() as u64),
+                        connections: bin(c.connections),
+                        bytes_read: c.read,
+                        bytes_written: c.written,
+                    };
+                    (name, usage)
+                })
+                .collect(),
+        }
+    }
+
+    /// Rotate every `interval` until `cancel`, saving each report to `state_dir` if there is
+    /// one and writing it as `STATUS` lines on stdout when running as a managed transport.
+    pub async fn run(
+        &self,
+        interval: Duration,
+        state_dir: Option<PathBuf>,
+        cancel: CancellationToken,
+    ) {
+        let managed = std::env::var_os(MANAGED_TRANSPORT_VER).is_some();
+        loop {
+            tokio::select! {
+                _ = cancel.cancelled() => return,
+                _ = tokio::time::sleep(interval) => {}
+            }
+            let report = self.rotate();
+            if let Some(dir) = &state_dir {
+                if let Err(e) = report.save(dir) {
+                    warn!("failed to save bridge stats to {}: {e}", dir.display());
+                }
+            }
+            if managed {
+                let mut out = std::io::stdout().lock();
+                for line in report.status_lines() {
+                    let _ = writeln!(out, "{line}");
+                }
+                let _ = out.flush();
+            }
+        }
+    }
+}
+
+/// Usage of one transport over an interval.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TransportUsage {
+    /// Binned to [`BIN_SIZE`].
+    pub unique_ips: u64,
+    /// Binned to [`BIN_SIZE`].
+    pub connections: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+}
+
+/// Statistics for one interval, with times in seconds since the unix epoch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+    pub start: u64,
+    pub end: u64,
+    pub transports: BTreeMap<String, TransportUsage>,
+}
+
+impl Report {
+    pub fn to_json(&self) -> String {
+        let mut out = format!(
+            "{{\"start\":{},\"end\":{},\"transports\":{{",
+            self.start, self.end
+        );
+        for (i, (name, u)) in self.transports.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let _ = write!(
+                out,
+                "{}:{{\"unique_ips\":{},\"connections\":{},\"bytes_read\":{},\"bytes_written\":{}}}",
+                json_string(name),
+                u.unique_ips,
+                u.connections,
+                u.bytes_read,
+                u.bytes_written
+            );
+        }
+        out.push_str("}}");
+        out
+    }
+
+    /// One `STATUS` line per transport.
+    pub fn status_lines(&self) -> Vec<String> {
+        self.transports
+            .iter()
+            .map(|(name, u)| {
+                format!(
+                    "STATUS TRANSPORT={} TYPE=BRIDGE_STATS START={} END={} UNIQUE_IPS={} CONNECTIONS={} READ={} WRITTEN={}",
+                    quote(name),
+                    self.start,
+                    self.end,
+                    u.unique_ips,
+                    u.connections,
+                    u.bytes_read,
+                    u.bytes_written
+                )
+            })
+            .collect()
+    }
+
+    /// Write the report as JSON to [`STATE_FILE`] in `dir`, replacing the previous one.
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let tmp = dir.join(format!("{STATE_FILE}.tmp"));
+        std::fs::write(&tmp, self.to_json() + "\n")?;
+        std::fs::rename(tmp, dir.join(STATE_FILE))?;
+        Ok(())
+    }
+}
+
+fn bin(n: u64) -> u64 {
+    n.div_ceil(BIN_SIZE) * BIN_SIZE
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::{parse_line, PtLine};
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn bins_unique_addresses() {
+        let stats = BridgeStats::new();
+        for _ in 0..3 {
+            stats.record_connection("obfs4", ip("192.0.2.1"));
+        }
+        // the same client over v4 and v4 mapped v6
+        stats.record_connection("obfs4", ip("::ffff:192.0.2.1"));
+        for i in 0..9 {
+            stats.record_connection("obfs4", ip(&format!("198.51.100.{i}")));
+        }
+        stats.record_connection("identity", ip("2001:db8::1"));
+        stats.record_connection("unix", None);
+        stats.record_bytes("obfs4", 100, 2000);
+        stats.record_bytes("obfs4", 1, 2);
+
+        let report = stats.rotate();
+        assert!(report.start <= report.end);
+        assert_eq!(
+            report.transports["obfs4"],
+            TransportUsage {
+                unique_ips: 16,
+                connections: 16,
+                bytes_read: 101,
+                bytes_written: 2002,
+            }
+        );
+        assert_eq!(report.transports["identity"].unique_ips, 8);
+        assert_eq!(report.transports["unix"].unique_ips, 0);
+        assert_eq!(report.transports["unix"].connections, 8);
+
+        // the next interval starts from nothing
+        assert!(stats.rotate().transports.is_empty());
+    }
+
+    #[test]
+    fn writes_json_and_status_lines() -> Result<()> {
+        let report = Report {
+            start: 10,
+            end: 20,
+            transports: [
+                ("a\"b".to_string(), TransportUsage::default()),
+                (
+                    "obfs4".to_string(),
+                    TransportUsage {
+                        unique_ips: 8,
+                        connections: 16,
+                        bytes_read: 1,
+                        bytes_written: 2,
+                    },
+                ),
+            ]
+            .into(),
+        };
+        assert_eq!(
+            report.to_json(),
+            r#"{"start":10,"end":20,"transports":{"a\"b":{"unique_ips":0,"connections":0,"bytes_read":0,"bytes_written":0},"obfs4":{"unique_ips":8,"connections":16,"bytes_read":1,"bytes_written":2}}}"#
+        );
+
+        let lines = report.status_lines();
+        assert_eq!(
+            lines[1],
+            "STATUS TRANSPORT=obfs4 TYPE=BRIDGE_STATS START=10 END=20 UNIQUE_IPS=8 CONNECTIONS=16 READ=1 WRITTEN=2"
+        );
+        let PtLine::Status { transport, .. } = parse_line(&lines[0])? else {
+            panic!("not a STATUS line");
+        };
+        assert_eq!(transport, "a\"b");
+
+        let dir = tempfile::tempdir()?;
+        report.save(dir.path())?;
+        let saved = std::fs::read_to_string(dir.path().join(STATE_FILE))?;
+        assert_eq!(saved.trim_end(), report.to_json());
+        Ok(())
+    }
+}
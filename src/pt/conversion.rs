@@ -1,67 +1,122 @@
 use crate::{
-    // pt::transform::{BufferTransform, ReadTransform, WriteTransform},
-    pt::transform::BufferTransform,
+    pt::transform::{BufferTransform, ReadTransform, Staging, TransformFactory, WriteTransform},
     stream::{combine, Stream},
     wrap::WrapTransport,
-    Result,
-    Transport,
+    Capabilities, Error, Result, Role, Transport,
 };
 
-use tokio::io::{split, AsyncRead, AsyncWrite};
+use tokio::io::{split, AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
 
-pub fn from_transforms<'a, T1, T2, A, B>(t1: T1, t2: T2) -> impl Transport<'a, A>
+use std::sync::Mutex;
+
+/// Build a transport that applies `reveal` to everything read from the wrapped stream and
+/// `seal` to everything written to it.
+///
+/// Transforms usually carry per-connection state, so the pair is taken by the first call to
+/// [`Transport::wrap`] and later calls fail. Use [`from_factories`] to wrap more than one
+/// connection.
+pub fn from_transforms<'a, A, T1, T2>(reveal: T1, seal: T2) -> impl Transport<'a, A>
 where
-    A: AsyncRead + AsyncWrite + Clone + Unpin + Send + Sync + 'a,
-    B: AsyncRead + AsyncWrite + Clone + Unpin + Send + Sync + 'a,
-    T1: BufferTransform<'a, A, B> + 'a,
-    T2: BufferTransform<'a, B, A> + 'a,
+    A: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'a,
+    T1: BufferTransform<'a, ReadHalf<A>, Staging> + Unpin + Send + Sync + 'a,
+    T2: BufferTransform<'a, Staging, WriteHalf<A>> + Unpin + Send + Sync + 'a,
 {
     FromTransforms {
-        t1: Box::new(t1),
-        t2: Box::new(t2),
+        transforms: Mutex::new(Some((reveal, seal))),
     }
 }
 
-struct FromTransforms<'a, R1, R2, W1, W2>
+/// Like [`from_transforms`], but uses `reveal` and `seal` to make a fresh pair of transforms
+/// for every wrapped connection.
+pub fn from_factories<'a, A, F1, F2>(reveal: F1, seal: F2) -> impl Transport<'a, A>
+where
+    A: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'a,
+    F1: TransformFactory<'a, ReadHalf<A>, Staging>,
+    F2: TransformFactory<'a, Staging, WriteHalf<A>>,
+{
+    FromFactories { reveal, seal }
+}
+
+fn wrap_with<'a, A, T1, T2>(a: A, reveal: T1, seal: T2) -> Box<dyn Stream + 'a>
+where
+    A: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'a,
+    T1: BufferTransform<'a, ReadHalf<A>, Staging> + Unpin + Send + Sync + 'a,
+    T2: BufferTransform<'a, Staging, WriteHalf<A>> + Unpin + Send + Sync + 'a,
+{
+    let (r, w) = split(a);
+    let r_prime = ReadTransform::new(r, reveal);
+    let w_prime = WriteTransform::new(w, seal);
+    Box::new(combine(r_prime, w_prime))
+}
+
+struct FromTransforms<T1, T2> {
+    transforms: Mutex<Option<(T1, T2)>>,
+}
+
+impl<'a, A, T1, T2> Transport<'a, A> for FromTransforms<T1, T2>
 where
-    R1: AsyncRead + Unpin + Send + Sync + 'a,
-    R2: AsyncRead + Unpin + Send + Sync + 'a,
-    W1: AsyncWrite + Unpin + Send + Sync + 'a,
-    W2: AsyncWrite + Unpin + Send + Sync + 'a,
+    A: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'a,
+    T1: BufferTransform<'a, ReadHalf<A>, Staging> + Unpin + Send + Sync + 'a,
+    T2: BufferTransform<'a, Staging, WriteHalf<A>> + Unpin + Send + Sync + 'a,
 {
-    t1: Box<dyn BufferTransform<'a, R1, W1> + 'a>,
-    t2: Box<dyn BufferTransform<'a, R2, W2> + 'a>,
+    fn wrap(&self, a: A) -> Result<Box<dyn Stream + 'a>> {
+        let taken = self
+            .transforms
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        let (reveal, seal) = taken.ok_or_else(|| {
+            Error::new("transforms already used by another connection, see from_factories")
+        })?;
+        Ok(wrap_with(a, reveal, seal))
+    }
 }
 
-impl<'a, A, R1, R2, W1, W2> Transport<'a, A> for FromTransforms<'a, R1, R2, W1, W2>
+struct FromFactories<F1, F2> {
+    reveal: F1,
+    seal: F2,
+}
+
+impl<'a, A, F1, F2> Transport<'a, A> for FromFactories<F1, F2>
 where
-    R1: AsyncRead + Unpin + Send + Sync + 'a,
-    R2: AsyncRead + Unpin + Send + Sync + 'a,
-    W1: AsyncWrite + Unpin + Send + Sync + 'a,
-    W2: AsyncWrite + Unpin + Send + Sync + 'a,
     A: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'a,
+    F1: TransformFactory<'a, ReadHalf<A>, Staging>,
+    F2: TransformFactory<'a, Staging, WriteHalf<A>>,
 {
     fn wrap(&self, a: A) -> Result<Box<dyn Stream + 'a>> {
-        let (r1, w1) = split(a);
-        let (_t1, _t2) = (&self.t1, &self.t2);
-        Ok(Box::new(combine(r1, w1)))
+        Ok(wrap_with(a, self.reveal.make(), self.seal.make()))
+    }
+}
 
-        // let r_prime = ReadTransform::new(r1,  t1);
-        // let w_prime = WriteTransform::new( w1,  t2);
-        // Ok(Box::new(combine(r_prime, w_prime)))
+/// The `role` side of `wrapping`: its [`WrapTransport::wrapper`] when sealing and its
+/// [`WrapTransport::unwrapper`] when revealing.
+pub fn from_wrapping<'a, A, W>(wrapping: W, role: Role) -> impl Transport<'a, A>
+where
+    A: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'a,
+    W: WrapTransport,
+{
+    WrapSide {
+        inner: wrapping,
+        role,
     }
 }
 
-impl<'a, A> Transport<'a, A> for Box<dyn WrapTransport>
+pub(crate) struct WrapSide<W> {
+    pub(crate) inner: W,
+    pub(crate) role: Role,
+}
+
+impl<'a, A, W> Transport<'a, A> for WrapSide<W>
 where
     A: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'a,
+    W: WrapTransport,
 {
     fn wrap(&self, a: A) -> Result<Box<dyn Stream + 'a>> {
-        let (r1, w1) = tokio::io::split(a);
-        let (sealer, revealer) = self.wrapper()?;
-        let r_prime = revealer.reveal(Box::new(r1)); // seal outgoing stream
-        let w_prime = sealer.seal(Box::new(w1)); // reveal incoming stream
-        Ok(Box::new(combine(r_prime, w_prime)))
+        let wrapper = match self.role {
+            Role::Sealer => self.inner.wrapper()?,
+            Role::Revealer => self.inner.unwrapper()?,
+        };
+        Ok(wrapper.wrap(a))
     }
 }
 
@@ -72,6 +127,10 @@ where
     fn wrap(&self, a: A) -> Result<Box<dyn Stream + 'a>> {
         (**self).wrap(a)
     }
+
+    fn capabilities(&self) -> Capabilities {
+        (**self).capabilities()
+    }
 }
 
 impl<'a, A> Transport<'a, A> for &'_ dyn Transport<'a, A>
@@ -81,4 +140,68 @@ where
     fn wrap(&self, a: A) -> Result<Box<dyn Stream + 'a>> {
         (**self).wrap(a)
     }
+
+    fn capabilities(&self) -> Capabilities {
+        (**self).capabilities()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{xor, xor_transform, TRAILER};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn wrapping_sides_follow_the_role() {
+        struct SealOnly;
+
+        impl WrapTransport for SealOnly {
+            fn wrapper(&self) -> Result<crate::wrap::Wrapper> {
+                crate::transports::identity::Identity::new().wrapper()
+            }
+
+            fn unwrapper(&self) -> Result<crate::wrap::Wrapper> {
+                Err(Error::new("no revealing side"))
+            }
+        }
+
+        let client = from_wrapping(SealOnly, Role::Sealer);
+        assert!(client.wrap(tokio::io::duplex(64).0).is_ok());
+        let server = from_wrapping(SealOnly, Role::Revealer);
+        assert!(server.wrap(tokio::io::duplex(64).0).is_err());
+    }
+
+    #[tokio::test]
+    async fn transforms_are_taken_once() -> Result<()> {
+        let t = from_transforms(xor_transform(3), xor_transform(9));
+        let (a, mut wire) = tokio::io::duplex(64);
+        let mut s = t.wrap(a)?;
+
+        s.write_all(b"hello").await?;
+        s.shutdown().await?;
+        let mut buf = vec![];
+        wire.read_to_end(&mut buf).await?;
+        assert_eq!(&buf[..5], xor(b"hello", 9));
+        assert_eq!(&buf[5..], TRAILER);
+
+        let (b, _wire) = tokio::io::duplex(64);
+        assert!(t.wrap(b).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn factories_give_fresh_state() -> Result<()> {
+        let t = from_factories(|| xor_transform(3), || xor_transform(9));
+        for _ in 0..3 {
+            let (a, mut wire) = tokio::io::duplex(64);
+            let mut s = t.wrap(a)?;
+            wire.write_all(&xor(b"ping", 3)).await?;
+            let mut buf = [0_u8; 4];
+            s.read_exact(&mut buf).await?;
+            assert_eq!(&buf, b"ping");
+        }
+        Ok(())
+    }
 }
@@ -1,13 +1,21 @@
 use crate::pt::copy_buffer::*;
+use crate::stream::AnyStream;
 use crate::{Error, Result};
 
 use futures::{future::poll_fn, ready};
 use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::time::Sleep;
 
 use async_trait::async_trait;
 
+use std::any::Any;
+use std::future::Future;
 use std::io;
+use std::pin::Pin;
+use std::str::FromStr;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
 pub enum TransferState {
     Running(CopyBuffer),
@@ -15,6 +23,152 @@ pub enum TransferState {
     Done(u64),
 }
 
+impl TransferState {
+    /// Bytes copied in this direction so far.
+    pub fn transferred(&self) -> u64 {
+        match self {
+            TransferState::Running(buf) => buf.amount(),
+            TransferState::ShuttingDown(n) | TransferState::Done(n) => *n,
+        }
+    }
+}
+
+/// What a duplex copy does with the other direction once one direction reaches EOF.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HalfClosePolicy {
+    /// Shut down the writer for the finished direction and keep copying the other way until
+    /// it finishes too. Protocols that send a request then wait for the response after EOF
+    /// (e.g. HTTP/1.0) rely on this.
+    #[default]
+    PropagateHalfClose,
+    /// Stop copying in both directions as soon as either reaches EOF.
+    CloseBoth,
+    /// Propagate the half-close, but give the other direction at most this long to finish.
+    LingerTimeout(Duration),
+}
+
+impl FromStr for HalfClosePolicy {
+    type Err = Error;
+
+    /// Parses `half-close`, `close-both` or `linger:<seconds>`.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "half-close" => Ok(HalfClosePolicy::PropagateHalfClose),
+            "close-both" => Ok(HalfClosePolicy::CloseBoth),
+            _ => match s.strip_prefix("linger:").map(str::parse::<u64>) {
+                Some(Ok(secs)) => Ok(HalfClosePolicy::LingerTimeout(Duration::from_secs(secs))),
+                _ => Err(Error::Config(
+                    format!("unknown half-close policy \"{s}\"").into(),
+                )),
+            },
+        }
+    }
+}
+
+/// When a copy flushes what it has written. Flushing late lets writers that buffer (like
+/// transports framing records or cells) pack more into each unit, at the cost of latency;
+/// either way the timing shows up in the traffic's shape.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// Flush after writing each chunk read.
+    EveryWrite,
+    /// Flush whenever the reader has nothing more for now.
+    #[default]
+    OnStall,
+    /// Flush at most this often while data keeps coming, and otherwise leave it to the
+    /// writer. Everything is still flushed at EOF.
+    Periodic(Duration),
+}
+
+impl FromStr for FlushPolicy {
+    type Err = Error;
+
+    /// Parses `every-write`, `on-stall` or `periodic:<milliseconds>`.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "every-write" => Ok(FlushPolicy::EveryWrite),
+            "on-stall" => Ok(FlushPolicy::OnStall),
+            _ => match s.strip_prefix("periodic:").map(str::parse::<u64>) {
+                Some(Ok(ms)) => Ok(FlushPolicy::Periodic(Duration::from_millis(ms))),
+                _ => Err(Error::Config(
+                    format!("unknown flush policy \"{s}\"").into(),
+                )),
+            },
+        }
+    }
+}
+
+/// State of a copy in both directions, applying a [`HalfClosePolicy`] when one direction
+/// finishes before the other. [`DuplexTransform`] implementations drive it with their own
+/// per-direction transfer functions.
+pub struct DuplexCopy {
+    policy: HalfClosePolicy,
+    a_to_b: TransferState,
+    b_to_a: TransferState,
+    linger: Option<Pin<Box<Sleep>>>,
+}
+
+impl DuplexCopy {
+    pub fn new(policy: HalfClosePolicy) -> Self {
+        Self {
+            policy,
+            a_to_b: TransferState::Running(CopyBuffer::new()),
+            b_to_a: TransferState::Running(CopyBuffer::new()),
+            linger: None,
+        }
+    }
+
+    /// Flush per `flush` in both directions, rather than [`FlushPolicy::OnStall`].
+    pub fn with_flush(mut self, flush: FlushPolicy) -> Self {
+        self.a_to_b = TransferState::Running(CopyBuffer::new().flush_policy(flush));
+        self.b_to_a = TransferState::Running(CopyBuffer::new().flush_policy(flush));
+        self
+    }
+
+    /// Bytes copied from `a` to `b` and from `b` to `a` so far.
+    pub fn transferred(&self) -> (u64, u64) {
+        (self.a_to_b.transferred(), self.b_to_a.transferred())
+    }
+
+    /// Polls both directions, resolving with the byte counts once both finish or the policy
+    /// gives up on the one still running.
+    pub fn poll_copy<A, B, F1, F2>(
+        &mut self,
+        cx: &mut Context<'_>,
+        a: &mut A,
+        b: &mut B,
+        mut t1: F1,
+        mut t2: F2,
+    ) -> Poll<io::Result<(u64, u64)>>
+    where
+        A: ?Sized,
+        B: ?Sized,
+        F1: FnMut(&mut Context<'_>, &mut TransferState, &mut A, &mut B) -> Poll<io::Result<u64>>,
+        F2: FnMut(&mut Context<'_>, &mut TransferState, &mut B, &mut A) -> Poll<io::Result<u64>>,
+    {
+        let a_to_b = t1(cx, &mut self.a_to_b, a, b)?;
+        let b_to_a = t2(cx, &mut self.b_to_a, b, a)?;
+        match (a_to_b, b_to_a) {
+            (Poll::Ready(up), Poll::Ready(down)) => return Poll::Ready(Ok((up, down))),
+            (Poll::Pending, Poll::Pending) => return Poll::Pending,
+            _ => {}
+        }
+
+        // exactly one direction has finished
+        match self.policy {
+            HalfClosePolicy::PropagateHalfClose => Poll::Pending,
+            HalfClosePolicy::CloseBoth => Poll::Ready(Ok(self.transferred())),
+            HalfClosePolicy::LingerTimeout(d) => {
+                let linger = self
+                    .linger
+                    .get_or_insert_with(|| Box::pin(tokio::time::sleep(d)));
+                ready!(linger.as_mut().poll(cx));
+                Poll::Ready(Ok(self.transferred()))
+            }
+        }
+    }
+}
+
 pub trait SimplexTransform<A: ?Sized, B: ?Sized>: Send + Sync {
     fn transfer_one_direction(
         &self,
@@ -23,6 +177,12 @@ pub trait SimplexTransform<A: ?Sized, B: ?Sized>: Send + Sync {
         r: &mut A,
         w: &mut B,
     ) -> Poll<io::Result<u64>>;
+
+    /// Reports whether this transform copies bytes through unmodified, allowing callers to skip
+    /// the transform entirely (e.g. moving data in-kernel between two sockets).
+    fn is_passthrough(&self) -> bool {
+        false
+    }
 }
 
 impl<A, B, S> SimplexTransform<A, B> for Box<S>
@@ -38,6 +198,10 @@ where
     ) -> Poll<io::Result<u64>> {
         (**self).transfer_one_direction(cx, state, r, w)
     }
+
+    fn is_passthrough(&self) -> bool {
+        (**self).is_passthrough()
+    }
 }
 
 impl<A, B, S: SimplexTransform<A, B> + ?Sized> SimplexTransform<A, B> for &'_ S {
@@ -50,6 +214,10 @@ impl<A, B, S: SimplexTransform<A, B> + ?Sized> SimplexTransform<A, B> for &'_ S
     ) -> Poll<io::Result<u64>> {
         (**self).transfer_one_direction(cx, state, r, w)
     }
+
+    fn is_passthrough(&self) -> bool {
+        (**self).is_passthrough()
+    }
 }
 
 #[async_trait]
@@ -66,6 +234,23 @@ where
     where
         A: AsyncRead + AsyncWrite + Unpin,
         B: AsyncRead + AsyncWrite + Unpin;
+
+    /// Like [`DuplexTransform::copy_bidirectional`], but choosing what happens to the other
+    /// direction when one side reaches EOF. `copy_bidirectional` propagates half-closes.
+    async fn copy_bidirectional_with<'a, 'b>(
+        &self,
+        a: &'a mut A,
+        b: &'b mut B,
+        policy: HalfClosePolicy,
+    ) -> std::result::Result<(u64, u64), std::io::Error>
+    where
+        A: AsyncRead + AsyncWrite + Unpin,
+        B: AsyncRead + AsyncWrite + Unpin;
+
+    /// Reports whether this transform copies bytes through unmodified in both directions.
+    fn is_passthrough(&self) -> bool {
+        false
+    }
 }
 
 pub fn duplex_from_simplices<'t, 's, A, B, T1, T2>(t1: T1, t2: T2) -> DuplexFromSimplices<'t, A, B>
@@ -102,21 +287,71 @@ where
         a: &'a mut A,
         b: &'b mut B,
     ) -> std::result::Result<(u64, u64), std::io::Error> {
-        let mut a_to_b = TransferState::Running(CopyBuffer::new());
-        let mut b_to_a = TransferState::Running(CopyBuffer::new());
-        poll_fn(move |cx| {
-            let a_to_b = self.t1.transfer_one_direction(cx, &mut a_to_b, a, b)?;
-            let b_to_a = self.t2.transfer_one_direction(cx, &mut b_to_a, b, a)?;
-
-            // It is not a problem if ready! returns early because transfer_one_direction for the
-            // other direction will keep returning TransferState::Done(count) in future calls to poll
-            let a_to_b = ready!(a_to_b);
-            let b_to_a = ready!(b_to_a);
+        self.copy_bidirectional_with(a, b, HalfClosePolicy::default())
+            .await
+    }
 
-            Poll::Ready(Ok((a_to_b, b_to_a)))
+    async fn copy_bidirectional_with<'a, 'b>(
+        &self,
+        a: &'a mut A,
+        b: &'b mut B,
+        policy: HalfClosePolicy,
+    ) -> std::result::Result<(u64, u64), std::io::Error> {
+        let mut copy = DuplexCopy::new(policy);
+        poll_fn(move |cx| {
+            copy.poll_copy(
+                cx,
+                a,
+                b,
+                |cx, state, a, b| self.t1.transfer_one_direction(cx, state, a, b),
+                |cx, state, b, a| self.t2.transfer_one_direction(cx, state, b, a),
+            )
         })
         .await
     }
+
+    fn is_passthrough(&self) -> bool {
+        self.t1.is_passthrough() && self.t2.is_passthrough()
+    }
+}
+
+/// Copies data in both directions between `a` and `b` using `transform`.
+///
+/// On Linux, if both are TCP sockets (or `Box<dyn AnyStream>`s holding them), the transform
+/// reports that it is a passthrough and `policy` propagates half-closes, the only policy the
+/// splice copy implements, the data is moved between the sockets in-kernel using `splice(2)`
+/// rather than being copied through userspace buffers. Otherwise this is equivalent to
+/// [`DuplexTransform::copy_bidirectional_with`].
+pub async fn copy_bidirectional_tcp<T, A, B>(
+    transform: &T,
+    a: &mut A,
+    b: &mut B,
+    policy: HalfClosePolicy,
+) -> std::result::Result<(u64, u64), std::io::Error>
+where
+    T: DuplexTransform<A, B> + Sync + ?Sized,
+    A: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+    B: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+{
+    #[cfg(target_os = "linux")]
+    if transform.is_passthrough() && policy == HalfClosePolicy::PropagateHalfClose {
+        if let (Some(a), Some(b)) = (as_tcp(a), as_tcp(b)) {
+            return super::splice::copy_bidirectional(a, b).await;
+        }
+    }
+
+    transform.copy_bidirectional_with(a, b, policy).await
+}
+
+/// `s` as a TCP socket, if that is what it is, looking inside a `Box<dyn AnyStream>`.
+#[cfg(target_os = "linux")]
+pub(crate) fn as_tcp<S: Any>(s: &mut S) -> Option<&mut TcpStream> {
+    let s = s as &mut dyn Any;
+    if s.is::<Box<dyn AnyStream>>() {
+        // `dyn AnyStream`'s downcasts see through however many boxes there are
+        return s.downcast_mut::<Box<dyn AnyStream>>()?.downcast_mut();
+    }
+    s.downcast_mut()
 }
 
 pub(crate) fn duplex_from_transform_buffer<T, A, B>(
@@ -161,6 +396,79 @@ mod test {
         try_join!(client_task, server_task).unwrap();
     }
 
+    #[tokio::test]
+    async fn half_close_propagates() {
+        let (mut client, mut a) = tokio::io::duplex(64);
+        let (mut b, mut server) = tokio::io::duplex(64);
+        let identity = crate::transports::identity::Identity::new();
+        let copy = identity.copy_bidirectional_with(&mut a, &mut b, HalfClosePolicy::default());
+
+        // the response is only written once the request has been closed
+        let server_task = async {
+            let mut request = vec![];
+            server.read_to_end(&mut request).await?;
+            server.write_all(b"response").await?;
+            server.shutdown().await?;
+            io::Result::Ok(request)
+        };
+        let client_task = async {
+            client.write_all(b"request").await?;
+            client.shutdown().await?;
+            let mut response = vec![];
+            client.read_to_end(&mut response).await?;
+            io::Result::Ok(response)
+        };
+        let (counts, request, response) = try_join!(copy, server_task, client_task).unwrap();
+        assert_eq!(counts, (7, 8));
+        assert_eq!(request, b"request");
+        assert_eq!(response, b"response");
+    }
+
+    #[tokio::test]
+    async fn half_close_policies() {
+        let cases = [
+            (HalfClosePolicy::PropagateHalfClose, false),
+            (HalfClosePolicy::CloseBoth, true),
+            (
+                HalfClosePolicy::LingerTimeout(std::time::Duration::from_millis(10)),
+                true,
+            ),
+        ];
+        for (policy, finishes) in cases {
+            let (mut client, mut a) = tokio::io::duplex(64);
+            let (mut b, _server) = tokio::io::duplex(64);
+            client.write_all(b"hi").await.unwrap();
+            client.shutdown().await.unwrap();
+
+            let identity = crate::transports::identity::Identity::new();
+            let copy = identity.copy_bidirectional_with(&mut a, &mut b, policy);
+            let r = tokio::time::timeout(std::time::Duration::from_millis(200), copy).await;
+            assert_eq!(r.is_ok(), finishes, "{policy:?}");
+            if let Ok(counts) = r {
+                assert_eq!(counts.unwrap(), (2, 0));
+            }
+        }
+
+        assert_eq!(
+            "linger:5".parse::<HalfClosePolicy>().unwrap(),
+            HalfClosePolicy::LingerTimeout(std::time::Duration::from_secs(5))
+        );
+        assert!("linger".parse::<HalfClosePolicy>().is_err());
+    }
+
+    #[test]
+    fn parse_flush_policy() {
+        assert_eq!(
+            "periodic:20".parse::<FlushPolicy>().unwrap(),
+            FlushPolicy::Periodic(std::time::Duration::from_millis(20))
+        );
+        assert_eq!(
+            "on-stall".parse::<FlushPolicy>().unwrap(),
+            FlushPolicy::default()
+        );
+        assert!("periodic:soon".parse::<FlushPolicy>().is_err());
+    }
+
     ///
     ///						 write 	 ===================>    encode   ===================>  >|
     ///						 read 	 <===================    decode   <===================  <| echo
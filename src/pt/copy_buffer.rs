@@ -1,23 +1,53 @@
+use crate::pt::copy::FlushPolicy;
+
 use futures::ready;
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::Sleep;
 
+use std::future::Future;
 use std::io;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
 const DEFAULT_BUF_SIZE: usize = 8 * 1024;
+/// Adaptive buffers shrink no smaller than this, which covers interactive traffic.
+const MIN_BUF_SIZE: usize = 2 * 1024;
+/// Adaptive buffers grow no larger than this, enough to keep a high-BDP link busy.
+const MAX_BUF_SIZE: usize = 256 * 1024;
+/// Grow after this many reads in a row fill the buffer (the high watermark).
+const GROW_AFTER: u32 = 2;
+/// Shrink after this many reads in a row use under an eighth of it (the low watermark).
+const SHRINK_AFTER: u32 = 16;
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct CopyBuffer {
     read_done: bool,
     need_flush: bool,
     pos: usize,
     cap: usize,
     amt: u64,
-    buf: Box<[u8]>,
+    buf: Vec<u8>,
+    /// Bounds the buffer is resized within; equal for a fixed size buffer.
+    min: usize,
+    max: usize,
+    /// Consecutive reads at the high and low watermarks.
+    full_reads: u32,
+    small_reads: u32,
+    /// Keep reading while a write is pending.
+    read_ahead: bool,
+    flush: FlushPolicy,
+    /// Running while there are unflushed writes under [`FlushPolicy::Periodic`].
+    flush_timer: Option<Pin<Box<Sleep>>>,
+}
+
+impl Default for CopyBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CopyBuffer {
+    /// A buffer that sizes itself to the traffic, and reads ahead while writes are pending.
     pub fn new() -> Self {
         Self {
             read_done: false,
@@ -25,10 +55,47 @@ impl CopyBuffer {
             pos: 0,
             cap: 0,
             amt: 0,
-            buf: vec![0; DEFAULT_BUF_SIZE].into_boxed_slice(),
+            buf: vec![0; DEFAULT_BUF_SIZE],
+            min: MIN_BUF_SIZE,
+            max: MAX_BUF_SIZE,
+            full_reads: 0,
+            small_reads: 0,
+            read_ahead: true,
+            flush: FlushPolicy::default(),
+            flush_timer: None,
         }
     }
 
+    /// A buffer that stays `size` bytes.
+    pub fn with_size(size: usize) -> Self {
+        Self {
+            buf: vec![0; size],
+            min: size,
+            max: size,
+            ..Self::new()
+        }
+    }
+
+    pub fn read_ahead(mut self, read_ahead: bool) -> Self {
+        self.read_ahead = read_ahead;
+        self
+    }
+
+    pub fn flush_policy(mut self, flush: FlushPolicy) -> Self {
+        self.flush = flush;
+        self
+    }
+
+    /// Bytes written out so far.
+    pub fn amount(&self) -> u64 {
+        self.amt
+    }
+
+    /// Current size of the buffer.
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
     pub fn poll_fill_buf<R>(
         &mut self,
         cx: &mut Context<'_>,
@@ -41,15 +108,82 @@ impl CopyBuffer {
         let mut buf = ReadBuf::new(&mut me.buf);
         buf.set_filled(me.cap);
 
+        let offered = buf.remaining();
         let res = reader.poll_read(cx, &mut buf);
         if let Poll::Ready(Ok(_)) = res {
             let filled_len = buf.filled().len();
-            me.read_done = me.cap == filled_len;
+            let n = filled_len - me.cap;
+            me.read_done = n == 0;
             me.cap = filled_len;
+            me.observe(n, offered);
         }
         res
     }
 
+    /// Track how reads compare to the buffer's watermarks.
+    fn observe(&mut self, n: usize, offered: usize) {
+        if n == 0 {
+            return;
+        }
+        if n == offered {
+            self.full_reads += 1;
+            self.small_reads = 0;
+        } else if n <= self.buf.len() / 8 {
+            self.small_reads += 1;
+            self.full_reads = 0;
+        } else {
+            self.full_reads = 0;
+            self.small_reads = 0;
+        }
+    }
+
+    /// Grow or shrink the buffer per the recent reads. Only called while it is empty.
+    fn resize(&mut self) {
+        let len = self.buf.len();
+        let new_len = if self.full_reads >= GROW_AFTER {
+            (len * 2).min(self.max)
+        } else if self.small_reads >= SHRINK_AFTER {
+            (len / 2).max(self.min)
+        } else {
+            return;
+        };
+        if new_len != len {
+            self.buf = vec![0; new_len];
+        }
+        self.full_reads = 0;
+        self.small_reads = 0;
+    }
+
+    /// Flush if the policy says it is time to, regardless of the reader.
+    fn poll_flush_due<W>(
+        &mut self,
+        cx: &mut Context<'_>,
+        writer: Pin<&mut W>,
+    ) -> Poll<io::Result<()>>
+    where
+        W: AsyncWrite + ?Sized,
+    {
+        if !self.need_flush {
+            return Poll::Ready(Ok(()));
+        }
+        match self.flush {
+            FlushPolicy::EveryWrite => {}
+            FlushPolicy::OnStall => return Poll::Ready(Ok(())),
+            FlushPolicy::Periodic(every) => {
+                let timer = self
+                    .flush_timer
+                    .get_or_insert_with(|| Box::pin(tokio::time::sleep(every)));
+                if timer.as_mut().poll(cx).is_pending() {
+                    return Poll::Ready(Ok(()));
+                }
+            }
+        }
+        ready!(writer.poll_flush(cx))?;
+        self.need_flush = false;
+        self.flush_timer = None;
+        Poll::Ready(Ok(()))
+    }
+
     pub fn poll_write_buf<R, W>(
         &mut self,
         cx: &mut Context<'_>,
@@ -63,9 +197,17 @@ impl CopyBuffer {
         let me = &mut *self;
         match writer.as_mut().poll_write(cx, &me.buf[me.pos..me.cap]) {
             Poll::Pending => {
-                // Top up the buffer towards full if we can read a bit more
-                // data - this should improve the chances of a large write
-                if !me.read_done && me.cap < me.buf.len() {
+                if !me.read_ahead || me.read_done {
+                    return Poll::Pending;
+                }
+                // Move what's left to the front, then top the buffer up while the writer is
+                // busy - this should improve the chances of a large write
+                if me.cap == me.buf.len() && me.pos > 0 {
+                    me.buf.copy_within(me.pos..me.cap, 0);
+                    me.cap -= me.pos;
+                    me.pos = 0;
+                }
+                if me.cap < me.buf.len() {
                     ready!(me.poll_fill_buf(cx, reader.as_mut()))?;
                 }
                 Poll::Pending
@@ -85,11 +227,14 @@ impl CopyBuffer {
         W: AsyncWrite + ?Sized,
     {
         loop {
+            ready!(self.poll_flush_due(cx, writer.as_mut()))?;
+
             // If our buffer is empty, then we need to read some data to
             // continue.
             if self.pos == self.cap && !self.read_done {
                 self.pos = 0;
                 self.cap = 0;
+                self.resize();
 
                 match self.poll_fill_buf(cx, reader.as_mut()) {
                     Poll::Ready(Ok(_)) => (),
@@ -97,7 +242,7 @@ impl CopyBuffer {
                     Poll::Pending => {
                         // Try flushing when the reader has no progress to avoid deadlock
                         // when the reader depends on buffered writer.
-                        if self.need_flush {
+                        if self.need_flush && self.flush == FlushPolicy::OnStall {
                             ready!(writer.as_mut().poll_flush(cx))?;
                             self.need_flush = false;
                         }
@@ -134,8 +279,139 @@ impl CopyBuffer {
             // data and finish the transfer.
             if self.pos == self.cap && self.read_done {
                 ready!(writer.as_mut().poll_flush(cx))?;
+                self.need_flush = false;
+                self.flush_timer = None;
                 return Poll::Ready(Ok(self.amt));
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::scripted::ScriptedStream;
+
+    use futures::future::poll_fn;
+
+    use std::time::Duration;
+
+    /// Records how many bytes had been written at each flush.
+    #[derive(Default)]
+    struct Flushes {
+        written: usize,
+        at: Vec<usize>,
+    }
+
+    impl AsyncWrite for Flushes {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.written += buf.len();
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            let written = self.written;
+            self.at.push(written);
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    async fn flushes(flush: FlushPolicy) -> io::Result<Vec<usize>> {
+        let mut reader = ScriptedStream::builder()
+            .read(b"a")
+            .read(b"bc")
+            .read_pending()
+            .read(b"def")
+            .read_pending()
+            .eof()
+            .build();
+        let mut writer = Flushes::default();
+        let mut buf = CopyBuffer::new().flush_policy(flush);
+        poll_fn(|cx| buf.poll_copy(cx, Pin::new(&mut reader), Pin::new(&mut writer))).await?;
+        Ok(writer.at)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn flush_policies() -> io::Result<()> {
+        assert_eq!(flushes(FlushPolicy::EveryWrite).await?, [1, 3, 6, 6]);
+        assert_eq!(flushes(FlushPolicy::OnStall).await?, [3, 6, 6]);
+        let hourly = FlushPolicy::Periodic(Duration::from_secs(3600));
+        assert_eq!(flushes(hourly).await?, [6]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn grows_for_bulk_and_shrinks_for_interactive() -> io::Result<()> {
+        let bulk = vec![1u8; 1 << 20];
+        let mut script = ScriptedStream::builder();
+        for chunk in bulk.chunks(64 * 1024) {
+            script = script.read(chunk).read_pending();
+        }
+        for _ in 0..200 {
+            script = script.read(b"ls\n").read_pending();
+        }
+        let mut reader = script.eof().build();
+        let mut writer = tokio::io::sink();
+
+        let mut buf = CopyBuffer::new();
+        let mut peak = 0;
+        let mut phase = |buf: &mut CopyBuffer, cx: &mut Context<'_>| {
+            let r = buf.poll_copy(cx, Pin::new(&mut reader), Pin::new(&mut writer));
+            peak = peak.max(buf.capacity());
+            r
+        };
+        let n = poll_fn(|cx| phase(&mut buf, cx)).await?;
+        assert_eq!(n, (1 << 20) + 600);
+        assert!(peak >= 64 * 1024);
+        assert_eq!(buf.capacity(), MIN_BUF_SIZE);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fixed_size() -> io::Result<()> {
+        let mut reader = ScriptedStream::builder()
+            .read(&[0; 4096])
+            .read(&[0; 4096])
+            .read(&[0; 4096])
+            .eof()
+            .build();
+        let mut buf = CopyBuffer::with_size(1024);
+        let mut sink = tokio::io::sink();
+        let n = poll_fn(|cx| buf.poll_copy(cx, Pin::new(&mut reader), Pin::new(&mut sink))).await?;
+        assert_eq!((n, buf.capacity()), (3 * 4096, 1024));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reads_ahead_while_writes_are_pending() -> io::Result<()> {
+        let mut reader = ScriptedStream::builder().read(b"abcd").read(b"ef").build();
+        let mut writer = ScriptedStream::builder()
+            .write(b"ab")
+            .write_pending()
+            .write(b"cdef")
+            .build();
+        let mut buf = CopyBuffer::with_size(4);
+
+        // the first read fills the buffer, half of it goes out, then the writer stalls and
+        // the rest of the input is read into the space freed up
+        poll_fn(|cx| {
+            let _ = buf.poll_copy(cx, Pin::new(&mut reader), Pin::new(&mut writer));
+            Poll::Ready(())
+        })
+        .await;
+        assert_eq!(&buf.buf[..], b"cdef");
+
+        let mut reader = ScriptedStream::builder().eof().build();
+        poll_fn(|cx| buf.poll_copy(cx, Pin::new(&mut reader), Pin::new(&mut writer))).await?;
+        assert_eq!(buf.amount(), 6);
+        Ok(())
+    }
+}
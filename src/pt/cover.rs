@@ -0,0 +1,274 @@
+//! # Cover
+//!
+//! Dummy traffic for idle connections. A link that carries nothing for long stretches and
+//! then bursts is easy to pick out, so [`attach`] sends cover records of randomly chosen
+//! sizes whenever the application has been quiet for a while, per a [`Schedule`]. Every
+//! record is tagged, and the far end, which must be attached as well, strips cover before
+//! the application sees it.
+//!
+//! Records are `[tag: u8][len: u16 big-endian][payload]`. Attach above the transport that
+//! encrypts or encodes the connection, so the tags and cover payloads are hidden by it.
+
+use crate::{
+    codec::{self, ChunkTransform},
+    pt::transform::{Chunked, ReadTransform},
+    rand::Rng,
+    stream::Stream,
+};
+
+use rand::RngCore;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::debug;
+
+use std::io;
+use std::time::Duration;
+
+const DATA: u8 = 0;
+const COVER: u8 = 1;
+const HEADER_LEN: usize = 3;
+const MAX_PAYLOAD: usize = u16::MAX as usize;
+
+/// Bytes of application data buffered between the caller and the sending task.
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+/// When to send cover, and how much.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Schedule {
+    /// How long the application must be quiet before cover is sent, and the gap between
+    /// cover records while it stays quiet.
+    pub idle: Duration,
+    /// Up to this much random delay is added to each wait, so cover isn't periodic.
+    pub jitter: Duration,
+    /// Cover payload sizes with their relative weights.
+    pub sizes: Vec<(u16, u32)>,
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Self {
+            idle: Duration::from_secs(1),
+            jitter: Duration::from_millis(500),
+            sizes: vec![(64, 4), (512, 2), (1400, 1)],
+        }
+    }
+}
+
+impl Schedule {
+    /// Never send cover, only strip what the peer sends.
+    pub fn off() -> Self {
+        Self {
+            sizes: vec![],
+            ..Self::default()
+        }
+    }
+
+    fn total_weight(&self) -> u64 {
+        self.sizes.iter().map(|&(_, w)| w as u64).sum()
+    }
+
+    /// How long to wait for application data before sending cover, `None` if never.
+    fn next_delay(&self, rng: &mut Rng) -> Option<Duration> {
+        if self.total_weight() == 0 {
+            return None;
+        }
+        let jitter = match self.jitter.as_micros() as u64 {
+            0 => 0,
+            max => rng.next_u64() % (max + 1),
+        };
+        Some(self.idle + Duration::from_micros(jitter))
+    }
+
+    /// A cover size, chosen by weight.
+    fn pick_size(&self, rng: &mut Rng) -> usize {
+        let mut pick = rng.next_u64() % self.total_weight();
+        for &(size, weight) in &self.sizes {
+            if pick < weight as u64 {
+                return size as usize;
+            }
+            pick -= weight as u64;
+        }
+        unreachable!("pick is below the total weight")
+    }
+}
+
+fn record(tag: u8, payload: &[u8], out: &mut Vec<u8>) {
+    out.push(tag);
+    out.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    out.extend_from_slice(payload);
+}
+
+/// Tags application data as data records.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Tag;
+
+impl ChunkTransform for Tag {
+    fn transform(&mut self, input: &[u8], out: &mut Vec<u8>) -> codec::Result<()> {
+        for payload in input.chunks(MAX_PAYLOAD) {
+            record(DATA, payload, out);
+        }
+        Ok(())
+    }
+}
+
+/// Reverses [`Tag`], dropping cover records.
+#[derive(Clone, Debug, Default)]
+pub struct Strip {
+    pending: Vec<u8>,
+}
+
+impl ChunkTransform for Strip {
+    fn transform(&mut self, input: &[u8], out: &mut Vec<u8>) -> codec::Result<()> {
+        self.pending.extend_from_slice(input);
+        let mut pos = 0;
+        while let Some(header) = self.pending.get(pos..pos + HEADER_LEN) {
+            let tag = header[0];
+            let len = u16::from_be_bytes([header[1], header[2]]) as usize;
+            let Some(payload) = self.pending.get(pos + HEADER_LEN..pos + HEADER_LEN + len) else {
+                break;
+            };
+            match tag {
+                DATA => out.extend_from_slice(payload),
+                COVER => {}
+                _ => return Err(codec::Error::InvalidData("cover record tag")),
+            }
+            pos += HEADER_LEN + len;
+        }
+        self.pending.drain(..pos);
+        Ok(())
+    }
+
+    fn finish(&mut self, _out: &mut Vec<u8>) -> codec::Result<()> {
+        match self.pending.is_empty() {
+            true => Ok(()),
+            false => Err(codec::Error::Truncated("cover record")),
+        }
+    }
+}
+
+/// Send cover on `stream` per `schedule` and strip the peer's. Writes are handed to a task
+/// (so this must be called inside a tokio runtime) that also sends the cover; it shuts the
+/// stream's write side down once the returned stream is shut down or dropped.
+pub fn attach<S>(stream: S, schedule: Schedule, rng: Rng) -> Box<dyn Stream>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+{
+    let (r, w) = tokio::io::split(stream);
+    let (app, pipe) = tokio::io::duplex(PIPE_CAPACITY);
+    tokio::spawn(async move {
+        if let Err(e) = send(pipe, w, schedule, rng).await {
+            debug!("cover: sending stopped: {e}");
+        }
+    });
+    let reader = ReadTransform::new(r, Chunked::new(Strip::default()));
+    Box::new(tokio::io::join(reader, app))
+}
+
+/// Forward the application's writes from `pipe` to `w` as data records, filling quiet
+/// periods with cover.
+async fn send<R, W>(mut pipe: R, mut w: W, schedule: Schedule, mut rng: Rng) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0u8; MAX_PAYLOAD];
+    let mut out = Vec::with_capacity(HEADER_LEN + MAX_PAYLOAD);
+    loop {
+        out.clear();
+        let read = pipe.read(&mut buf);
+        let n = match schedule.next_delay(&mut rng) {
+            Some(delay) => tokio::select! {
+                n = read => n?,
+                _ = tokio::time::sleep(delay) => {
+                    let mut payload = vec![0u8; schedule.pick_size(&mut rng)];
+                    rng.fill_bytes(&mut payload);
+                    record(COVER, &payload, &mut out);
+                    w.write_all(&out).await?;
+                    w.flush().await?;
+                    continue;
+                }
+            },
+            None => read.await?,
+        };
+        if n == 0 {
+            return w.shutdown().await;
+        }
+        record(DATA, &buf[..n], &mut out);
+        w.write_all(&out).await?;
+        w.flush().await?;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::codec::apply;
+
+    fn fast(sizes: Vec<(u16, u32)>) -> Schedule {
+        Schedule {
+            idle: Duration::from_millis(5),
+            jitter: Duration::from_millis(5),
+            sizes,
+        }
+    }
+
+    #[test]
+    fn strips_cover_records() -> codec::Result<()> {
+        let mut wire = apply(&mut Tag, b"abc")?;
+        record(COVER, &[9; 10], &mut wire);
+        wire.extend(apply(&mut Tag, b"def")?);
+
+        let mut strip = Strip::default();
+        let mut out = vec![];
+        for b in wire.chunks(2) {
+            strip.transform(b, &mut out)?;
+        }
+        strip.finish(&mut out)?;
+        assert_eq!(out, b"abcdef");
+
+        assert!(apply(&mut Strip::default(), b"\x07\x00\x00").is_err());
+        assert!(apply(&mut Strip::default(), b"\x00\x00\x05ab").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sizes_follow_weights() {
+        let schedule = fast(vec![(10, 3), (20, 1), (30, 0)]);
+        let mut rng = Rng::from_seed(4894);
+        let picks: Vec<usize> = (0..4000).map(|_| schedule.pick_size(&mut rng)).collect();
+        let tens = picks.iter().filter(|&&s| s == 10).count();
+        assert!((2700..3300).contains(&tens), "{tens}");
+        assert!(!picks.contains(&30));
+        assert_eq!(Schedule::off().next_delay(&mut rng), None);
+    }
+
+    #[tokio::test]
+    async fn idle_link_carries_cover() -> io::Result<()> {
+        let (a, b) = tokio::io::duplex(1 << 16);
+        let mut a = attach(a, fast(vec![(100, 1)]), Rng::from_seed(1));
+        let mut b = attach(b, Schedule::off(), Rng::from_seed(2));
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        a.write_all(b"after the quiet").await?;
+        a.shutdown().await?;
+
+        let mut got = vec![];
+        b.read_to_end(&mut got).await?;
+        assert_eq!(got, b"after the quiet");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cover_is_on_the_wire() -> io::Result<()> {
+        let (a, mut raw) = tokio::io::duplex(1 << 16);
+        let mut a = attach(a, fast(vec![(100, 1)]), Rng::from_seed(3));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        a.shutdown().await?;
+
+        let mut wire = vec![];
+        raw.read_to_end(&mut wire).await?;
+        assert!(!wire.is_empty());
+        assert_eq!(wire.len() % (HEADER_LEN + 100), 0);
+        assert_eq!(&wire[..HEADER_LEN], [COVER, 0, 100]);
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+//! Constant time comparisons for values a peer can probe byte by byte, e.g. MACs,
+//! authenticators and auth cookies. A plain `==` stops at the first differing byte, so its
+//! timing tells an attacker how much of a guess was right.
+//!
+//! Only the contents are protected: comparing slices of different lengths returns early, as
+//! the lengths of tags are public anyway.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+
+/// Shortest truncated HMAC tag [`verify_hmac_sha256`] accepts, as obfs4 sends.
+pub const MIN_TAG_LEN: usize = 16;
+
+/// Whether `a` and `b` are equal, in time that depends only on their lengths.
+pub fn eq(a: &[u8], b: &[u8]) -> bool {
+    bool::from(a.ct_eq(b))
+}
+
+/// Whether every byte of `a` is zero, e.g. the shared secret a low order point produces.
+pub fn is_zero(a: &[u8]) -> bool {
+    let any = a.iter().fold(0u8, |acc, &b| acc | b);
+    bool::from(any.ct_eq(&0))
+}
+
+/// HMAC-SHA256 over the concatenation of `parts`.
+pub fn hmac_sha256(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    mac(key, parts).finalize().into_bytes().into()
+}
+
+/// Whether `tag` is the HMAC-SHA256 of `parts` under `key`, or its first `tag.len()` bytes.
+/// Tags shorter than [`MIN_TAG_LEN`] are rejected.
+pub fn verify_hmac_sha256(key: &[u8], parts: &[&[u8]], tag: &[u8]) -> bool {
+    tag.len() >= MIN_TAG_LEN && mac(key, parts).verify_truncated_left(tag).is_ok()
+}
+
+fn mac(key: &[u8], parts: &[&[u8]]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac takes any key length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn comparisons() {
+        assert!(eq(b"", b""));
+        assert!(eq(b"cookie", b"cookie"));
+        assert!(!eq(b"cookie", b"cookiE"));
+        assert!(!eq(b"cookie", b"cookies"));
+
+        assert!(is_zero(&[]));
+        assert!(is_zero(&[0; 32]));
+        assert!(!is_zero(&[0, 0, 0x80, 0]));
+    }
+
+    #[test]
+    fn hmac_tags() {
+        // RFC 4231 test case 2
+        let tag = hmac_sha256(b"Jefe", &[b"what do ya want ", b"for nothing?"]);
+        assert_eq!(
+            hex::encode(tag),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+
+        let parts: &[&[u8]] = &[b"what do ya want for nothing?"];
+        assert!(verify_hmac_sha256(b"Jefe", parts, &tag));
+        assert!(verify_hmac_sha256(b"Jefe", parts, &tag[..16]));
+        assert!(!verify_hmac_sha256(b"Jefe", parts, &tag[..15]));
+        assert!(!verify_hmac_sha256(b"jefe", parts, &tag));
+        let mut flipped = tag;
+        flipped[31] ^= 1;
+        assert!(!verify_hmac_sha256(b"Jefe", parts, &flipped));
+    }
+}
@@ -0,0 +1,256 @@
+//! Arithmetic modulo p = 2^255 - 19, as five 51 bit limbs.
+//!
+//! curve25519-dalek keeps its field type private, and elligator2 needs square roots and
+//! quadratic residuosity, so the handful of operations it uses live here. Nothing branches on
+//! secret values; selection goes through [`subtle`].
+
+use subtle::{Choice, ConditionallySelectable, ConstantTimeEq};
+
+use core::ops::{Add, Mul, Neg, Sub};
+
+const MASK: u64 = (1 << 51) - 1;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Fe([u64; 5]);
+
+/// sqrt(-1) mod p.
+const SQRT_M1: Fe = Fe([
+    1718705420411056,
+    234908883556509,
+    2233514472574048,
+    2117202627021982,
+    765476049583133,
+]);
+
+impl Fe {
+    pub(crate) const ZERO: Fe = Fe([0; 5]);
+    pub(crate) const ONE: Fe = Fe([1, 0, 0, 0, 0]);
+
+    pub(crate) const fn from_u64(n: u64) -> Fe {
+        Fe([n & MASK, n >> 51, 0, 0, 0])
+    }
+
+    /// Little endian, ignoring the top bit.
+    pub(crate) fn from_bytes(b: &[u8; 32]) -> Fe {
+        let load = |i: usize| u64::from_le_bytes(b[i..i + 8].try_into().expect("8 bytes"));
+        Fe([
+            load(0) & MASK,
+            (load(6) >> 3) & MASK,
+            (load(12) >> 6) & MASK,
+            (load(19) >> 1) & MASK,
+            (load(24) >> 12) & MASK,
+        ])
+    }
+
+    /// Canonical little endian encoding.
+    pub(crate) fn to_bytes(self) -> [u8; 32] {
+        let mut l = self.carry().0;
+        // add 19 and see whether that overflows 2^255, i.e. whether the value is >= p
+        let mut q = (l[0] + 19) >> 51;
+        for limb in &l[1..] {
+            q = (limb + q) >> 51;
+        }
+        l[0] += 19 * q;
+        for i in 0..4 {
+            l[i + 1] += l[i] >> 51;
+            l[i] &= MASK;
+        }
+        l[4] &= MASK;
+
+        let mut out = [0u8; 32];
+        let (mut acc, mut bits, mut i) = (0u128, 0, 0);
+        for limb in l {
+            acc |= (limb as u128) << bits;
+            bits += 51;
+            while bits >= 8 {
+                out[i] = acc as u8;
+                acc >>= 8;
+                bits -= 8;
+                i += 1;
+            }
+        }
+        out[i] = acc as u8;
+        out
+    }
+
+    fn carry(self) -> Fe {
+        let mut l = self.0;
+        for i in 0..4 {
+            l[i + 1] += l[i] >> 51;
+            l[i] &= MASK;
+        }
+        l[0] += 19 * (l[4] >> 51);
+        l[4] &= MASK;
+        Fe(l)
+    }
+
+    pub(crate) fn square(self) -> Fe {
+        self * self
+    }
+
+    fn pow2k(self, k: u32) -> Fe {
+        (0..k).fold(self, |x, _| x.square())
+    }
+
+    /// (x^(2^250 - 1), x^11), shared by [`Fe::invert`] and [`Fe::pow_p58`].
+    fn pow22501(self) -> (Fe, Fe) {
+        let t0 = self.square();
+        let t2 = self * t0.pow2k(2);
+        let t3 = t0 * t2;
+        let t5 = t2 * t3.square();
+        let t7 = t5.pow2k(5) * t5;
+        let t9 = t7.pow2k(10) * t7;
+        let t11 = t9.pow2k(20) * t9;
+        let t13 = t11.pow2k(10) * t7;
+        let t15 = t13.pow2k(50) * t13;
+        let t17 = t15.pow2k(100) * t15;
+        let t19 = t17.pow2k(50) * t13;
+        (t19, t3)
+    }
+
+    /// x^(p - 2), which is 1/x for non-zero x and 0 for zero.
+    pub(crate) fn invert(self) -> Fe {
+        let (t19, t3) = self.pow22501();
+        t19.pow2k(5) * t3
+    }
+
+    /// x^((p - 5) / 8).
+    fn pow_p58(self) -> Fe {
+        self * self.pow22501().0.pow2k(2)
+    }
+
+    /// A square root of `self` if it is a square (zero included).
+    pub(crate) fn sqrt(self) -> (Choice, Fe) {
+        let beta = self * self.pow_p58();
+        let beta2 = beta.square();
+        let direct = beta2.ct_eq(&self);
+        let flipped = beta2.ct_eq(&-self);
+        let root = Fe::conditional_select(&(beta * SQRT_M1), &beta, direct);
+        (direct | flipped, root)
+    }
+
+    /// Whether `self` is a square, zero included.
+    pub(crate) fn is_square(self) -> Choice {
+        self.sqrt().0
+    }
+
+    /// Of `self` and `-self`, the one that is at most (p - 1) / 2, so the top two bits of
+    /// its encoding are clear.
+    pub(crate) fn abs(self) -> Fe {
+        // 2x wraps around p, making it odd, exactly when x > (p - 1) / 2
+        let high = Choice::from((self + self).to_bytes()[0] & 1);
+        Fe::conditional_select(&self, &-self, high)
+    }
+}
+
+impl ConstantTimeEq for Fe {
+    fn ct_eq(&self, other: &Fe) -> Choice {
+        self.to_bytes().ct_eq(&other.to_bytes())
+    }
+}
+
+impl ConditionallySelectable for Fe {
+    fn conditional_select(a: &Fe, b: &Fe, choice: Choice) -> Fe {
+        let mut out = [0u64; 5];
+        for (i, o) in out.iter_mut().enumerate() {
+            *o = u64::conditional_select(&a.0[i], &b.0[i], choice);
+        }
+        Fe(out)
+    }
+}
+
+impl Add for Fe {
+    type Output = Fe;
+    fn add(self, rhs: Fe) -> Fe {
+        let mut l = self.0;
+        for (a, b) in l.iter_mut().zip(rhs.0) {
+            *a += b;
+        }
+        Fe(l).carry()
+    }
+}
+
+impl Sub for Fe {
+    type Output = Fe;
+    fn sub(self, rhs: Fe) -> Fe {
+        // add 16p first so no limb underflows
+        let rhs = rhs.carry().0;
+        let l = self.carry().0;
+        Fe([
+            (l[0] + 36028797018963664) - rhs[0],
+            (l[1] + 36028797018963952) - rhs[1],
+            (l[2] + 36028797018963952) - rhs[2],
+            (l[3] + 36028797018963952) - rhs[3],
+            (l[4] + 36028797018963952) - rhs[4],
+        ])
+        .carry()
+    }
+}
+
+impl Neg for Fe {
+    type Output = Fe;
+    fn neg(self) -> Fe {
+        Fe::ZERO - self
+    }
+}
+
+impl Mul for Fe {
+    type Output = Fe;
+    fn mul(self, rhs: Fe) -> Fe {
+        let m = |x: u64, y: u64| x as u128 * y as u128;
+        let [a0, a1, a2, a3, a4] = self.0;
+        let [b0, b1, b2, b3, b4] = rhs.0;
+        let (b1_19, b2_19, b3_19, b4_19) = (b1 * 19, b2 * 19, b3 * 19, b4 * 19);
+
+        let c0 = m(a0, b0) + m(a4, b1_19) + m(a3, b2_19) + m(a2, b3_19) + m(a1, b4_19);
+        let mut c1 = m(a1, b0) + m(a0, b1) + m(a4, b2_19) + m(a3, b3_19) + m(a2, b4_19);
+        let mut c2 = m(a2, b0) + m(a1, b1) + m(a0, b2) + m(a4, b3_19) + m(a3, b4_19);
+        let mut c3 = m(a3, b0) + m(a2, b1) + m(a1, b2) + m(a0, b3) + m(a4, b4_19);
+        let mut c4 = m(a4, b0) + m(a3, b1) + m(a2, b2) + m(a1, b3) + m(a0, b4);
+
+        c1 += c0 >> 51;
+        c2 += c1 >> 51;
+        c3 += c2 >> 51;
+        c4 += c3 >> 51;
+        let mut l = [
+            (c0 as u64) & MASK,
+            (c1 as u64) & MASK,
+            (c2 as u64) & MASK,
+            (c3 as u64) & MASK,
+            (c4 as u64) & MASK,
+        ];
+        l[0] += (c4 >> 51) as u64 * 19;
+        l[1] += l[0] >> 51;
+        l[0] &= MASK;
+        Fe(l)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn arithmetic_identities() {
+        let x = Fe::from_bytes(&[0xa5; 32]);
+        let y = Fe::from_u64(486662);
+        assert_eq!((x * x.invert()).to_bytes(), Fe::ONE.to_bytes());
+        assert_eq!((x - y + y).to_bytes(), x.to_bytes());
+        assert_eq!((x + -x).to_bytes(), Fe::ZERO.to_bytes());
+        assert_eq!(SQRT_M1.square().to_bytes(), (-Fe::ONE).to_bytes());
+
+        let (ok, root) = x.square().sqrt();
+        assert!(bool::from(ok));
+        assert_eq!(root.square().to_bytes(), x.square().to_bytes());
+        // 2 is not a square mod p
+        assert!(!bool::from(Fe::from_u64(2).is_square()));
+
+        // p itself and p + 1 are not canonical encodings
+        let mut p = [0xff; 32];
+        p[0] = 0xed;
+        p[31] = 0x7f;
+        assert_eq!(Fe::from_bytes(&p).to_bytes(), [0; 32]);
+        p[0] = 0xee;
+        assert_eq!(Fe::from_bytes(&p).to_bytes(), Fe::ONE.to_bytes());
+    }
+}
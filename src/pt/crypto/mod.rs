@@ -0,0 +1,8 @@
+//! # Crypto
+//!
+//! Building blocks shared by handshake based transports.
+
+pub mod ct;
+mod field;
+pub mod ntor;
+pub mod uniform;
@@ -0,0 +1,388 @@
+//! The ntor handshake from tor's `tor-spec.txt` section 5.1.4.
+//!
+//! A client that knows a server's identity and static X25519 key ([`NodeInfo`]) sends an
+//! ephemeral public key; the server answers with its own and an authenticator proving it
+//! holds the static key. Both sides end up with a [`KeyGenerator`] for the session keys.
+//!
+//! The message layouts here are tor's (`ID | B | X` and `Y | AUTH`). Transports that frame
+//! the keys differently, as obfs4 does with [`Representative`]s, can use [`client_keys`] and
+//! [`server_keys`] directly.
+//!
+//! [`Representative`]: super::uniform::Representative
+
+use super::ct;
+use crate::{Error, Result};
+
+use curve25519_dalek::MontgomeryPoint;
+use hkdf::Hkdf;
+use rand::{CryptoRng, RngCore};
+use sha2::Sha256;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+pub const PROTO_ID: &[u8] = b"ntor-curve25519-sha256-1";
+const T_MAC: &[u8] = b"ntor-curve25519-sha256-1:mac";
+const T_KEY: &[u8] = b"ntor-curve25519-sha256-1:key_extract";
+const T_VERIFY: &[u8] = b"ntor-curve25519-sha256-1:verify";
+const M_EXPAND: &[u8] = b"ntor-curve25519-sha256-1:key_expand";
+
+/// Length of a node identity (an RSA identity digest in tor).
+pub const ID_LEN: usize = 20;
+pub const KEY_LEN: usize = 32;
+pub const AUTH_LEN: usize = 32;
+/// `ID | B | X`
+pub const CLIENT_HANDSHAKE_LEN: usize = ID_LEN + 2 * KEY_LEN;
+/// `Y | AUTH`
+pub const SERVER_HANDSHAKE_LEN: usize = KEY_LEN + AUTH_LEN;
+
+/// What a client knows about the server it is connecting to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NodeInfo {
+    pub id: [u8; ID_LEN],
+    /// The server's static public key, `B`.
+    pub public: [u8; KEY_LEN],
+}
+
+/// A server's identity and static keypair.
+#[derive(Clone)]
+pub struct ServerKeys {
+    node: NodeInfo,
+    secret: [u8; KEY_LEN],
+}
+
+impl ServerKeys {
+    pub fn new(id: [u8; ID_LEN], secret: [u8; KEY_LEN]) -> Self {
+        let public = public_key(&secret);
+        Self {
+            node: NodeInfo { id, public },
+            secret,
+        }
+    }
+
+    pub fn generate<R: RngCore + CryptoRng>(id: [u8; ID_LEN], rng: &mut R) -> Self {
+        Self::new(id, random_secret(rng))
+    }
+
+    /// The half clients need, distributed out of band (e.g. in a bridge line).
+    pub fn node_info(&self) -> &NodeInfo {
+        &self.node
+    }
+}
+
+impl Zeroize for ServerKeys {
+    fn zeroize(&mut self) {
+        self.secret.zeroize();
+    }
+}
+
+impl Drop for ServerKeys {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for ServerKeys {}
+
+impl core::fmt::Debug for ServerKeys {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ServerKeys")
+            .field("node", &self.node)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Session key material both sides derive from a completed handshake.
+pub struct KeyGenerator {
+    /// `KEY_SEED`, kept rather than the HKDF state so that it can be wiped.
+    seed: [u8; 32],
+}
+
+impl KeyGenerator {
+    /// `len` bytes of key material, at most 255 * 32. Wiped when dropped.
+    pub fn expand(&self, len: usize) -> Result<Zeroizing<Vec<u8>>> {
+        let mut out = Zeroizing::new(vec![0u8; len]);
+        Hkdf::<Sha256>::from_prk(&self.seed)
+            .expect("seed is a full sha256 output")
+            .expand(M_EXPAND, &mut out)
+            .map_err(|_| Error::new("ntor: too much key material requested"))?;
+        Ok(out)
+    }
+}
+
+impl Zeroize for KeyGenerator {
+    fn zeroize(&mut self) {
+        self.seed.zeroize();
+    }
+}
+
+impl Drop for KeyGenerator {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for KeyGenerator {}
+
+/// Client state between sending its handshake and receiving the server's.
+pub struct ClientState {
+    node: NodeInfo,
+    secret: [u8; KEY_LEN],
+    public: [u8; KEY_LEN],
+}
+
+impl ClientState {
+    /// Start a handshake with `node`, returning the state and the message to send.
+    pub fn new<R: RngCore + CryptoRng>(
+        node: &NodeInfo,
+        rng: &mut R,
+    ) -> (Self, [u8; CLIENT_HANDSHAKE_LEN]) {
+        Self::with_ephemeral(node, random_secret(rng))
+    }
+
+    /// [`ClientState::new`] with a caller chosen ephemeral secret key.
+    pub fn with_ephemeral(
+        node: &NodeInfo,
+        secret: [u8; KEY_LEN],
+    ) -> (Self, [u8; CLIENT_HANDSHAKE_LEN]) {
+        let public = public_key(&secret);
+        let mut msg = [0u8; CLIENT_HANDSHAKE_LEN];
+        msg[..ID_LEN].copy_from_slice(&node.id);
+        msg[ID_LEN..ID_LEN + KEY_LEN].copy_from_slice(&node.public);
+        msg[ID_LEN + KEY_LEN..].copy_from_slice(&public);
+        let state = ClientState {
+            node: *node,
+            secret,
+            public,
+        };
+        (state, msg)
+    }
+
+    /// Check the server's reply and derive the session keys.
+    pub fn finish(self, msg: &[u8]) -> Result<KeyGenerator> {
+        let msg: &[u8; SERVER_HANDSHAKE_LEN] = msg.try_into().map_err(|_| {
+            Error::Protocol(format!("ntor: server handshake is {} bytes", msg.len()).into())
+        })?;
+        let (y, auth) = msg.split_at(KEY_LEN);
+        let y: [u8; KEY_LEN] = y.try_into().expect("split at KEY_LEN");
+        client_keys(&self.node, &self.secret, &self.public, &y, auth)
+    }
+}
+
+impl Zeroize for ClientState {
+    fn zeroize(&mut self) {
+        self.secret.zeroize();
+    }
+}
+
+impl Drop for ClientState {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for ClientState {}
+
+/// Answer the client handshake `msg`, returning the session keys and the reply to send.
+pub fn server_handshake<R: RngCore + CryptoRng>(
+    keys: &ServerKeys,
+    msg: &[u8],
+    rng: &mut R,
+) -> Result<(KeyGenerator, [u8; SERVER_HANDSHAKE_LEN])> {
+    server_handshake_with_ephemeral(keys, msg, random_secret(rng))
+}
+
+/// [`server_handshake`] with a caller chosen ephemeral secret key.
+pub fn server_handshake_with_ephemeral(
+    keys: &ServerKeys,
+    msg: &[u8],
+    secret: [u8; KEY_LEN],
+) -> Result<(KeyGenerator, [u8; SERVER_HANDSHAKE_LEN])> {
+    let secret = Zeroizing::new(secret);
+    let msg: &[u8; CLIENT_HANDSHAKE_LEN] = msg.try_into().map_err(|_| {
+        Error::Protocol(format!("ntor: client handshake is {} bytes", msg.len()).into())
+    })?;
+    let (node, x) = msg.split_at(ID_LEN + KEY_LEN);
+    let expected = [&keys.node.id[..], &keys.node.public[..]].concat();
+    if !ct::eq(node, &expected) {
+        return Err(Error::HandshakeRejected(
+            "ntor: handshake is for another node".into(),
+        ));
+    }
+    let x: [u8; KEY_LEN] = x.try_into().expect("split at ID_LEN + KEY_LEN");
+
+    let public = public_key(&secret);
+    let (generator, auth) = server_keys(keys, &secret, &public, &x)?;
+    let mut reply = [0u8; SERVER_HANDSHAKE_LEN];
+    reply[..KEY_LEN].copy_from_slice(&public);
+    reply[KEY_LEN..].copy_from_slice(&auth);
+    Ok((generator, reply))
+}
+
+/// Client side key derivation: checks `auth` against ephemeral keys `x`/`X` and the server's
+/// `Y`.
+pub fn client_keys(
+    node: &NodeInfo,
+    x: &[u8; KEY_LEN],
+    x_pub: &[u8; KEY_LEN],
+    y_pub: &[u8; KEY_LEN],
+    auth: &[u8],
+) -> Result<KeyGenerator> {
+    let xy = dh(x, y_pub)?;
+    let xb = dh(x, &node.public)?;
+    let (generator, expected) = derive(node, &xy, &xb, x_pub, y_pub);
+    if !ct::eq(&expected, auth) {
+        return Err(Error::HandshakeRejected(
+            "ntor: server authenticator mismatch".into(),
+        ));
+    }
+    Ok(generator)
+}
+
+/// Server side key derivation for ephemeral keys `y`/`Y` and the client's `X`, returning the
+/// session keys and the authenticator to send.
+pub fn server_keys(
+    keys: &ServerKeys,
+    y: &[u8; KEY_LEN],
+    y_pub: &[u8; KEY_LEN],
+    x_pub: &[u8; KEY_LEN],
+) -> Result<(KeyGenerator, [u8; AUTH_LEN])> {
+    let xy = dh(y, x_pub)?;
+    let xb = dh(&keys.secret, x_pub)?;
+    Ok(derive(&keys.node, &xy, &xb, x_pub, y_pub))
+}
+
+/// secret_input = EXP(Y,x) | EXP(B,x) | ID | B | X | Y | PROTOID, then
+/// KEY_SEED = H(secret_input, t_key) and AUTH = H(verify | ID | B | Y | X | PROTOID |
+/// "Server", t_mac) with verify = H(secret_input, t_verify).
+fn derive(
+    node: &NodeInfo,
+    xy: &[u8; KEY_LEN],
+    xb: &[u8; KEY_LEN],
+    x_pub: &[u8; KEY_LEN],
+    y_pub: &[u8; KEY_LEN],
+) -> (KeyGenerator, [u8; AUTH_LEN]) {
+    let secret_input =
+        Zeroizing::new([&xy[..], xb, &node.id, &node.public, x_pub, y_pub, PROTO_ID].concat());
+    let verify = Zeroizing::new(ct::hmac_sha256(T_VERIFY, &[&secret_input]));
+    let auth = ct::hmac_sha256(
+        T_MAC,
+        &[
+            &*verify,
+            &node.id,
+            &node.public,
+            y_pub,
+            x_pub,
+            PROTO_ID,
+            b"Server",
+        ],
+    );
+    let seed = ct::hmac_sha256(T_KEY, &[&secret_input]);
+    (KeyGenerator { seed }, auth)
+}
+
+/// X25519, rejecting the all zero output a low order point produces. The shared secret is
+/// wiped when dropped.
+fn dh(secret: &[u8; KEY_LEN], public: &[u8; KEY_LEN]) -> Result<Zeroizing<[u8; KEY_LEN]>> {
+    let shared = Zeroizing::new(MontgomeryPoint(*public).mul_clamped(*secret).to_bytes());
+    match ct::is_zero(&*shared) {
+        true => Err(Error::HandshakeRejected(
+            "ntor: peer sent a low order key".into(),
+        )),
+        false => Ok(shared),
+    }
+}
+
+fn public_key(secret: &[u8; KEY_LEN]) -> [u8; KEY_LEN] {
+    MontgomeryPoint::mul_base_clamped(*secret).to_bytes()
+}
+
+fn random_secret<R: RngCore + CryptoRng>(rng: &mut R) -> [u8; KEY_LEN] {
+    let mut secret = [0u8; KEY_LEN];
+    rng.fill_bytes(&mut secret);
+    secret
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rand::Rng;
+
+    fn unhex<const N: usize>(s: &str) -> [u8; N] {
+        hex::decode(s).unwrap().try_into().unwrap()
+    }
+
+    /// Vectors from tor's reference implementation, `src/test/ntor_ref.py`.
+    #[test]
+    fn reference_vectors() -> Result<()> {
+        let b = unhex("4820544f4c4420594f5520444f474954204b454550532048415050454e494e47");
+        let x = unhex("706f6461792069207075742e2e2e2e2e2e2e2e4a454c4c59206f6e2074686973");
+        let y = unhex("70686520737175697272656c2e2e2e2e2e2e2e2e686173206869732067616d65");
+        let id = unhex("69546f6c64596f7541626f75745374616972732e");
+        let client_msg: [u8; CLIENT_HANDSHAKE_LEN] = unhex(
+            "69546f6c64596f7541626f75745374616972732e\
+             ccbc8541904d18af08753eae967874749e6149f873de937f57f8fd903a21c471\
+             e65dfdbef8b2635837fe2cebc086a8096eae3213e6830dc407516083d412b078",
+        );
+        let server_msg: [u8; SERVER_HANDSHAKE_LEN] = unhex(
+            "390480a14362761d6aec1fea840f6e9e928fb2adb7b25c670be1045e35133a37\
+             1cbdf68b89923e1f85e8e18ee6e805ea333fe4849c790ffd2670bd80fec95cc8",
+        );
+        let keys: [u8; 72] = unhex(
+            "0c62dee7f48893370d0ef896758d35729867beef1a5121df80e00f79ed349af3\
+             9b51cae125719182f19d932a667dae1afbf2e336e6910e7822223e763afad0a1\
+             3342157969dc6b79",
+        );
+
+        let server = ServerKeys::new(id, b);
+        let (state, msg) = ClientState::with_ephemeral(server.node_info(), x);
+        assert_eq!(msg, client_msg);
+
+        let (server_gen, reply) = server_handshake_with_ephemeral(&server, &msg, y)?;
+        assert_eq!(reply, server_msg);
+
+        let client_gen = state.finish(&reply)?;
+        assert_eq!(*client_gen.expand(keys.len())?, keys);
+        assert_eq!(*server_gen.expand(keys.len())?, keys);
+        Ok(())
+    }
+
+    #[test]
+    fn key_types_zeroize() {
+        fn wiped_on_drop<T: Zeroize + ZeroizeOnDrop>() {}
+        wiped_on_drop::<ServerKeys>();
+        wiped_on_drop::<ClientState>();
+        wiped_on_drop::<KeyGenerator>();
+
+        let mut keys = ServerKeys::new([1; ID_LEN], [2; KEY_LEN]);
+        keys.zeroize();
+        assert_eq!(keys.secret, [0; KEY_LEN]);
+        let mut generator = KeyGenerator { seed: [3; 32] };
+        generator.zeroize();
+        assert_eq!(generator.seed, [0; 32]);
+    }
+
+    #[test]
+    fn rejects_bad_handshakes() -> Result<()> {
+        let mut rng = Rng::from_seed(4892);
+        let server = ServerKeys::generate([7; ID_LEN], &mut rng);
+        let other = ServerKeys::generate([8; ID_LEN], &mut rng);
+
+        let (_, msg) = ClientState::new(other.node_info(), &mut rng);
+        let err = server_handshake(&server, &msg, &mut rng).err().unwrap();
+        assert_eq!(err.kind(), crate::ErrorKind::HandshakeRejected);
+        let err = server_handshake(&server, &msg[1..], &mut rng)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), crate::ErrorKind::Protocol);
+
+        let (state, msg) = ClientState::new(server.node_info(), &mut rng);
+        let (_, mut reply) = server_handshake(&server, &msg, &mut rng)?;
+        reply[SERVER_HANDSHAKE_LEN - 1] ^= 1;
+        let err = state.finish(&reply).err().unwrap();
+        assert_eq!(err.kind(), crate::ErrorKind::HandshakeRejected);
+
+        // an all zero (low order) ephemeral key
+        let (state, _) = ClientState::new(server.node_info(), &mut rng);
+        assert!(state.finish(&[0; SERVER_HANDSHAKE_LEN]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+//! Elligator2: X25519 public keys encoded as uniformly random strings.
+//!
+//! A public key on the wire is a curve point, and an observer can tell that from random
+//! bytes (about half of all 32 byte strings aren't valid points). Elligator2 maps roughly
+//! half of the points to a [`Representative`] that is indistinguishable from random, so
+//! handshakes like obfs4's send the representative instead. The map is the one in
+//! RFC 9380 section 6.7.1 with Z = 2, the same one obfs4 uses.
+//!
+//! Two details keep the representatives uniform:
+//!
+//! * A clean public key is always in the prime order subgroup, which is detectable after
+//!   decoding. [`Keypair::generate`] adds a random low order point instead; that changes
+//!   nothing about the shared secret because X25519 clamps scalars to multiples of 8.
+//! * The map only produces 254 bit values, so the top two bits are filled with randomness
+//!   and ignored when decoding.
+
+use super::field::Fe;
+
+use curve25519_dalek::{constants::EIGHT_TORSION, EdwardsPoint, MontgomeryPoint};
+use rand::{CryptoRng, RngCore};
+use subtle::{ConditionallySelectable, ConstantTimeEq};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+/// The Montgomery `A` coefficient of curve25519.
+const A: u64 = 486662;
+
+/// Bits of a representative that carry no information.
+const HIGH_BITS: u8 = 0xc0;
+
+/// A public key encoded so it looks like 32 random bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Representative(pub [u8; 32]);
+
+impl Representative {
+    /// Encode the public key `u`, or `None` if it has no representative. Bit 0 of `tweak`
+    /// picks between the two representatives a point has, and its top two bits fill the
+    /// unused top bits of the encoding; callers should pass a random byte.
+    pub fn from_public(u: &[u8; 32], tweak: u8) -> Option<Self> {
+        let u_fe = Fe::from_bytes(u);
+        let a = Fe::from_u64(A);
+        let two = Fe::from_u64(2);
+
+        // invert the map for either candidate w: u itself, or -u - A
+        let r_for_u = -(u_fe + a) * (two * u_fe).invert();
+        let r_for_other = -u_fe * (two * (u_fe + a)).invert();
+        let r2 = Fe::conditional_select(&r_for_u, &r_for_other, (tweak & 1).into());
+        let (is_square, r) = r2.sqrt();
+
+        let mut out = r.abs().to_bytes();
+        out[31] |= tweak & HIGH_BITS;
+        let rep = Representative(out);
+        // a square isn't enough on its own (e.g. for u = -A), so check the forward map
+        let maps_back = rep.to_public().ct_eq(&u_fe.to_bytes());
+        bool::from(is_square & maps_back).then_some(rep)
+    }
+
+    /// Decode to the public key this represents. Every 32 byte string decodes to a point.
+    pub fn to_public(&self) -> [u8; 32] {
+        let mut r = self.0;
+        r[31] &= !HIGH_BITS;
+        let r = Fe::from_bytes(&r);
+        let a = Fe::from_u64(A);
+
+        // w = -A / (1 + 2r^2); u = w if w^3 + Aw^2 + w is square, else -w - A
+        let w = -a * (Fe::ONE + Fe::from_u64(2) * r.square()).invert();
+        let g = w * (w.square() + a * w + Fe::ONE);
+        let u = Fe::conditional_select(&(-w - a), &w, g.is_square());
+        u.to_bytes()
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+/// An X25519 keypair whose public key has a [`Representative`].
+#[derive(Clone)]
+pub struct Keypair {
+    secret: [u8; 32],
+    public: [u8; 32],
+    representative: Representative,
+}
+
+impl Keypair {
+    /// Generate keys until one is representable, which takes two tries on average.
+    pub fn generate<R: RngCore + CryptoRng>(rng: &mut R) -> Self {
+        loop {
+            let mut secret = Zeroizing::new([0u8; 32]);
+            rng.fill_bytes(&mut *secret);
+            let tweak = rng.next_u32() as u8;
+
+            let torsion = EIGHT_TORSION[(tweak >> 1 & 7) as usize];
+            let point = EdwardsPoint::mul_base_clamped(*secret) + torsion;
+            let public = point.to_montgomery().to_bytes();
+            if let Some(representative) = Representative::from_public(&public, tweak) {
+                return Keypair {
+                    secret: *secret,
+                    public,
+                    representative,
+                };
+            }
+        }
+    }
+
+    /// The public key as a curve point, for computing the shared secret.
+    pub fn public(&self) -> &[u8; 32] {
+        &self.public
+    }
+
+    /// The public key as sent on the wire.
+    pub fn representative(&self) -> &Representative {
+        &self.representative
+    }
+
+    /// X25519 with the peer's public key, decoded from its representative if need be. The
+    /// shared secret is wiped when dropped.
+    pub fn diffie_hellman(&self, their_public: &[u8; 32]) -> Zeroizing<[u8; 32]> {
+        Zeroizing::new(
+            MontgomeryPoint(*their_public)
+                .mul_clamped(self.secret)
+                .to_bytes(),
+        )
+    }
+}
+
+impl Zeroize for Keypair {
+    fn zeroize(&mut self) {
+        self.secret.zeroize();
+    }
+}
+
+impl Drop for Keypair {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for Keypair {}
+
+impl core::fmt::Debug for Keypair {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Keypair")
+            .field("public", &self.public)
+            .field("representative", &self.representative)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rand::Rng;
+
+    fn hex(s: &str) -> [u8; 32] {
+        hex::decode(s).unwrap().try_into().unwrap()
+    }
+
+    /// Vectors from an independent big integer implementation of RFC 9380 section 6.7.1
+    /// (curve25519, Z = 2); the top two bits of each representative are ignored.
+    const FORWARD: [(&str, &str); 5] = [
+        (
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+        ),
+        (
+            "0100000000000000000000000000000000000000000000000000000000000000",
+            "9cdb525555555555555555555555555555555555555555555555555555555555",
+        ),
+        (
+            "39521f2b9a87edaeebe3410f2fc41601ad0f508aba597cff24884bd198f8962c",
+            "f66120f3b9784da43063d77b4627dc509d23a037cceb30f95b0df4dfaa9dc720",
+        ),
+        (
+            "072a64df02dd55620520c0a431841fcbaed46d7903d375430c71371a111c9291",
+            "7e55954c1ba85d8918535890d55392e355faa14fcb58215a738b513e6d6aa10b",
+        ),
+        (
+            "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+            "80e5132b658f7f451b2b658f7f451b2b658f7f451b2b658f7f451b2b658f7f45",
+        ),
+    ];
+
+    #[test]
+    fn known_answers() {
+        for (rep, public) in FORWARD {
+            assert_eq!(Representative(hex(rep)).to_public(), hex(public), "{rep}");
+        }
+
+        let u = hex("7e55954c1ba85d8918535890d55392e355faa14fcb58215a738b513e6d6aa10b");
+        let reps = [
+            (
+                0x00,
+                "072a64df02dd55620520c0a431841fcbaed46d7903d375430c71371a111c9211",
+            ),
+            (
+                0x01,
+                "b9ac7abb876b08897fc1501e1f2091d3cc4b14e1f02d60a2019a0adb64c85e01",
+            ),
+            (
+                0xc0,
+                "072a64df02dd55620520c0a431841fcbaed46d7903d375430c71371a111c92d1",
+            ),
+            (
+                0x41,
+                "b9ac7abb876b08897fc1501e1f2091d3cc4b14e1f02d60a2019a0adb64c85e41",
+            ),
+        ];
+        for (tweak, rep) in reps {
+            assert_eq!(
+                Representative::from_public(&u, tweak),
+                Some(Representative(hex(rep)))
+            );
+        }
+
+        // u = 18 is on the curve but outside the image of the map
+        let mut u = [0u8; 32];
+        u[0] = 18;
+        assert_eq!(Representative::from_public(&u, 0), None);
+        assert_eq!(Representative::from_public(&u, 1), None);
+    }
+
+    #[test]
+    fn keypairs_agree_and_round_trip() {
+        let mut rng = Rng::from_seed(4891);
+        for _ in 0..16 {
+            let alice = Keypair::generate(&mut rng);
+            let bob = Keypair::generate(&mut rng);
+            assert_eq!(&alice.representative().to_public(), alice.public());
+
+            let from_wire = bob.representative().to_public();
+            let shared = alice.diffie_hellman(&from_wire);
+            assert_eq!(shared, bob.diffie_hellman(alice.public()));
+            // the low order component doesn't change the result
+            let clean = MontgomeryPoint::mul_base_clamped(bob.secret);
+            assert_eq!(shared, alice.diffie_hellman(&clean.to_bytes()));
+        }
+    }
+
+    #[test]
+    fn keypair_zeroizes() {
+        fn wiped_on_drop<T: Zeroize + ZeroizeOnDrop>() {}
+        wiped_on_drop::<Keypair>();
+
+        let mut keys = Keypair::generate(&mut Rng::from_seed(1));
+        keys.zeroize();
+        assert_eq!(keys.secret, [0; 32]);
+    }
+}
@@ -0,0 +1,363 @@
+//! # FTE
+//!
+//! Format transforming encoding, as in fteproxy: bytes are carried in strings of a fixed
+//! length that match a regular expression, so the wire follows whatever grammar the regex
+//! describes. The strings of that length accepted by the regex's DFA are counted, which
+//! orders them; a block of input is read as an integer and replaced by the string at that
+//! position (unranking), and the receiver recovers the integer from the string's position
+//! (ranking).
+//!
+//! Strings map one to one onto integers, so they are only as uniformly distributed as the
+//! input. Put an encrypting transport underneath when that matters.
+
+use crate::{codec, Error, Result};
+
+use num_bigint::BigUint;
+use regex_automata::{
+    dfa::{dense, Automaton, StartKind},
+    nfa::thompson,
+    util::{start, syntax},
+    Anchored, MatchKind,
+};
+
+use alloc::sync::Arc;
+use std::collections::HashMap;
+
+/// Largest DFA accepted, in states; each costs a count per string position.
+const MAX_STATES: usize = 4096;
+/// Longest strings accepted.
+pub const MAX_LEN: usize = 4096;
+
+/// Bytes `lo..=hi` lead to state `next`.
+#[derive(Clone, Copy, Debug)]
+struct Run {
+    lo: u8,
+    hi: u8,
+    next: usize,
+}
+
+impl Run {
+    fn width(&self) -> u32 {
+        (self.hi - self.lo) as u32 + 1
+    }
+}
+
+/// The strings of one length matched by a regular expression.
+#[derive(Debug)]
+pub struct Language {
+    /// Live transitions of each state, in byte order; missing bytes lead nowhere.
+    runs: Vec<Vec<Run>>,
+    /// `counts[k][s]` is the number of accepted strings of length `k` starting from `s`.
+    counts: Vec<Vec<BigUint>>,
+    len: usize,
+    capacity: usize,
+}
+
+impl Language {
+    /// The strings of exactly `len` bytes that `regex` matches in full. The regex works on
+    /// bytes, not unicode, e.g. `.` is any byte but `\n`.
+    pub fn new(regex: &str, len: usize) -> Result<Self> {
+        if len == 0 || len > MAX_LEN {
+            return Err(config(format!("length {len} is not in 1..={MAX_LEN}")));
+        }
+        let runs = transitions(regex)?;
+        let accept: Vec<bool> = runs.iter().map(|(accept, _)| *accept).collect();
+        let runs: Vec<Vec<Run>> = runs.into_iter().map(|(_, runs)| runs).collect();
+
+        let mut counts = vec![accept
+            .iter()
+            .map(|&a| BigUint::from(a as u8))
+            .collect::<Vec<_>>()];
+        for k in 1..=len {
+            let prev = &counts[k - 1];
+            let row = runs
+                .iter()
+                .map(|state| {
+                    state
+                        .iter()
+                        .map(|run| &prev[run.next] * run.width())
+                        .sum::<BigUint>()
+                })
+                .collect();
+            counts.push(row);
+        }
+
+        // whole bytes such that every value of that many bytes has a string
+        let bits = counts[len][0].bits();
+        let capacity = (bits.saturating_sub(1) / 8) as usize;
+        if capacity < 2 {
+            return Err(config(format!(
+                "\"{regex}\" has too few strings of length {len} to carry data"
+            )));
+        }
+        Ok(Self {
+            runs,
+            counts,
+            len,
+            capacity,
+        })
+    }
+
+    /// Length of every string in the language.
+    pub fn string_len(&self) -> usize {
+        self.len
+    }
+
+    /// Bytes each string can carry: [`Language::encode`] takes blocks of this size.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The string for a block of [`Language::capacity`] bytes.
+    pub fn encode(&self, block: &[u8]) -> Vec<u8> {
+        debug_assert_eq!(block.len(), self.capacity);
+        self.unrank(BigUint::from_bytes_be(block))
+            .expect("a block is smaller than the language")
+    }
+
+    /// The block a string carries, or `None` if it isn't in the language or carries none.
+    pub fn decode(&self, s: &[u8]) -> Option<Vec<u8>> {
+        let value = self.rank(s)?.to_bytes_be();
+        let pad = self.capacity.checked_sub(value.len())?;
+        let mut block = vec![0u8; pad];
+        block.extend_from_slice(&value);
+        Some(block)
+    }
+
+    fn unrank(&self, mut rank: BigUint) -> Option<Vec<u8>> {
+        let mut out = Vec::with_capacity(self.len);
+        let mut state = 0;
+        for remaining in (0..self.len).rev() {
+            let counts = &self.counts[remaining];
+            let mut found = None;
+            for run in &self.runs[state] {
+                let each = &counts[run.next];
+                let total = each * run.width();
+                if rank < total {
+                    let offset = &rank / each;
+                    rank -= &offset * each;
+                    let offset = u8::try_from(&offset).expect("offset within a run");
+                    found = Some((run.lo + offset, run.next));
+                    break;
+                }
+                rank -= total;
+            }
+            let (byte, next) = found?;
+            out.push(byte);
+            state = next;
+        }
+        Some(out)
+    }
+
+    fn rank(&self, s: &[u8]) -> Option<BigUint> {
+        if s.len() != self.len {
+            return None;
+        }
+        let mut rank = BigUint::ZERO;
+        let mut state = 0;
+        for (i, &b) in s.iter().enumerate() {
+            let counts = &self.counts[self.len - i - 1];
+            let mut next = None;
+            for run in &self.runs[state] {
+                if b > run.hi {
+                    rank += &counts[run.next] * run.width();
+                    continue;
+                }
+                if b >= run.lo {
+                    rank += &counts[run.next] * (b - run.lo) as u32;
+                    next = Some(run.next);
+                }
+                break;
+            }
+            state = next?;
+        }
+        (self.counts[0][state] == BigUint::from(1u8)).then_some(rank)
+    }
+}
+
+/// Compile `regex` to a DFA and return, for each state reachable from the start (which is
+/// state 0), whether it accepts and its live transitions.
+fn transitions(regex: &str) -> Result<Vec<(bool, Vec<Run>)>> {
+    let dfa = dense::Builder::new()
+        .configure(
+            dense::Config::new()
+                .match_kind(MatchKind::All)
+                .start_kind(StartKind::Anchored),
+        )
+        .syntax(syntax::Config::new().unicode(false).utf8(false))
+        .thompson(thompson::Config::new().utf8(false))
+        .build(&format!("(?:{regex})$"))
+        .map_err(|e| config(format!("bad regex \"{regex}\": {e}")))?;
+    let start = dfa
+        .start_state(&start::Config::new().anchored(Anchored::Yes))
+        .map_err(|e| config(format!("bad regex \"{regex}\": {e}")))?;
+
+    let mut ids = vec![start];
+    let mut index = HashMap::from([(start, 0)]);
+    let mut states = vec![];
+    while let Some(&id) = ids.get(states.len()) {
+        let accept = dfa.is_match_state(dfa.next_eoi_state(id));
+        let mut runs: Vec<Run> = vec![];
+        for b in 0..=255u8 {
+            let next = dfa.next_state(id, b);
+            if dfa.is_dead_state(next) || dfa.is_quit_state(next) {
+                continue;
+            }
+            let next = *index.entry(next).or_insert_with(|| {
+                ids.push(next);
+                ids.len() - 1
+            });
+            match runs.last_mut() {
+                Some(run) if run.next == next && run.hi as u16 + 1 == b as u16 => run.hi = b,
+                _ => runs.push(Run { lo: b, hi: b, next }),
+            }
+        }
+        states.push((accept, runs));
+        if ids.len() > MAX_STATES {
+            return Err(config(format!("\"{regex}\" needs too large a DFA")));
+        }
+    }
+    Ok(states)
+}
+
+fn config(msg: String) -> Error {
+    Error::Config(msg.into())
+}
+
+/// Each string carries a length byte followed by that much data, padded with zeros.
+pub(crate) fn payload_per_string(lang: &Language) -> usize {
+    (lang.capacity() - 1).min(u8::MAX as usize)
+}
+
+/// Turns input into strings of the language, one per [`Language::capacity`] - 1 bytes (or
+/// fewer, for the end of a chunk).
+#[derive(Clone, Debug)]
+pub struct Encode {
+    lang: Arc<Language>,
+}
+
+impl Encode {
+    pub fn new(lang: Arc<Language>) -> Self {
+        Self { lang }
+    }
+}
+
+impl codec::ChunkTransform for Encode {
+    fn transform(&mut self, input: &[u8], out: &mut Vec<u8>) -> codec::Result<()> {
+        let mut block = vec![0u8; self.lang.capacity()];
+        for data in input.chunks(payload_per_string(&self.lang)) {
+            block.fill(0);
+            block[0] = data.len() as u8;
+            block[1..=data.len()].copy_from_slice(data);
+            out.extend_from_slice(&self.lang.encode(&block));
+        }
+        Ok(())
+    }
+}
+
+/// Reverses [`Encode`], holding partial strings until the rest arrives.
+#[derive(Clone, Debug)]
+pub struct Decode {
+    lang: Arc<Language>,
+    pending: Vec<u8>,
+}
+
+impl Decode {
+    pub fn new(lang: Arc<Language>) -> Self {
+        Self {
+            lang,
+            pending: vec![],
+        }
+    }
+}
+
+impl codec::ChunkTransform for Decode {
+    fn transform(&mut self, input: &[u8], out: &mut Vec<u8>) -> codec::Result<()> {
+        self.pending.extend_from_slice(input);
+        let n = self.lang.string_len();
+        let whole = self.pending.len() - self.pending.len() % n;
+        for s in self.pending[..whole].chunks(n) {
+            let block = self
+                .lang
+                .decode(s)
+                .ok_or(codec::Error::InvalidData("fte string"))?;
+            let len = block[0] as usize;
+            if len > payload_per_string(&self.lang) {
+                return Err(codec::Error::InvalidData("fte block length"));
+            }
+            out.extend_from_slice(&block[1..=len]);
+        }
+        self.pending.drain(..whole);
+        Ok(())
+    }
+
+    fn finish(&mut self, _out: &mut Vec<u8>) -> codec::Result<()> {
+        match self.pending.is_empty() {
+            true => Ok(()),
+            false => Err(codec::Error::Truncated("fte string")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::codec::{apply, ChunkTransform};
+
+    #[test]
+    fn ranks_in_lexicographic_order() {
+        let lang = Language::new("[ab]+", 16).unwrap();
+        assert_eq!(lang.counts[16][0], BigUint::from(1u32 << 16));
+        assert_eq!(lang.capacity(), 2);
+
+        let first = lang.unrank(BigUint::ZERO).unwrap();
+        let fifth = lang.unrank(BigUint::from(5u8)).unwrap();
+        let last = lang.unrank(BigUint::from(u16::MAX)).unwrap();
+        assert_eq!(first, b"aaaaaaaaaaaaaaaa");
+        assert_eq!(fifth, b"aaaaaaaaaaaaabab");
+        assert_eq!(last, b"bbbbbbbbbbbbbbbb");
+        for i in [0u32, 5, 1000, 65535] {
+            let s = lang.unrank(BigUint::from(i)).unwrap();
+            assert_eq!(lang.rank(&s), Some(BigUint::from(i)));
+        }
+        assert_eq!(lang.unrank(BigUint::from(1u32 << 16)), None);
+        assert_eq!(lang.rank(b"aaaaaaaaaaaaabca"), None);
+        assert_eq!(lang.rank(b"aaaa"), None);
+    }
+
+    #[test]
+    fn round_trips_through_a_grammar() -> codec::Result<()> {
+        let lang = Arc::new(Language::new(r"GET /[a-z0-9/]+ HTTP/1\.1", 64).unwrap());
+        assert_eq!(lang.capacity(), 32);
+        let data: Vec<u8> = (0..=255).collect();
+
+        let wire = apply(&mut Encode::new(lang.clone()), &data)?;
+        assert_eq!(wire.len() % 64, 0);
+        for s in wire.chunks(64) {
+            assert!(
+                s.starts_with(b"GET /") && s.ends_with(b" HTTP/1.1"),
+                "{s:?}"
+            );
+        }
+
+        let mut dec = Decode::new(lang.clone());
+        let mut out = vec![];
+        for piece in wire.chunks(7) {
+            dec.transform(piece, &mut out)?;
+        }
+        dec.finish(&mut out)?;
+        assert_eq!(out, data);
+
+        let mut bad = wire[..64].to_vec();
+        bad[0] = b'P';
+        assert!(apply(&mut Decode::new(lang), &bad).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_unusable_languages() {
+        assert!(Language::new("[a", 8).is_err());
+        assert!(Language::new("a{8}", 8).is_err());
+        assert!(Language::new("[a-z]+", 0).is_err());
+    }
+}
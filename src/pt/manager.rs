@@ -0,0 +1,460 @@
+//! Launch and manage external pluggable transport processes (e.g. lyrebird), acting as the
+//! parent ("tor side") of the managed transport interface.
+//!
+//! The manager sets the `TOR_PT_*` environment for the child, reads its stdout using
+//! [`parser`](crate::parser) until the transport reports that its methods are done, and returns
+//! the endpoints the transport opened.
+
+use crate::parser::{parse_line, ProxyProtocol, PtLine};
+use crate::{Error, Result};
+
+use tokio::io::{AsyncBufReadExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tracing::{debug, trace};
+
+use std::ffi::OsString;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+
+/// Default time to wait for a transport to report its methods.
+pub const DEFAULT_LAUNCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Configuration for launching a transport in client mode.
+#[derive(Clone, Debug, Default)]
+pub struct ClientConfig {
+    /// Transports the child should launch (`TOR_PT_CLIENT_TRANSPORTS`).
+    pub transports: Vec<String>,
+    /// Upstream proxy URL the child should dial through (`TOR_PT_PROXY`).
+    pub proxy: Option<String>,
+}
+
+/// Configuration for launching a transport in server mode.
+#[derive(Clone, Debug, Default)]
+pub struct ServerConfig {
+    /// Transports the child should launch (`TOR_PT_SERVER_TRANSPORTS`).
+    pub transports: Vec<String>,
+    /// Address each transport should listen on (`TOR_PT_SERVER_BINDADDR`).
+    pub bindaddrs: Vec<(String, SocketAddr)>,
+    /// Per-transport options as `(transport, key, value)` (`TOR_PT_SERVER_TRANSPORT_OPTIONS`).
+    pub options: Vec<(String, String, String)>,
+    /// Where revealed connections should be forwarded (`TOR_PT_ORPORT`).
+    pub orport: Option<SocketAddr>,
+    /// Extended ORPort to forward to instead of the ORPort (`TOR_PT_EXTENDED_SERVER_PORT`).
+    pub extended_orport: Option<SocketAddr>,
+    /// Cookie file used to authenticate to the Extended ORPort (`TOR_PT_AUTH_COOKIE_FILE`).
+    pub auth_cookie_file: Option<PathBuf>,
+}
+
+/// A SOCKS endpoint opened by a client transport.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientMethod {
+    pub transport: String,
+    pub protocol: ProxyProtocol,
+    pub addr: SocketAddr,
+}
+
+/// A listener opened by a server transport along with the args clients need to connect.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerMethod {
+    pub transport: String,
+    pub addr: SocketAddr,
+    pub args: Vec<(String, String)>,
+}
+
+/// Result of launching a transport: the methods it opened, the methods it failed to open as
+/// `(transport, message)` pairs, and a handle to the running process.
+pub struct Launched<M> {
+    pub methods: Vec<M>,
+    pub errors: Vec<(String, String)>,
+    pub process: ManagedPt,
+}
+
+/// Launches external pluggable transport binaries.
+#[derive(Clone, Debug)]
+pub struct Manager {
+    program: PathBuf,
+    args: Vec<OsString>,
+    state_location: PathBuf,
+    exit_on_stdin_close: bool,
+    timeout: Duration,
+}
+
+impl Manager {
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        Self {
+            program: program.into(),
+            args: vec![],
+            state_location: std::env::temp_dir().join("ptrs-state"),
+            exit_on_stdin_close: true,
+            timeout: DEFAULT_LAUNCH_TIMEOUT,
+        }
+    }
+
+    /// Add a command line argument passed to the transport binary.
+    pub fn arg(mut self, arg: impl Into<OsString>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Directory the transport may use for persistent state (`TOR_PT_STATE_LOCATION`).
+    pub fn state_location(mut self, path: impl Into<PathBuf>) -> Self {
+        self.state_location = path.into();
+        self
+    }
+
+    /// Whether the transport should exit when its stdin is closed
+    /// (`TOR_PT_EXIT_ON_STDIN_CLOSE`). Enabled by default.
+    pub fn exit_on_stdin_close(mut self, enabled: bool) -> Self {
+        self.exit_on_stdin_close = enabled;
+        self
+    }
+
+    /// How long to wait for the transport to report its methods.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Launch the transport in client mode and wait for it to report its SOCKS endpoints.
+    pub async fn launch_client(&self, config: &ClientConfig) -> Result<Launched<ClientMethod>> {
+        let mut cmd = self.command();
+        cmd.env("TOR_PT_CLIENT_TRANSPORTS", config.transports.join(","));
+        if let Some(proxy) = &config.proxy {
+            cmd.env("TOR_PT_PROXY", proxy);
+        }
+
+        let mut process = ManagedPt::spawn(cmd)?;
+        let mut proxy_pending = config.proxy.is_some();
+        let (methods, errors) = self
+            .read_methods(&mut process, |line, methods, errors| match line {
+                PtLine::Cmethod {
+                    transport,
+                    protocol,
+                    addr,
+                } => {
+                    methods.push(ClientMethod {
+                        transport,
+                        protocol,
+                        addr,
+                    });
+                    Ok(false)
+                }
+                PtLine::CmethodError { transport, message } => {
+                    errors.push((transport, message));
+                    Ok(false)
+                }
+                PtLine::ProxyDone => {
+                    proxy_pending = false;
+                    Ok(false)
+                }
+                PtLine::ProxyError(msg) => Err(launch_error("PROXY-ERROR", msg)),
+                PtLine::CmethodsDone if proxy_pending => {
+                    Err(launch_error("PROXY-ERROR", "proxy was not acknowledged"))
+                }
+                PtLine::CmethodsDone => Ok(true),
+                _ => Ok(false),
+            })
+            .await?;
+
+        Ok(Launched {
+            methods,
+            errors,
+            process,
+        })
+    }
+
+    /// Launch the transport in server mode and wait for it to report its listeners.
+    pub async fn launch_server(&self, config: &ServerConfig) -> Result<Launched<ServerMethod>> {
+        let mut cmd = self.command();
+        cmd.env("TOR_PT_SERVER_TRANSPORTS", config.transports.join(","));
+        if !config.bindaddrs.is_empty() {
+            let bindaddrs: Vec<String> = config
+                .bindaddrs
+                .iter()
+                .map(|(name, addr)| format!("{name}-{addr}"))
+                .collect();
+            cmd.env("TOR_PT_SERVER_BINDADDR", bindaddrs.join(","));
+        }
+        if !config.options.is_empty() {
+            let options: Vec<String> = config
+                .options
+                .iter()
+                .map(|(t, k, v)| format!("{}:{}={}", escape_opt(t), escape_opt(k), escape_opt(v)))
+                .collect();
+            cmd.env("TOR_PT_SERVER_TRANSPORT_OPTIONS", options.join(";"));
+        }
+        if let Some(orport) = config.orport {
+            cmd.env("TOR_PT_ORPORT", orport.to_string());
+        }
+        if let Some(ext) = config.extended_orport {
+            cmd.env("TOR_PT_EXTENDED_SERVER_PORT", ext.to_string());
+        }
+        if let Some(cookie) = &config.auth_cookie_file {
+            cmd.env("TOR_PT_AUTH_COOKIE_FILE", cookie);
+        }
+
+        let mut process = ManagedPt::spawn(cmd)?;
+        let (methods, errors) = self
+            .read_methods(&mut process, |line, methods, errors| match line {
+                PtLine::Smethod {
+                    transport,
+                    addr,
+                    args,
+                } => {
+                    methods.push(ServerMethod {
+                        transport,
+                        addr,
+                        args,
+                    });
+                    Ok(false)
+                }
+                PtLine::SmethodError { transport, message } => {
+                    errors.push((transport, message));
+                    Ok(false)
+                }
+                PtLine::SmethodsDone => Ok(true),
+                _ => Ok(false),
+            })
+            .await?;
+
+        Ok(Launched {
+            methods,
+            errors,
+            process,
+        })
+    }
+
+    fn command(&self) -> Command {
+        let mut cmd = Command::new(&self.program);
+        cmd.args(&self.args)
+            .env("TOR_PT_MANAGED_TRANSPORT_VER", "1")
+            .env("TOR_PT_STATE_LOCATION", &self.state_location)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true);
+        if self.exit_on_stdin_close {
+            cmd.env("TOR_PT_EXIT_ON_STDIN_CLOSE", "1");
+        }
+        cmd
+    }
+
+    /// Read lines from the child until `handle` reports that the method list is done, handling
+    /// the version negotiation and errors common to both modes.
+    async fn read_methods<M, F>(
+        &self,
+        process: &mut ManagedPt,
+        mut handle: F,
+    ) -> Result<(Vec<M>, Vec<(String, String)>)>
+    where
+        F: FnMut(PtLine, &mut Vec<M>, &mut Vec<(String, String)>) -> Result<bool>,
+    {
+        let (mut methods, mut errors) = (vec![], vec![]);
+        let read = async {
+            let mut version = false;
+            loop {
+                let line = process.next_line().await?.ok_or_else(|| {
+                    launch_error("launch", "transport exited before reporting its methods")
+                })?;
+                match line {
+                    PtLine::Version(v) if v == "1" => version = true,
+                    PtLine::Version(v) => {
+                        return Err(launch_error("VERSION", format!("unsupported version {v}")))
+                    }
+                    PtLine::VersionError(msg) => return Err(launch_error("VERSION-ERROR", msg)),
+                    PtLine::EnvError(msg) => return Err(launch_error("ENV-ERROR", msg)),
+                    line if !version => {
+                        return Err(launch_error(
+                            "VERSION",
+                            format!("expected VERSION, got {line:?}"),
+                        ))
+                    }
+                    line => {
+                        if handle(line, &mut methods, &mut errors)? {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        };
+        tokio::time::timeout(self.timeout, read)
+            .await
+            .map_err(|_| Error::Timeout)??;
+
+        Ok((methods, errors))
+    }
+}
+
+/// A running managed transport process. The process is killed when this is dropped.
+pub struct ManagedPt {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    lines: Lines<BufReader<ChildStdout>>,
+}
+
+impl ManagedPt {
+    fn spawn(mut cmd: Command) -> Result<Self> {
+        let mut child = cmd.spawn()?;
+        let stdin = child.stdin.take();
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| launch_error("launch", "transport stdout unavailable"))?;
+        debug!("launched managed transport pid={:?}", child.id());
+        Ok(Self {
+            child,
+            stdin,
+            lines: BufReader::new(stdout).lines(),
+        })
+    }
+
+    /// Process id of the transport, if it is still running.
+    pub fn id(&self) -> Option<u32> {
+        self.child.id()
+    }
+
+    /// Read the next line the transport writes (e.g. `LOG` and `STATUS` lines once it is
+    /// running). Returns `None` once the transport closes its stdout.
+    pub async fn next_line(&mut self) -> Result<Option<PtLine>> {
+        match self.lines.next_line().await? {
+            Some(line) => {
+                trace!("managed transport: {line}");
+                Ok(Some(parse_line(&line)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Ask the transport to exit by closing its stdin, killing it if it has not exited within
+    /// `grace`.
+    pub async fn shutdown(mut self, grace: Duration) -> Result<()> {
+        drop(self.stdin.take());
+        if tokio::time::timeout(grace, self.child.wait())
+            .await
+            .is_err()
+        {
+            self.child.kill().await?;
+        }
+        Ok(())
+    }
+}
+
+fn launch_error(stage: &str, msg: impl std::fmt::Display) -> Error {
+    Error::Other(format!("managed transport {stage}: {msg}").into())
+}
+
+/// Escape the separators used in `TOR_PT_SERVER_TRANSPORT_OPTIONS`.
+fn escape_opt(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '\\' | ':' | ';' | '=') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A manager that runs `script` with `sh` in place of a transport binary.
+    fn fake_pt(script: &str) -> Manager {
+        Manager::new("sh")
+            .arg("-c")
+            .arg(script)
+            .timeout(Duration::from_secs(5))
+    }
+
+    #[tokio::test]
+    async fn launch_client() -> Result<()> {
+        let script = r#"
+            echo "VERSION 1"
+            for t in $(echo "$TOR_PT_CLIENT_TRANSPORTS" | tr , ' '); do
+                echo "CMETHOD $t socks5 127.0.0.1:1080"
+            done
+            echo "CMETHOD-ERROR broken not today"
+            echo "CMETHODS DONE"
+            echo "LOG SEVERITY=notice MESSAGE=\"$TOR_PT_EXIT_ON_STDIN_CLOSE\""
+            cat > /dev/null
+        "#;
+        let config = ClientConfig {
+            transports: vec!["hex".into(), "base64".into()],
+            proxy: None,
+        };
+        let mut launched = fake_pt(script).launch_client(&config).await?;
+
+        let names: Vec<_> = launched.methods.iter().map(|m| &m.transport).collect();
+        assert_eq!(names, ["hex", "base64"]);
+        assert_eq!(launched.methods[0].protocol, ProxyProtocol::Socks5);
+        assert_eq!(launched.methods[0].addr, "127.0.0.1:1080".parse().unwrap());
+        assert_eq!(
+            launched.errors,
+            [("broken".to_string(), "not today".to_string())]
+        );
+
+        let log = launched.process.next_line().await?;
+        assert!(matches!(log, Some(PtLine::Log { message, .. }) if message == "1"));
+
+        launched.process.shutdown(Duration::from_secs(5)).await
+    }
+
+    #[tokio::test]
+    async fn launch_server() -> Result<()> {
+        let script = r#"
+            echo "VERSION 1"
+            echo "SMETHOD hex ${TOR_PT_SERVER_BINDADDR#hex-} ARGS:opts=$TOR_PT_SERVER_TRANSPORT_OPTIONS"
+            echo "SMETHODS DONE"
+        "#;
+        let config = ServerConfig {
+            transports: vec!["hex".into()],
+            bindaddrs: vec![("hex".into(), "127.0.0.1:4430".parse().unwrap())],
+            options: vec![("hex".into(), "case".into(), "upper".into())],
+            orport: Some("127.0.0.1:9001".parse().unwrap()),
+            ..Default::default()
+        };
+        let launched = fake_pt(script).launch_server(&config).await?;
+        assert_eq!(
+            launched.methods,
+            [ServerMethod {
+                transport: "hex".into(),
+                addr: "127.0.0.1:4430".parse().unwrap(),
+                args: vec![("opts".into(), "hex:case=upper".into())],
+            }]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn launch_failures() {
+        let cases = [
+            "echo 'VERSION-ERROR no-version'",
+            "echo 'ENV-ERROR missing TOR_PT_STATE_LOCATION'",
+            "echo 'CMETHODS DONE'",
+            "echo 'VERSION 1'",
+            "echo 'VERSION 1'; sleep 10",
+        ];
+        for script in cases {
+            let manager = fake_pt(script).timeout(Duration::from_millis(500));
+            let result = manager.launch_client(&ClientConfig::default()).await;
+            assert!(result.is_err(), "{script}");
+        }
+
+        let config = ClientConfig {
+            transports: vec!["hex".into()],
+            proxy: Some("socks5://127.0.0.1:1".into()),
+        };
+        let script = "echo 'VERSION 1'; echo 'PROXY-ERROR unreachable'";
+        assert!(fake_pt(script).launch_client(&config).await.is_err());
+        let script = "echo 'VERSION 1'; echo 'PROXY DONE'; echo 'CMETHODS DONE'";
+        assert!(fake_pt(script).launch_client(&config).await.is_ok());
+    }
+
+    #[test]
+    fn escape_options() {
+        assert_eq!(escape_opt(r"a;b:c=d\e"), r"a\;b\:c\=d\\e");
+    }
+}
@@ -2,8 +2,39 @@
 //!
 
 pub(crate) mod copy_buffer;
+#[cfg(target_os = "linux")]
+pub(crate) mod splice;
 
+pub mod bridge_stats;
 pub mod conversion;
 pub mod copy;
+#[cfg(feature = "experimental")]
+#[doc(hidden)]
+pub mod cover;
+#[cfg(feature = "crypto")]
+pub mod crypto;
+#[cfg(feature = "fte")]
+pub mod fte;
+pub mod manager;
+pub mod parser;
+pub mod proxy_dialer;
+pub mod reconnect;
+#[cfg(feature = "experimental")]
+#[doc(hidden)]
+pub mod replay;
+pub mod retry;
+#[cfg(feature = "experimental")]
+#[doc(hidden)]
+pub mod rotation;
+#[cfg(feature = "experimental")]
+#[doc(hidden)]
+pub mod session;
+pub mod shutdown;
+#[cfg(feature = "experimental")]
+#[doc(hidden)]
+pub mod state;
+pub mod status;
 pub mod transform;
 pub mod wrap;
+
+pub use shutdown::exit_on_stdin_close;
@@ -0,0 +1,426 @@
+//! Parser for the lines a managed pluggable transport writes to stdout for its parent (tor)
+//! process, as described in the [pt-spec](https://spec.torproject.org/pt-spec/).
+//!
+//! This lets Rust applications play the "tor side" of the managed transport interface, and
+//! lets tests round-trip the lines a transport emits.
+
+use crate::{Error, Result};
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+/// Proxy protocol a client transport exposes through a `CMETHOD` line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProxyProtocol {
+    Socks4,
+    Socks5,
+}
+
+impl FromStr for ProxyProtocol {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "socks4" => Ok(ProxyProtocol::Socks4),
+            "socks5" => Ok(ProxyProtocol::Socks5),
+            _ => Err(parse_error(format!("unknown proxy protocol \"{s}\""))),
+        }
+    }
+}
+
+impl fmt::Display for ProxyProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProxyProtocol::Socks4 => write!(f, "socks4"),
+            ProxyProtocol::Socks5 => write!(f, "socks5"),
+        }
+    }
+}
+
+/// Severity of a `LOG` line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Debug,
+    Info,
+    Notice,
+    Warning,
+    Error,
+}
+
+impl FromStr for Severity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "debug" => Ok(Severity::Debug),
+            "info" => Ok(Severity::Info),
+            "notice" => Ok(Severity::Notice),
+            "warning" => Ok(Severity::Warning),
+            "error" => Ok(Severity::Error),
+            _ => Err(parse_error(format!("unknown log severity \"{s}\""))),
+        }
+    }
+}
+
+/// A single line written by a pluggable transport to its parent process.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PtLine {
+    /// `VERSION <version>`
+    Version(String),
+    /// `VERSION-ERROR <message>`
+    VersionError(String),
+    /// `ENV-ERROR <message>`
+    EnvError(String),
+    /// `CMETHOD <transport> <socks4|socks5> <address:port>`
+    Cmethod {
+        transport: String,
+        protocol: ProxyProtocol,
+        addr: SocketAddr,
+    },
+    /// `CMETHOD-ERROR <transport> <message>`
+    CmethodError { transport: String, message: String },
+    /// `CMETHODS DONE`
+    CmethodsDone,
+    /// `SMETHOD <transport> <address:port> [ARGS:k=v,k=v]`
+    Smethod {
+        transport: String,
+        addr: SocketAddr,
+        args: Vec<(String, String)>,
+    },
+    /// `SMETHOD-ERROR <transport> <message>`
+    SmethodError { transport: String, message: String },
+    /// `SMETHODS DONE`
+    SmethodsDone,
+    /// `PROXY DONE`
+    ProxyDone,
+    /// `PROXY-ERROR <message>`
+    ProxyError(String),
+    /// `LOG SEVERITY=<severity> MESSAGE=<message>`
+    Log { severity: Severity, message: String },
+    /// `STATUS TRANSPORT=<transport> <k>=<v> ...`
+    Status {
+        transport: String,
+        fields: Vec<(String, String)>,
+    },
+    /// A line with a keyword this parser does not know. The spec requires these be ignored.
+    Unknown(String),
+}
+
+impl FromStr for PtLine {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        parse_line(s)
+    }
+}
+
+/// Parse a single line (without the trailing newline) written by a pluggable transport.
+pub fn parse_line(line: &str) -> Result<PtLine> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let (keyword, rest) = match line.split_once(' ') {
+        Some((k, r)) => (k, r.trim_start()),
+        None => (line, ""),
+    };
+
+    let out = match keyword {
+        "VERSION" => PtLine::Version(required(rest, "VERSION")?.to_string()),
+        "VERSION-ERROR" => PtLine::VersionError(rest.to_string()),
+        "ENV-ERROR" => PtLine::EnvError(rest.to_string()),
+        "CMETHOD" => {
+            let mut parts = rest.split_whitespace();
+            let transport = next_field(&mut parts, "CMETHOD", "transport")?;
+            let protocol = next_field(&mut parts, "CMETHOD", "protocol")?.parse()?;
+            let addr = parse_addr(next_field(&mut parts, "CMETHOD", "address")?)?;
+            PtLine::Cmethod {
+                transport: transport.to_string(),
+                protocol,
+                addr,
+            }
+        }
+        "CMETHOD-ERROR" => {
+            let (transport, message) = method_error(rest, "CMETHOD-ERROR")?;
+            PtLine::CmethodError { transport, message }
+        }
+        "SMETHOD" => {
+            let mut parts = rest.split_whitespace();
+            let transport = next_field(&mut parts, "SMETHOD", "transport")?;
+            let addr = parse_addr(next_field(&mut parts, "SMETHOD", "address")?)?;
+            let mut args = vec![];
+            for opt in parts {
+                if let Some(a) = opt.strip_prefix("ARGS:") {
+                    args = parse_smethod_args(a)?;
+                }
+            }
+            PtLine::Smethod {
+                transport: transport.to_string(),
+                addr,
+                args,
+            }
+        }
+        "SMETHOD-ERROR" => {
+            let (transport, message) = method_error(rest, "SMETHOD-ERROR")?;
+            PtLine::SmethodError { transport, message }
+        }
+        "CMETHODS" if rest == "DONE" => PtLine::CmethodsDone,
+        "SMETHODS" if rest == "DONE" => PtLine::SmethodsDone,
+        "PROXY" if rest == "DONE" => PtLine::ProxyDone,
+        "PROXY-ERROR" => PtLine::ProxyError(rest.to_string()),
+        "LOG" => {
+            let (mut severity, mut message) = (None, None);
+            for (k, v) in parse_kv_pairs(rest)? {
+                match k.as_str() {
+                    "SEVERITY" => severity = Some(v.parse()?),
+                    "MESSAGE" => message = Some(v),
+                    _ => {}
+                }
+            }
+            PtLine::Log {
+                severity: severity.ok_or_else(|| parse_error("LOG missing SEVERITY"))?,
+                message: message.ok_or_else(|| parse_error("LOG missing MESSAGE"))?,
+            }
+        }
+        "STATUS" => {
+            let mut fields = parse_kv_pairs(rest)?;
+            let i = fields
+                .iter()
+                .position(|(k, _)| k == "TRANSPORT")
+                .ok_or_else(|| parse_error("STATUS missing TRANSPORT"))?;
+            let (_, transport) = fields.remove(i);
+            PtLine::Status { transport, fields }
+        }
+        _ => PtLine::Unknown(line.to_string()),
+    };
+    Ok(out)
+}
+
+fn parse_error(msg: impl Into<String>) -> Error {
+    Error::Other(msg.into().into())
+}
+
+fn required<'a>(s: &'a str, keyword: &str) -> Result<&'a str> {
+    if s.is_empty() {
+        return Err(parse_error(format!("{keyword} missing argument")));
+    }
+    Ok(s)
+}
+
+fn next_field<'a>(
+    parts: &mut impl Iterator<Item = &'a str>,
+    keyword: &str,
+    field: &str,
+) -> Result<&'a str> {
+    parts
+        .next()
+        .ok_or_else(|| parse_error(format!("{keyword} missing {field}")))
+}
+
+fn parse_addr(s: &str) -> Result<SocketAddr> {
+    s.parse()
+        .map_err(|e| parse_error(format!("bad address \"{s}\": {e}")))
+}
+
+fn method_error(rest: &str, keyword: &str) -> Result<(String, String)> {
+    let (transport, message) = rest.split_once(' ').unwrap_or((rest, ""));
+    let transport = required(transport, keyword)?;
+    Ok((transport.to_string(), message.trim_start().to_string()))
+}
+
+/// Parse the value of an SMETHOD `ARGS:` option, `k=v` pairs separated by commas where `,`,
+/// `=` and `\` within keys and values are backslash escaped.
+pub fn parse_smethod_args(s: &str) -> Result<Vec<(String, String)>> {
+    let mut out = vec![];
+    let (mut key, mut cur) = (None, String::new());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => cur.push(
+                chars
+                    .next()
+                    .ok_or_else(|| parse_error("ARGS ends with an escape"))?,
+            ),
+            '=' if key.is_none() => key = Some(std::mem::take(&mut cur)),
+            ',' => {
+                let k = key
+                    .take()
+                    .ok_or_else(|| parse_error(format!("ARGS entry \"{cur}\" missing '='")))?;
+                out.push((k, std::mem::take(&mut cur)));
+            }
+            _ => cur.push(c),
+        }
+    }
+    match key {
+        Some(k) => out.push((k, cur)),
+        None if cur.is_empty() => {}
+        None => return Err(parse_error(format!("ARGS entry \"{cur}\" missing '='"))),
+    }
+    Ok(out)
+}
+
+/// Parse space separated `KEY=value` pairs where values may be C-style quoted strings.
+fn parse_kv_pairs(s: &str) -> Result<Vec<(String, String)>> {
+    let mut out = vec![];
+    let mut chars = s.chars().peekable();
+    loop {
+        while chars.next_if(|c| *c == ' ').is_some() {}
+        if chars.peek().is_none() {
+            return Ok(out);
+        }
+
+        let mut key = String::new();
+        while let Some(c) = chars.next_if(|c| *c != '=' && *c != ' ') {
+            key.push(c);
+        }
+        if chars.next() != Some('=') {
+            return Err(parse_error(format!("\"{key}\" is not a KEY=value pair")));
+        }
+
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next() {
+                    None => return Err(parse_error(format!("unterminated quote in {key}"))),
+                    Some('"') => break,
+                    Some('\\') => value.push(unescape(&mut chars)?),
+                    Some(c) => value.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != ' ') {
+                value.push(c);
+            }
+        }
+        out.push((key, value));
+    }
+}
+
+fn unescape(chars: &mut impl Iterator<Item = char>) -> Result<char> {
+    let c = chars
+        .next()
+        .ok_or_else(|| parse_error("quoted string ends with an escape"))?;
+    Ok(match c {
+        'n' => '\n',
+        'r' => '\r',
+        't' => '\t',
+        '0'..='7' => {
+            let mut n = c.to_digit(8).unwrap_or(0);
+            for _ in 0..2 {
+                let d = chars
+                    .next()
+                    .and_then(|d| d.to_digit(8))
+                    .ok_or_else(|| parse_error("bad octal escape"))?;
+                n = n * 8 + d;
+            }
+            char::from_u32(n).ok_or_else(|| parse_error("bad octal escape"))?
+        }
+        c => c,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_client_lines() -> Result<()> {
+        assert_eq!(parse_line("VERSION 1")?, PtLine::Version("1".into()));
+        assert_eq!(
+            parse_line("VERSION-ERROR no-version")?,
+            PtLine::VersionError("no-version".into())
+        );
+        assert_eq!(
+            parse_line("CMETHOD obfs4 socks5 127.0.0.1:1080\n")?,
+            PtLine::Cmethod {
+                transport: "obfs4".into(),
+                protocol: ProxyProtocol::Socks5,
+                addr: "127.0.0.1:1080".parse().unwrap(),
+            }
+        );
+        assert_eq!(
+            parse_line("CMETHOD-ERROR trebuchet no rocks available")?,
+            PtLine::CmethodError {
+                transport: "trebuchet".into(),
+                message: "no rocks available".into(),
+            }
+        );
+        assert_eq!(parse_line("CMETHODS DONE")?, PtLine::CmethodsDone);
+        assert_eq!(parse_line("PROXY DONE")?, PtLine::ProxyDone);
+        assert_eq!(
+            parse_line("PROXY-ERROR unsupported scheme")?,
+            PtLine::ProxyError("unsupported scheme".into())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_server_lines() -> Result<()> {
+        assert_eq!(
+            parse_line(r"SMETHOD obfs4 [::1]:4430 ARGS:cert=a\,b\=c,iat-mode=0")?,
+            PtLine::Smethod {
+                transport: "obfs4".into(),
+                addr: "[::1]:4430".parse().unwrap(),
+                args: vec![
+                    ("cert".into(), "a,b=c".into()),
+                    ("iat-mode".into(), "0".into())
+                ],
+            }
+        );
+        assert_eq!(
+            parse_line("SMETHOD hex 0.0.0.0:80")?,
+            PtLine::Smethod {
+                transport: "hex".into(),
+                addr: "0.0.0.0:80".parse().unwrap(),
+                args: vec![],
+            }
+        );
+        assert_eq!(parse_line("SMETHODS DONE")?, PtLine::SmethodsDone);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_log_and_status() -> Result<()> {
+        assert_eq!(
+            parse_line(r#"LOG SEVERITY=warning MESSAGE="bad \"thing\"\n happened\041""#)?,
+            PtLine::Log {
+                severity: Severity::Warning,
+                message: "bad \"thing\"\n happened!".into(),
+            }
+        );
+        assert_eq!(
+            parse_line(
+                r#"STATUS TRANSPORT=obfs4 ADDRESS=198.51.100.123:1234 CONNECT=Success MSG="a b""#
+            )?,
+            PtLine::Status {
+                transport: "obfs4".into(),
+                fields: vec![
+                    ("ADDRESS".into(), "198.51.100.123:1234".into()),
+                    ("CONNECT".into(), "Success".into()),
+                    ("MSG".into(), "a b".into()),
+                ],
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_and_malformed() {
+        assert_eq!(
+            parse_line("FUTURE-THING 1 2").unwrap(),
+            PtLine::Unknown("FUTURE-THING 1 2".into())
+        );
+
+        let bad = [
+            "VERSION",
+            "CMETHOD obfs4 socks5",
+            "CMETHOD obfs4 http 127.0.0.1:1",
+            "SMETHOD obfs4 not-an-addr",
+            "SMETHOD obfs4 127.0.0.1:1 ARGS:novalue",
+            "LOG SEVERITY=loud MESSAGE=x",
+            "LOG MESSAGE=\"unterminated",
+            "STATUS CONNECT=Success",
+        ];
+        for line in bad {
+            assert!(parse_line(line).is_err(), "{line}");
+        }
+    }
+}
@@ -0,0 +1,287 @@
+//! Dial through the upstream proxy tor hands a client transport in `TOR_PT_PROXY`.
+//!
+//! Only SOCKS4a is implemented for now. `socks5://` and `http://` URLs are parsed so the
+//! proxy can be reported back to tor, but dialing through them returns an error.
+
+use crate::{Error, Result};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
+
+const SOCKS4_VERSION: u8 = 0x04;
+const SOCKS4_CMD_CONNECT: u8 = 0x01;
+const SOCKS4_REPLY_GRANTED: u8 = 0x5a;
+
+/// Upstream proxy protocols tor may ask a transport to use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProxyKind {
+    Socks4a,
+    Socks5,
+    Http,
+}
+
+/// A parsed `TOR_PT_PROXY` URL, e.g. `socks4a://user@198.51.100.7:1080`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProxyUrl {
+    pub kind: ProxyKind,
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl FromStr for ProxyUrl {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bad = |why: &str| Error::Other(format!("bad proxy url \"{s}\": {why}").into());
+
+        let (scheme, rest) = s.split_once("://").ok_or_else(|| bad("missing scheme"))?;
+        let kind = match scheme.to_ascii_lowercase().as_str() {
+            "socks4a" => ProxyKind::Socks4a,
+            "socks5" => ProxyKind::Socks5,
+            "http" => ProxyKind::Http,
+            _ => return Err(bad("unsupported scheme")),
+        };
+
+        let rest = rest.trim_end_matches('/');
+        let (userinfo, hostport) = match rest.rsplit_once('@') {
+            Some((userinfo, hostport)) => (Some(userinfo), hostport),
+            None => (None, rest),
+        };
+        let (username, password) = match userinfo {
+            Some(info) => match info.split_once(':') {
+                Some((user, pass)) => (Some(user.to_string()), Some(pass.to_string())),
+                None => (Some(info.to_string()), None),
+            },
+            None => (None, None),
+        };
+
+        let (host, port) = hostport
+            .rsplit_once(':')
+            .ok_or_else(|| bad("missing port"))?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            return Err(bad("missing host"));
+        }
+        let port = port.parse().map_err(|_| bad("invalid port"))?;
+
+        Ok(Self {
+            kind,
+            host: host.to_string(),
+            port,
+            username,
+            password,
+        })
+    }
+}
+
+impl fmt::Display for ProxyUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = match self.kind {
+            ProxyKind::Socks4a => "socks4a",
+            ProxyKind::Socks5 => "socks5",
+            ProxyKind::Http => "http",
+        };
+        write!(f, "{scheme}://")?;
+        if let Some(user) = &self.username {
+            write!(f, "{user}")?;
+            if let Some(pass) = &self.password {
+                write!(f, ":{pass}")?;
+            }
+            write!(f, "@")?;
+        }
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+/// Connect to `host:port` through `proxy`.
+pub async fn dial(proxy: &ProxyUrl, host: &str, port: u16) -> Result<TcpStream> {
+    let mut stream = TcpStream::connect((proxy.host.as_str(), proxy.port)).await?;
+    match proxy.kind {
+        ProxyKind::Socks4a => {
+            let user_id = proxy.username.as_deref().unwrap_or_default();
+            socks4a_connect(&mut stream, host, port, user_id).await?;
+        }
+        kind => {
+            return Err(Error::Other(
+                format!("{kind:?} upstream proxies are not supported yet").into(),
+            ))
+        }
+    }
+    Ok(stream)
+}
+
+/// Perform a SOCKS4a CONNECT handshake on an established connection to the proxy. IPv4
+/// literals are sent in the request itself, anything else is sent as a domain name for the
+/// proxy to resolve.
+pub async fn socks4a_connect<S>(stream: &mut S, host: &str, port: u16, user_id: &str) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    if user_id.as_bytes().contains(&0) || host.as_bytes().contains(&0) {
+        return Err(Error::Other("socks4a fields may not contain NUL".into()));
+    }
+
+    let mut req = vec![SOCKS4_VERSION, SOCKS4_CMD_CONNECT];
+    req.extend_from_slice(&port.to_be_bytes());
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            req.extend_from_slice(&ip.octets());
+            req.extend_from_slice(user_id.as_bytes());
+            req.push(0);
+        }
+        Ok(IpAddr::V6(_)) => {
+            return Err(Error::Other(
+                "socks4a cannot connect to IPv6 addresses".into(),
+            ))
+        }
+        Err(_) => {
+            // 0.0.0.x with x != 0 tells the proxy that a domain name follows the user id.
+            req.extend_from_slice(&Ipv4Addr::new(0, 0, 0, 1).octets());
+            req.extend_from_slice(user_id.as_bytes());
+            req.push(0);
+            req.extend_from_slice(host.as_bytes());
+            req.push(0);
+        }
+    }
+    stream.write_all(&req).await?;
+    stream.flush().await?;
+
+    let mut reply = [0_u8; 8];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != 0 {
+        return Err(Error::Other(
+            format!("socks4a: bad reply version {}", reply[0]).into(),
+        ));
+    }
+    if reply[1] != SOCKS4_REPLY_GRANTED {
+        return Err(Error::Other(
+            format!("socks4a: request rejected ({:#04x})", reply[1]).into(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tokio::io::DuplexStream;
+    use tokio::net::TcpListener;
+
+    /// Minimal SOCKS4a server: reads one request and returns (port, ip, user id, domain),
+    /// replying with `code`.
+    async fn mock_server<S>(s: &mut S, code: u8) -> (u16, [u8; 4], String, Option<String>)
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        async fn read_cstr<S: AsyncRead + Unpin>(s: &mut S) -> String {
+            let mut out = vec![];
+            loop {
+                let b = s.read_u8().await.unwrap();
+                if b == 0 {
+                    return String::from_utf8(out).unwrap();
+                }
+                out.push(b);
+            }
+        }
+
+        assert_eq!(s.read_u8().await.unwrap(), SOCKS4_VERSION);
+        assert_eq!(s.read_u8().await.unwrap(), SOCKS4_CMD_CONNECT);
+        let port = s.read_u16().await.unwrap();
+        let mut ip = [0_u8; 4];
+        s.read_exact(&mut ip).await.unwrap();
+        let user = read_cstr(s).await;
+        let domain = if ip[..3] == [0, 0, 0] && ip[3] != 0 {
+            Some(read_cstr(s).await)
+        } else {
+            None
+        };
+        s.write_all(&[0, code, 0, 0, 0, 0, 0, 0]).await.unwrap();
+        (port, ip, user, domain)
+    }
+
+    #[tokio::test]
+    async fn socks4a_domain_and_ip() -> Result<()> {
+        let (mut client, mut server): (DuplexStream, DuplexStream) = tokio::io::duplex(1024);
+        let srv = tokio::spawn(async move { mock_server(&mut server, SOCKS4_REPLY_GRANTED).await });
+        socks4a_connect(&mut client, "example.com", 443, "tor").await?;
+        let (port, ip, user, domain) = srv.await.unwrap();
+        assert_eq!(port, 443);
+        assert_eq!(ip, [0, 0, 0, 1]);
+        assert_eq!(user, "tor");
+        assert_eq!(domain.as_deref(), Some("example.com"));
+
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let srv = tokio::spawn(async move { mock_server(&mut server, SOCKS4_REPLY_GRANTED).await });
+        socks4a_connect(&mut client, "192.0.2.10", 9001, "").await?;
+        let (port, ip, user, domain) = srv.await.unwrap();
+        assert_eq!((port, ip), (9001, [192, 0, 2, 10]));
+        assert_eq!(user, "");
+        assert_eq!(domain, None);
+
+        let (mut client, _server) = tokio::io::duplex(1024);
+        assert!(socks4a_connect(&mut client, "2001:db8::1", 80, "")
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn socks4a_rejected() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        tokio::spawn(async move { mock_server(&mut server, 0x5b).await });
+        let err = socks4a_connect(&mut client, "example.com", 80, "")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("rejected"));
+    }
+
+    #[tokio::test]
+    async fn dial_socks4a() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            let (mut conn, _) = listener.accept().await.unwrap();
+            let (_, _, user, domain) = mock_server(&mut conn, SOCKS4_REPLY_GRANTED).await;
+            assert_eq!(user, "alice");
+            assert_eq!(domain.as_deref(), Some("bridge.example"));
+            conn.write_all(b"hello").await.unwrap();
+        });
+
+        let proxy: ProxyUrl = format!("socks4a://alice@{addr}").parse()?;
+        let mut conn = dial(&proxy, "bridge.example", 443).await?;
+        let mut buf = [0_u8; 5];
+        conn.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"hello");
+        Ok(())
+    }
+
+    #[test]
+    fn parse_proxy_url() -> Result<()> {
+        let p: ProxyUrl = "socks4a://198.51.100.7:1080".parse()?;
+        assert_eq!(p.kind, ProxyKind::Socks4a);
+        assert_eq!((p.host.as_str(), p.port), ("198.51.100.7", 1080));
+        assert_eq!(p.username, None);
+
+        let p: ProxyUrl = "socks5://user:pass@[2001:db8::1]:9050".parse()?;
+        assert_eq!(p.kind, ProxyKind::Socks5);
+        assert_eq!(p.host, "2001:db8::1");
+        assert_eq!(p.password.as_deref(), Some("pass"));
+        assert_eq!(p.to_string(), "socks5://user:pass@[2001:db8::1]:9050");
+
+        assert!("ftp://h:1".parse::<ProxyUrl>().is_err());
+        assert!("socks4a://host".parse::<ProxyUrl>().is_err());
+        assert!("socks4a://:80".parse::<ProxyUrl>().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,259 @@
+//! Dial an upstream (e.g. a bridge's ORPort) that may be briefly unavailable, retrying with
+//! exponential backoff and reporting each failure as an event rather than an error.
+
+use crate::{sockopt::SocketOpts, Error, Result};
+
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// How long to wait between connection attempts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Backoff {
+    /// Delay after the first failure.
+    pub initial: Duration,
+    /// Longest delay between attempts, however many have failed.
+    pub max: Duration,
+    /// Attempts to make before giving up, `None` to keep trying.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(30),
+            max_attempts: Some(10),
+        }
+    }
+}
+
+impl Backoff {
+    /// Delay before attempt `attempt + 1`, doubling from [`Backoff::initial`] up to
+    /// [`Backoff::max`].
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(attempt.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+}
+
+/// What happened while dialing, for callers that want to log or count failures.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DialEvent {
+    /// Attempt `attempt` (counting from 1) failed; the next is made after `retry_in`.
+    Failed {
+        attempt: u32,
+        error: String,
+        retry_in: Duration,
+    },
+    /// Attempt `attempt` connected.
+    Connected { attempt: u32 },
+    /// The last allowed attempt failed, or the error can't be fixed by retrying.
+    GaveUp { attempts: u32, error: String },
+}
+
+/// Dials one address, retrying retriable failures (see [`Error::is_retriable`]) with
+/// [`Backoff`].
+#[derive(Clone, Debug)]
+pub struct ReconnectingDialer {
+    addr: SocketAddr,
+    opts: SocketOpts,
+    backoff: Backoff,
+    connect_timeout: Option<Duration>,
+    events: Option<mpsc::UnboundedSender<DialEvent>>,
+}
+
+impl ReconnectingDialer {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            opts: SocketOpts::default(),
+            backoff: Backoff::default(),
+            connect_timeout: None,
+            events: None,
+        }
+    }
+
+    pub fn with_socket_opts(mut self, opts: SocketOpts) -> Self {
+        self.opts = opts;
+        self
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Fail an attempt that hasn't connected after `timeout` with
+    /// [`io::ErrorKind::TimedOut`], which is retried like any other retriable failure.
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Report every attempt's outcome on the returned channel. Events are dropped once the
+    /// receiver is.
+    pub fn subscribe(&mut self) -> mpsc::UnboundedReceiver<DialEvent> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.events = Some(tx);
+        rx
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Connect, retrying until an attempt succeeds or the backoff gives up.
+    pub async fn connect(&self) -> Result<TcpStream> {
+        self.connect_until(&CancellationToken::new()).await
+    }
+
+    /// Like [`ReconnectingDialer::connect`], but stops with [`Error::Cancelled`] as soon as
+    /// `cancel` is cancelled, including while waiting between attempts.
+    pub async fn connect_until(&self, cancel: &CancellationToken) -> Result<TcpStream> {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let dial = tokio::select! {
+                r = self.dial_once() => r,
+                _ = cancel.cancelled() => return Err(Error::Cancelled),
+            };
+            let e = match dial {
+                Ok(stream) => {
+                    self.emit(DialEvent::Connected { attempt });
+                    return Ok(stream);
+                }
+                Err(e) => e,
+            };
+
+            let exhausted = self.backoff.max_attempts.is_some_and(|max| attempt >= max);
+            if exhausted || !e.is_retriable() {
+                self.emit(DialEvent::GaveUp {
+                    attempts: attempt,
+                    error: e.to_string(),
+                });
+                return Err(e);
+            }
+
+            let retry_in = self.backoff.delay(attempt);
+            self.emit(DialEvent::Failed {
+                attempt,
+                error: e.to_string(),
+                retry_in,
+            });
+            tokio::select! {
+                _ = tokio::time::sleep(retry_in) => {}
+                _ = cancel.cancelled() => return Err(Error::Cancelled),
+            }
+        }
+    }
+
+    async fn dial_once(&self) -> Result<TcpStream> {
+        match self.connect_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.opts.connect(self.addr))
+                .await
+                .map_err(|_| Error::from(io::Error::from(io::ErrorKind::TimedOut)))?,
+            None => self.opts.connect(self.addr).await,
+        }
+    }
+
+    fn emit(&self, event: DialEvent) {
+        if let Some(events) = &self.events {
+            let _ = events.send(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tokio::net::TcpListener;
+
+    fn fast(max_attempts: Option<u32>) -> Backoff {
+        Backoff {
+            initial: Duration::from_millis(5),
+            max: Duration::from_millis(20),
+            max_attempts,
+        }
+    }
+
+    /// An address nothing is listening on: bind, note the port, close.
+    async fn closed_addr() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let b = Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(1),
+            max_attempts: None,
+        };
+        let delays: Vec<_> = (1..=6).map(|n| b.delay(n).as_millis()).collect();
+        assert_eq!(delays, [100, 200, 400, 800, 1000, 1000]);
+        assert_eq!(b.delay(100), b.max);
+    }
+
+    #[tokio::test]
+    async fn reconnects_once_backend_returns() {
+        let addr = closed_addr().await;
+        let mut dialer = ReconnectingDialer::new(addr).with_backoff(fast(None));
+        let mut events = dialer.subscribe();
+
+        // the backend comes back after a few failed attempts
+        let backend = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(40)).await;
+            let listener = TcpListener::bind(addr).await.unwrap();
+            listener.accept().await.unwrap()
+        });
+
+        let stream = dialer.connect().await.unwrap();
+        let (_, peer) = backend.await.unwrap();
+        assert_eq!(stream.local_addr().unwrap(), peer);
+
+        let mut failed = 0;
+        while let Ok(event) = events.try_recv() {
+            match event {
+                DialEvent::Failed { attempt, .. } => {
+                    failed += 1;
+                    assert_eq!(attempt, failed);
+                }
+                DialEvent::Connected { attempt } => assert_eq!(attempt, failed + 1),
+                e => panic!("unexpected {e:?}"),
+            }
+        }
+        assert!(failed > 0);
+    }
+
+    #[tokio::test]
+    async fn gives_up_and_cancels() {
+        let addr = closed_addr().await;
+        let mut dialer = ReconnectingDialer::new(addr).with_backoff(fast(Some(3)));
+        let mut events = dialer.subscribe();
+        assert!(dialer.connect().await.unwrap_err().is_retriable());
+        let mut last = None;
+        while let Ok(event) = events.try_recv() {
+            last = Some(event);
+        }
+        assert!(matches!(last, Some(DialEvent::GaveUp { attempts: 3, .. })));
+
+        let dialer = ReconnectingDialer::new(addr).with_backoff(Backoff {
+            initial: Duration::from_secs(60),
+            ..fast(None)
+        });
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        assert!(matches!(
+            dialer.connect_until(&cancel).await,
+            Err(Error::Cancelled)
+        ));
+    }
+}
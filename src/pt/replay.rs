@@ -0,0 +1,260 @@
+//! Replay protection for handshakes.
+//!
+//! A server transport records something unique to each client handshake (e.g. the MAC over
+//! it, as obfs4 does) and rejects any handshake it has already seen within the filter's time
+//! to live. Entries are grouped into time buckets so expiring them is cheap, the number held
+//! is capped, and the filter can be saved to the transport's state directory so a restart
+//! doesn't reopen the window.
+
+use super::state::StateDir;
+use crate::{Error, Result};
+
+use std::collections::{HashSet, VecDeque};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// File the filter is saved as inside a state directory.
+pub const STATE_FILE: &str = "replay_filter";
+
+/// Number of buckets the time to live is divided into. Entries expire up to one bucket width
+/// after their time to live.
+const BUCKETS: u64 = 8;
+
+/// Longest key the filter stores; handshake MACs and digests are well under this.
+const MAX_KEY_LEN: usize = u8::MAX as usize;
+
+struct Bucket {
+    /// Start of the bucket, in seconds since the unix epoch.
+    start: u64,
+    keys: HashSet<Box<[u8]>>,
+}
+
+struct Inner {
+    buckets: VecDeque<Bucket>,
+    len: usize,
+}
+
+impl Inner {
+    /// Drop whole buckets, oldest first, until at most `max` keys remain.
+    fn shrink_to(&mut self, max: usize) {
+        while self.len > max {
+            match self.buckets.pop_front() {
+                Some(oldest) => self.len -= oldest.keys.len(),
+                None => break,
+            }
+        }
+    }
+}
+
+/// Time bucketed set of recently seen handshakes.
+pub struct Filter {
+    ttl: Duration,
+    max_entries: usize,
+    inner: Mutex<Inner>,
+}
+
+impl Filter {
+    /// Remember keys for `ttl`, holding at most `max_entries`. When full, the oldest bucket
+    /// is dropped early, so a flood of handshakes shortens the window instead of growing
+    /// memory.
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            ttl,
+            max_entries,
+            inner: Mutex::new(Inner {
+                buckets: VecDeque::new(),
+                len: 0,
+            }),
+        }
+    }
+
+    /// Record `key`, returning `true` if it was already present (a replay).
+    pub fn test_and_set(&self, key: &[u8]) -> bool {
+        self.test_and_set_at(key, SystemTime::now())
+    }
+
+    /// [`Filter::test_and_set`] as if the current time were `now`.
+    pub fn test_and_set_at(&self, key: &[u8], now: SystemTime) -> bool {
+        let key = &key[..key.len().min(MAX_KEY_LEN)];
+        let now = unix_secs(now);
+        let mut inner = self.lock();
+        self.expire(&mut inner, now);
+        if inner.buckets.iter().any(|b| b.keys.contains(key)) {
+            return true;
+        }
+
+        let width = self.bucket_width();
+        let start = now - now % width;
+        if inner.buckets.back().is_none_or(|b| b.start != start) {
+            inner.buckets.push_back(Bucket {
+                start,
+                keys: HashSet::new(),
+            });
+        }
+        let bucket = inner.buckets.back_mut().expect("bucket was just pushed");
+        bucket.keys.insert(key.into());
+        inner.len += 1;
+
+        inner.shrink_to(self.max_entries);
+        false
+    }
+
+    /// Number of keys currently remembered.
+    pub fn len(&self) -> usize {
+        self.lock().len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Write the filter to [`STATE_FILE`] in `dir`, replacing any earlier copy.
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        self.save_to(&StateDir::new(dir))
+    }
+
+    /// [`Filter::save`] through `dir`, which seals the file if it has a key.
+    pub fn save_to(&self, dir: &StateDir) -> Result<()> {
+        let mut out = vec![];
+        for bucket in self.lock().buckets.iter() {
+            for key in &bucket.keys {
+                out.extend_from_slice(&bucket.start.to_be_bytes());
+                out.push(key.len() as u8);
+                out.extend_from_slice(key);
+            }
+        }
+        dir.write(STATE_FILE, &out)
+    }
+
+    /// Load a filter saved with [`Filter::save`] from `dir`, or start an empty one if there
+    /// is none. Entries that have expired since are dropped.
+    pub fn load(dir: &Path, ttl: Duration, max_entries: usize) -> Result<Self> {
+        Self::load_from(&StateDir::new(dir), ttl, max_entries)
+    }
+
+    /// [`Filter::load`] through `dir`, which opens the file if it was sealed.
+    pub fn load_from(dir: &StateDir, ttl: Duration, max_entries: usize) -> Result<Self> {
+        let filter = Self::new(ttl, max_entries);
+        let Some(data) = dir.read(STATE_FILE)? else {
+            return Ok(filter);
+        };
+
+        let now = unix_secs(SystemTime::now());
+        let truncated = || Error::new("truncated replay filter state");
+        let mut rest = &data[..];
+        {
+            let mut inner = filter.lock();
+            while !rest.is_empty() {
+                let (start, tail) = rest.split_first_chunk::<8>().ok_or_else(truncated)?;
+                let (&len, tail) = tail.split_first().ok_or_else(truncated)?;
+                let key = tail.get(..len as usize).ok_or_else(truncated)?;
+                rest = &tail[len as usize..];
+
+                let start = u64::from_be_bytes(*start);
+                if inner.buckets.back().is_none_or(|b| b.start != start) {
+                    inner.buckets.push_back(Bucket {
+                        start,
+                        keys: HashSet::new(),
+                    });
+                }
+                if let Some(bucket) = inner.buckets.back_mut() {
+                    if bucket.keys.insert(key.into()) {
+                        inner.len += 1;
+                    }
+                }
+            }
+            filter.expire(&mut inner, now);
+            inner.shrink_to(filter.max_entries);
+        }
+        Ok(filter)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn bucket_width(&self) -> u64 {
+        (self.ttl.as_secs() / BUCKETS).max(1)
+    }
+
+    /// Drop buckets that ended more than `ttl` ago.
+    fn expire(&self, inner: &mut Inner, now: u64) {
+        let width = self.bucket_width();
+        let ttl = self.ttl.as_secs();
+        while let Some(oldest) = inner.buckets.front() {
+            if oldest.start + width + ttl > now {
+                break;
+            }
+            inner.len -= oldest.keys.len();
+            inner.buckets.pop_front();
+        }
+    }
+}
+
+fn unix_secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(80);
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000 + secs)
+    }
+
+    #[test]
+    fn detects_replays_until_expiry() {
+        let filter = Filter::new(TTL, 100);
+        assert!(!filter.test_and_set_at(b"mac-1", at(0)));
+        assert!(filter.test_and_set_at(b"mac-1", at(1)));
+        assert!(!filter.test_and_set_at(b"mac-2", at(50)));
+        assert!(filter.test_and_set_at(b"mac-1", at(79)));
+        assert_eq!(filter.len(), 2);
+
+        // mac-1's bucket is gone a bucket width after the ttl, mac-2's is not
+        assert!(!filter.test_and_set_at(b"mac-1", at(95)));
+        assert!(filter.test_and_set_at(b"mac-2", at(95)));
+        assert_eq!(filter.len(), 2);
+
+        assert!(!filter.test_and_set_at(b"mac-3", at(500)));
+        assert_eq!(filter.len(), 1);
+    }
+
+    #[test]
+    fn evicts_oldest_bucket_when_full() {
+        let filter = Filter::new(TTL, 4);
+        for (i, t) in [0, 0, 20, 20, 40].into_iter().enumerate() {
+            assert!(!filter.test_and_set_at(&[i as u8], at(t)));
+        }
+        // the bucket from t=0 was dropped to make room
+        assert_eq!(filter.len(), 3);
+        assert!(!filter.test_and_set_at(&[0], at(41)));
+        assert!(filter.test_and_set_at(&[2], at(41)));
+    }
+
+    #[test]
+    fn persists_across_restarts() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let filter = Filter::new(TTL, 100);
+        assert!(!filter.test_and_set(b"seen before the restart"));
+        filter.save(dir.path())?;
+
+        let filter = Filter::load(dir.path(), TTL, 100)?;
+        assert_eq!(filter.len(), 1);
+        assert!(filter.test_and_set(b"seen before the restart"));
+
+        let empty = tempfile::tempdir()?;
+        assert!(Filter::load(empty.path(), TTL, 100)?.is_empty());
+
+        std::fs::write(
+            dir.path().join(STATE_FILE),
+            [0, 0, 0, 0, 0, 0, 0, 0, 5, 1, 2],
+        )?;
+        assert!(Filter::load(dir.path(), TTL, 100).is_err());
+        Ok(())
+    }
+}
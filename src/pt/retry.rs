@@ -0,0 +1,302 @@
+//! Retry a transport whose handshake fails for reasons that may pass, e.g. a bridge that is
+//! restarting or a path dropping packets. Wrapping consumes the stream, so each attempt dials
+//! a fresh one.
+
+use crate::reconnect::{Backoff, DialEvent};
+use crate::{DynTransport, Error, Result, Stream};
+
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use std::future::Future;
+
+/// Wraps a transport so that dialing and wrapping is retried with [`Backoff`] while the
+/// failures are retriable (see [`Error::is_retriable`]).
+#[derive(Clone, Debug)]
+pub struct Retrying<T> {
+    inner: T,
+    backoff: Backoff,
+    events: Option<mpsc::UnboundedSender<DialEvent>>,
+}
+
+impl<T: DynTransport> Retrying<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            backoff: Backoff::default(),
+            events: None,
+        }
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Report every attempt's outcome on the returned channel. Events are dropped once the
+    /// receiver is.
+    pub fn subscribe(&mut self) -> mpsc::UnboundedReceiver<DialEvent> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.events = Some(tx);
+        rx
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Get a stream from `dial`, wrap it and drive the transport's handshake (which wrapped
+    /// streams otherwise only start on first use), starting over with a new stream after each
+    /// retriable failure of any step.
+    pub async fn connect<'a, F, Fut, S>(&self, dial: F) -> Result<Box<dyn Stream + 'a>>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<S>>,
+        S: Stream + 'a,
+    {
+        self.connect_until(&CancellationToken::new(), dial).await
+    }
+
+    /// Like [`Retrying::connect`], but stops with [`Error::Cancelled`] as soon as `cancel` is
+    /// cancelled, including while waiting between attempts. The returned stream is tied to
+    /// `cancel` as by [`DynTransport::wrap_boxed_with_cancel`].
+    pub async fn connect_until<'a, F, Fut, S>(
+        &self,
+        cancel: &CancellationToken,
+        mut dial: F,
+    ) -> Result<Box<dyn Stream + 'a>>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<S>>,
+        S: Stream + 'a,
+    {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let wrapped = async {
+                let stream = dial().await?;
+                let mut wrapped = self
+                    .inner
+                    .wrap_boxed_with_cancel(Box::new(stream), cancel.clone())
+                    .await?;
+                wrapped.flush().await?;
+                Ok::<_, Error>(wrapped)
+            };
+            let result = tokio::select! {
+                r = wrapped => r,
+                _ = cancel.cancelled() => return Err(Error::Cancelled),
+            };
+            let e = match result {
+                Ok(stream) => {
+                    self.emit(DialEvent::Connected { attempt });
+                    return Ok(stream);
+                }
+                Err(e) => e,
+            };
+
+            let exhausted = self.backoff.max_attempts.is_some_and(|max| attempt >= max);
+            if exhausted || !e.is_retriable() {
+                self.emit(DialEvent::GaveUp {
+                    attempts: attempt,
+                    error: e.to_string(),
+                });
+                return Err(e);
+            }
+
+            let retry_in = self.backoff.delay(attempt);
+            self.emit(DialEvent::Failed {
+                attempt,
+                error: e.to_string(),
+                retry_in,
+            });
+            tokio::select! {
+                _ = tokio::time::sleep(retry_in) => {}
+                _ = cancel.cancelled() => return Err(Error::Cancelled),
+            }
+        }
+    }
+
+    fn emit(&self, event: DialEvent) {
+        if let Some(events) = &self.events {
+            let _ = events.send(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Transport;
+
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+    use std::io;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+
+    /// Fails to wrap with `error` the first `failures` times.
+    struct Flaky {
+        failures: AtomicU32,
+        error: fn() -> Error,
+    }
+
+    impl<'a, A> Transport<'a, A> for Flaky
+    where
+        A: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'a,
+    {
+        fn wrap(&self, a: A) -> Result<Box<dyn Stream + 'a>> {
+            let left = self.failures.load(Ordering::Relaxed);
+            if left > 0 {
+                self.failures.store(left - 1, Ordering::Relaxed);
+                return Err((self.error)());
+            }
+            Ok(Box::new(a))
+        }
+    }
+
+    /// Wraps fine, but the handshake (driven by the first flush) is reset the first
+    /// `failures` times.
+    struct FlakyHandshake(AtomicU32);
+
+    struct ResetOnFlush<A>(A);
+
+    impl<A: AsyncRead + Unpin> AsyncRead for ResetOnFlush<A> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.0).poll_read(cx, buf)
+        }
+    }
+
+    impl<A: AsyncWrite + Unpin> AsyncWrite for ResetOnFlush<A> {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.0).poll_write(cx, buf)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()))
+        }
+
+        fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.0).poll_shutdown(cx)
+        }
+    }
+
+    impl<'a, A> Transport<'a, A> for FlakyHandshake
+    where
+        A: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'a,
+    {
+        fn wrap(&self, a: A) -> Result<Box<dyn Stream + 'a>> {
+            let left = self.0.load(Ordering::Relaxed);
+            if left > 0 {
+                self.0.store(left - 1, Ordering::Relaxed);
+                return Ok(Box::new(ResetOnFlush(a)));
+            }
+            Ok(Box::new(a))
+        }
+    }
+
+    fn flaky(failures: u32, error: fn() -> Error) -> Flaky {
+        Flaky {
+            failures: AtomicU32::new(failures),
+            error,
+        }
+    }
+
+    fn reset() -> Error {
+        io::Error::from(io::ErrorKind::ConnectionReset).into()
+    }
+
+    fn fast(max_attempts: Option<u32>) -> Backoff {
+        Backoff {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(5),
+            max_attempts,
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_with_fresh_streams() -> Result<()> {
+        let mut retrying = Retrying::new(flaky(2, reset)).with_backoff(fast(None));
+        let mut events = retrying.subscribe();
+        let mut peers = vec![];
+        let mut stream = retrying
+            .connect(|| {
+                let (a, b) = tokio::io::duplex(64);
+                peers.push(b);
+                async { Ok(a) }
+            })
+            .await?;
+        assert_eq!(peers.len(), 3);
+
+        stream.write_all(b"third time").await?;
+        let mut buf = [0u8; 10];
+        peers[2].read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"third time");
+
+        let got: Vec<_> = std::iter::from_fn(|| events.try_recv().ok()).collect();
+        assert!(matches!(
+            got[..],
+            [
+                DialEvent::Failed { attempt: 1, .. },
+                DialEvent::Failed { attempt: 2, .. },
+                DialEvent::Connected { attempt: 3 },
+            ]
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retries_failed_handshakes() -> Result<()> {
+        let retrying = Retrying::new(FlakyHandshake(AtomicU32::new(2))).with_backoff(fast(None));
+        let mut dials = 0;
+        retrying
+            .connect(|| {
+                dials += 1;
+                async { Ok(tokio::io::duplex(64).0) }
+            })
+            .await?;
+        assert_eq!(dials, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn gives_up() {
+        let dial = || async { Ok(tokio::io::duplex(64).0) };
+
+        // not worth retrying
+        let retrying =
+            Retrying::new(flaky(1, || Error::new("bad config"))).with_backoff(fast(None));
+        assert!(retrying.connect(dial).await.is_err());
+        assert_eq!(retrying.inner().failures.load(Ordering::Relaxed), 0);
+
+        // out of attempts
+        let retrying = Retrying::new(flaky(5, reset)).with_backoff(fast(Some(3)));
+        assert!(matches!(retrying.connect(dial).await, Err(e) if e.is_retriable()));
+        assert_eq!(retrying.inner().failures.load(Ordering::Relaxed), 2);
+
+        let retrying = Retrying::new(flaky(5, reset)).with_backoff(Backoff {
+            initial: Duration::from_secs(60),
+            ..fast(None)
+        });
+        let cancel = CancellationToken::new();
+        let canceller = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            canceller.cancel();
+        });
+        assert!(matches!(
+            retrying.connect_until(&cancel, dial).await,
+            Err(Error::Cancelled)
+        ));
+    }
+}
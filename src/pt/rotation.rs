@@ -0,0 +1,199 @@
+//! Time based rotation of server keys.
+//!
+//! A server transport that keys its obfuscation with a secret only the server side holds
+//! (e.g. the ephemeral key it derives padding or cookies from) keeps it in a [`KeyRing`] and
+//! registers the ring with a [`Rotation`]. Every period the ring gets a fresh key, and the
+//! one it replaced is still accepted for a grace window so connections from clients that
+//! started with it don't fail.
+
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// How often keys are rotated unless configured otherwise.
+pub const DEFAULT_PERIOD: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How long a replaced key is still accepted unless configured otherwise.
+pub const DEFAULT_GRACE: Duration = Duration::from_secs(60 * 60);
+
+/// Something holding keys that can be swapped for fresh ones.
+pub trait Rotatable: Send + Sync {
+    /// Start using a new key, keeping the current one only for the grace window.
+    fn rotate(&self);
+}
+
+struct Keys<K> {
+    current: K,
+    /// Replaced key and when it stops being accepted.
+    previous: Option<(K, SystemTime)>,
+}
+
+/// The current key plus the one it replaced, while that is still within its grace window.
+pub struct KeyRing<K> {
+    grace: Duration,
+    generate: Box<dyn Fn() -> K + Send + Sync>,
+    keys: Mutex<Keys<K>>,
+}
+
+impl<K: Clone> KeyRing<K> {
+    /// A ring starting from a key made by `generate`, which is also called for each rotation.
+    pub fn new(grace: Duration, generate: impl Fn() -> K + Send + Sync + 'static) -> Self {
+        Self {
+            grace,
+            keys: Mutex::new(Keys {
+                current: generate(),
+                previous: None,
+            }),
+            generate: Box::new(generate),
+        }
+    }
+
+    /// Key to use for new connections.
+    pub fn current(&self) -> K {
+        self.lock().current.clone()
+    }
+
+    /// Keys to accept from a client, newest first.
+    pub fn accepted(&self) -> Vec<K> {
+        self.accepted_at(SystemTime::now())
+    }
+
+    /// [`KeyRing::accepted`] as if the current time were `now`.
+    pub fn accepted_at(&self, now: SystemTime) -> Vec<K> {
+        let keys = self.lock();
+        let mut out = vec![keys.current.clone()];
+        if let Some((key, until)) = &keys.previous {
+            if now < *until {
+                out.push(key.clone());
+            }
+        }
+        out
+    }
+
+    /// [`Rotatable::rotate`] as if the current time were `now`.
+    pub fn rotate_at(&self, now: SystemTime) {
+        let next = (self.generate)();
+        let mut keys = self.lock();
+        let old = std::mem::replace(&mut keys.current, next);
+        keys.previous = Some((old, now + self.grace));
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Keys<K>> {
+        self.keys.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<K: Clone + Send> Rotatable for KeyRing<K> {
+    fn rotate(&self) {
+        self.rotate_at(SystemTime::now())
+    }
+}
+
+struct Scheduled {
+    transport: String,
+    period: Duration,
+    next: Instant,
+    target: Arc<dyn Rotatable>,
+}
+
+/// Rotates each registered transport's keys on its own period.
+#[derive(Default)]
+pub struct Rotation {
+    scheduled: Vec<Scheduled>,
+}
+
+impl Rotation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rotate `target`, the keys of `transport`, every `period` starting one period from now.
+    pub fn add(
+        &mut self,
+        transport: impl Into<String>,
+        period: Duration,
+        target: Arc<dyn Rotatable>,
+    ) -> &mut Self {
+        self.scheduled.push(Scheduled {
+            transport: transport.into(),
+            period,
+            next: Instant::now() + period,
+            target,
+        });
+        self
+    }
+
+    /// Rotate keys as they come due until `cancel`.
+    pub async fn run(mut self, cancel: CancellationToken) {
+        loop {
+            let Some(next) = self.scheduled.iter().map(|s| s.next).min() else {
+                return;
+            };
+            tokio::select! {
+                _ = cancel.cancelled() => return,
+                _ = tokio::time::sleep_until(next) => {}
+            }
+            let now = Instant::now();
+            for s in self.scheduled.iter_mut().filter(|s| s.next <= now) {
+                info!("rotating keys for {}", s.transport);
+                s.target.rotate();
+                s.next = now + s.period;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::UNIX_EPOCH;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000 + secs)
+    }
+
+    fn counter() -> impl Fn() -> u32 + Send + Sync {
+        let n = AtomicU32::new(0);
+        move || n.fetch_add(1, Ordering::Relaxed)
+    }
+
+    #[test]
+    fn keeps_previous_key_for_grace() {
+        let ring = KeyRing::new(Duration::from_secs(60), counter());
+        assert_eq!(ring.current(), 0);
+        assert_eq!(ring.accepted_at(at(0)), [0]);
+
+        ring.rotate_at(at(0));
+        assert_eq!(ring.current(), 1);
+        assert_eq!(ring.accepted_at(at(59)), [1, 0]);
+        assert_eq!(ring.accepted_at(at(60)), [1]);
+
+        // only one previous key is kept, however soon the next rotation comes
+        ring.rotate_at(at(10));
+        assert_eq!(ring.accepted_at(at(11)), [2, 1]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rotates_each_transport_on_its_period() {
+        let fast = Arc::new(KeyRing::new(DEFAULT_GRACE, counter()));
+        let slow = Arc::new(KeyRing::new(DEFAULT_GRACE, counter()));
+        let mut rotation = Rotation::new();
+        rotation
+            .add("fast", Duration::from_secs(10), fast.clone())
+            .add("slow", Duration::from_secs(25), slow.clone());
+
+        let cancel = CancellationToken::new();
+        let task = tokio::spawn(rotation.run(cancel.clone()));
+        tokio::time::sleep(Duration::from_secs(51)).await;
+        assert_eq!(fast.current(), 5);
+        assert_eq!(slow.current(), 2);
+
+        cancel.cancel();
+        task.await.unwrap();
+    }
+}
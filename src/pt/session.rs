@@ -0,0 +1,274 @@
+//! Session resumption for handshake transports.
+//!
+//! After a full handshake a server transport can [`issue`](Sessions::issue) the client an
+//! opaque token bound to a secret from that handshake (e.g. a key derived from the shared
+//! secret). A client that presents the token on a later connection lets the server
+//! [`redeem`](Sessions::redeem) it for the secret and skip the asymmetric crypto.
+//!
+//! Tokens are random and mean nothing without the server's store, so they reveal nothing
+//! about the secret. Each can be redeemed once, which keeps a token seen on the wire from
+//! being replayed, and expires after the store's lifetime. The store can be saved to the
+//! transport's state directory so clients can resume across a restart.
+
+use super::state::StateDir;
+use crate::rand::Rng;
+use crate::{Error, Result};
+
+use rand::RngCore;
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// File the store is saved as inside a state directory.
+pub const STATE_FILE: &str = "session_tokens";
+
+/// Length of an issued token.
+pub const TOKEN_LEN: usize = 32;
+
+/// Longest secret a token can be bound to; derived keys are well under this.
+pub const MAX_SECRET_LEN: usize = u8::MAX as usize;
+
+/// How long tokens stay valid unless the store is created with another lifetime.
+pub const DEFAULT_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Token handed to a client to resume with later.
+pub type Token = [u8; TOKEN_LEN];
+
+struct Entry {
+    /// Seconds since the unix epoch.
+    expires: u64,
+    secret: Box<[u8]>,
+}
+
+struct Inner {
+    rng: Rng,
+    entries: HashMap<Token, Entry>,
+}
+
+/// Server side store of outstanding resumption tokens.
+pub struct Sessions {
+    lifetime: Duration,
+    max_entries: usize,
+    inner: Mutex<Inner>,
+}
+
+impl Sessions {
+    /// Keep tokens valid for `lifetime`, holding at most `max_entries`. When full, the token
+    /// closest to expiry is dropped to make room.
+    pub fn new(lifetime: Duration, max_entries: usize) -> Self {
+        Self::with_rng(lifetime, max_entries, Rng::from_entropy())
+    }
+
+    /// A store drawing tokens from `rng`, e.g. a seeded one in tests.
+    pub fn with_rng(lifetime: Duration, max_entries: usize, rng: Rng) -> Self {
+        Self {
+            lifetime,
+            max_entries,
+            inner: Mutex::new(Inner {
+                rng,
+                entries: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Issue a token that can later be redeemed once for `secret`.
+    pub fn issue(&self, secret: &[u8]) -> Result<Token> {
+        self.issue_at(secret, SystemTime::now())
+    }
+
+    /// [`Sessions::issue`] as if the current time were `now`.
+    pub fn issue_at(&self, secret: &[u8], now: SystemTime) -> Result<Token> {
+        if secret.len() > MAX_SECRET_LEN {
+            return Err(Error::new("session secret too long"));
+        }
+        let now = unix_secs(now);
+        let mut inner = self.lock();
+        expire(&mut inner, now);
+        while !inner.entries.is_empty() && inner.entries.len() >= self.max_entries {
+            let oldest = inner
+                .entries
+                .iter()
+                .min_by_key(|(_, e)| e.expires)
+                .map(|(t, _)| *t);
+            if let Some(token) = oldest {
+                inner.entries.remove(&token);
+            }
+        }
+
+        let mut token = [0u8; TOKEN_LEN];
+        inner.rng.fill_bytes(&mut token);
+        let entry = Entry {
+            expires: now + self.lifetime.as_secs(),
+            secret: secret.into(),
+        };
+        inner.entries.insert(token, entry);
+        Ok(token)
+    }
+
+    /// The secret `token` was issued for, if it is known and unexpired. The token is used up
+    /// either way.
+    pub fn redeem(&self, token: &[u8]) -> Option<Vec<u8>> {
+        self.redeem_at(token, SystemTime::now())
+    }
+
+    /// [`Sessions::redeem`] as if the current time were `now`.
+    pub fn redeem_at(&self, token: &[u8], now: SystemTime) -> Option<Vec<u8>> {
+        let token: &Token = token.try_into().ok()?;
+        let entry = self.lock().entries.remove(token)?;
+        (entry.expires > unix_secs(now)).then(|| entry.secret.into_vec())
+    }
+
+    /// Number of outstanding tokens, including any that have expired but not been dropped.
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Write the store to [`STATE_FILE`] in `dir`, replacing any earlier copy. The file holds
+    /// the secrets, so on unix it is only readable by its owner.
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        self.save_to(&StateDir::new(dir))
+    }
+
+    /// [`Sessions::save`] through `dir`, which seals the file if it has a key.
+    pub fn save_to(&self, dir: &StateDir) -> Result<()> {
+        let mut out = vec![];
+        for (token, entry) in self.lock().entries.iter() {
+            out.extend_from_slice(&entry.expires.to_be_bytes());
+            out.extend_from_slice(token);
+            out.push(entry.secret.len() as u8);
+            out.extend_from_slice(&entry.secret);
+        }
+        dir.write(STATE_FILE, &out)
+    }
+
+    /// Load a store saved with [`Sessions::save`] from `dir`, or start an empty one if there
+    /// is none. Tokens that have expired since are dropped.
+    pub fn load(dir: &Path, lifetime: Duration, max_entries: usize) -> Result<Self> {
+        Self::load_from(&StateDir::new(dir), lifetime, max_entries)
+    }
+
+    /// [`Sessions::load`] through `dir`, which opens the file if it was sealed.
+    pub fn load_from(dir: &StateDir, lifetime: Duration, max_entries: usize) -> Result<Self> {
+        let sessions = Self::new(lifetime, max_entries);
+        let Some(data) = dir.read(STATE_FILE)? else {
+            return Ok(sessions);
+        };
+
+        let now = unix_secs(SystemTime::now());
+        let truncated = || Error::new("truncated session state");
+        let mut rest = &data[..];
+        {
+            let mut inner = sessions.lock();
+            while !rest.is_empty() {
+                let (expires, tail) = rest.split_first_chunk::<8>().ok_or_else(truncated)?;
+                let (token, tail) = tail
+                    .split_first_chunk::<TOKEN_LEN>()
+                    .ok_or_else(truncated)?;
+                let (&len, tail) = tail.split_first().ok_or_else(truncated)?;
+                let secret = tail.get(..len as usize).ok_or_else(truncated)?;
+                rest = &tail[len as usize..];
+
+                let entry = Entry {
+                    expires: u64::from_be_bytes(*expires),
+                    secret: secret.into(),
+                };
+                inner.entries.insert(*token, entry);
+            }
+            expire(&mut inner, now);
+        }
+        Ok(sessions)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn expire(inner: &mut Inner, now: u64) {
+    inner.entries.retain(|_, e| e.expires > now);
+}
+
+fn unix_secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const LIFETIME: Duration = Duration::from_secs(60);
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000 + secs)
+    }
+
+    #[test]
+    fn tokens_redeem_once_until_expiry() -> Result<()> {
+        let sessions = Sessions::with_rng(LIFETIME, 10, Rng::from_seed(1));
+        let a = sessions.issue_at(b"key a", at(0))?;
+        let b = sessions.issue_at(b"key b", at(0))?;
+        assert_ne!(a, b);
+        assert_eq!(sessions.len(), 2);
+
+        assert_eq!(
+            sessions.redeem_at(&a, at(59)).as_deref(),
+            Some(&b"key a"[..])
+        );
+        assert_eq!(sessions.redeem_at(&a, at(59)), None);
+        assert_eq!(sessions.redeem_at(&b, at(60)), None);
+        assert_eq!(sessions.redeem_at(&[0; TOKEN_LEN], at(0)), None);
+        assert_eq!(sessions.redeem_at(b"short", at(0)), None);
+        assert!(sessions.is_empty());
+
+        assert!(sessions.issue(&[0; MAX_SECRET_LEN + 1]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn drops_oldest_when_full() -> Result<()> {
+        let sessions = Sessions::new(LIFETIME, 2);
+        let first = sessions.issue_at(b"1", at(0))?;
+        let second = sessions.issue_at(b"2", at(10))?;
+        let third = sessions.issue_at(b"3", at(20))?;
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions.redeem_at(&first, at(21)), None);
+        assert!(sessions.redeem_at(&second, at(21)).is_some());
+        assert!(sessions.redeem_at(&third, at(21)).is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn persists_across_restarts() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let sessions = Sessions::new(LIFETIME, 10);
+        let token = sessions.issue(b"resume me")?;
+        sessions.issue_at(b"long expired", at(0))?;
+        sessions.save(dir.path())?;
+
+        let sessions = Sessions::load(dir.path(), LIFETIME, 10)?;
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions.redeem(&token).as_deref(), Some(&b"resume me"[..]));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(dir.path().join(STATE_FILE))?
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let empty = tempfile::tempdir()?;
+        assert!(Sessions::load(empty.path(), LIFETIME, 10)?.is_empty());
+
+        std::fs::write(dir.path().join(STATE_FILE), [0; 12])?;
+        assert!(Sessions::load(dir.path(), LIFETIME, 10).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+//! Helpers for stopping a pluggable transport process when its parent asks it to.
+
+use crate::Result;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+/// Set to `1` by the parent process when the transport should exit once its stdin is closed.
+pub const EXIT_ON_STDIN_CLOSE: &str = "TOR_PT_EXIT_ON_STDIN_CLOSE";
+
+/// Cancel `shutdown` when stdin is closed, if the parent asked for that through
+/// [`EXIT_ON_STDIN_CLOSE`]. The watcher runs as a task on the current runtime and stops early
+/// if `shutdown` is cancelled for another reason. Returns `None` when the variable isn't set,
+/// in which case stdin is left alone.
+pub fn exit_on_stdin_close(shutdown: CancellationToken) -> Option<JoinHandle<()>> {
+    if std::env::var(EXIT_ON_STDIN_CLOSE).as_deref() != Ok("1") {
+        return None;
+    }
+    Some(tokio::spawn(cancel_on_eof(tokio::io::stdin(), shutdown)))
+}
+
+/// Returns a token that is cancelled on SIGINT or SIGTERM (only ctrl-c off unix), so every
+/// task holding a clone can wind down. Must be called from within a runtime; the handlers are
+/// installed before this returns.
+pub fn listen() -> Result<CancellationToken> {
+    let shutdown = CancellationToken::new();
+    let mut signals = Signals::new(false)?;
+    let token = shutdown.clone();
+    tokio::spawn(async move {
+        while signals.recv().await != Caught::Terminate {}
+        token.cancel();
+    });
+    Ok(shutdown)
+}
+
+/// Like [`listen`], but SIGHUP is also caught and reported on the returned channel rather than
+/// terminating the process, e.g. to reload configuration. Off unix nothing is ever sent.
+pub fn listen_with_reload() -> Result<(CancellationToken, mpsc::Receiver<()>)> {
+    let shutdown = CancellationToken::new();
+    let (reload_tx, reload_rx) = mpsc::channel(1);
+    let mut signals = Signals::new(true)?;
+    let token = shutdown.clone();
+    tokio::spawn(async move {
+        while signals.recv().await == Caught::Hangup {
+            // a reload already pending covers this one too
+            let _ = reload_tx.try_send(());
+        }
+        token.cancel();
+    });
+    Ok((shutdown, reload_rx))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Caught {
+    Terminate,
+    Hangup,
+}
+
+#[cfg(unix)]
+struct Signals {
+    interrupt: tokio::signal::unix::Signal,
+    terminate: tokio::signal::unix::Signal,
+    hangup: Option<tokio::signal::unix::Signal>,
+}
+
+#[cfg(unix)]
+impl Signals {
+    fn new(hangup: bool) -> Result<Self> {
+        use tokio::signal::unix::{signal, SignalKind};
+        Ok(Self {
+            interrupt: signal(SignalKind::interrupt())?,
+            terminate: signal(SignalKind::terminate())?,
+            hangup: hangup.then(|| signal(SignalKind::hangup())).transpose()?,
+        })
+    }
+
+    async fn recv(&mut self) -> Caught {
+        let hangup = async {
+            match &mut self.hangup {
+                Some(hangup) => hangup.recv().await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            _ = self.interrupt.recv() => Caught::Terminate,
+            _ = self.terminate.recv() => Caught::Terminate,
+            _ = hangup => Caught::Hangup,
+        }
+    }
+}
+
+#[cfg(not(unix))]
+struct Signals;
+
+#[cfg(not(unix))]
+impl Signals {
+    fn new(_hangup: bool) -> Result<Self> {
+        Ok(Signals)
+    }
+
+    async fn recv(&mut self) -> Caught {
+        let _ = tokio::signal::ctrl_c().await;
+        Caught::Terminate
+    }
+}
+
+/// Read and discard `r` until it ends or errors, then cancel `shutdown`.
+async fn cancel_on_eof<R: AsyncRead + Unpin>(mut r: R, shutdown: CancellationToken) {
+    let mut buf = [0u8; 256];
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => return,
+            n = r.read(&mut buf) => match n {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            },
+        }
+    }
+    shutdown.cancel();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::env::EnvGuard;
+
+    use tokio::io::AsyncWriteExt;
+
+    #[test]
+    fn leaves_stdin_alone_unless_asked() {
+        let mut env = EnvGuard::lock();
+        env.remove(EXIT_ON_STDIN_CLOSE);
+        assert!(exit_on_stdin_close(CancellationToken::new()).is_none());
+        env.set(EXIT_ON_STDIN_CLOSE, "0");
+        assert!(exit_on_stdin_close(CancellationToken::new()).is_none());
+    }
+
+    #[tokio::test]
+    async fn cancels_on_eof() {
+        let (mut parent, child) = tokio::io::duplex(64);
+        let shutdown = CancellationToken::new();
+        let watcher = tokio::spawn(cancel_on_eof(child, shutdown.clone()));
+
+        parent.write_all(b"still here").await.unwrap();
+        tokio::task::yield_now().await;
+        assert!(!shutdown.is_cancelled());
+
+        drop(parent);
+        watcher.await.unwrap();
+        assert!(shutdown.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn stops_when_cancelled_elsewhere() {
+        let (_parent, child) = tokio::io::duplex(64);
+        let shutdown = CancellationToken::new();
+        let watcher = tokio::spawn(cancel_on_eof(child, shutdown.clone()));
+        shutdown.cancel();
+        watcher.await.unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn hangup_reloads_then_term_cancels() {
+        let (shutdown, mut reload) = listen_with_reload().unwrap();
+        let raise = |sig| unsafe { libc::kill(libc::getpid(), sig) };
+
+        raise(libc::SIGHUP);
+        tokio::time::timeout(std::time::Duration::from_secs(5), reload.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!shutdown.is_cancelled());
+
+        raise(libc::SIGTERM);
+        tokio::time::timeout(std::time::Duration::from_secs(5), shutdown.cancelled())
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,193 @@
+//! In-kernel copy between TCP sockets using `splice(2)`, used as a fast path for passthrough
+//! transforms.
+
+use tokio::io::Interest;
+use tokio::net::TcpStream;
+
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+/// Max bytes moved through the intermediate pipe per splice call (the default pipe capacity).
+const PIPE_SIZE: usize = 64 * 1024;
+
+/// Copies data in both directions between `a` and `b` without passing it through userspace.
+///
+/// When one side reports EOF the write half of the other side is shut down, and copying in the
+/// other direction continues, matching [`tokio::io::copy_bidirectional`]. Returns the number of
+/// bytes copied from `a` to `b` and from `b` to `a`.
+pub(crate) async fn copy_bidirectional(
+    a: &mut TcpStream,
+    b: &mut TcpStream,
+) -> io::Result<(u64, u64)> {
+    let (a, b) = (&*a, &*b);
+    tokio::try_join!(splice_one_direction(a, b), splice_one_direction(b, a))
+}
+
+async fn splice_one_direction(r: &TcpStream, w: &TcpStream) -> io::Result<u64> {
+    let pipe = Pipe::new()?;
+    let mut total = 0;
+
+    loop {
+        // Move whatever is available on the socket into the pipe.
+        let n = loop {
+            r.readable().await?;
+            match r.try_io(Interest::READABLE, || {
+                splice(r.as_raw_fd(), pipe.w.as_raw_fd(), PIPE_SIZE)
+            }) {
+                Ok(n) => break n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e),
+            }
+        };
+
+        if n == 0 {
+            shutdown_write(w)?;
+            return Ok(total);
+        }
+
+        // Drain the pipe into the other socket before reading more.
+        let mut remaining = n;
+        while remaining > 0 {
+            w.writable().await?;
+            match w.try_io(Interest::WRITABLE, || {
+                splice(pipe.r.as_raw_fd(), w.as_raw_fd(), remaining)
+            }) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "write zero byte into writer",
+                    ))
+                }
+                Ok(m) => remaining -= m,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        total += n as u64;
+    }
+}
+
+fn splice(fd_in: RawFd, fd_out: RawFd, len: usize) -> io::Result<usize> {
+    // SAFETY: both descriptors are owned by live objects for the duration of the call and
+    // null offsets are valid for sockets and pipes.
+    let n = unsafe {
+        libc::splice(
+            fd_in,
+            std::ptr::null_mut(),
+            fd_out,
+            std::ptr::null_mut(),
+            len,
+            libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK,
+        )
+    };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(n as usize)
+}
+
+fn shutdown_write(s: &TcpStream) -> io::Result<()> {
+    // SAFETY: the descriptor is owned by `s` which outlives the call.
+    if unsafe { libc::shutdown(s.as_raw_fd(), libc::SHUT_WR) } < 0 {
+        let e = io::Error::last_os_error();
+        // The peer may have already gone away, in which case there is nothing to shut down.
+        if e.kind() != io::ErrorKind::NotConnected {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Non-blocking pipe used as the in-kernel staging buffer between two sockets.
+struct Pipe {
+    r: OwnedFd,
+    w: OwnedFd,
+}
+
+impl Pipe {
+    fn new() -> io::Result<Self> {
+        let mut fds = [0 as RawFd; 2];
+        // SAFETY: `fds` has room for the two descriptors written by pipe2.
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: pipe2 succeeded so both descriptors are open and owned by us.
+        unsafe {
+            Ok(Self {
+                r: OwnedFd::from_raw_fd(fds[0]),
+                w: OwnedFd::from_raw_fd(fds[1]),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::pt::copy::{as_tcp, copy_bidirectional_tcp, HalfClosePolicy};
+    use crate::stream::AnyStream;
+    use crate::transports::identity::Identity;
+    use crate::DuplexTransform;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    async fn tcp_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (client, accepted) = tokio::join!(TcpStream::connect(addr), listener.accept());
+        (client.unwrap(), accepted.unwrap().0)
+    }
+
+    ///
+    ///        | source | -> | plaintext | == splice == | ciphertext | -> | echo |
+    ///                  tcp                                          tcp
+    ///
+    #[tokio::test]
+    async fn splice_identity_end_to_end() {
+        assert!(DuplexTransform::<TcpStream, TcpStream>::is_passthrough(
+            &Identity::new()
+        ));
+
+        let (mut source, plaintext) = tcp_pair().await;
+        let (mut ciphertext, mut echo) = tcp_pair().await;
+
+        // as the proxy holds an accepted socket
+        let mut plaintext: Box<dyn AnyStream> = Box::new(plaintext);
+        assert!(as_tcp(&mut plaintext).is_some());
+        assert!(as_tcp(&mut tokio::io::empty()).is_none());
+
+        let proxy_task = tokio::spawn(async move {
+            copy_bidirectional_tcp(
+                &Identity::new(),
+                &mut plaintext,
+                &mut ciphertext,
+                HalfClosePolicy::PropagateHalfClose,
+            )
+            .await
+        });
+
+        let echo_task = tokio::spawn(async move {
+            let (mut r, mut w) = echo.split();
+            tokio::io::copy(&mut r, &mut w).await.unwrap();
+            w.shutdown().await.unwrap();
+        });
+
+        let (mut source_r, mut source_w) = source.split();
+        let write_all = async {
+            let write_me = vec![7_u8; 1024];
+            for _ in 0..1024 {
+                source_w.write_all(&write_me).await.unwrap();
+            }
+            source_w.shutdown().await.unwrap();
+        };
+        let mut out = vec![];
+        let (_, nr) = tokio::join!(write_all, source_r.read_to_end(&mut out));
+        assert_eq!(nr.unwrap(), 1024 * 1024);
+        assert!(out.iter().all(|b| *b == 7));
+
+        echo_task.await.unwrap();
+        let (up, down) = proxy_task.await.unwrap().unwrap();
+        assert_eq!(up, 1024 * 1024);
+        assert_eq!(down, 1024 * 1024);
+    }
+}
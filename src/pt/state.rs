@@ -0,0 +1,355 @@
+//! Files a transport keeps in its state directory (`TOR_PT_STATE_LOCATION`).
+//!
+//! Bridges often run on shared hosts, so with the `encrypted-state` feature the sensitive
+//! files (replay filters, resumption secrets) can be sealed with a key derived from a
+//! passphrase, or with a raw key kept elsewhere, e.g. in the OS keyring. Sealed files are
+//! encrypted with ChaCha20-Poly1305 and bound to their file name, so one can't be swapped in
+//! for another. A directory that has a key refuses to read plaintext files, since an attacker
+//! who can write to it could otherwise replace a sealed file with one of their own; an
+//! existing deployment runs `migrate_plaintext` once to seal its files in place.
+//!
+//! ```text
+//! sealed file:  "ptrsenc1" | nonce (12) | ciphertext | tag (16)
+//! ```
+
+use crate::{Error, Result};
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Start of every sealed file.
+pub const MAGIC: &[u8; 8] = b"ptrsenc1";
+
+/// Files holding secrets, which `migrate_plaintext` seals.
+pub const SENSITIVE: &[&str] = &[super::replay::STATE_FILE, super::session::STATE_FILE];
+
+/// Random salt the passphrase is stretched with, stored in the clear next to the files.
+#[cfg(feature = "encrypted-state")]
+pub const SALT_FILE: &str = "state_salt";
+
+#[cfg(feature = "encrypted-state")]
+const SALT_LEN: usize = 16;
+
+#[cfg(feature = "encrypted-state")]
+const NONCE_LEN: usize = 12;
+
+/// Key sealing the files in a [`StateDir`].
+#[cfg(feature = "encrypted-state")]
+#[derive(Clone)]
+pub struct StateKey([u8; 32]);
+
+#[cfg(feature = "encrypted-state")]
+impl StateKey {
+    /// Use `key` as is, e.g. one read from the OS keyring.
+    pub fn from_bytes(key: [u8; 32]) -> Self {
+        Self(key)
+    }
+
+    /// Stretch `passphrase` with Argon2id.
+    pub fn from_passphrase(passphrase: &[u8], salt: &[u8]) -> Result<Self> {
+        let mut key = zeroize::Zeroizing::new([0u8; 32]);
+        argon2::Argon2::default()
+            .hash_password_into(passphrase, salt, &mut *key)
+            .map_err(|e| Error::Config(format!("failed to derive state key: {e}").into()))?;
+        Ok(Self(*key))
+    }
+
+    fn seal(&self, name: &str, plaintext: &[u8]) -> Result<Vec<u8>> {
+        use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+        use chacha20poly1305::ChaCha20Poly1305;
+
+        let cipher = ChaCha20Poly1305::new(&self.0.into());
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: plaintext,
+            aad: name.as_bytes(),
+        };
+        let sealed = cipher
+            .encrypt(&nonce, payload)
+            .map_err(|_| Error::new("failed to seal state file"))?;
+        Ok([&MAGIC[..], &nonce, &sealed].concat())
+    }
+
+    fn open(&self, name: &str, data: &[u8]) -> Result<Vec<u8>> {
+        use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+        use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+
+        let body = &data[MAGIC.len()..];
+        if body.len() < NONCE_LEN {
+            return Err(Error::new(format!("state file {name} is truncated")));
+        }
+        let (nonce, sealed) = body.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: sealed,
+            aad: name.as_bytes(),
+        };
+        ChaCha20Poly1305::new(&self.0.into())
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| Error::new(format!("state file {name} is corrupt or the key is wrong")))
+    }
+}
+
+#[cfg(feature = "encrypted-state")]
+impl zeroize::Zeroize for StateKey {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+#[cfg(feature = "encrypted-state")]
+impl Drop for StateKey {
+    fn drop(&mut self) {
+        zeroize::Zeroize::zeroize(self);
+    }
+}
+
+#[cfg(feature = "encrypted-state")]
+impl zeroize::ZeroizeOnDrop for StateKey {}
+
+/// A transport's state directory.
+pub struct StateDir {
+    path: PathBuf,
+    #[cfg(feature = "encrypted-state")]
+    key: Option<StateKey>,
+}
+
+impl StateDir {
+    /// Files are written in the clear.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            #[cfg(feature = "encrypted-state")]
+            key: None,
+        }
+    }
+
+    /// Every file written through the directory is sealed with `key`.
+    #[cfg(feature = "encrypted-state")]
+    pub fn with_key(path: impl Into<PathBuf>, key: StateKey) -> Self {
+        Self {
+            path: path.into(),
+            key: Some(key),
+        }
+    }
+
+    /// Every file written through the directory is sealed with a key derived from
+    /// `passphrase` and the directory's [`SALT_FILE`], which is created on first use.
+    #[cfg(feature = "encrypted-state")]
+    pub fn with_passphrase(path: impl Into<PathBuf>, passphrase: &[u8]) -> Result<Self> {
+        use rand::RngCore;
+
+        let path = path.into();
+        let salt_path = path.join(SALT_FILE);
+        let salt = match std::fs::read(&salt_path) {
+            Ok(salt) => salt,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let mut salt = vec![0u8; SALT_LEN];
+                rand::rngs::OsRng.fill_bytes(&mut salt);
+                write_atomic(&salt_path, &salt)?;
+                salt
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let key = StateKey::from_passphrase(passphrase, &salt)?;
+        Ok(Self::with_key(path, key))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether files written through this directory are sealed.
+    pub fn is_encrypted(&self) -> bool {
+        #[cfg(feature = "encrypted-state")]
+        return self.key.is_some();
+        #[cfg(not(feature = "encrypted-state"))]
+        false
+    }
+
+    /// Replace the file `name` with `data`, sealed if the directory has a key. The file is
+    /// only readable by its owner on unix, and a crash never leaves it half written.
+    pub fn write(&self, name: &str, data: &[u8]) -> Result<()> {
+        #[cfg(feature = "encrypted-state")]
+        if let Some(key) = &self.key {
+            return write_atomic(&self.path.join(name), &key.seal(name, data)?);
+        }
+        write_atomic(&self.path.join(name), data)
+    }
+
+    /// Contents of the file `name`, or `None` if there isn't one. With a key, the file must
+    /// be sealed.
+    pub fn read(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let data = match std::fs::read(self.path.join(name)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        #[cfg(feature = "encrypted-state")]
+        if let Some(key) = &self.key {
+            if !is_sealed(&data) {
+                return Err(Error::Config(
+                    format!("state file {name} is not sealed; migrate it first").into(),
+                ));
+            }
+            return key.open(name, &data).map(Some);
+        }
+        if is_sealed(&data) {
+            return Err(Error::Config(
+                format!("state file {name} is encrypted but no state key was given").into(),
+            ));
+        }
+        Ok(Some(data))
+    }
+}
+
+/// Seal each of the [`SENSITIVE`] files in `dir` that is still in the clear, returning the
+/// names of those it sealed.
+#[cfg(feature = "encrypted-state")]
+pub fn migrate_plaintext(dir: &StateDir) -> Result<Vec<&'static str>> {
+    if !dir.is_encrypted() {
+        return Err(Error::Config("no state key to migrate with".into()));
+    }
+    let mut migrated = vec![];
+    for &name in SENSITIVE {
+        let Ok(data) = std::fs::read(dir.path.join(name)) else {
+            continue;
+        };
+        if !is_sealed(&data) {
+            dir.write(name, &data)?;
+            migrated.push(name);
+        }
+    }
+    Ok(migrated)
+}
+
+fn is_sealed(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    // the mode only applies to a new file, so a temp file left by an earlier crash must not
+    // be reused with whatever permissions it has
+    match std::fs::remove_file(&tmp) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    std::fs::rename(tmp, path)?;
+    // and the rename itself is only durable once the directory is synced
+    #[cfg(unix)]
+    {
+        let dir = path.parent().filter(|d| !d.as_os_str().is_empty());
+        std::fs::File::open(dir.unwrap_or(Path::new(".")))?.sync_all()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn plaintext_round_trip() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dir = StateDir::new(tmp.path());
+        assert!(!dir.is_encrypted());
+        assert_eq!(dir.read("missing")?, None);
+        dir.write("file", b"contents")?;
+        assert_eq!(std::fs::read(tmp.path().join("file"))?, b"contents");
+        assert_eq!(dir.read("file")?.as_deref(), Some(&b"contents"[..]));
+
+        std::fs::write(tmp.path().join("sealed"), [&MAGIC[..], &[0; 40]].concat())?;
+        assert!(matches!(dir.read("sealed"), Err(Error::Config(_))));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stale_temp_files_are_replaced() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::tempdir()?;
+        let stale = tmp.path().join("file.tmp");
+        std::fs::write(&stale, b"left over from a crash")?;
+        std::fs::set_permissions(&stale, std::fs::Permissions::from_mode(0o644))?;
+
+        StateDir::new(tmp.path()).write("file", b"contents")?;
+        let written = std::fs::metadata(tmp.path().join("file"))?;
+        assert_eq!(written.permissions().mode() & 0o777, 0o600);
+        assert_eq!(std::fs::read(tmp.path().join("file"))?, b"contents");
+        assert!(!stale.exists());
+        Ok(())
+    }
+
+    #[cfg(feature = "encrypted-state")]
+    #[test]
+    fn key_zeroizes() {
+        fn wiped_on_drop<T: zeroize::Zeroize + zeroize::ZeroizeOnDrop>() {}
+        wiped_on_drop::<StateKey>();
+    }
+
+    #[cfg(feature = "encrypted-state")]
+    #[test]
+    fn sealed_files_need_the_key() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let dir = StateDir::with_key(tmp.path(), StateKey::from_bytes([7; 32]));
+        dir.write("secret", b"contents")?;
+        let on_disk = std::fs::read(tmp.path().join("secret"))?;
+        assert!(on_disk.starts_with(MAGIC));
+        assert!(!on_disk.windows(8).any(|w| w == b"contents"));
+        assert_eq!(dir.read("secret")?.as_deref(), Some(&b"contents"[..]));
+
+        // bound to the file name and the key
+        std::fs::copy(tmp.path().join("secret"), tmp.path().join("other"))?;
+        assert!(dir.read("other").is_err());
+        let wrong = StateDir::with_key(tmp.path(), StateKey::from_bytes([8; 32]));
+        assert!(wrong.read("secret").is_err());
+        assert!(StateDir::new(tmp.path()).read("secret").is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "encrypted-state")]
+    #[test]
+    fn migrates_plaintext_files() -> Result<()> {
+        use crate::pt::{replay, session};
+        use std::time::Duration;
+
+        let tmp = tempfile::tempdir()?;
+        let filter = replay::Filter::new(Duration::from_secs(60), 10);
+        filter.test_and_set(b"handshake mac");
+        filter.save(tmp.path())?;
+        std::fs::write(tmp.path().join("bridge-stats.json"), b"{}")?;
+
+        let dir = StateDir::with_passphrase(tmp.path(), b"correct horse")?;
+        // plaintext is refused until it has been migrated
+        assert!(matches!(
+            dir.read(replay::STATE_FILE),
+            Err(Error::Config(_))
+        ));
+        assert!(replay::Filter::load_from(&dir, Duration::from_secs(60), 10).is_err());
+        assert_eq!(migrate_plaintext(&dir)?, [replay::STATE_FILE]);
+        assert!(migrate_plaintext(&dir)?.is_empty());
+        assert!(std::fs::read(tmp.path().join(replay::STATE_FILE))?.starts_with(MAGIC));
+        assert_eq!(std::fs::read(tmp.path().join("bridge-stats.json"))?, b"{}");
+
+        // the salt is kept, so the same passphrase opens the files again
+        let dir = StateDir::with_passphrase(tmp.path(), b"correct horse")?;
+        let filter = replay::Filter::load_from(&dir, Duration::from_secs(60), 10)?;
+        assert!(filter.test_and_set(b"handshake mac"));
+
+        let sessions = session::Sessions::new(Duration::from_secs(60), 10);
+        let token = sessions.issue(b"resume")?;
+        sessions.save_to(&dir)?;
+        let sessions = session::Sessions::load_from(&dir, Duration::from_secs(60), 10)?;
+        assert!(sessions.redeem(&token).is_some());
+        Ok(())
+    }
+}
@@ -0,0 +1,242 @@
+//! `STATUS` lines a client transport writes as each connection to its bridge progresses, so
+//! the parent (tor, or a monitoring tool built on [`crate::manager`]) can show whether the
+//! bridge is reachable and where connections fail:
+//!
+//! ```text
+//! STATUS TRANSPORT=obfs4 ADDRESS=198.51.100.7:443 CONNECT=Success
+//! STATUS TRANSPORT=obfs4 ADDRESS=198.51.100.7:443 HANDSHAKE=Failed ERRSTR="bad auth"
+//! STATUS TRANSPORT=obfs4 ADDRESS=198.51.100.7:443 DONE=Success
+//! ```
+
+use crate::parser::PtLine;
+use crate::{Error, Result};
+
+use std::borrow::Cow;
+use std::fmt::{self, Write as _};
+use std::io::Write as _;
+
+/// Set by the parent process when the transport is run in managed mode.
+pub const MANAGED_TRANSPORT_VER: &str = "TOR_PT_MANAGED_TRANSPORT_VER";
+
+/// How far a connection got.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// Connecting to the bridge.
+    Connect,
+    /// The transport's handshake over that connection.
+    Handshake,
+    /// The connection closed, cleanly or not.
+    Done,
+}
+
+impl Stage {
+    const ALL: [Stage; 3] = [Stage::Connect, Stage::Handshake, Stage::Done];
+
+    fn key(self) -> &'static str {
+        match self {
+            Stage::Connect => "CONNECT",
+            Stage::Handshake => "HANDSHAKE",
+            Stage::Done => "DONE",
+        }
+    }
+}
+
+/// One connection event, formatted as a `STATUS` line by its `Display` impl.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConnectStatus {
+    pub transport: String,
+    /// The bridge the connection is to.
+    pub address: String,
+    pub stage: Stage,
+    /// `None` if the stage succeeded, otherwise what went wrong.
+    pub error: Option<String>,
+}
+
+impl fmt::Display for ConnectStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "STATUS TRANSPORT={} ADDRESS={} {}=",
+            quote(&self.transport),
+            quote(&self.address),
+            self.stage.key()
+        )?;
+        match &self.error {
+            None => write!(f, "Success"),
+            Some(e) => write!(f, "Failed ERRSTR={}", quote(e)),
+        }
+    }
+}
+
+impl TryFrom<&PtLine> for ConnectStatus {
+    type Error = Error;
+
+    /// Read a connection event back from a parsed `STATUS` line. Fails for lines that are
+    /// not `STATUS` lines or report something else.
+    fn try_from(line: &PtLine) -> Result<Self> {
+        let PtLine::Status { transport, fields } = line else {
+            return Err(status_error("not a STATUS line"));
+        };
+        let get = |key: &str| fields.iter().find(|(k, _)| k == key).map(|(_, v)| v);
+
+        let mut stages = Stage::ALL
+            .into_iter()
+            .filter_map(|s| get(s.key()).map(|v| (s, v)));
+        let (stage, outcome) = match (stages.next(), stages.next()) {
+            (Some(found), None) => found,
+            _ => return Err(status_error("STATUS line is not a connection event")),
+        };
+        let address = get("ADDRESS")
+            .ok_or_else(|| status_error("connection STATUS missing ADDRESS"))?
+            .clone();
+        let error = match outcome.as_str() {
+            "Success" => None,
+            "Failed" => Some(get("ERRSTR").cloned().unwrap_or_default()),
+            o => {
+                return Err(status_error(format!(
+                    "unknown {} outcome \"{o}\"",
+                    stage.key()
+                )))
+            }
+        };
+        Ok(Self {
+            transport: transport.clone(),
+            address,
+            stage,
+            error,
+        })
+    }
+}
+
+fn status_error(msg: impl Into<String>) -> Error {
+    Error::Other(msg.into().into())
+}
+
+/// C-style quote `v` if it would otherwise be split or misread by the parent.
+pub(crate) fn quote(v: &str) -> Cow<'_, str> {
+    let plain = |c: char| c != ' ' && c != '"' && c != '\\' && !c.is_control();
+    if !v.is_empty() && v.chars().all(plain) {
+        return Cow::Borrowed(v);
+    }
+    let mut out = String::with_capacity(v.len() + 2);
+    out.push('"');
+    for c in v.chars() {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            // control characters all sit below 0o400, so three octal digits cover them
+            c if c.is_control() => {
+                let _ = write!(out, "\\{:03o}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    Cow::Owned(out)
+}
+
+/// Reports connection events for one transport on stdout when running as a managed
+/// transport (per [`MANAGED_TRANSPORT_VER`]), and does nothing otherwise.
+#[derive(Clone, Debug)]
+pub struct StatusReporter {
+    transport: String,
+    enabled: bool,
+}
+
+impl StatusReporter {
+    pub fn from_env(transport: &str) -> Self {
+        Self {
+            transport: transport.to_string(),
+            enabled: std::env::var_os(MANAGED_TRANSPORT_VER).is_some(),
+        }
+    }
+
+    /// Report that the connection to `address` reached the end of `stage` with `result`.
+    pub fn report<E: fmt::Display>(
+        &self,
+        address: impl fmt::Display,
+        stage: Stage,
+        result: std::result::Result<(), E>,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        let status = ConnectStatus {
+            transport: self.transport.clone(),
+            address: address.to_string(),
+            stage,
+            error: result.err().map(|e| e.to_string()),
+        };
+        // the parent reads whole lines, so hold the lock across the write
+        let mut out = std::io::stdout().lock();
+        let _ = writeln!(out, "{status}").and_then(|_| out.flush());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::parse_line;
+    use crate::test_utils::env::EnvGuard;
+
+    fn status(stage: Stage, error: Option<&str>) -> ConnectStatus {
+        ConnectStatus {
+            transport: "obfs4".into(),
+            address: "198.51.100.7:443".into(),
+            stage,
+            error: error.map(String::from),
+        }
+    }
+
+    #[test]
+    fn round_trips_through_the_parser() -> Result<()> {
+        let connected = status(Stage::Connect, None);
+        assert_eq!(
+            connected.to_string(),
+            "STATUS TRANSPORT=obfs4 ADDRESS=198.51.100.7:443 CONNECT=Success"
+        );
+
+        let failed = status(Stage::Handshake, Some("bad \"auth\"\\\n\x07"));
+        assert_eq!(
+            failed.to_string(),
+            r#"STATUS TRANSPORT=obfs4 ADDRESS=198.51.100.7:443 HANDSHAKE=Failed ERRSTR="bad \"auth\"\\\n\007""#
+        );
+
+        for s in [connected, failed, status(Stage::Done, Some(""))] {
+            let line = parse_line(&s.to_string())?;
+            assert_eq!(ConnectStatus::try_from(&line)?, s);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_other_lines() -> Result<()> {
+        for line in [
+            "LOG SEVERITY=notice MESSAGE=hi",
+            "STATUS TRANSPORT=obfs4 TYPE=bootstrap",
+            "STATUS TRANSPORT=obfs4 CONNECT=Success",
+            "STATUS TRANSPORT=obfs4 ADDRESS=1.2.3.4:5 CONNECT=Maybe",
+            "STATUS TRANSPORT=obfs4 ADDRESS=1.2.3.4:5 CONNECT=Success DONE=Success",
+        ] {
+            assert!(
+                ConnectStatus::try_from(&parse_line(line)?).is_err(),
+                "{line}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reports_only_when_managed() {
+        let mut env = EnvGuard::lock();
+        env.remove(MANAGED_TRANSPORT_VER);
+        assert!(!StatusReporter::from_env("obfs4").enabled);
+        env.set(MANAGED_TRANSPORT_VER, "1");
+        assert!(StatusReporter::from_env("obfs4").enabled);
+    }
+}
@@ -1,28 +1,41 @@
 use std::io;
 use std::marker::PhantomData;
+use std::mem::MaybeUninit;
 use std::pin::Pin;
-use std::task::{Context, Poll};
+use std::task::{ready, Context, Poll};
 
-use pin_project::pin_project;
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 
+use crate::codec;
+pub use crate::codec::ChunkTransform;
+use crate::{Error, Result};
+
 pub trait BufferTransform<'a, R, W>
 where
     R: AsyncRead + ?Sized + 'a,
     W: AsyncWrite + ?Sized + 'a,
 {
+    /// Transform bytes from `reader` into `writer`, returning `Ready` once `reader` has
+    /// reached eof and all output has been written, and `Pending` while waiting on either
+    /// side. Output the writer could not accept must be kept and retried on the next call.
     fn poll_copy(
         &mut self,
         cx: &mut Context<'_>,
         reader: Pin<&mut R>,
         writer: Pin<&mut W>,
     ) -> Poll<io::Result<u64>>;
+
+    /// Reports whether this transform copies bytes through unmodified.
+    fn is_passthrough(&self) -> bool {
+        false
+    }
 }
 
-impl<'a, R, W> BufferTransform<'a, R, W> for Box<dyn BufferTransform<'a, R, W> + 'a>
+impl<'a, R, W, T> BufferTransform<'a, R, W> for Box<T>
 where
-    R: AsyncRead + Unpin + Send + Sync + ?Sized + 'a,
-    W: AsyncWrite + Unpin + Send + Sync + ?Sized + 'a,
+    R: AsyncRead + ?Sized + 'a,
+    W: AsyncWrite + ?Sized + 'a,
+    T: BufferTransform<'a, R, W> + ?Sized,
 {
     fn poll_copy(
         &mut self,
@@ -32,6 +45,10 @@ where
     ) -> Poll<io::Result<u64>> {
         (**self).poll_copy(cx, reader, writer)
     }
+
+    fn is_passthrough(&self) -> bool {
+        (**self).is_passthrough()
+    }
 }
 
 // impl<'a, R, W> BufferTransform<'a, Box<R>, Box<W>> for Box<dyn BufferTransform<'a,R,W> + 'a>
@@ -66,29 +83,236 @@ where
 //     }
 // }
 
-#[pin_project]
-pub struct ReadTransform<'a, T, R, W>
+/// Makes a fresh transform for each connection, since transforms usually carry state (cipher
+/// state, counters, partial frames) that can't be shared between streams.
+///
+/// Implemented for any `Fn() -> T` returning a transform.
+pub trait TransformFactory<'a, R, W>: Send + Sync
 where
-    R: AsyncRead + Unpin + Send + Sync + 'a,
-    W: AsyncWrite + Unpin + Send + Sync + 'a,
+    R: AsyncRead + ?Sized + 'a,
+    W: AsyncWrite + ?Sized + 'a,
+{
+    fn make(&self) -> Box<dyn BufferTransform<'a, R, W> + Unpin + Send + Sync + 'a>;
+
+    /// Apply `args` to the transforms made from now on, see
+    /// [`Wrapper::with_seal_config`](crate::wrap::Wrapper::with_seal_config). Factories without
+    /// options reject anything but an empty string.
+    fn configure(&mut self, args: &str) -> Result<()> {
+        no_options(args)
+    }
+}
+
+/// For [`TransformFactory::configure`] and the like on types that take no options.
+pub(crate) fn no_options(args: &str) -> Result<()> {
+    match args.trim().is_empty() {
+        true => Ok(()),
+        false => Err(Error::Config(
+            format!("no options are supported, got \"{args}\"").into(),
+        )),
+    }
+}
+
+impl<'a, R, W, F, T> TransformFactory<'a, R, W> for F
+where
+    R: AsyncRead + ?Sized + 'a,
+    W: AsyncWrite + ?Sized + 'a,
+    F: Fn() -> T + Send + Sync,
     T: BufferTransform<'a, R, W> + Unpin + Send + Sync + 'a,
+{
+    fn make(&self) -> Box<dyn BufferTransform<'a, R, W> + Unpin + Send + Sync + 'a> {
+        Box::new(self())
+    }
+}
+
+/// Size of the reads [`Chunked`] makes from its reader.
+pub(crate) const CHUNK_SIZE: usize = 4096;
+
+pub(crate) fn codec_error(e: codec::Error) -> io::Error {
+    let kind = match e {
+        codec::Error::InvalidData(_) => io::ErrorKind::InvalidData,
+        codec::Error::Truncated(_) => io::ErrorKind::UnexpectedEof,
+    };
+    io::Error::new(kind, e)
+}
+
+/// Drives a [`ChunkTransform`] as a [`BufferTransform`], holding output the writer hasn't
+/// accepted yet.
+pub struct Chunked<C> {
+    inner: C,
+    out: Vec<u8>,
+    pos: usize,
+    finished: bool,
+    total: u64,
+}
+
+impl<C: ChunkTransform> Chunked<C> {
+    pub fn new(inner: C) -> Self {
+        Chunked {
+            inner,
+            out: vec![],
+            pos: 0,
+            finished: false,
+            total: 0,
+        }
+    }
+}
+
+impl<'a, R, W, C> BufferTransform<'a, R, W> for Chunked<C>
+where
+    R: AsyncRead + Unpin + ?Sized + 'a,
+    W: AsyncWrite + Unpin + ?Sized + 'a,
+    C: ChunkTransform,
+{
+    fn poll_copy(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut reader: Pin<&mut R>,
+        mut writer: Pin<&mut W>,
+    ) -> Poll<io::Result<u64>> {
+        loop {
+            while self.pos < self.out.len() {
+                let n = ready!(writer.as_mut().poll_write(cx, &self.out[self.pos..]))?;
+                if n == 0 {
+                    return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+                }
+                self.pos += n;
+                self.total += n as u64;
+            }
+            self.out.clear();
+            self.pos = 0;
+            if self.finished {
+                return Poll::Ready(Ok(self.total));
+            }
+
+            let mut b = [MaybeUninit::<u8>::uninit(); CHUNK_SIZE];
+            let mut rb = ReadBuf::uninit(&mut b);
+            ready!(reader.as_mut().poll_read(cx, &mut rb))?;
+            let r = if rb.filled().is_empty() {
+                self.finished = true;
+                self.inner.finish(&mut self.out)
+            } else {
+                self.inner.transform(rb.filled(), &mut self.out)
+            };
+            r.map_err(codec_error)?;
+        }
+    }
+}
+
+/// Bytes staged between a [`BufferTransform`] and the caller of [`ReadTransform`] or
+/// [`WriteTransform`]. Holds at most [`STAGING_CAPACITY`] bytes; writes past that return
+/// `Pending` without registering a waker, since the owning transform stream drains the buffer
+/// before polling again.
+#[derive(Debug, Default)]
+pub struct Staging {
+    buf: Vec<u8>,
+    pos: usize,
+    eof: bool,
+    starved: bool,
+}
+
+/// Upper bound on the bytes held in a [`Staging`] buffer, so a slow consumer pushes back on
+/// the transform rather than growing the buffer without limit.
+pub const STAGING_CAPACITY: usize = 16 * 1024;
+
+impl Staging {
+    fn len(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn is_full(&self) -> bool {
+        self.len() >= STAGING_CAPACITY
+    }
+
+    /// Move as many staged bytes as fit into `dst`.
+    fn drain_into(&mut self, dst: &mut ReadBuf<'_>) {
+        let n = self.len().min(dst.remaining());
+        dst.put_slice(&self.buf[self.pos..self.pos + n]);
+        self.consume(n);
+    }
+
+    /// Stage as much of `src` as fits, returning how many bytes were taken.
+    fn fill_from(&mut self, src: &[u8]) -> usize {
+        let n = (STAGING_CAPACITY - self.len().min(STAGING_CAPACITY)).min(src.len());
+        self.buf.extend_from_slice(&src[..n]);
+        n
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.pos += n;
+        if self.pos == self.buf.len() {
+            self.buf.clear();
+            self.pos = 0;
+        }
+    }
+}
+
+impl AsyncRead for Staging {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.is_empty() && !self.eof {
+            self.starved = true;
+            return Poll::Pending;
+        }
+        self.drain_into(buf);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for Staging {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.fill_from(buf) {
+            0 if !buf.is_empty() => Poll::Pending,
+            n => Poll::Ready(Ok(n)),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Reader that applies `inner` to everything read from `r`.
+pub struct ReadTransform<'a, T, R>
+where
+    R: AsyncRead + Unpin + Send + Sync + 'a,
+    T: BufferTransform<'a, R, Staging> + Unpin + Send + Sync + 'a,
 {
     inner: T,
-    #[pin]
     r: R,
-    _phantom: PhantomData<&'a W>,
+    staged: Staging,
+    done: bool,
+    /// Why the transform failed, so every later read fails too rather than looking like EOF.
+    failed: Option<(io::ErrorKind, String)>,
+    _phantom: PhantomData<&'a ()>,
 }
 
-impl<'a, T, R, W> ReadTransform<'a, T, R, W>
+impl<'a, T, R> ReadTransform<'a, T, R>
 where
     R: AsyncRead + Unpin + Send + Sync + 'a,
-    W: AsyncWrite + Unpin + Send + Sync + 'a,
-    T: BufferTransform<'a, R, W> + Unpin + Send + Sync + 'a,
+    T: BufferTransform<'a, R, Staging> + Unpin + Send + Sync + 'a,
 {
     pub fn new(r: R, inner: T) -> Self {
         Self {
             inner,
             r,
+            staged: Staging::default(),
+            done: false,
+            failed: None,
             _phantom: PhantomData,
         }
     }
@@ -98,139 +322,253 @@ where
     }
 }
 
-#[pin_project]
-pub struct WriteTransform<'a, T, R, W>
+/// Writer that applies `inner` to everything written before passing it on to `w`.
+///
+/// Written bytes are staged and pushed through the transform as `w` accepts them, so a
+/// successful write only means the bytes were accepted; call `flush` to wait until they have
+/// been transformed and written to `w`. `shutdown` signals end of input to the transform so it
+/// can emit any trailing output before `w` is shut down.
+pub struct WriteTransform<'a, T, W>
 where
-    R: AsyncRead + Unpin + Send + Sync + 'a,
     W: AsyncWrite + Unpin + Send + Sync + 'a,
-    T: BufferTransform<'a, R, W> + Unpin + Send + Sync + 'a,
+    T: BufferTransform<'a, Staging, W> + Unpin + Send + Sync + 'a,
 {
     inner: T,
-    #[pin]
     w: W,
-    _phantom: PhantomData<&'a R>,
+    staged: Staging,
+    done: bool,
+    _phantom: PhantomData<&'a ()>,
 }
 
-impl<'a, T, R, W> WriteTransform<'a, T, R, W>
+impl<'a, T, W> WriteTransform<'a, T, W>
 where
-    R: AsyncRead + Unpin + Send + Sync + 'a,
     W: AsyncWrite + Unpin + Send + Sync + 'a,
-    T: BufferTransform<'a, R, W> + Unpin + Send + Sync + 'a,
+    T: BufferTransform<'a, Staging, W> + Unpin + Send + Sync + 'a,
 {
     pub fn new(w: W, inner: T) -> Self {
         Self {
             inner,
             w,
+            staged: Staging::default(),
+            done: false,
             _phantom: PhantomData,
         }
     }
+
     pub fn as_writer(self) -> Box<dyn AsyncWrite + Unpin + Send + Sync + 'a> {
         Box::new(self)
     }
+
+    /// Push staged bytes through the transform until it is waiting for more input. Returns
+    /// `Pending` only if `w` is applying back-pressure.
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.done {
+            return Poll::Ready(Ok(()));
+        }
+        self.staged.starved = false;
+        match self
+            .inner
+            .poll_copy(cx, Pin::new(&mut self.staged), Pin::new(&mut self.w))
+        {
+            Poll::Ready(r) => {
+                self.done = true;
+                Poll::Ready(r.map(|_| ()))
+            }
+            Poll::Pending if self.staged.starved => Poll::Ready(Ok(())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
 }
 
-impl<'a, T, R, W> AsyncRead for ReadTransform<'a, T, R, W>
+impl<'a, T, R> AsyncRead for ReadTransform<'a, T, R>
 where
     R: AsyncRead + Unpin + Send + Sync + 'a,
-    W: AsyncWrite + Unpin + Send + Sync + 'a,
-    T: BufferTransform<'a, R, W> + Unpin + Send + Sync + 'a,
+    T: BufferTransform<'a, R, Staging> + Unpin + Send + Sync + 'a,
 {
     fn poll_read(
-        mut self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-        buf: &mut ReadBuf,
-    ) -> std::task::Poll<std::io::Result<()>> {
-        let this = self.as_mut().project();
-        this.r.poll_read(cx, buf)
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        if let Some((kind, msg)) = &this.failed {
+            return Poll::Ready(Err(io::Error::new(*kind, msg.clone())));
+        }
+        if this.staged.is_empty() && !this.done {
+            match this
+                .inner
+                .poll_copy(cx, Pin::new(&mut this.r), Pin::new(&mut this.staged))
+            {
+                Poll::Ready(r) => {
+                    this.done = true;
+                    if let Err(e) = r {
+                        this.failed = Some((e.kind(), e.to_string()));
+                        return Poll::Ready(Err(e));
+                    }
+                }
+                // either `r` has nothing for us yet, in which case it has registered the waker,
+                // or the staging buffer filled up and there is something to return.
+                Poll::Pending if this.staged.is_empty() => return Poll::Pending,
+                Poll::Pending => {}
+            }
+        }
+        this.staged.drain_into(buf);
+        Poll::Ready(Ok(()))
     }
 }
 
-impl<'a, T, R, W> AsyncWrite for WriteTransform<'a, T, R, W>
+impl<'a, T, W> AsyncWrite for WriteTransform<'a, T, W>
 where
-    R: AsyncRead + Unpin + Send + Sync,
-    W: AsyncWrite + Unpin + Send + Sync,
-    T: BufferTransform<'a, R, W> + Unpin + Send + Sync,
+    W: AsyncWrite + Unpin + Send + Sync + 'a,
+    T: BufferTransform<'a, Staging, W> + Unpin + Send + Sync + 'a,
 {
     fn poll_write(
-        mut self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
         buf: &[u8],
-    ) -> std::task::Poll<std::io::Result<usize>> {
-        let this = self.as_mut().project();
-        this.w.poll_write(cx, buf)
-    }
-
-    fn poll_flush(
-        mut self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<std::io::Result<()>> {
-        let this = self.as_mut().project();
-        this.w.poll_flush(cx)
-    }
-
-    fn poll_shutdown(
-        mut self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<std::io::Result<()>> {
-        let this = self.as_mut().project();
-        this.w.poll_shutdown(cx)
-    }
-}
-
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-//     use futures::executor::block_on;
-//     use tokio::io::AsyncReadExt;
-
-//     struct ExampleTransform {}
-
-//     impl<'a,R,W> BufferTransform<'a,R,W> for ExampleTransform
-//     where
-//         R: AsyncRead + Unpin + Send + Sync + ?Sized + 'a,
-//         W: AsyncWrite + Unpin + Send + Sync + ?Sized + 'a,
-//     {
-//         fn poll_copy(
-//             &mut self,
-//             cx: &mut Context<'_>,
-//             reader: Pin<&mut R>,
-//             writer: Pin<&mut W>,
-//         ) -> Poll<io::Result<u64>> {
-//             let b = [0; 1024];
-//             let mut buf = ReadBuf::new(&mut b);
-//             let mut total = 0;
-//             loop {
-//                 let n = match reader.poll_read(cx, &mut buf) {
-//                     Poll::Ready(Ok(_)) => buf.filled().len(),
-//                     Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
-//                     Poll::Pending => return Poll::Ready(Ok(total)),
-//                 };
-//                 if n == 0 {
-//                     return Poll::Ready(Ok(total));
-//                 }
-//                 total += n as u64;
-//                 match writer.poll_write(cx, &b[..n]) {
-//                     Poll::Ready(Ok(_)) => {}
-//                     Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
-//                     Poll::Pending => return Poll::Ready(Ok(total)),
-//                 }
-//             }
-//         }
-//     }
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        if this.done || this.staged.eof {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        if this.staged.is_full() {
+            ready!(this.poll_drain(cx))?;
+        }
+        let n = this.staged.fill_from(buf);
+        if n == 0 && !buf.is_empty() {
+            // the transform is waiting for input yet hasn't taken any of a full buffer
+            return Poll::Ready(Err(io::Error::other("transform stalled")));
+        }
+        // opportunistically make progress; back-pressure is surfaced on the next full buffer
+        if let Poll::Ready(Err(e)) = this.poll_drain(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(n))
+    }
 
-//     #[test]
-//     fn test_read_transform() {
-//         // Create a buffer transform that just copies data from the input to the output.
-//         let transform = ExampleTransform {};
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.w).poll_flush(cx)
+    }
 
-//         // Create a reader that reads from a cursor.
-//         let input = &b"hello world";
-//         let (mut client, mut server) = tokio::io::duplex(64);
-//         let reader = ReadTransform::new(client, transform);
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        this.staged.eof = true;
+        while !this.done {
+            ready!(this.poll_drain(cx))?;
+            if !this.done && this.staged.starved {
+                // the transform must finish once its input reports eof
+                return Poll::Ready(Err(io::Error::other("transform did not finish at eof")));
+            }
+        }
+        ready!(Pin::new(&mut this.w).poll_flush(cx))?;
+        Pin::new(&mut this.w).poll_shutdown(cx)
+    }
+}
 
-//         // Read the data from the reader and check that it matches the input.
-//         let mut buf = [0; 11];
-//         block_on(reader.read_exact(&mut buf)).unwrap();
-//         assert_eq!(&buf, b"hello world");
-//     }
-// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::scripted::ScriptedStream;
+    use crate::test_utils::{xor, xor_transform, TRAILER};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn write_transform_applies_under_backpressure() -> io::Result<()> {
+        // a tiny pipe forces partial writes all the way through
+        let (client, mut wire) = tokio::io::duplex(7);
+        let mut w = WriteTransform::new(client, xor_transform(0x5a));
+
+        let data: Vec<u8> = (0..STAGING_CAPACITY * 3).map(|i| i as u8).collect();
+        let expected = data.clone();
+        let writer = tokio::spawn(async move {
+            w.write_all(&data).await?;
+            w.flush().await?;
+            w.shutdown().await
+        });
+
+        let mut on_wire = vec![];
+        wire.read_to_end(&mut on_wire).await?;
+        writer.await.unwrap()?;
+
+        let (body, trailer) = on_wire.split_at(expected.len());
+        assert_eq!(body, xor(&expected, 0x5a));
+        assert_eq!(trailer, TRAILER);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_transform_applies() -> io::Result<()> {
+        let (mut wire, server) = tokio::io::duplex(5);
+        let mut r = ReadTransform::new(server, xor_transform(0x21));
+
+        let data: Vec<u8> = (0..STAGING_CAPACITY * 2 + 3)
+            .map(|i| (i * 7) as u8)
+            .collect();
+        let sent = xor(&data, 0x21);
+        tokio::spawn(async move {
+            wire.write_all(&sent).await.unwrap();
+            wire.shutdown().await.unwrap();
+        });
+
+        let mut out = vec![];
+        r.read_to_end(&mut out).await?;
+        assert_eq!(&out[..data.len()], &data[..]);
+        assert_eq!(&out[data.len()..], TRAILER);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn flush_waits_for_transformed_bytes() -> io::Result<()> {
+        let (client, mut wire) = tokio::io::duplex(1024);
+        let mut w = WriteTransform::new(client, xor_transform(1));
+        w.write_all(b"abc").await?;
+        w.flush().await?;
+
+        let mut buf = [0_u8; 3];
+        wire.read_exact(&mut buf).await?;
+        assert_eq!(&buf[..], xor(b"abc", 1));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_transform_passes_on_pending_and_errors() -> io::Result<()> {
+        let sent = xor(b"abcdef", 3);
+        let script = ScriptedStream::builder()
+            .read(&sent[..2])
+            .read_pending()
+            .read(&sent[2..])
+            .read_pending()
+            .read_error(io::ErrorKind::ConnectionReset)
+            .build();
+        let mut r = ReadTransform::new(script, xor_transform(3));
+
+        let mut out = [0u8; 6];
+        r.read_exact(&mut out).await?;
+        assert_eq!(&out, b"abcdef");
+        let err = r.read(&mut out).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_transform_errors_stick() -> io::Result<()> {
+        let (mut wire, r) = tokio::io::duplex(64);
+        wire.write_all(b"6869zz").await?;
+        drop(wire);
+        let mut r = ReadTransform::new(r, Chunked::new(codec::hex::Decode::default()));
+
+        let mut out = vec![];
+        let err = r.read_to_end(&mut out).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        // a corrupt stream must not end up looking like a clean EOF
+        for _ in 0..2 {
+            let again = r.read(&mut [0u8; 8]).await.unwrap_err();
+            assert_eq!(again.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(again.to_string(), err.to_string());
+        }
+        Ok(())
+    }
+}
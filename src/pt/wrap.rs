@@ -1,12 +1,27 @@
-use crate::Result;
+use crate::pt::transform::{no_options, ReadTransform, Staging, TransformFactory, WriteTransform};
+use crate::stream::{AddrInfo, Addressed};
+use crate::{Configurable, Error, Named, OverheadEstimate, Result, Role, Stream};
 
-use tokio::io::{AsyncRead, AsyncWrite};
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{ready, Context, Poll};
 
 pub trait Reveal {
     fn reveal<'a>(
         &self,
         r: Box<dyn AsyncRead + Unpin + Send + Sync + 'a>,
     ) -> Box<dyn AsyncRead + Unpin + Send + Sync + 'a>;
+
+    /// Options for the revealing direction, see [`Wrapper::with_reveal_config`]. By default
+    /// only an empty string is accepted.
+    fn configure(&mut self, args: &str) -> Result<()> {
+        no_options(args)
+    }
 }
 
 pub trait Seal {
@@ -14,26 +29,339 @@ pub trait Seal {
         &self,
         w: Box<dyn AsyncWrite + Unpin + Send + Sync + 'a>,
     ) -> Box<dyn AsyncWrite + Unpin + Send + Sync + 'a>;
+
+    /// Options for the sealing direction, see [`Wrapper::with_seal_config`]. By default only
+    /// an empty string is accepted.
+    fn configure(&mut self, args: &str) -> Result<()> {
+        no_options(args)
+    }
 }
-pub trait WrapTransport {
-    fn wrapper(
+
+/// Seals by applying a fresh transform from the factory to each write half.
+pub struct SealWith<F>(pub F);
+
+impl<F> Seal for SealWith<F>
+where
+    F: for<'a> TransformFactory<'a, Staging, Box<dyn AsyncWrite + Unpin + Send + Sync + 'a>>,
+{
+    fn seal<'a>(
         &self,
-    ) -> Result<(
-        Box<dyn Seal + Unpin + Send + Sync>,
-        Box<dyn Reveal + Unpin + Send + Sync>,
-    )>;
+        w: Box<dyn AsyncWrite + Unpin + Send + Sync + 'a>,
+    ) -> Box<dyn AsyncWrite + Unpin + Send + Sync + 'a> {
+        Box::new(WriteTransform::new(w, self.0.make()))
+    }
 
-    fn unwrapper(
+    /// Passed on to the factory.
+    fn configure(&mut self, args: &str) -> Result<()> {
+        TransformFactory::<Staging, Box<dyn AsyncWrite + Unpin + Send + Sync>>::configure(
+            &mut self.0,
+            args,
+        )
+    }
+}
+
+/// Reveals by applying a fresh transform from the factory to each read half.
+pub struct RevealWith<F>(pub F);
+
+impl<F> Reveal for RevealWith<F>
+where
+    F: for<'a> TransformFactory<'a, Box<dyn AsyncRead + Unpin + Send + Sync + 'a>, Staging>,
+{
+    fn reveal<'a>(
         &self,
-    ) -> Result<(
+        r: Box<dyn AsyncRead + Unpin + Send + Sync + 'a>,
+    ) -> Box<dyn AsyncRead + Unpin + Send + Sync + 'a> {
+        Box::new(ReadTransform::new(r, self.0.make()))
+    }
+
+    /// Passed on to the factory.
+    fn configure(&mut self, args: &str) -> Result<()> {
+        TransformFactory::<Box<dyn AsyncRead + Unpin + Send + Sync>, Staging>::configure(
+            &mut self.0,
+            args,
+        )
+    }
+}
+
+/// An exchange run over the full duplex stream before it is split into sealed and revealed
+/// halves, for transports that need to agree on keys or parameters with the peer first.
+#[async_trait]
+pub trait Handshake: Send + Sync {
+    async fn handshake(&self, stream: &mut (dyn Stream + '_)) -> Result<()>;
+}
+
+/// The seal and reveal halves for one side of a connection, along with the identity of the
+/// transport that built them.
+pub struct Wrapper {
+    name: &'static str,
+    role: Role,
+    seal_config: String,
+    reveal_config: String,
+    seal: Box<dyn Seal + Unpin + Send + Sync>,
+    reveal: Box<dyn Reveal + Unpin + Send + Sync>,
+    handshake: Option<BoxFuture<'static, Result<()>>>,
+    exchange: Option<Box<dyn Handshake>>,
+}
+
+impl Wrapper {
+    pub fn new(
+        name: &'static str,
+        role: Role,
+        seal: Box<dyn Seal + Unpin + Send + Sync>,
+        reveal: Box<dyn Reveal + Unpin + Send + Sync>,
+    ) -> Self {
+        Self {
+            name,
+            role,
+            seal_config: String::new(),
+            reveal_config: String::new(),
+            seal,
+            reveal,
+            handshake: None,
+            exchange: None,
+        }
+    }
+
+    /// Require `handshake` to complete before any data is sealed or revealed, e.g. to fetch
+    /// keys for this connection. If it fails, reads and writes on the stream fail with its
+    /// error.
+    pub fn with_handshake<F>(mut self, handshake: F) -> Self
+    where
+        F: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.handshake = Some(Box::pin(handshake));
+        self
+    }
+
+    /// Run `exchange` with the peer over the unsplit stream before any data is sealed or
+    /// revealed. It runs after the handshake future, if there is one.
+    pub fn with_exchange<H: Handshake + 'static>(mut self, exchange: H) -> Self {
+        self.exchange = Some(Box::new(exchange));
+        self
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// Configure the sealing direction, passing `args` on to the [`Seal`] (and from there to
+    /// its [`TransformFactory`]).
+    pub fn with_seal_config(mut self, args: &str) -> Result<Self> {
+        self.seal.configure(args)?;
+        self.seal_config = args.to_string();
+        Ok(self)
+    }
+
+    /// Configure the revealing direction, passing `args` on to the [`Reveal`] (and from there
+    /// to its [`TransformFactory`]).
+    pub fn with_reveal_config(mut self, args: &str) -> Result<Self> {
+        self.reveal.configure(args)?;
+        self.reveal_config = args.to_string();
+        Ok(self)
+    }
+
+    /// The options the sealing direction was configured with.
+    pub fn seal_config(&self) -> &str {
+        &self.seal_config
+    }
+
+    /// The options the revealing direction was configured with.
+    pub fn reveal_config(&self) -> &str {
+        &self.reveal_config
+    }
+
+    /// The seal and reveal halves, discarding the name, config and handshakes.
+    pub fn into_parts(
+        self,
+    ) -> (
         Box<dyn Seal + Unpin + Send + Sync>,
         Box<dyn Reveal + Unpin + Send + Sync>,
-    )>;
+    ) {
+        (self.seal, self.reveal)
+    }
+
+    /// Split `a` and apply the seal and reveal halves to it. If there is a handshake or an
+    /// exchange they are driven on the first read or write, and the stream fails with their
+    /// error if either does.
+    pub fn wrap<'a, A>(self, a: A) -> Box<dyn Stream + 'a>
+    where
+        A: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'a,
+    {
+        let Wrapper {
+            seal,
+            reveal,
+            handshake,
+            exchange,
+            ..
+        } = self;
+        let split = move |a: A| -> Box<dyn Stream + 'a> {
+            let (r, w) = tokio::io::split(a);
+            let r = reveal.reveal(Box::new(r));
+            let w = seal.seal(Box::new(w));
+            Box::new(crate::stream::combine(r, w))
+        };
+        if handshake.is_none() && exchange.is_none() {
+            return split(a);
+        }
+
+        let ready = async move {
+            if let Some(handshake) = handshake {
+                handshake.await.map_err(io_error)?;
+            }
+            let mut a = a;
+            if let Some(exchange) = exchange {
+                exchange.handshake(&mut a).await.map_err(io_error)?;
+            }
+            Ok(split(a))
+        };
+        Box::new(Handshaking::Pending(Mutex::new(Box::pin(ready))))
+    }
+
+    /// [`wrap`](Wrapper::wrap) `a`, keeping its socket addresses on the returned stream. They
+    /// are read before `a` is split into the halves the seal and reveal sides take.
+    pub fn wrap_addressed<'a, A>(self, a: A) -> Addressed<Box<dyn Stream + 'a>>
+    where
+        A: AsyncRead + AsyncWrite + AddrInfo + Unpin + Send + Sync + 'a,
+    {
+        let (peer, local) = (a.peer_addr(), a.local_addr());
+        Addressed::new(self.wrap(a), peer, local)
+    }
+}
+
+impl Named for Wrapper {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+/// Configures both directions with the same options.
+impl Configurable for Wrapper {
+    fn with_config(self, args: &str) -> Result<Self> {
+        self.with_seal_config(args)?.with_reveal_config(args)
+    }
+}
+
+impl std::fmt::Debug for Wrapper {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Wrapper")
+            .field("name", &self.name)
+            .field("role", &self.role)
+            .field("seal_config", &self.seal_config)
+            .field("reveal_config", &self.reveal_config)
+            .field("handshake", &self.handshake.is_some())
+            .field("exchange", &self.exchange.is_some())
+            .finish()
+    }
+}
+
+/// Builds the [`Wrapper`] for each side of a connection. Transports that must exchange
+/// messages with the peer before sealing attach a [`Handshake`] with
+/// [`Wrapper::with_exchange`].
+pub trait WrapTransport {
+    /// Build the sealing (client) side.
+    fn wrapper(&self) -> Result<Wrapper>;
+
+    /// Build the revealing (server) side.
+    fn unwrapper(&self) -> Result<Wrapper>;
+
+    /// What sealing adds to a payload of `payload_len` bytes written at once. Defaults to
+    /// nothing.
+    fn overhead(&self, payload_len: usize) -> OverheadEstimate {
+        OverheadEstimate::passthrough(payload_len)
+    }
+}
+
+impl<W: WrapTransport + ?Sized> WrapTransport for Box<W> {
+    fn wrapper(&self) -> Result<Wrapper> {
+        (**self).wrapper()
+    }
+
+    fn unwrapper(&self) -> Result<Wrapper> {
+        (**self).unwrapper()
+    }
+
+    fn overhead(&self, payload_len: usize) -> OverheadEstimate {
+        (**self).overhead(payload_len)
+    }
+}
+
+/// Converts a handshake failure for the wrapped stream's callers.
+fn io_error(e: Error) -> std::io::Error {
+    match e {
+        Error::IOError(e) => e,
+        e => std::io::Error::other(e.to_string()),
+    }
+}
+
+/// Stream that drives the handshakes to completion and only then builds the wrapped stream
+/// that I/O is passed through to. The future is only ever accessed through `&mut self`; the
+/// mutex just makes the stream `Sync`.
+enum Handshaking<'a> {
+    Pending(Mutex<BoxFuture<'a, std::io::Result<Box<dyn Stream + 'a>>>>),
+    Ready(Box<dyn Stream + 'a>),
+    Failed,
+}
+
+impl<'a> Handshaking<'a> {
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::io::Result<&mut (dyn Stream + 'a)>> {
+        if let Handshaking::Pending(fut) = self {
+            let fut = fut.get_mut().unwrap_or_else(|e| e.into_inner());
+            match ready!(fut.as_mut().poll(cx)) {
+                Ok(stream) => *self = Handshaking::Ready(stream),
+                Err(e) => {
+                    *self = Handshaking::Failed;
+                    return Poll::Ready(Err(e));
+                }
+            }
+        }
+        match self {
+            Handshaking::Ready(stream) => Poll::Ready(Ok(stream.as_mut())),
+            _ => Poll::Ready(Err(std::io::Error::other("handshake failed"))),
+        }
+    }
+}
+
+impl AsyncRead for Handshaking<'_> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let inner = ready!(self.poll_ready(cx))?;
+        Pin::new(inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Handshaking<'_> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let inner = ready!(self.poll_ready(cx))?;
+        Pin::new(inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let inner = ready!(self.poll_ready(cx))?;
+        Pin::new(inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let inner = ready!(self.poll_ready(cx))?;
+        Pin::new(inner).poll_shutdown(cx)
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+    use super::*;
+    use crate::transports::identity::Identity;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
     fn wrap_read<R: AsyncRead + Unpin>(r: R) -> impl AsyncRead {
         r
@@ -57,4 +385,93 @@ mod test {
         let nr = client.read(&mut buf).await.unwrap();
         assert_eq!(nr, 1024);
     }
+
+    #[tokio::test]
+    async fn wrapper_identity_and_handshake() -> Result<()> {
+        let w = Identity::new().wrapper()?.with_config("")?;
+        assert_eq!(w.name(), "identity");
+        assert_eq!(w.role(), Role::Sealer);
+        assert!(Identity::new().wrapper()?.with_config("k=v").is_err());
+        assert_eq!(Identity::new().unwrapper()?.role(), Role::Revealer);
+
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let (a, mut b) = tokio::net::UnixStream::pair()?;
+        let w = Identity::new().wrapper()?.with_handshake(async move {
+            rx.await.map_err(Error::new)?;
+            Ok(())
+        });
+        let mut s = w.wrap(a);
+
+        // writes wait for the handshake
+        let write = tokio::spawn(async move {
+            s.write_all(b"hi").await?;
+            Ok::<_, std::io::Error>(s)
+        });
+        tokio::task::yield_now().await;
+        assert!(!write.is_finished());
+        tx.send(()).unwrap();
+        let _s = write.await.unwrap()?;
+        let mut buf = [0_u8; 2];
+        b.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"hi");
+
+        // a failed handshake fails the stream
+        let (a, _b) = tokio::net::UnixStream::pair()?;
+        let w = Identity::new()
+            .wrapper()?
+            .with_handshake(async { Err(Error::new("no keys")) });
+        let mut s = w.wrap(a);
+        assert!(s.read(&mut buf).await.is_err());
+        Ok(())
+    }
+
+    /// Each side sends a nonce and expects the peer's before any wrapped data.
+    struct NonceExchange(u8);
+
+    #[async_trait]
+    impl Handshake for NonceExchange {
+        async fn handshake(&self, stream: &mut (dyn Stream + '_)) -> Result<()> {
+            stream.write_all(&[self.0]).await?;
+            let mut peer = [0_u8; 1];
+            stream.read_exact(&mut peer).await?;
+            if peer[0] == self.0 {
+                return Err(Error::new("peer reused our nonce"));
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn exchange_runs_before_split() -> Result<()> {
+        let (a, b) = tokio::net::UnixStream::pair()?;
+        let mut client = Identity::new()
+            .wrapper()?
+            .with_exchange(NonceExchange(1))
+            .wrap(a);
+        let mut server = Identity::new()
+            .unwrapper()?
+            .with_exchange(NonceExchange(2))
+            .wrap(b);
+
+        let (w, r) = tokio::join!(client.write_all(b"data"), async {
+            let mut buf = [0_u8; 4];
+            server.read_exact(&mut buf).await.map(|_| buf)
+        });
+        w?;
+        assert_eq!(&r?, b"data");
+
+        let (a, b) = tokio::net::UnixStream::pair()?;
+        let mut client = Identity::new()
+            .wrapper()?
+            .with_exchange(NonceExchange(1))
+            .wrap(a);
+        let mut server = Identity::new()
+            .unwrapper()?
+            .with_exchange(NonceExchange(1))
+            .wrap(b);
+        let mut buf = [0_u8; 4];
+        let (w, r) = tokio::join!(client.write_all(b"data"), server.read(&mut buf));
+        assert!(w.is_err() && r.is_err());
+        Ok(())
+    }
 }
@@ -0,0 +1,190 @@
+//! # Python bindings
+//!
+//! A thin `ptrs` Python module so that existing Python measurement harnesses can drive
+//! transports directly. Streams are exposed with a blocking, socket-like interface
+//! (`send`/`sendall`/`recv`/`close`) backed by a shared tokio runtime; the GIL is released
+//! while waiting on the network.
+//!
+//! Build with `maturin build --features python`.
+
+// the code generated by pyo3's #[pymethods] / #[pyfunction] trips this lint
+#![allow(clippy::useless_conversion)]
+
+use crate::{transports::Transports, Role, Stream};
+
+use once_cell::sync::Lazy;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+
+use std::io;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+static RUNTIME: Lazy<Runtime> = Lazy::new(|| Runtime::new().expect("failed to start runtime"));
+
+fn io_error(e: crate::Error) -> io::Error {
+    match e {
+        crate::Error::IOError(e) => e,
+        e => io::Error::other(e.to_string()),
+    }
+}
+
+/// Checks the transport name and arguments before any connection is made.
+fn check_transport(transport: &str, args: Option<&str>) -> PyResult<()> {
+    Transports::from_str(transport)
+        .map_err(|_| PyValueError::new_err(format!("unknown transport \"{transport}\"")))?;
+    if args.is_some_and(|a| !a.is_empty()) {
+        return Err(PyValueError::new_err(
+            "transport arguments are not supported by the registry yet",
+        ));
+    }
+    Ok(())
+}
+
+fn wrap(transport: &str, role: Role, stream: TcpStream) -> io::Result<Box<dyn Stream>> {
+    let t = Transports::from_str(transport).map_err(io_error)?;
+    t.build::<TcpStream>(&role).wrap(stream).map_err(io_error)
+}
+
+/// A connected, wrapped stream.
+#[pyclass(name = "Stream")]
+struct PyStream {
+    inner: Mutex<Option<Box<dyn Stream>>>,
+}
+
+impl PyStream {
+    fn new(s: Box<dyn Stream>) -> Self {
+        PyStream {
+            inner: Mutex::new(Some(s)),
+        }
+    }
+
+    fn with<T, F>(&self, py: Python<'_>, f: F) -> PyResult<T>
+    where
+        T: Send,
+        F: for<'s> FnOnce(&'s mut Box<dyn Stream>) -> futures::future::BoxFuture<'s, io::Result<T>>
+            + Send,
+    {
+        py.allow_threads(|| {
+            let mut guard = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+            let s = guard
+                .as_mut()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+            RUNTIME.block_on(f(s))
+        })
+        .map_err(PyErr::from)
+    }
+}
+
+#[pymethods]
+impl PyStream {
+    /// Send some of `data`, returning how many bytes were sent.
+    fn send(&self, py: Python<'_>, data: Vec<u8>) -> PyResult<usize> {
+        self.with(py, move |s| Box::pin(async move { s.write(&data).await }))
+    }
+
+    /// Send all of `data`.
+    fn sendall(&self, py: Python<'_>, data: Vec<u8>) -> PyResult<()> {
+        self.with(py, move |s| {
+            Box::pin(async move {
+                s.write_all(&data).await?;
+                s.flush().await
+            })
+        })
+    }
+
+    /// Receive up to `n` bytes. Returns empty bytes once the peer has closed the stream.
+    fn recv<'py>(&self, py: Python<'py>, n: usize) -> PyResult<Bound<'py, PyBytes>> {
+        let buf = self.with(py, move |s| {
+            Box::pin(async move {
+                let mut buf = vec![0_u8; n];
+                let nr = s.read(&mut buf).await?;
+                buf.truncate(nr);
+                Ok(buf)
+            })
+        })?;
+        Ok(PyBytes::new_bound(py, &buf))
+    }
+
+    /// Shut down the write side, signalling end of stream to the peer.
+    fn shutdown(&self, py: Python<'_>) -> PyResult<()> {
+        self.with(py, |s| Box::pin(async move { s.shutdown().await }))
+    }
+
+    fn close(&self) {
+        let mut guard = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        // dropping the stream inside the runtime lets tokio deregister the socket
+        let _g = RUNTIME.enter();
+        guard.take();
+    }
+}
+
+/// A listening socket whose accepted connections are wrapped with a transport.
+#[pyclass(name = "Listener")]
+struct PyListener {
+    inner: TcpListener,
+    transport: String,
+}
+
+#[pymethods]
+impl PyListener {
+    /// Wait for a connection, returning the wrapped stream and the peer address.
+    fn accept(&self, py: Python<'_>) -> PyResult<(PyStream, String)> {
+        let (s, peer) = py.allow_threads(|| {
+            RUNTIME.block_on(async {
+                let (s, peer) = self.inner.accept().await?;
+                Ok::<_, io::Error>((wrap(&self.transport, Role::Revealer, s)?, peer))
+            })
+        })?;
+        Ok((PyStream::new(s), peer.to_string()))
+    }
+
+    #[getter]
+    fn local_addr(&self) -> PyResult<String> {
+        Ok(self.inner.local_addr()?.to_string())
+    }
+}
+
+/// Connect to `addr` and wrap the connection with `transport`.
+#[pyfunction]
+#[pyo3(signature = (addr, transport, args=None))]
+fn wrap_client(
+    py: Python<'_>,
+    addr: &str,
+    transport: &str,
+    args: Option<&str>,
+) -> PyResult<PyStream> {
+    check_transport(transport, args)?;
+    let s = py.allow_threads(|| {
+        RUNTIME.block_on(async {
+            let s = TcpStream::connect(addr).await?;
+            wrap(transport, Role::Sealer, s)
+        })
+    })?;
+    Ok(PyStream::new(s))
+}
+
+/// Listen on `addr`, wrapping accepted connections with `transport`.
+#[pyfunction]
+#[pyo3(signature = (addr, transport, args=None))]
+fn listen(py: Python<'_>, addr: &str, transport: &str, args: Option<&str>) -> PyResult<PyListener> {
+    check_transport(transport, args)?;
+    let inner = py.allow_threads(|| RUNTIME.block_on(TcpListener::bind(addr)))?;
+    Ok(PyListener {
+        inner,
+        transport: transport.to_string(),
+    })
+}
+
+#[pymodule]
+fn ptrs(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyStream>()?;
+    m.add_class::<PyListener>()?;
+    m.add_function(wrap_pyfunction!(wrap_client, m)?)?;
+    m.add_function(wrap_pyfunction!(listen, m)?)?;
+    Ok(())
+}
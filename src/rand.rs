@@ -0,0 +1,125 @@
+//! # Rand
+//!
+//! Randomness facade for transports. Transports that pad, generate keys, or otherwise draw
+//! random bytes should do so through [`Rng`] so that tests and fuzzing can make them
+//! reproducible by providing a seed through the testing-only [`SEED_KEY`] config option.
+//! [`Rng::from_args`] reads that option, e.g. for the QUIC carrier's certificates (see
+//! `transports::quic::self_signed_with`).
+
+use crate::{Error, Result};
+
+use ::rand::{CryptoRng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+
+/// Config key used to seed a transport's RNG. This is for tests only, a seeded transport
+/// produces predictable output and provides no security.
+pub const SEED_KEY: &str = "test-seed";
+
+/// Cryptographically secure RNG that is seeded from the OS by default, or from a fixed seed
+/// when running deterministic tests.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    inner: ChaCha20Rng,
+}
+
+impl Rng {
+    /// Create an RNG seeded from OS entropy.
+    pub fn from_entropy() -> Self {
+        Self {
+            inner: ChaCha20Rng::from_entropy(),
+        }
+    }
+
+    /// Create a deterministic RNG from `seed`. Only use this in tests.
+    pub fn from_seed(seed: u64) -> Self {
+        Self {
+            inner: ChaCha20Rng::seed_from_u64(seed),
+        }
+    }
+
+    /// Create an RNG from an optional seed, falling back to OS entropy.
+    pub fn new(seed: Option<u64>) -> Self {
+        match seed {
+            Some(seed) => Self::from_seed(seed),
+            None => Self::from_entropy(),
+        }
+    }
+
+    /// Create an RNG from a transport config string, honoring the [`SEED_KEY`] option if set.
+    pub fn from_args(args: &str) -> Result<Self> {
+        Ok(Self::new(seed_from_args(args)?))
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::from_entropy()
+    }
+}
+
+impl RngCore for Rng {
+    fn next_u32(&mut self) -> u32 {
+        self.inner.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.inner.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.inner.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> std::result::Result<(), ::rand::Error> {
+        self.inner.try_fill_bytes(dest)
+    }
+}
+
+impl CryptoRng for Rng {}
+
+/// Find the [`SEED_KEY`] option in a transport config string. Options are `key=value` pairs
+/// separated by whitespace, `;` or `,`.
+pub fn seed_from_args(args: &str) -> Result<Option<u64>> {
+    for opt in args.split(|c: char| c.is_whitespace() || c == ';' || c == ',') {
+        if let Some((SEED_KEY, value)) = opt.split_once('=') {
+            let seed = value
+                .parse()
+                .map_err(|e| Error::Other(format!("bad {SEED_KEY} \"{value}\": {e}").into()))?;
+            return Ok(Some(seed));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn seeded_is_deterministic() {
+        let (mut a, mut b) = (Rng::from_seed(7), Rng::from_seed(7));
+        let (mut buf_a, mut buf_b) = ([0_u8; 64], [0_u8; 64]);
+        a.fill_bytes(&mut buf_a);
+        b.fill_bytes(&mut buf_b);
+        assert_eq!(buf_a, buf_b);
+
+        Rng::from_seed(8).fill_bytes(&mut buf_b);
+        assert_ne!(buf_a, buf_b);
+    }
+
+    #[test]
+    fn seed_from_config() -> Result<()> {
+        assert_eq!(seed_from_args("")?, None);
+        assert_eq!(seed_from_args("upper")?, None);
+        assert_eq!(seed_from_args("test-seed=42")?, Some(42));
+        assert_eq!(seed_from_args("mode=1;test-seed=9, x=y")?, Some(9));
+        assert!(seed_from_args("test-seed=abc").is_err());
+
+        let mut buf_a = [0_u8; 16];
+        let mut buf_b = [0_u8; 16];
+        Rng::from_args("test-seed=3")?.fill_bytes(&mut buf_a);
+        Rng::from_seed(3).fill_bytes(&mut buf_b);
+        assert_eq!(buf_a, buf_b);
+        Ok(())
+    }
+}
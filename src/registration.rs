@@ -0,0 +1,131 @@
+//! # Registration
+//!
+//! Out-of-band bridge registration. Some designs (Conjure, Snowflake) require the client to
+//! tell a registration service or broker about an upcoming connection, over a separate channel
+//! such as an HTTP API, a domain-fronted AMP cache, or email, before it can dial the bridge.
+//! A [`Registrar`] performs that exchange and returns what the client needs to dial and wrap
+//! its connection.
+
+use crate::{ClientTransport, Result, TransportBuilder};
+
+use async_trait::async_trait;
+
+use std::net::SocketAddr;
+
+/// What a client sends to the registration service.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Registration {
+    /// Name of the transport the client intends to use.
+    pub transport: String,
+    /// Opaque, transport specific registration payload (e.g. a session key or SDP offer).
+    pub payload: Vec<u8>,
+}
+
+/// What the registration service answered.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Registered {
+    /// Address to dial, if the service assigned one.
+    pub addr: Option<SocketAddr>,
+    /// Transport config to build the client with, in the form accepted by
+    /// [`Configurable::with_config`](crate::Configurable::with_config).
+    pub config: String,
+    /// Opaque, transport specific response payload (e.g. an SDP answer).
+    pub payload: Vec<u8>,
+}
+
+/// Performs out-of-band registration for a transport.
+#[async_trait]
+pub trait Registrar: Send + Sync {
+    async fn register(&self, registration: &Registration) -> Result<Registered>;
+}
+
+#[async_trait]
+impl<R: Registrar + ?Sized> Registrar for Box<R> {
+    async fn register(&self, registration: &Registration) -> Result<Registered> {
+        (**self).register(registration).await
+    }
+}
+
+/// Registrar that returns a fixed answer without contacting anything. Useful for bridges whose
+/// registration details are distributed ahead of time, and in tests.
+#[derive(Clone, Debug, Default)]
+pub struct StaticRegistrar {
+    answer: Registered,
+}
+
+impl StaticRegistrar {
+    pub fn new(answer: Registered) -> Self {
+        Self { answer }
+    }
+}
+
+#[async_trait]
+impl Registrar for StaticRegistrar {
+    async fn register(&self, _registration: &Registration) -> Result<Registered> {
+        Ok(self.answer.clone())
+    }
+}
+
+/// Register with `registrar`, then build the client side of the transport using the config
+/// the registration service returned. The caller dials [`Registered::addr`] and wraps the
+/// connection with the returned transport.
+pub async fn client_setup<B>(
+    registrar: &dyn Registrar,
+    registration: &Registration,
+    builder: B,
+) -> Result<(Registered, ClientTransport)>
+where
+    B: TransportBuilder,
+{
+    let registered = registrar.register(registration).await?;
+    let transport = builder.with_config(&registered.config)?.client()?;
+    Ok((registered, transport))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transports::identity::Identity;
+    use crate::{Error, Transport};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::UnixStream;
+
+    struct Rejecting;
+
+    #[async_trait]
+    impl Registrar for Rejecting {
+        async fn register(&self, r: &Registration) -> Result<Registered> {
+            Err(Error::new(format!("no registrations for {}", r.transport)))
+        }
+    }
+
+    #[tokio::test]
+    async fn setup_then_wrap() -> Result<()> {
+        let answer = Registered {
+            addr: Some("192.0.2.1:443".parse().unwrap()),
+            config: String::new(),
+            payload: b"answer".to_vec(),
+        };
+        let registrar: Box<dyn Registrar> = Box::new(StaticRegistrar::new(answer.clone()));
+        let registration = Registration {
+            transport: "identity".into(),
+            payload: b"offer".to_vec(),
+        };
+
+        let (registered, transport) =
+            client_setup(&registrar, &registration, Identity::new()).await?;
+        assert_eq!(registered, answer);
+
+        let (a, mut b) = UnixStream::pair()?;
+        let mut wrapped = transport.wrap(a)?;
+        wrapped.write_all(b"hi").await?;
+        let mut buf = [0_u8; 2];
+        b.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"hi");
+
+        let err = client_setup(&Rejecting, &registration, Identity::new()).await;
+        assert!(err.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+//! # Safe logging
+//!
+//! Client addresses, bridge lines and keys in logs can identify users or burn bridges if the
+//! logs leak. Wrap such values in [`Sensitive`] (or call [`sensitive`]) where they are
+//! logged, and they are shown as `[scrubbed]` unless the operator turns on unsafe logging
+//! with [`set_unsafe_logging`], e.g. while debugging a deployment.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Shown in place of a [`Sensitive`] value.
+pub const SCRUBBED: &str = "[scrubbed]";
+
+static UNSAFE_LOGGING: AtomicBool = AtomicBool::new(false);
+
+/// Show [`Sensitive`] values as they are, process wide.
+pub fn set_unsafe_logging(enabled: bool) {
+    UNSAFE_LOGGING.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether [`Sensitive`] values are currently shown.
+pub fn unsafe_logging() -> bool {
+    UNSAFE_LOGGING.load(Ordering::Relaxed)
+}
+
+/// A value that is scrubbed when formatted, unless unsafe logging is on.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Sensitive<T>(T);
+
+impl<T> Sensitive<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+
+    pub fn as_inner(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> From<T> for Sensitive<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+/// Wrap `value` so that it is scrubbed from logs.
+pub fn sensitive<T>(value: T) -> Sensitive<T> {
+    Sensitive(value)
+}
+
+impl<T: fmt::Display> fmt::Display for Sensitive<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match unsafe_logging() {
+            true => fmt::Display::fmt(&self.0, f),
+            false => f.write_str(SCRUBBED),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Sensitive<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match unsafe_logging() {
+            true => fmt::Debug::fmt(&self.0, f),
+            false => f.write_str(SCRUBBED),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::net::SocketAddr;
+
+    #[test]
+    fn scrubs_unless_unsafe() {
+        // the flag is process wide, so both states are checked in one test
+        let addr: SocketAddr = "192.0.2.1:443".parse().unwrap();
+        assert_eq!(format!("{}", sensitive(addr)), SCRUBBED);
+        assert_eq!(format!("{:?}", sensitive(&addr)), SCRUBBED);
+
+        set_unsafe_logging(true);
+        assert_eq!(format!("{}", sensitive(addr)), "192.0.2.1:443");
+        assert_eq!(format!("{:?}", Sensitive::new("bridge")), "\"bridge\"");
+        set_unsafe_logging(false);
+        assert_eq!(format!("peer {}", sensitive(addr)), "peer [scrubbed]");
+        assert_eq!(sensitive(addr).into_inner(), addr);
+    }
+}
@@ -0,0 +1,402 @@
+//! # Sandbox
+//!
+//! Optional hardening for server deployments (feature `sandbox`, Linux only): a chroot,
+//! landlock rules limiting the file system to what a network proxy needs, and a seccomp
+//! filter refusing the syscalls it never makes (running programs, tracing other processes,
+//! loading kernel modules, new namespaces and the like).
+//!
+//! Landlock and `no_new_privs` only take hold in the calling thread and threads it starts
+//! later, so apply the sandbox before starting the runtime or any other thread. Neither
+//! restricts sockets, so listeners can still be bound afterwards.
+
+use crate::{Error, Result};
+
+use std::path::PathBuf;
+
+/// Read-only paths allowed under landlock on top of [`Sandbox::read_paths`], so names can
+/// still be resolved (`/etc/hosts`, `/etc/resolv.conf`, nss modules). Missing ones are
+/// skipped.
+pub const DEFAULT_READ_PATHS: &[&str] = &["/etc", "/usr/lib", "/lib", "/lib64", "/dev/urandom"];
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Sandbox {
+    /// Directory to chroot into, which needs root. The paths below are inside it.
+    pub chroot: Option<PathBuf>,
+    /// Limit file system access with landlock.
+    pub landlock: bool,
+    /// Readable under landlock.
+    pub read_paths: Vec<PathBuf>,
+    /// Readable and writable, including creating and removing files and unix sockets,
+    /// under landlock.
+    pub write_paths: Vec<PathBuf>,
+    /// Refuse syscalls a proxy has no use for with `EPERM`.
+    pub seccomp: bool,
+}
+
+impl Sandbox {
+    pub fn is_enabled(&self) -> bool {
+        self.chroot.is_some() || self.landlock || self.seccomp
+    }
+
+    /// Chroot, then restrict the file system, then syscalls. Fails without applying the
+    /// rest if any step is unsupported or fails.
+    pub fn apply(&self) -> Result<()> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        imp::apply(self)
+    }
+}
+
+fn unsupported(what: &str) -> Error {
+    Error::Other(format!("{what} is not supported {}", imp::UNSUPPORTED_WHERE).into())
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::*;
+
+    use std::fs::File;
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::path::Path;
+
+    pub const UNSUPPORTED_WHERE: &str = "by this kernel";
+
+    pub fn apply(sandbox: &Sandbox) -> Result<()> {
+        if let Some(dir) = &sandbox.chroot {
+            chroot(dir)?;
+        }
+        if sandbox.landlock || sandbox.seccomp {
+            check(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } as libc::c_long)?;
+        }
+        if sandbox.landlock {
+            landlock(&sandbox.read_paths, &sandbox.write_paths)?;
+        }
+        if sandbox.seccomp {
+            seccomp(libc::SECCOMP_FILTER_FLAG_TSYNC)?;
+        }
+        Ok(())
+    }
+
+    fn check(ret: libc::c_long) -> io::Result<libc::c_long> {
+        match ret {
+            -1 => Err(io::Error::last_os_error()),
+            ret => Ok(ret),
+        }
+    }
+
+    fn chroot(dir: &Path) -> Result<()> {
+        let c_dir = std::ffi::CString::new(dir.as_os_str().as_encoded_bytes())
+            .map_err(|e| Error::Config(e.into()))?;
+        match check(unsafe { libc::chroot(c_dir.as_ptr()) } as libc::c_long) {
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                return Err(Error::Config(
+                    format!("chroot to {} needs root", dir.display()).into(),
+                ))
+            }
+            r => r?,
+        };
+        std::env::set_current_dir("/")?;
+        Ok(())
+    }
+
+    // from linux/landlock.h, which libc doesn't carry yet
+    const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1 << 0;
+    const LANDLOCK_RULE_PATH_BENEATH: libc::c_long = 1;
+
+    const EXECUTE: u64 = 1 << 0;
+    const WRITE_FILE: u64 = 1 << 1;
+    const READ_FILE: u64 = 1 << 2;
+    const READ_DIR: u64 = 1 << 3;
+    const MAKE_CHAR: u64 = 1 << 6;
+    const MAKE_BLOCK: u64 = 1 << 11;
+    /// Everything in the first landlock ABI.
+    const ABI_1: u64 = (1 << 13) - 1;
+    const REFER: u64 = 1 << 13;
+    const TRUNCATE: u64 = 1 << 14;
+    /// Rights that apply to files rather than directories.
+    const FILE_RIGHTS: u64 = EXECUTE | WRITE_FILE | READ_FILE | TRUNCATE;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    /// Allow `read` and `write` (and [`DEFAULT_READ_PATHS`]) and nothing else.
+    pub(super) fn landlock(read: &[PathBuf], write: &[PathBuf]) -> Result<()> {
+        let abi = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<RulesetAttr>(),
+                0,
+                LANDLOCK_CREATE_RULESET_VERSION,
+            )
+        };
+        if abi < 1 {
+            return Err(unsupported("landlock"));
+        }
+        let mut handled = ABI_1;
+        if abi >= 2 {
+            handled |= REFER;
+        }
+        if abi >= 3 {
+            handled |= TRUNCATE;
+        }
+
+        let attr = RulesetAttr {
+            handled_access_fs: handled,
+        };
+        let fd = check(unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr,
+                std::mem::size_of::<RulesetAttr>(),
+                0,
+            )
+        })?;
+        let ruleset = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+
+        let readable = READ_FILE | READ_DIR;
+        let writable = handled & !(EXECUTE | MAKE_CHAR | MAKE_BLOCK);
+        for path in DEFAULT_READ_PATHS.iter().map(Path::new) {
+            if path.exists() {
+                allow(&ruleset, path, readable)?;
+            }
+        }
+        for path in read {
+            allow(&ruleset, path, readable)?;
+        }
+        for path in write {
+            allow(&ruleset, path, writable)?;
+        }
+        check(unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0) })?;
+        Ok(())
+    }
+
+    fn allow(ruleset: &OwnedFd, path: &Path, access: u64) -> Result<()> {
+        let file = File::options()
+            .read(true)
+            .custom_flags(libc::O_PATH | libc::O_CLOEXEC)
+            .open(path)
+            .map_err(|e| Error::Config(format!("sandbox path {}: {e}", path.display()).into()))?;
+        let access = match file.metadata()?.is_dir() {
+            true => access,
+            false => access & FILE_RIGHTS,
+        };
+        let attr = PathBeneathAttr {
+            allowed_access: access,
+            parent_fd: file.as_raw_fd(),
+        };
+        check(unsafe {
+            libc::syscall(
+                libc::SYS_landlock_add_rule,
+                ruleset.as_raw_fd(),
+                LANDLOCK_RULE_PATH_BENEATH,
+                &attr,
+                0,
+            )
+        })?;
+        Ok(())
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xc000_003e;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+    /// Syscalls refused with `EPERM`.
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    const DENIED: &[libc::c_long] = &[
+        // running other programs
+        libc::SYS_execve,
+        libc::SYS_execveat,
+        // other processes' memory
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_readv,
+        libc::SYS_process_vm_writev,
+        // the file system and namespaces
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_pivot_root,
+        libc::SYS_chroot,
+        libc::SYS_unshare,
+        libc::SYS_setns,
+        libc::SYS_open_by_handle_at,
+        libc::SYS_name_to_handle_at,
+        libc::SYS_swapon,
+        libc::SYS_swapoff,
+        libc::SYS_acct,
+        libc::SYS_quotactl,
+        // the kernel
+        libc::SYS_reboot,
+        libc::SYS_kexec_load,
+        libc::SYS_kexec_file_load,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_bpf,
+        libc::SYS_perf_event_open,
+        libc::SYS_userfaultfd,
+        libc::SYS_io_uring_setup,
+        libc::SYS_io_uring_enter,
+        libc::SYS_io_uring_register,
+        libc::SYS_keyctl,
+        libc::SYS_add_key,
+        libc::SYS_request_key,
+        libc::SYS_personality,
+        libc::SYS_syslog,
+        libc::SYS_fanotify_init,
+        // the clock
+        libc::SYS_settimeofday,
+        libc::SYS_clock_settime,
+        libc::SYS_clock_adjtime,
+        libc::SYS_adjtimex,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_iopl,
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_ioperm,
+    ];
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    pub(super) fn seccomp(flags: libc::c_ulong) -> Result<()> {
+        let program = filter();
+        let prog = libc::sock_fprog {
+            len: program.len() as u16,
+            filter: program.as_ptr() as *mut _,
+        };
+        match check(unsafe {
+            libc::syscall(
+                libc::SYS_seccomp,
+                libc::SECCOMP_SET_MODE_FILTER,
+                flags,
+                &prog,
+            )
+        }) {
+            Err(e) if e.raw_os_error() == Some(libc::EINVAL) => Err(unsupported("seccomp")),
+            r => r.map(|_| ()).map_err(Error::from),
+        }
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub(super) fn seccomp(_flags: libc::c_ulong) -> Result<()> {
+        Err(Error::Other(
+            "the seccomp filter is not available on this architecture".into(),
+        ))
+    }
+
+    /// Deny [`DENIED`], and everything made through another architecture's syscall table
+    /// (or the x32 one), whose numbers differ.
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    fn filter() -> Vec<libc::sock_filter> {
+        const LD: u16 = (libc::BPF_LD | libc::BPF_W | libc::BPF_ABS) as u16;
+        const JEQ: u16 = (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16;
+        const JGE: u16 = (libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K) as u16;
+        const RET: u16 = (libc::BPF_RET | libc::BPF_K) as u16;
+        const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+        let op = |code, jt, jf, k| libc::sock_filter { code, jt, jf, k };
+        let deny = libc::SECCOMP_RET_ERRNO | (libc::EPERM as u32 & libc::SECCOMP_RET_DATA);
+
+        // offsets into struct seccomp_data
+        let (nr, arch) = (0, 4);
+        let mut program = vec![
+            op(LD, 0, 0, arch),
+            op(JEQ, 1, 0, AUDIT_ARCH),
+            op(RET, 0, 0, deny),
+            op(LD, 0, 0, nr),
+        ];
+        let checks = DENIED.len() as u8 + 1;
+        // jumps are relative to the next instruction, and land on the final deny
+        program.push(op(JGE, checks, 0, X32_SYSCALL_BIT));
+        for (i, &syscall) in DENIED.iter().enumerate() {
+            program.push(op(JEQ, checks - 1 - i as u8, 0, syscall as u32));
+        }
+        program.push(op(RET, 0, 0, libc::SECCOMP_RET_ALLOW));
+        program.push(op(RET, 0, 0, deny));
+        program
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use super::*;
+
+    pub const UNSUPPORTED_WHERE: &str = "off linux";
+
+    pub fn apply(_: &Sandbox) -> Result<()> {
+        Err(unsupported("sandboxing"))
+    }
+}
+
+#[cfg(all(
+    test,
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod test {
+    use super::*;
+
+    use std::io;
+
+    /// Run `f` on its own thread, so restrictions that only apply to the calling thread don't
+    /// leak into other tests.
+    fn isolated<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+        std::thread::spawn(f).join().unwrap()
+    }
+
+    #[test]
+    fn seccomp_refuses_exec() {
+        let spawned = isolated(|| {
+            let r = unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) };
+            assert_eq!(r, 0);
+            // just this thread, not the whole test process
+            imp::seccomp(0).unwrap();
+            std::process::Command::new("true").status()
+        });
+        assert_eq!(spawned.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        // everyone else can still
+        assert!(std::process::Command::new("true").status().is_ok());
+    }
+
+    #[test]
+    fn landlock_limits_reads() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let allowed = dir.path().join("allowed");
+        std::fs::write(&allowed, b"ok")?;
+        let outside = tempfile::NamedTempFile::new_in(std::env::temp_dir())?;
+
+        let read = vec![allowed.clone()];
+        let result = isolated(move || -> Result<_> {
+            unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) };
+            imp::landlock(&read, &[])?;
+            Ok((
+                std::fs::read(&allowed).ok(),
+                std::fs::read(outside.path()).map_err(|e| e.kind()),
+            ))
+        });
+        let (inside, outside) = match result {
+            Err(e) if e.to_string().contains("not supported") => return Ok(()),
+            r => r?,
+        };
+        assert_eq!(inside.as_deref(), Some(&b"ok"[..]));
+        assert_eq!(outside, Err(io::ErrorKind::PermissionDenied));
+        Ok(())
+    }
+
+    #[test]
+    fn missing_paths_are_config_errors() {
+        let err =
+            isolated(|| imp::landlock(&[PathBuf::from("/no/such/path/4907")], &[]).unwrap_err());
+        assert!(
+            matches!(err, Error::Config(_)) || err.to_string().contains("not supported"),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,216 @@
+//! # Socket options
+//!
+//! TCP tuning applied to the sockets transports listen and dial on. Transport performance
+//! is sensitive to these, e.g. Nagle's algorithm adding latency to small handshake messages,
+//! or idle connections being dropped by middleboxes without keepalives.
+//!
+//! The DSCP and `SO_MARK` options are for operators who route transport traffic with policy
+//! routing or QoS rules. They are implemented on Linux and ignored elsewhere.
+
+use crate::{Error, Result};
+
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+use tokio::net::{TcpListener, TcpStream};
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Backlog used by [`SocketOpts::bind`], matching the one tokio uses.
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// DSCP is a six bit field.
+const MAX_DSCP: u8 = 63;
+
+/// Socket options for listeners and dialed connections. Unset options keep the OS default.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SocketOpts {
+    /// Disable Nagle's algorithm (`TCP_NODELAY`).
+    pub nodelay: Option<bool>,
+    /// Send keepalive probes after the connection has been idle this long, and (where
+    /// supported) at this interval afterwards.
+    pub keepalive: Option<Duration>,
+    /// Allow several listeners to bind the same address (`SO_REUSEPORT`).
+    #[cfg(unix)]
+    pub reuse_port: bool,
+    /// Restrict IPv6 listeners to IPv6 (`IPV6_V6ONLY`).
+    pub only_v6: Option<bool>,
+    /// `SO_SNDBUF` in bytes.
+    pub send_buffer: Option<usize>,
+    /// `SO_RCVBUF` in bytes.
+    pub recv_buffer: Option<usize>,
+    /// Differentiated services code point (0-63), set in the IPv4 TOS or IPv6 traffic class
+    /// byte of outgoing packets.
+    pub dscp: Option<u8>,
+    /// Linux `SO_MARK` (fwmark) for policy routing. Needs `CAP_NET_ADMIN`.
+    pub mark: Option<u32>,
+}
+
+impl SocketOpts {
+    /// Bind a listener on `addr` with these options. Accepted connections should be passed
+    /// to [`apply`](Self::apply), since not every option is inherited from the listener.
+    pub fn bind(&self, addr: SocketAddr) -> Result<TcpListener> {
+        let socket = self.socket(addr)?;
+        self.apply_to(&socket)?;
+        self.mark(&socket, addr.is_ipv6())?;
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?;
+        #[cfg(unix)]
+        if self.reuse_port {
+            socket.set_reuse_port(true)?;
+        }
+        if let (Some(only_v6), SocketAddr::V6(_)) = (self.only_v6, addr) {
+            socket.set_only_v6(only_v6)?;
+        }
+        socket.bind(&addr.into())?;
+        socket.listen(LISTEN_BACKLOG)?;
+        Ok(TcpListener::from_std(socket.into())?)
+    }
+
+    /// Connect to `addr` with these options.
+    pub async fn connect(&self, addr: SocketAddr) -> Result<TcpStream> {
+        let socket = self.socket(addr)?;
+        self.apply_to(&socket)?;
+        self.mark(&socket, addr.is_ipv6())?;
+        let socket = tokio::net::TcpSocket::from_std_stream(socket.into());
+        Ok(socket.connect(addr).await?)
+    }
+
+    /// Apply the per-connection options to an established stream, e.g. one just accepted.
+    pub fn apply(&self, stream: &TcpStream) -> Result<()> {
+        let socket = SockRef::from(stream);
+        self.apply_to(&socket)?;
+        self.mark(&socket, stream.local_addr()?.is_ipv6())
+    }
+
+    fn socket(&self, addr: SocketAddr) -> Result<Socket> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        socket.set_nonblocking(true)?;
+        Ok(socket)
+    }
+
+    fn apply_to(&self, socket: &Socket) -> Result<()> {
+        if let Some(nodelay) = self.nodelay {
+            socket.set_tcp_nodelay(nodelay)?;
+        }
+        if let Some(idle) = self.keepalive {
+            let keepalive = TcpKeepalive::new().with_time(idle);
+            #[cfg(target_os = "linux")]
+            let keepalive = keepalive.with_interval(idle);
+            socket.set_tcp_keepalive(&keepalive)?;
+        }
+        if let Some(size) = self.send_buffer {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer {
+            socket.set_recv_buffer_size(size)?;
+        }
+        Ok(())
+    }
+
+    /// Apply the DSCP and `SO_MARK` options, which need the address family.
+    fn mark(&self, socket: &Socket, v6: bool) -> Result<()> {
+        if let Some(dscp) = self.dscp.filter(|&d| d > MAX_DSCP) {
+            return Err(Error::Config(
+                format!("DSCP {dscp} is out of range 0-{MAX_DSCP}").into(),
+            ));
+        }
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(dscp) = self.dscp {
+                // the low two bits of the byte are ECN
+                let tos = (dscp as u32) << 2;
+                match v6 {
+                    true => socket.set_tclass_v6(tos)?,
+                    false => socket.set_tos_v4(tos)?,
+                }
+            }
+            if let Some(mark) = self.mark {
+                socket.set_mark(mark)?;
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        if self.dscp.is_some() || self.mark.is_some() {
+            let _ = (socket, v6);
+            tracing::debug!("dscp and mark socket options are only supported on linux");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn options_are_applied() -> Result<()> {
+        let opts = SocketOpts {
+            nodelay: Some(true),
+            keepalive: Some(Duration::from_secs(30)),
+            recv_buffer: Some(64 * 1024),
+            ..Default::default()
+        };
+        let listener = opts.bind("127.0.0.1:0".parse().unwrap())?;
+        let addr = listener.local_addr()?;
+
+        let (dialed, accepted) = tokio::join!(opts.connect(addr), listener.accept());
+        let dialed = dialed?;
+        let (accepted, _) = accepted?;
+        opts.apply(&accepted)?;
+
+        for s in [&dialed, &accepted] {
+            let sock = SockRef::from(s);
+            assert!(sock.tcp_nodelay()?);
+            assert!(sock.keepalive()?);
+            // the kernel may round the buffer size, but never below what was asked for
+            assert!(sock.recv_buffer_size()? >= 64 * 1024);
+        }
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn reuse_port() -> Result<()> {
+        let opts = SocketOpts {
+            reuse_port: true,
+            ..Default::default()
+        };
+        let first = opts.bind("127.0.0.1:0".parse().unwrap())?;
+        let addr = first.local_addr()?;
+        assert!(opts.bind(addr).is_ok());
+        assert!(SocketOpts::default().bind(addr).is_err());
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn marks_outbound_connections() -> Result<()> {
+        let listener = SocketOpts::default().bind("127.0.0.1:0".parse().unwrap())?;
+        let addr = listener.local_addr()?;
+
+        // expedited forwarding
+        let opts = SocketOpts {
+            dscp: Some(46),
+            ..Default::default()
+        };
+        let dialed = opts.connect(addr).await?;
+        assert_eq!(SockRef::from(&dialed).tos_v4()?, 46 << 2);
+
+        let out_of_range = SocketOpts {
+            dscp: Some(64),
+            ..Default::default()
+        };
+        let err = out_of_range.connect(addr).await.unwrap_err();
+        assert_eq!(err.kind(), crate::ErrorKind::Config);
+
+        // setting a mark needs CAP_NET_ADMIN, which tests don't usually have
+        let marked = SocketOpts {
+            mark: Some(0x2a),
+            ..Default::default()
+        };
+        match marked.connect(addr).await {
+            Ok(s) => assert_eq!(SockRef::from(&s).mark()?, 0x2a),
+            Err(e) => assert_eq!(e.kind(), crate::ErrorKind::Io),
+        }
+        Ok(())
+    }
+}
@@ -1,26 +1,172 @@
+use crate::errors::Cancelled;
+
+use std::any::Any;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::ready;
+
 use pin_project::pin_project;
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
 
 /// A [`Stream`] is a type that implements both AsyncRead and AsyncWrite representing making it a
 /// generic full-duplex I/O stream.
 pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send + Sync {}
 impl<T> Stream for T where T: AsyncRead + AsyncWrite + Unpin + Send + Sync {}
 
+/// A `'static` [`Stream`] that can be downcast back to its concrete type, e.g. to get the
+/// `TcpStream` out of a boxed stream for its addresses. Box a stream as
+/// `Box<dyn AnyStream>` (rather than `Box<dyn Stream>`) to keep the option open.
+///
+/// A `Box<dyn AnyStream>` is itself an `AnyStream`, so boxing one again, or calling
+/// [`as_any`](AnyStream::as_any) on the box rather than on `*boxed`, gets the box and not the
+/// stream in it. The downcasts on `dyn AnyStream` look through any number of such boxes.
+pub trait AnyStream: Stream + Any {
+    /// `self` as [`Any`]. On a `Box<dyn AnyStream>` this is the box itself; downcast with
+    /// the methods on `dyn AnyStream` instead to reach the stream inside.
+    fn as_any(&self) -> &dyn Any;
+
+    /// Like [`as_any`](AnyStream::as_any), with the same caveat for boxes.
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<T: Stream + 'static> AnyStream for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+impl dyn AnyStream {
+    pub fn is<T: Any>(&self) -> bool {
+        self.unboxed().as_any().is::<T>()
+    }
+
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.unboxed().as_any().downcast_ref()
+    }
+
+    pub fn downcast_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.unboxed_mut().as_any_mut().downcast_mut()
+    }
+
+    /// Take the concrete stream back out of the box, or return the box unchanged if it holds
+    /// something else (less any `Box<dyn AnyStream>` layers around the stream).
+    pub fn downcast<T: Any>(self: Box<Self>) -> Result<Box<T>, Box<dyn AnyStream>> {
+        if (*self).as_any().is::<Box<dyn AnyStream>>() {
+            let inner = self.into_any().downcast::<Box<dyn AnyStream>>();
+            return (*inner.expect("checked above")).downcast();
+        }
+        if self.is::<T>() {
+            Ok(self.into_any().downcast().expect("checked above"))
+        } else {
+            Err(self)
+        }
+    }
+
+    /// The stream inside any `Box<dyn AnyStream>` layers around it.
+    fn unboxed(&self) -> &(dyn AnyStream + 'static) {
+        match self.as_any().downcast_ref::<Box<dyn AnyStream>>() {
+            Some(inner) => (**inner).unboxed(),
+            None => self,
+        }
+    }
+
+    fn unboxed_mut(&mut self) -> &mut (dyn AnyStream + 'static) {
+        if !(*self).as_any().is::<Box<dyn AnyStream>>() {
+            return self;
+        }
+        let inner = self.as_any_mut().downcast_mut::<Box<dyn AnyStream>>();
+        (**inner.expect("checked above")).unboxed_mut()
+    }
+}
+
+/// Addresses of the socket at the bottom of a stack of streams. Stream wrappers forward to
+/// the stream they wrap, so the addresses survive being wrapped by anything that keeps its
+/// concrete type. `None` when the innermost stream is not an inet socket.
+pub trait AddrInfo {
+    fn peer_addr(&self) -> Option<SocketAddr>;
+
+    fn local_addr(&self) -> Option<SocketAddr>;
+}
+
+impl AddrInfo for TcpStream {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        TcpStream::peer_addr(self).ok()
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        TcpStream::local_addr(self).ok()
+    }
+}
+
+impl<S: AddrInfo + ?Sized> AddrInfo for Box<S> {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        (**self).peer_addr()
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        (**self).local_addr()
+    }
+}
+
+impl<S: AddrInfo + ?Sized> AddrInfo for &mut S {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        (**self).peer_addr()
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        (**self).local_addr()
+    }
+}
+
+/// Implement [`AddrInfo`] for a wrapper by forwarding to the stream it holds in `$field`.
+macro_rules! forward_addr_info {
+    ($ty:ident, $field:ident) => {
+        impl<S: $crate::stream::AddrInfo> $crate::stream::AddrInfo for $ty<S> {
+            fn peer_addr(&self) -> Option<std::net::SocketAddr> {
+                self.$field.peer_addr()
+            }
+
+            fn local_addr(&self) -> Option<std::net::SocketAddr> {
+                self.$field.local_addr()
+            }
+        }
+    };
+}
+pub(crate) use forward_addr_info;
+
 pub trait ReadHalf: AsyncRead + Unpin + Send + Sync {}
 impl<T> ReadHalf for T where T: AsyncRead + Unpin + Send + Sync {}
 
 pub trait WriteHalf: AsyncWrite + Unpin + Send + Sync {}
 impl<T> WriteHalf for T where T: AsyncWrite + Unpin + Send + Sync {}
 
+/// A read half and a write half joined into one stream, see [`combine`].
 #[pin_project]
-struct Combined<R, W> {
+pub struct Combined<R, W> {
     #[pin]
     r: R,
     #[pin]
     w: W,
 }
 
-pub fn combine<R, W>(r: R, w: W) -> impl Stream
+pub fn combine<R, W>(r: R, w: W) -> Combined<R, W>
 where
     R: AsyncRead + Unpin + Send + Sync,
     W: AsyncWrite + Unpin + Send + Sync,
@@ -28,6 +174,17 @@ where
     Combined { r, w }
 }
 
+/// The addresses come from the read half.
+impl<R: AddrInfo, W> AddrInfo for Combined<R, W> {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.r.peer_addr()
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.r.local_addr()
+    }
+}
+
 impl<R: AsyncRead, W> AsyncRead for Combined<R, W> {
     fn poll_read(
         self: std::pin::Pin<&mut Self>,
@@ -65,3 +222,476 @@ impl<R, W: AsyncWrite> AsyncWrite for Combined<R, W> {
         this.w.poll_shutdown(cx)
     }
 }
+
+/// Stream carrying the socket addresses of the stream it was made from, for stacks where the
+/// original is no longer reachable, e.g. after [`Transport::wrap_addressed`] has split it and
+/// boxed the halves.
+///
+/// [`Transport::wrap_addressed`]: crate::Transport::wrap_addressed
+#[pin_project]
+pub struct Addressed<S> {
+    #[pin]
+    inner: S,
+    peer: Option<SocketAddr>,
+    local: Option<SocketAddr>,
+}
+
+impl<S> Addressed<S> {
+    pub fn new(inner: S, peer: Option<SocketAddr>, local: Option<SocketAddr>) -> Self {
+        Self { inner, peer, local }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S> AddrInfo for Addressed<S> {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.local
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for Addressed<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        self.project().inner.poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite> AsyncWrite for Addressed<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.project().inner.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.project().inner.poll_shutdown(cx)
+    }
+}
+
+/// Stream that fails all I/O with [`Error::Cancelled`](crate::Error::Cancelled) once its token
+/// is cancelled. See [`Transport::wrap_with_cancel`](crate::Transport::wrap_with_cancel).
+pub struct Cancellable<S> {
+    inner: S,
+    token: CancellationToken,
+    cancelled: Pin<Box<WaitForCancellationFutureOwned>>,
+}
+
+forward_addr_info!(Cancellable, inner);
+
+impl<S> Cancellable<S> {
+    pub fn new(inner: S, token: CancellationToken) -> Self {
+        let cancelled = Box::pin(token.clone().cancelled_owned());
+        Self {
+            inner,
+            token,
+            cancelled,
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Errors if the token has been cancelled, otherwise arranges for the task to be woken
+    /// when it is.
+    fn poll_cancelled(&mut self, cx: &mut Context<'_>) -> std::io::Result<()> {
+        if self.token.is_cancelled() || self.cancelled.as_mut().poll(cx).is_ready() {
+            return Err(Cancelled.into());
+        }
+        Ok(())
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Cancellable<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        self.poll_cancelled(cx)?;
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Cancellable<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.poll_cancelled(cx)?;
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.poll_cancelled(cx)?;
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.poll_cancelled(cx)?;
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Stream that sends some bytes to the peer ahead of anything written to it, for transports
+/// that can't carry data in their handshake. See
+/// [`Transport::wrap_with_early_data`](crate::Transport::wrap_with_early_data).
+pub struct EarlyData<S> {
+    inner: S,
+    early: Vec<u8>,
+    sent: usize,
+    /// The early bytes went out with no write behind them to flush them.
+    unflushed: bool,
+}
+
+forward_addr_info!(EarlyData, inner);
+
+impl<S> EarlyData<S> {
+    pub fn new(inner: S, early: &[u8]) -> Self {
+        Self {
+            inner,
+            early: early.to_vec(),
+            sent: 0,
+            unflushed: false,
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: AsyncWrite + Unpin> EarlyData<S> {
+    fn poll_send_early(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        while self.sent < self.early.len() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.early[self.sent..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+            }
+            self.sent += n;
+            self.unflushed = true;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for EarlyData<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        // the peer may be waiting on the early bytes before it says anything
+        ready!(self.poll_send_early(cx))?;
+        if self.unflushed {
+            ready!(Pin::new(&mut self.inner).poll_flush(cx))?;
+            self.unflushed = false;
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for EarlyData<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        ready!(self.poll_send_early(cx))?;
+        self.unflushed = false;
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        ready!(self.poll_send_early(cx))?;
+        ready!(Pin::new(&mut self.inner).poll_flush(cx))?;
+        self.unflushed = false;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        ready!(self.poll_send_early(cx))?;
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Byte counters for one layer of a stream, shared between an [`InstrumentedStream`] and
+/// whoever is watching it.
+#[derive(Debug, Default)]
+pub struct Stats {
+    read: AtomicU64,
+    written: AtomicU64,
+}
+
+impl Stats {
+    /// Bytes read up through this layer.
+    pub fn bytes_read(&self) -> u64 {
+        self.read.load(Ordering::Relaxed)
+    }
+
+    /// Bytes written down through this layer.
+    pub fn bytes_written(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+
+    /// How many bytes this layer moved for every byte `plaintext` moved, where `plaintext`
+    /// counts the layer above, e.g. the stream a transport returned from `wrap`. `None` until
+    /// the layer above has moved anything.
+    pub fn expansion(&self, plaintext: &Stats) -> Option<f64> {
+        let inner = plaintext.bytes_read() + plaintext.bytes_written();
+        let outer = self.bytes_read() + self.bytes_written();
+        (inner > 0).then(|| outer as f64 / inner as f64)
+    }
+}
+
+/// Stream that counts the bytes passing through it. Instrumenting the streams on either side
+/// of a transport, or each layer of a chain, shows how much each layer adds.
+pub struct InstrumentedStream<S> {
+    inner: S,
+    stats: Arc<Stats>,
+}
+
+forward_addr_info!(InstrumentedStream, inner);
+
+impl<S> InstrumentedStream<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            stats: Arc::default(),
+        }
+    }
+
+    /// The counters for this stream. The handle stays valid after the stream is wrapped or
+    /// dropped.
+    pub fn as_stats(&self) -> Arc<Stats> {
+        self.stats.clone()
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for InstrumentedStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        let n = buf.filled().len() - before;
+        self.stats.read.fetch_add(n as u64, Ordering::Relaxed);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for InstrumentedStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let n = ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        self.stats.written.fetch_add(n as u64, Ordering::Relaxed);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transports::identity::Identity;
+    use crate::{DynTransport, Error, Transport};
+
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn downcasts_boxed_streams() -> std::io::Result<()> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let (conn, _) = tokio::join!(TcpStream::connect(addr), listener.accept());
+
+        let mut s: Box<dyn AnyStream> = Box::new(conn?);
+        assert!(!s.is::<tokio::io::DuplexStream>());
+        assert_eq!(s.downcast_ref::<TcpStream>().unwrap().peer_addr()?, addr);
+        s.downcast_mut::<TcpStream>().unwrap().set_nodelay(true)?;
+        // the trait's accessors reach the stream when called on the box's contents
+        assert!((*s).as_any().is::<TcpStream>());
+        assert!((*s).as_any_mut().downcast_mut::<TcpStream>().is_some());
+
+        // still usable as a plain stream, e.g. by a transport
+        let wrapped = Identity::new().wrap(&mut s).unwrap();
+        drop(wrapped);
+
+        let s = s.downcast::<tokio::io::DuplexStream>().unwrap_err();
+        let Ok(tcp) = s.downcast::<TcpStream>() else {
+            panic!("not the stream that was boxed");
+        };
+        assert!(tcp.nodelay()?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn downcasts_through_nested_boxes() -> std::io::Result<()> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let (conn, _) = tokio::join!(TcpStream::connect(addr), listener.accept());
+
+        let inner: Box<dyn AnyStream> = Box::new(conn?);
+        let mut s: Box<dyn AnyStream> = Box::new(inner);
+        // the trap: the outer box's own `as_any` is the inner box
+        assert!(s.as_any().is::<Box<dyn AnyStream>>());
+        assert!((*s).as_any().is::<Box<dyn AnyStream>>());
+
+        assert!(s.is::<TcpStream>());
+        assert_eq!(s.downcast_ref::<TcpStream>().unwrap().peer_addr()?, addr);
+        s.downcast_mut::<TcpStream>().unwrap().set_nodelay(true)?;
+
+        let s = s.downcast::<tokio::io::DuplexStream>().unwrap_err();
+        let Ok(tcp) = s.downcast::<TcpStream>() else {
+            panic!("not the stream that was boxed");
+        };
+        assert!(tcp.nodelay()?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wrappers_keep_socket_addresses() -> std::io::Result<()> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let (conn, accepted) = tokio::join!(TcpStream::connect(addr), listener.accept());
+        let (conn, (_, client_addr)) = (conn?, accepted?);
+
+        let s = InstrumentedStream::new(Cancellable::new(Box::new(conn), CancellationToken::new()));
+        assert_eq!(AddrInfo::peer_addr(&s), Some(addr));
+        assert_eq!(AddrInfo::local_addr(&s), Some(client_addr));
+
+        let (r, w) = (s, tokio::io::sink());
+        let combined = combine(r, w);
+        assert_eq!(AddrInfo::peer_addr(&combined), Some(addr));
+
+        // wrapping splits and boxes the socket, so the addresses are taken beforehand
+        let (conn, accepted) = tokio::join!(TcpStream::connect(addr), listener.accept());
+        let (conn, (_, client_addr)) = (conn?, accepted?);
+        let wrapped = Identity::new().wrap_addressed(Box::new(conn)).unwrap();
+        assert_eq!(wrapped.peer_addr(), Some(addr));
+        assert_eq!(wrapped.local_addr(), Some(client_addr));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cancel_interrupts_pending_read() {
+        let (a, _b) = tokio::io::duplex(64);
+        let token = CancellationToken::new();
+        let mut wrapped = Identity::new().wrap_with_cancel(a, token.clone()).unwrap();
+
+        let canceller = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            canceller.cancel();
+        });
+        let err = wrapped.read(&mut [0u8; 8]).await.unwrap_err();
+        assert!(matches!(Error::from(err), Error::Cancelled));
+
+        let (a, _b) = tokio::io::duplex(64);
+        assert!(matches!(
+            Identity::new().wrap_with_cancel(a, token),
+            Err(Error::Cancelled)
+        ));
+    }
+
+    #[tokio::test]
+    async fn wrap_boxed_with_cancel() {
+        let (a, _b) = tokio::io::duplex(64);
+        let token = CancellationToken::new();
+        let transport: Box<dyn DynTransport> = Box::new(Identity::new());
+        let mut wrapped = transport
+            .wrap_boxed_with_cancel(Box::new(a), token.clone())
+            .await
+            .unwrap();
+        token.cancel();
+        let err = wrapped.read(&mut [0u8; 8]).await.unwrap_err();
+        assert!(matches!(Error::from(err), Error::Cancelled));
+    }
+
+    #[tokio::test]
+    async fn early_data_goes_first() {
+        use tokio::io::AsyncWriteExt;
+
+        let (a, mut b) = tokio::io::duplex(64);
+        let mut wrapped = Identity::new().wrap_with_early_data(a, b"early ").unwrap();
+        wrapped.write_all(b"late").await.unwrap();
+        wrapped.shutdown().await.unwrap();
+        let mut got = vec![];
+        b.read_to_end(&mut got).await.unwrap();
+        assert_eq!(got, b"early late");
+
+        // a peer waiting on the early data gets it even if the caller only reads
+        let (a, mut b) = tokio::io::duplex(64);
+        let mut wrapped = Identity::new().wrap_with_early_data(a, b"ping").unwrap();
+        tokio::spawn(async move {
+            let mut ping = [0u8; 4];
+            b.read_exact(&mut ping).await.unwrap();
+            b.write_all(b"pong").await.unwrap();
+        });
+        let mut pong = [0u8; 4];
+        wrapped.read_exact(&mut pong).await.unwrap();
+        assert_eq!(&pong, b"pong");
+    }
+
+    #[cfg(feature = "codecs")]
+    #[tokio::test]
+    async fn instrumented_layers() {
+        use crate::transports::base64::Base64Builder;
+        use crate::WrapTransport;
+        use tokio::io::AsyncWriteExt;
+
+        let (a, mut b) = tokio::io::duplex(1024);
+        let wire = InstrumentedStream::new(a);
+        let wire_stats = wire.as_stats();
+        let sealed = Base64Builder::default().wrapper().unwrap().wrap(wire);
+        let mut plain = InstrumentedStream::new(sealed);
+        let plain_stats = plain.as_stats();
+
+        plain.write_all(b"hello").await.unwrap();
+        plain.flush().await.unwrap();
+        let mut out = [0u8; 8];
+        b.read_exact(&mut out).await.unwrap();
+
+        assert_eq!(plain_stats.bytes_written(), 5);
+        assert_eq!(&out, b"aGVsbG8=");
+        assert_eq!(wire_stats.bytes_written(), 8);
+        assert_eq!(wire_stats.expansion(&plain_stats), Some(1.6));
+        assert_eq!(Stats::default().expansion(&Stats::default()), None);
+    }
+}
@@ -1 +1,281 @@
-pub mod constructions;
+//! # Sync
+//!
+//! Blocking counterpart to the async wrap interface, built on [`std::io::Read`] and
+//! [`std::io::Write`] so that transports can be used without a tokio runtime. Transports
+//! made of [`ChunkTransform`]s share them with their async side through [`ChunkWriter`] and
+//! [`ChunkReader`], the blocking counterparts of [`Chunked`](crate::pt::transform::Chunked).
+
+use crate::codec::ChunkTransform;
+use crate::pt::transform::{codec_error, CHUNK_SIZE};
+use crate::Result;
+
+use std::io::{self, Read, Write};
+
+/// A transport that can be applied to the halves of a blocking [`std::io`] stream.
+///
+/// `seal_write` wraps the outgoing half so that plaintext written to the returned writer is
+/// sealed before being written to `w`; call [`SealWriter::finish`] on it once everything has
+/// been written. `reveal_read` wraps the incoming half so that sealed data read from `r` is
+/// revealed before being returned to the caller.
+pub trait SyncTransport {
+    fn seal_write<'a>(&self, w: Box<dyn Write + Send + 'a>) -> Result<Box<dyn SealWriter + 'a>>;
+
+    fn reveal_read<'a>(&self, r: Box<dyn Read + Send + 'a>) -> Result<Box<dyn Read + Send + 'a>>;
+}
+
+impl<T: SyncTransport + ?Sized> SyncTransport for Box<T> {
+    fn seal_write<'a>(&self, w: Box<dyn Write + Send + 'a>) -> Result<Box<dyn SealWriter + 'a>> {
+        (**self).seal_write(w)
+    }
+
+    fn reveal_read<'a>(&self, r: Box<dyn Read + Send + 'a>) -> Result<Box<dyn Read + Send + 'a>> {
+        (**self).reveal_read(r)
+    }
+}
+
+/// The sealing writer returned by [`SyncTransport::seal_write`]. Some transports only produce
+/// part of their output once the input ends, so dropping the writer without calling
+/// [`finish`](SealWriter::finish) may lose the end of the stream.
+pub trait SealWriter: Write + Send {
+    /// Write any trailing output and flush it to the inner writer.
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+/// A writer with nothing to add at the end, such as the one a passthrough transport returns.
+impl SealWriter for Box<dyn Write + Send + '_> {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl<C: ChunkTransform + Send, W: Write + Send> SealWriter for ChunkWriter<C, W> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        ChunkWriter::finish(*self).map(drop)
+    }
+}
+
+/// Passes everything written to it through a [`ChunkTransform`] before writing it to the
+/// inner writer. Output the transform only produces once the input ends is written by
+/// [`ChunkWriter::finish`].
+pub struct ChunkWriter<C, W> {
+    inner: C,
+    w: W,
+    out: Vec<u8>,
+}
+
+impl<C: ChunkTransform, W: Write> ChunkWriter<C, W> {
+    pub fn new(inner: C, w: W) -> Self {
+        ChunkWriter {
+            inner,
+            w,
+            out: vec![],
+        }
+    }
+
+    /// Write any trailing output and hand back the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.clear();
+        self.inner.finish(&mut self.out).map_err(codec_error)?;
+        self.w.write_all(&self.out)?;
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+impl<C: ChunkTransform, W: Write> Write for ChunkWriter<C, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.out.clear();
+        self.inner
+            .transform(buf, &mut self.out)
+            .map_err(codec_error)?;
+        self.w.write_all(&self.out)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+/// Passes everything read from the inner reader through a [`ChunkTransform`].
+pub struct ChunkReader<C, R> {
+    inner: C,
+    r: R,
+    out: Vec<u8>,
+    pos: usize,
+    finished: bool,
+}
+
+impl<C: ChunkTransform, R: Read> ChunkReader<C, R> {
+    pub fn new(inner: C, r: R) -> Self {
+        ChunkReader {
+            inner,
+            r,
+            out: vec![],
+            pos: 0,
+            finished: false,
+        }
+    }
+}
+
+impl<C: ChunkTransform, R: Read> Read for ChunkReader<C, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.out.len() {
+            if self.finished || buf.is_empty() {
+                return Ok(0);
+            }
+            self.out.clear();
+            self.pos = 0;
+            let mut chunk = [0u8; CHUNK_SIZE];
+            let n = self.r.read(&mut chunk)?;
+            let r = if n == 0 {
+                self.finished = true;
+                self.inner.finish(&mut self.out)
+            } else {
+                self.inner.transform(&chunk[..n], &mut self.out)
+            };
+            r.map_err(codec_error)?;
+        }
+        let n = buf.len().min(self.out.len() - self.pos);
+        buf[..n].copy_from_slice(&self.out[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{pipes, xor, Xor, TRAILER};
+    #[cfg(feature = "fte")]
+    use crate::transports::fte::FteBuilder;
+    use crate::transports::identity::Identity;
+    #[cfg(feature = "codecs")]
+    use crate::transports::{base64::Base64Builder, hex_encoder::HexEncoder};
+    #[cfg(feature = "codecs")]
+    use crate::Configurable;
+
+    use std::sync::Arc;
+    use std::thread;
+
+    ///
+    ///        write  =============>  seal  =============>  reveal  =============>  read
+    ///        [ message ] -> | sealer | -> | revealer | -> [ out ]
+    ///                                 pipe
+    ///
+    fn round_trip<T: SyncTransport + Send + Sync + 'static>(
+        t: T,
+        message: &'static [u8],
+    ) -> Result<()> {
+        let (client, server) = pipes()?;
+        let t = Arc::new(t);
+
+        let sealer = thread::spawn({
+            let t = t.clone();
+            move || -> Result<()> {
+                let mut w = t.seal_write(Box::new(client))?;
+                w.write_all(message)?;
+                w.finish()?;
+                Ok(())
+            }
+        });
+
+        let mut out = vec![];
+        t.reveal_read(Box::new(server))?.read_to_end(&mut out)?;
+        sealer.join().unwrap()?;
+
+        assert_eq!(&out[..], message);
+        Ok(())
+    }
+
+    #[test]
+    fn identity_round_trip() -> Result<()> {
+        round_trip(Identity::new(), b"hello world")
+    }
+
+    #[cfg(feature = "codecs")]
+    #[test]
+    fn hex_round_trip() -> Result<()> {
+        round_trip(HexEncoder::new(), &[0xa5_u8; 4096])?;
+        round_trip(HexEncoder::new().with_config("lower")?, b"hello world")
+    }
+
+    #[cfg(feature = "codecs")]
+    #[test]
+    fn base64_round_trip() -> Result<()> {
+        round_trip(Base64Builder::default(), b"hello world")
+    }
+
+    #[cfg(feature = "fte")]
+    #[test]
+    fn fte_round_trip() -> Result<()> {
+        round_trip(FteBuilder::default(), &[0x5a; 1000])
+    }
+
+    #[test]
+    fn finish_writes_the_trailer() -> Result<()> {
+        struct Trailing;
+
+        impl SyncTransport for Trailing {
+            fn seal_write<'a>(
+                &self,
+                w: Box<dyn Write + Send + 'a>,
+            ) -> Result<Box<dyn SealWriter + 'a>> {
+                Ok(Box::new(ChunkWriter::new(Xor::new(3), w)))
+            }
+
+            fn reveal_read<'a>(
+                &self,
+                r: Box<dyn Read + Send + 'a>,
+            ) -> Result<Box<dyn Read + Send + 'a>> {
+                Ok(r)
+            }
+        }
+
+        let mut wire = vec![];
+        let mut w = Trailing.seal_write(Box::new(&mut wire))?;
+        w.write_all(b"abc")?;
+        w.finish()?;
+        assert_eq!(wire, [xor(b"abc", 3), TRAILER.to_vec()].concat());
+        Ok(())
+    }
+
+    #[test]
+    fn chunk_adapters() -> io::Result<()> {
+        use crate::codec::hex;
+
+        let mut w = ChunkWriter::new(hex::Encode { upper: false }, vec![]);
+        w.write_all(b"hi")?;
+        w.write_all(b"!")?;
+        let wire = w.finish()?;
+        assert_eq!(wire, b"686921");
+
+        // a reader handing out one byte at a time splits the digit pairs
+        let slow = wire
+            .chunks(1)
+            .fold(Box::new(io::empty()) as Box<dyn Read>, |r, b| {
+                Box::new(r.chain(io::Cursor::new(b.to_vec())))
+            });
+        let mut out = vec![];
+        ChunkReader::new(hex::Decode::default(), slow).read_to_end(&mut out)?;
+        assert_eq!(out, b"hi!");
+
+        let err = ChunkReader::new(hex::Decode::default(), &b"686"[..])
+            .read_to_end(&mut vec![])
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        Ok(())
+    }
+
+    #[cfg(feature = "codecs")]
+    #[test]
+    fn hex_seal_on_the_wire() -> Result<()> {
+        let mut sealed = vec![];
+        let h = HexEncoder::new().with_config("lower")?;
+        let mut w = h.seal_write(Box::new(&mut sealed))?;
+        w.write_all(b"hello world")?;
+        w.finish()?;
+        assert_eq!(&sealed[..], b"68656c6c6f20776f726c64");
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+//! Environment variables are process wide, so tests that set them race with any other test
+//! reading them. [`EnvGuard`] serializes those tests on one lock and puts every variable it
+//! touched back the way it was when dropped.
+//!
+//! ```ignore
+//! let mut env = EnvGuard::lock();
+//! env.set(MANAGED_TRANSPORT_VER, "1");
+//! env.remove(EXIT_ON_STDIN_CLOSE);
+//! ```
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::sync::{Mutex, MutexGuard};
+
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+/// Holds the environment lock, restoring the variables changed through it on drop.
+pub struct EnvGuard {
+    /// Value of each changed variable before the first change, `None` if it was unset.
+    saved: HashMap<OsString, Option<OsString>>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl EnvGuard {
+    /// Wait for any other test using the environment to finish. A test that panicked while
+    /// holding the lock has already had its changes undone, so poisoning is ignored.
+    pub fn lock() -> Self {
+        Self {
+            saved: HashMap::new(),
+            _lock: ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner()),
+        }
+    }
+
+    pub fn set(&mut self, key: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> &mut Self {
+        self.save(key.as_ref());
+        std::env::set_var(key, value);
+        self
+    }
+
+    pub fn remove(&mut self, key: impl AsRef<OsStr>) -> &mut Self {
+        self.save(key.as_ref());
+        std::env::remove_var(key);
+        self
+    }
+
+    fn save(&mut self, key: &OsStr) {
+        self.saved
+            .entry(key.to_owned())
+            .or_insert_with(|| std::env::var_os(key));
+    }
+}
+
+impl Drop for EnvGuard {
+    fn drop(&mut self) {
+        for (key, value) in self.saved.drain() {
+            match value {
+                Some(v) => std::env::set_var(key, v),
+                None => std::env::remove_var(key),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const KEY: &str = "PTRS_ENV_GUARD_TEST";
+
+    #[test]
+    fn restores_on_drop() {
+        {
+            let mut env = EnvGuard::lock();
+            env.set(KEY, "before");
+        }
+        assert_eq!(std::env::var_os(KEY), None);
+
+        let mut outer = EnvGuard::lock();
+        outer.set(KEY, "outer");
+        let result = std::panic::catch_unwind(move || {
+            let mut env = outer;
+            env.set(KEY, "a").set(KEY, "b").remove(KEY);
+            assert_eq!(std::env::var_os(KEY), None);
+            panic!("restored while unwinding");
+        });
+        assert!(result.is_err());
+        // the first saved value is the one put back, even after a panic
+        assert_eq!(std::env::var_os(KEY), None);
+    }
+}
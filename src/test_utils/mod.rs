@@ -1,12 +1,19 @@
 #![cfg(test)]
 #![allow(dead_code)]
 
+pub mod env;
+pub mod netem;
+pub mod scripted;
+pub mod snapshot;
 pub mod tests;
 
 use std::io::{Read, Result, Write};
 use std::os::unix::net::UnixStream;
 use std::sync::Once;
 
+use crate::codec::{self, ChunkTransform};
+use crate::pt::transform::Chunked;
+
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::UnixStream as AsyncUnixStream;
 use tracing_subscriber::filter::LevelFilter;
@@ -64,6 +71,49 @@ pub fn pipes_async() -> Result<(
     AsyncUnixStream::pair()
 }
 
+/// Appended by [`Xor`] once its input ends.
+pub const TRAILER: &[u8] = b"END";
+
+/// XORs each byte with the key plus the byte's offset in the stream, so the output depends on
+/// the transform seeing the stream from the start. Appends [`TRAILER`] once the input ends.
+pub struct Xor {
+    key: u8,
+    offset: usize,
+}
+
+impl Xor {
+    pub fn new(key: u8) -> Self {
+        Xor { key, offset: 0 }
+    }
+}
+
+impl ChunkTransform for Xor {
+    fn transform(&mut self, input: &[u8], out: &mut Vec<u8>) -> codec::Result<()> {
+        for b in input {
+            out.push(b ^ self.key.wrapping_add(self.offset as u8));
+            self.offset += 1;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) -> codec::Result<()> {
+        out.extend_from_slice(TRAILER);
+        Ok(())
+    }
+}
+
+pub fn xor_transform(key: u8) -> Chunked<Xor> {
+    Chunked::new(Xor::new(key))
+}
+
+/// What [`xor_transform`] produces for `data`, excluding the trailer.
+pub fn xor(data: &[u8], key: u8) -> Vec<u8> {
+    data.iter()
+        .enumerate()
+        .map(|(i, b)| b ^ key.wrapping_add(i as u8))
+        .collect()
+}
+
 // // TODO: implement with something like named_pipes for windows
 // #[cfg(windows)]
 // pub fn pipe_set<RW>() -> ((RW,RW), (RW,RW))
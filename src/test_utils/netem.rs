@@ -0,0 +1,289 @@
+//! Network condition emulation, along the lines of linux `netem`, for testing transports
+//! over slow or unreliable links. Run tests with `#[tokio::test(start_paused = true)]` so
+//! the delays cost no wall clock time and every run behaves the same.
+
+use crate::rand::Rng;
+
+use rand::RngCore;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{Instant, Sleep};
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+/// Bytes a [`NetemStream`] accepts ahead of delivery before writes push back.
+const QUEUE_LIMIT: usize = 64 * 1024;
+
+/// Conditions applied to data written through a [`NetemStream`]. Wrap both ends of a
+/// connection to affect both directions.
+#[derive(Clone, Debug, Default)]
+pub struct Netem {
+    /// Delay before written data reaches the underlying stream.
+    pub latency: Duration,
+    /// Up to this much extra random delay per write. Data is never reordered, so a write
+    /// can't overtake the one before it.
+    pub jitter: Duration,
+    /// Bandwidth cap in bytes per second.
+    pub rate: Option<u64>,
+    /// Chance of each write resetting the connection.
+    pub reset_chance: f64,
+    /// Reset the connection once this many bytes have been written.
+    pub reset_after: Option<u64>,
+    /// Seed for the jitter and resets.
+    pub seed: u64,
+}
+
+impl Netem {
+    pub fn wrap<S>(&self, inner: S) -> NetemStream<S> {
+        NetemStream {
+            inner,
+            conditions: self.clone(),
+            rng: Rng::from_seed(self.seed),
+            queue: VecDeque::new(),
+            queued: 0,
+            last_release: None,
+            written: 0,
+            reset: false,
+            sleep: Box::pin(tokio::time::sleep(Duration::ZERO)),
+        }
+    }
+}
+
+struct Pending {
+    at: Instant,
+    data: Vec<u8>,
+    pos: usize,
+}
+
+/// A stream whose writes are delayed, throttled and sometimes reset per [`Netem`]. Reads
+/// pass straight through. Delayed data is delivered whenever the stream is polled, so a
+/// reader waiting on the other end needs this side to be read, written or flushed.
+pub struct NetemStream<S> {
+    inner: S,
+    conditions: Netem,
+    rng: Rng,
+    queue: VecDeque<Pending>,
+    queued: usize,
+    last_release: Option<Instant>,
+    written: u64,
+    reset: bool,
+    sleep: Pin<Box<Sleep>>,
+}
+
+impl<S> NetemStream<S> {
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn check_reset(&self) -> io::Result<()> {
+        match self.reset {
+            true => Err(io::ErrorKind::ConnectionReset.into()),
+            false => Ok(()),
+        }
+    }
+
+    /// When data written now should be delivered.
+    fn release_time(&mut self, len: usize) -> Instant {
+        let c = &self.conditions;
+        let jitter = match c.jitter.as_micros() as u64 {
+            0 => 0,
+            max => self.rng.next_u64() % (max + 1),
+        };
+        let mut at = Instant::now() + c.latency + Duration::from_micros(jitter);
+        if let Some(last) = self.last_release {
+            at = at.max(last);
+        }
+        if let Some(rate) = c.rate {
+            at += Duration::from_secs_f64(len as f64 / rate.max(1) as f64);
+        }
+        self.last_release = Some(at);
+        at
+    }
+
+    fn roll_reset(&mut self, len: usize) -> bool {
+        self.written += len as u64;
+        let past_limit = self
+            .conditions
+            .reset_after
+            .is_some_and(|n| self.written > n);
+        let chance = self.conditions.reset_chance;
+        let unlucky = chance > 0.0 && (self.rng.next_u32() as f64 / u32::MAX as f64) < chance;
+        self.reset = past_limit || unlucky;
+        self.reset
+    }
+}
+
+impl<S: AsyncWrite + Unpin> NetemStream<S> {
+    /// Write whatever is due to the inner stream, ready once the queue is empty.
+    fn poll_deliver(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while let Some(front) = self.queue.front_mut() {
+            if Instant::now() < front.at {
+                self.sleep.as_mut().reset(front.at);
+                ready!(self.sleep.as_mut().poll(cx));
+                continue;
+            }
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &front.data[front.pos..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            front.pos += n;
+            self.queued -= n;
+            if front.pos == front.data.len() {
+                self.queue.pop_front();
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for NetemStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.check_reset()?;
+        if let Poll::Ready(Err(e)) = this.poll_deliver(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for NetemStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.check_reset()?;
+        if this.queued >= QUEUE_LIMIT {
+            ready!(this.poll_deliver(cx))?;
+        } else if let Poll::Ready(Err(e)) = this.poll_deliver(cx) {
+            return Poll::Ready(Err(e));
+        }
+        if this.roll_reset(buf.len()) {
+            this.queue.clear();
+            return Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+        }
+
+        let n = buf.len().min(QUEUE_LIMIT);
+        let at = this.release_time(n);
+        this.queue.push_back(Pending {
+            at,
+            data: buf[..n].to_vec(),
+            pos: 0,
+        });
+        this.queued += n;
+        // get the timer going even if nobody polls again until it fires
+        let _ = this.poll_deliver(cx)?;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.check_reset()?;
+        ready!(this.poll_deliver(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.check_reset()?;
+        ready!(this.poll_deliver(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test(start_paused = true)]
+    async fn delays_and_throttles_writes() -> io::Result<()> {
+        let (a, mut b) = tokio::io::duplex(1 << 20);
+        let netem = Netem {
+            latency: Duration::from_millis(100),
+            rate: Some(1000),
+            ..Netem::default()
+        };
+        let mut a = netem.wrap(a);
+
+        let start = Instant::now();
+        a.write_all(b"ping").await?;
+        assert!(start.elapsed() < Duration::from_millis(100));
+        a.flush().await?;
+        let mut buf = [0u8; 4];
+        b.read_exact(&mut buf).await?;
+        assert!(start.elapsed() >= Duration::from_millis(104));
+
+        // 2000 bytes at 1000 bytes per second
+        let start = Instant::now();
+        a.write_all(&[0; 2000]).await?;
+        a.flush().await?;
+        assert!(start.elapsed() >= Duration::from_secs(2));
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn jitter_keeps_order() -> io::Result<()> {
+        let (a, mut b) = tokio::io::duplex(1 << 20);
+        let netem = Netem {
+            latency: Duration::from_millis(20),
+            jitter: Duration::from_millis(50),
+            seed: 4895,
+            ..Netem::default()
+        };
+        let mut a = netem.wrap(a);
+        for i in 0..=255u8 {
+            a.write_all(&[i]).await?;
+        }
+        a.shutdown().await?;
+
+        let mut got = vec![];
+        b.read_to_end(&mut got).await?;
+        assert_eq!(got, (0..=255).collect::<Vec<u8>>());
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resets() -> io::Result<()> {
+        let (a, _b) = tokio::io::duplex(1 << 20);
+        let mut a = Netem {
+            reset_after: Some(10),
+            ..Netem::default()
+        }
+        .wrap(a);
+        a.write_all(&[0; 10]).await?;
+        let err = a.write_all(&[0]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        let err = a.read(&mut [0; 8]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+
+        // the same seed resets on the same write every time
+        let reset_at = |seed| async move {
+            let (a, _b) = tokio::io::duplex(1 << 20);
+            let mut a = Netem {
+                reset_chance: 0.1,
+                seed,
+                ..Netem::default()
+            }
+            .wrap(a);
+            let mut n = 0;
+            while a.write_all(b"x").await.is_ok() {
+                n += 1;
+            }
+            n
+        };
+        assert_eq!(reset_at(7).await, reset_at(7).await);
+        Ok(())
+    }
+}
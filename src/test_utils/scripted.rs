@@ -0,0 +1,245 @@
+//! A stream that plays back a fixed script of read results and checks writes against the
+//! ones expected, for unit testing poll-level code (copy buffers, transforms, wrappers)
+//! without sockets, including the `Pending` and error paths that real sockets rarely hit on
+//! demand.
+//!
+//! ```ignore
+//! let stream = ScriptedStream::builder()
+//!     .read(b"hello")
+//!     .read_pending()
+//!     .read_error(io::ErrorKind::ConnectionReset)
+//!     .write(b"olleh")
+//!     .build();
+//! ```
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use std::collections::VecDeque;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+#[derive(Debug)]
+enum Step {
+    Data(Vec<u8>),
+    /// Return `Pending` once. The waker is woken straight away, so callers that are driven
+    /// by a runtime carry on.
+    Pending,
+    Err(io::ErrorKind),
+    Eof,
+}
+
+/// Steps for a [`ScriptedStream`].
+#[derive(Debug, Default)]
+pub struct Builder {
+    reads: VecDeque<Step>,
+    writes: VecDeque<Step>,
+}
+
+impl Builder {
+    /// The next read returns (up to a buffer's worth of) `data`. What doesn't fit is returned
+    /// by the reads after.
+    pub fn read(mut self, data: &[u8]) -> Self {
+        self.reads.push_back(Step::Data(data.to_vec()));
+        self
+    }
+
+    pub fn read_pending(mut self) -> Self {
+        self.reads.push_back(Step::Pending);
+        self
+    }
+
+    pub fn read_error(mut self, kind: io::ErrorKind) -> Self {
+        self.reads.push_back(Step::Err(kind));
+        self
+    }
+
+    /// The next read returns end of stream.
+    pub fn eof(mut self) -> Self {
+        self.reads.push_back(Step::Eof);
+        self
+    }
+
+    /// The next writes must add up to `data`, in however many pieces.
+    pub fn write(mut self, data: &[u8]) -> Self {
+        self.writes.push_back(Step::Data(data.to_vec()));
+        self
+    }
+
+    pub fn write_pending(mut self) -> Self {
+        self.writes.push_back(Step::Pending);
+        self
+    }
+
+    pub fn write_error(mut self, kind: io::ErrorKind) -> Self {
+        self.writes.push_back(Step::Err(kind));
+        self
+    }
+
+    pub fn build(self) -> ScriptedStream {
+        ScriptedStream {
+            reads: self.reads,
+            writes: self.writes,
+            written: 0,
+        }
+    }
+}
+
+/// Plays back the reads given to its [`Builder`] in order and panics on any write that
+/// wasn't expected, on reading past the end of the script, and on being dropped before the
+/// whole script has run.
+#[derive(Debug)]
+pub struct ScriptedStream {
+    reads: VecDeque<Step>,
+    writes: VecDeque<Step>,
+    /// Bytes written so far, for pointing at where a write went wrong.
+    written: usize,
+}
+
+impl ScriptedStream {
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+}
+
+impl AsyncRead for ScriptedStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.reads.pop_front() {
+            Some(Step::Data(mut data)) => {
+                let n = data.len().min(buf.remaining());
+                buf.put_slice(&data[..n]);
+                if n < data.len() {
+                    this.reads.push_front(Step::Data(data.split_off(n)));
+                }
+                Poll::Ready(Ok(()))
+            }
+            Some(Step::Pending) => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            Some(Step::Err(kind)) => Poll::Ready(Err(kind.into())),
+            Some(Step::Eof) => Poll::Ready(Ok(())),
+            None => panic!("read past the end of the script"),
+        }
+    }
+}
+
+impl AsyncWrite for ScriptedStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        match this.writes.pop_front() {
+            Some(Step::Data(mut expected)) => {
+                let n = expected.len().min(buf.len());
+                if let Some(i) = (0..n).find(|&i| buf[i] != expected[i]) {
+                    panic!(
+                        "unexpected write at byte {}: {:?}, expected {:?}",
+                        this.written + i,
+                        &buf[..n],
+                        &expected[..n]
+                    );
+                }
+                this.written += n;
+                if n < expected.len() {
+                    this.writes.push_front(Step::Data(expected.split_off(n)));
+                }
+                Poll::Ready(Ok(n))
+            }
+            Some(Step::Pending) => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            Some(Step::Err(kind)) => Poll::Ready(Err(kind.into())),
+            Some(Step::Eof) => unreachable!("writes have no end of stream"),
+            None if buf.is_empty() => Poll::Ready(Ok(0)),
+            None => panic!(
+                "unexpected write of {} bytes at byte {}",
+                buf.len(),
+                this.written
+            ),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for ScriptedStream {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            return;
+        }
+        assert!(self.reads.is_empty(), "unread steps: {:?}", self.reads);
+        assert!(self.writes.is_empty(), "missing writes: {:?}", self.writes);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn plays_back_reads() -> io::Result<()> {
+        let mut s = ScriptedStream::builder()
+            .read(b"hello ")
+            .read_pending()
+            .read(b"world")
+            .eof()
+            .read_error(io::ErrorKind::ConnectionReset)
+            .build();
+
+        let mut buf = [0u8; 3];
+        s.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"hel");
+        let mut rest = vec![];
+        s.read_to_end(&mut rest).await?;
+        assert_eq!(rest, b"lo world");
+        let err = s.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn checks_writes() -> io::Result<()> {
+        let mut s = ScriptedStream::builder()
+            .write(b"ab")
+            .write_pending()
+            .write(b"cd")
+            .write_error(io::ErrorKind::BrokenPipe)
+            .build();
+
+        s.write_all(b"abc").await?;
+        s.write_all(b"d").await?;
+        let err = s.write_all(b"e").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "unexpected write at byte 1")]
+    async fn rejects_wrong_writes() {
+        let mut s = ScriptedStream::builder().write(b"ab").build();
+        let _ = s.write_all(b"ax").await;
+    }
+
+    #[test]
+    #[should_panic(expected = "missing writes")]
+    fn expects_the_whole_script() {
+        ScriptedStream::builder().write(b"never").build();
+    }
+}
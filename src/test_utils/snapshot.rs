@@ -0,0 +1,111 @@
+//! Wire format snapshots: the first bytes a transport puts on the wire for a fixed plaintext
+//! (and, for transports that draw randomness, a fixed [`SEED_KEY`](crate::rand::SEED_KEY)),
+//! compared against a copy stored in `tests/snapshots/`. A handshake or framing change that
+//! would break interop, or make the traffic look different, then fails a test instead of
+//! going unnoticed.
+//!
+//! Snapshots are stored as hex, 32 bytes to a line, so changes show up in a diff. Run with
+//! [`UPDATE_VAR`] set to write the current output as the new snapshot, after checking that
+//! the change is intended.
+
+use crate::{Result, Transport};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Set to `1` to overwrite snapshots with what the transports produce now.
+pub const UPDATE_VAR: &str = "PTRS_UPDATE_SNAPSHOTS";
+
+/// Plaintext written through a transport when taking its snapshot.
+pub const PLAINTEXT: &[u8] = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+
+/// Long enough to cover a handshake and the start of the first record.
+pub const DEFAULT_LEN: usize = 256;
+
+/// How long to wait for a transport that holds output back until more arrives.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Write `plaintext` through `transport` and close it, returning at most the first `n`
+/// bytes that reached the wire.
+pub async fn wire_prefix<'a>(
+    transport: &dyn Transport<'a, DuplexStream>,
+    plaintext: &[u8],
+    n: usize,
+) -> Result<Vec<u8>> {
+    let (near, mut far) = tokio::io::duplex(64 * 1024);
+    let mut stream = transport.wrap(near)?;
+    stream.write_all(plaintext).await?;
+    stream.shutdown().await?;
+
+    let mut wire = vec![];
+    let read = tokio::time::timeout(READ_TIMEOUT, async {
+        let mut buf = [0u8; 4096];
+        while wire.len() < n {
+            match far.read(&mut buf).await? {
+                0 => break,
+                nr => wire.extend_from_slice(&buf[..nr]),
+            }
+        }
+        Ok::<_, std::io::Error>(())
+    })
+    .await;
+    if let Ok(r) = read {
+        r?;
+    }
+    wire.truncate(n);
+    Ok(wire)
+}
+
+fn path(name: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", "snapshots"]
+        .iter()
+        .collect::<PathBuf>()
+        .join(format!("{name}.hex"))
+}
+
+fn to_hex(wire: &[u8]) -> String {
+    wire.chunks(32).map(|c| hex::encode(c) + "\n").collect()
+}
+
+/// Compare `wire` against the snapshot called `name`, or write it if [`UPDATE_VAR`] is set.
+pub fn assert_snapshot(name: &str, wire: &[u8]) {
+    let path = path(name);
+    let actual = to_hex(wire);
+    if std::env::var(UPDATE_VAR).as_deref() == Ok("1") {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+        panic!(
+            "no snapshot at {} ({e}), run with {UPDATE_VAR}=1 to create it",
+            path.display()
+        )
+    });
+    assert!(
+        expected == actual,
+        "wire format of {name} changed, run with {UPDATE_VAR}=1 if that is intended\n\
+         expected:\n{expected}\nactual:\n{actual}"
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transports::reverse::Reverse;
+
+    #[test]
+    fn hex_lines() {
+        assert_eq!(to_hex(&[]), "");
+        assert_eq!(to_hex(&[0xab; 33]), format!("{}\nab\n", "ab".repeat(32)));
+    }
+
+    #[tokio::test]
+    async fn captures_at_most_n_bytes() -> Result<()> {
+        let wire = wire_prefix(&Reverse::new(), PLAINTEXT, 4).await?;
+        assert_eq!(wire.len(), 4);
+        Ok(())
+    }
+}
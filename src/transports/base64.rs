@@ -1,10 +1,15 @@
 use crate::{
-    wrap::{Reveal, Seal, WrapTransport},
-    Configurable, Named, Result,
+    codec,
+    pt::transform::{Chunked, TransformFactory},
+    sync::{ChunkReader, ChunkWriter, SealWriter, SyncTransport},
+    wrap::{Reveal, RevealWith, Seal, SealWith, WrapTransport, Wrapper},
+    BufferTransform, Configurable, Named, OverheadEstimate, Result, Role,
 };
 
 use tokio::io::{AsyncRead, AsyncWrite};
 
+use std::io::{Read, Write};
+
 use base64::engine::general_purpose;
 
 struct Config {
@@ -50,54 +55,78 @@ impl Default for Base64 {
 }
 
 impl Base64Builder {
+    /// Per-connection base64 transforms for `role`: encoding when sealing, decoding when
+    /// revealing.
+    pub fn factory(&self, role: Role) -> Base64Factory {
+        Base64Factory { role }
+    }
+
     fn build_seal(&self) -> Result<Box<dyn Seal + Unpin + Send + Sync>> {
-        Ok(Box::<Base64>::default())
+        Ok(Box::new(SealWith(self.factory(Role::Sealer))))
     }
 
     fn build_reveal(&self) -> Result<Box<dyn Reveal + Unpin + Send + Sync>> {
-        Ok(Box::<Base64>::default())
+        Ok(Box::new(RevealWith(self.factory(Role::Revealer))))
+    }
+
+    fn wrapper_for(&self, role: Role) -> Result<Wrapper> {
+        Ok(Wrapper::new(
+            NAME,
+            role,
+            self.build_seal()?,
+            self.build_reveal()?,
+        ))
     }
 }
 
 impl WrapTransport for Base64Builder {
-    fn wrapper(
-        &self,
-    ) -> Result<(
-        Box<dyn Seal + Unpin + Send + Sync>,
-        Box<dyn Reveal + Unpin + Send + Sync>,
-    )> {
-        let seal = self.build_seal()?;
-        let reveal = self.build_reveal()?;
-        Ok((seal, reveal))
+    fn wrapper(&self) -> Result<Wrapper> {
+        self.wrapper_for(Role::Sealer)
     }
 
-    fn unwrapper(
-        &self,
-    ) -> Result<(
-        Box<dyn Seal + Unpin + Send + Sync>,
-        Box<dyn Reveal + Unpin + Send + Sync>,
-    )> {
-        let seal = self.build_seal()?;
-        let reveal = self.build_reveal()?;
-        Ok((seal, reveal))
+    fn unwrapper(&self) -> Result<Wrapper> {
+        self.wrapper_for(Role::Revealer)
     }
+
+    /// Four characters per three bytes, with each write padded to a whole group.
+    fn overhead(&self, payload_len: usize) -> OverheadEstimate {
+        OverheadEstimate {
+            wire_len: payload_len.div_ceil(3) * 4,
+            fixed: 0,
+            per_byte: 4.0 / 3.0,
+        }
+    }
+}
+
+/// Makes the base64 transforms for one side of a connection, see [`Base64Builder::factory`].
+#[derive(Clone, Copy, Debug)]
+pub struct Base64Factory {
+    role: Role,
 }
 
-impl Seal for Base64 {
-    fn seal<'a>(
-        &self,
-        r: Box<dyn AsyncWrite + Unpin + Send + Sync + 'a>,
-    ) -> Box<dyn AsyncWrite + Unpin + Send + Sync + 'a> {
-        r
+impl<'a, R, W> TransformFactory<'a, R, W> for Base64Factory
+where
+    R: AsyncRead + Unpin + ?Sized + 'a,
+    W: AsyncWrite + Unpin + ?Sized + 'a,
+{
+    fn make(&self) -> Box<dyn BufferTransform<'a, R, W> + Unpin + Send + Sync + 'a> {
+        match self.role {
+            Role::Sealer => Box::new(Chunked::new(codec::base64::Encode)),
+            Role::Revealer => Box::new(Chunked::new(codec::base64::Decode::default())),
+        }
     }
 }
 
-impl Reveal for Base64 {
-    fn reveal<'a>(
-        &self,
-        r: Box<dyn AsyncRead + Unpin + Send + Sync + 'a>,
-    ) -> Box<dyn AsyncRead + Unpin + Send + Sync + 'a> {
-        r
+impl SyncTransport for Base64Builder {
+    fn seal_write<'a>(&self, w: Box<dyn Write + Send + 'a>) -> Result<Box<dyn SealWriter + 'a>> {
+        Ok(Box::new(ChunkWriter::new(codec::base64::Encode, w)))
+    }
+
+    fn reveal_read<'a>(&self, r: Box<dyn Read + Send + 'a>) -> Result<Box<dyn Read + Send + 'a>> {
+        Ok(Box::new(ChunkReader::new(
+            codec::base64::Decode::default(),
+            r,
+        )))
     }
 }
 
@@ -186,8 +215,8 @@ mod test {
     ///
     #[tokio::test]
     async fn wrap_transport() {
-        let (sealer, revealer) = Base64Builder::default().wrapper().unwrap();
-        let (mut client, mut server) = tokio::net::UnixStream::pair().unwrap();
+        let (sealer, revealer) = Base64Builder::default().wrapper().unwrap().into_parts();
+        let (client, mut server) = tokio::net::UnixStream::pair().unwrap();
 
         let server_task = tokio::spawn(async move {
             let (r, w) = server.split();
@@ -199,13 +228,14 @@ mod test {
         });
 
         let client_task = tokio::spawn(async move {
-            let (mut cr, mut cw) = client.split();
-            let nw = cw.write(&[0_u8; 1024]).await.unwrap();
-            assert_eq!(nw, 1024);
+            // the wire carries base64, so the client needs to be wrapped as well
+            let mut c = Base64Builder::default().wrapper().unwrap().wrap(client);
+            c.write_all(&[7_u8; 1024]).await.unwrap();
+            c.flush().await.unwrap();
 
             let mut buf = [0_u8; 1024];
-            let nr = cr.read(&mut buf).await.unwrap();
-            assert_eq!(nr, 1024);
+            c.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf, [7_u8; 1024]);
         });
 
         try_join!(client_task, server_task).unwrap();
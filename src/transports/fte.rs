@@ -0,0 +1,236 @@
+use crate::{
+    fte::{payload_per_string, Decode, Encode, Language},
+    pt::transform::{Chunked, TransformFactory},
+    sync::{ChunkReader, ChunkWriter, SealWriter, SyncTransport},
+    wrap::{Reveal, RevealWith, Seal, SealWith, WrapTransport, Wrapper},
+    BufferTransform, Configurable, Error, Named, OverheadEstimate, Result, Role,
+};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+pub const NAME: &str = "fte";
+
+/// Lowercase words: 128 byte strings of letters and spaces, about 75 bytes of data each.
+pub const DEFAULT_REGEX: &str = "[a-z ]+";
+pub const DEFAULT_LEN: usize = 128;
+
+/// Carries the stream in fixed length strings matching a regex, see [`crate::fte`].
+///
+/// Configured with `len=<N>;regex=<REGEX>`; `regex` takes the rest of the line, so it goes
+/// last and may itself contain `;`.
+#[derive(Clone, Debug)]
+pub struct FteBuilder {
+    lang: Arc<Language>,
+}
+
+impl FteBuilder {
+    pub fn new(regex: &str, len: usize) -> Result<Self> {
+        Ok(Self {
+            lang: Arc::new(Language::new(regex, len)?),
+        })
+    }
+
+    /// Per-connection transforms for `role`: encoding when sealing, decoding when revealing.
+    pub fn factory(&self, role: Role) -> FteFactory {
+        FteFactory {
+            role,
+            lang: self.lang.clone(),
+        }
+    }
+
+    fn build_seal(&self) -> Box<dyn Seal + Unpin + Send + Sync> {
+        Box::new(SealWith(self.factory(Role::Sealer)))
+    }
+
+    fn build_reveal(&self) -> Box<dyn Reveal + Unpin + Send + Sync> {
+        Box::new(RevealWith(self.factory(Role::Revealer)))
+    }
+
+    fn wrapper_for(&self, role: Role) -> Wrapper {
+        Wrapper::new(NAME, role, self.build_seal(), self.build_reveal())
+    }
+}
+
+/// The regex and string length in `args`, as described on [`FteBuilder`].
+fn parse_options(args: &str) -> Result<(&str, usize)> {
+    let (mut regex, mut len) = (DEFAULT_REGEX, DEFAULT_LEN);
+    let mut rest = args.trim();
+    while !rest.is_empty() {
+        if let Some(r) = rest.strip_prefix("regex=") {
+            regex = r;
+            break;
+        }
+        let (opt, tail) = rest.split_once(';').unwrap_or((rest, ""));
+        rest = tail.trim_start();
+        match opt.split_once('=') {
+            Some(("len", v)) => {
+                len = v
+                    .parse()
+                    .map_err(|e| Error::Config(format!("bad len \"{v}\": {e}").into()))?;
+            }
+            _ => {
+                return Err(Error::Config(
+                    format!("unknown {NAME} option \"{opt}\"").into(),
+                ))
+            }
+        }
+    }
+    Ok((regex, len))
+}
+
+impl Default for FteBuilder {
+    fn default() -> Self {
+        Self::new(DEFAULT_REGEX, DEFAULT_LEN).expect("default language is usable")
+    }
+}
+
+impl Named for FteBuilder {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+}
+
+impl Configurable for FteBuilder {
+    fn with_config(self, args: &str) -> Result<Self> {
+        let (regex, len) = parse_options(args)?;
+        if (regex, len) == (DEFAULT_REGEX, DEFAULT_LEN) {
+            return Ok(self);
+        }
+        Self::new(regex, len)
+    }
+}
+
+impl SyncTransport for FteBuilder {
+    fn seal_write<'a>(&self, w: Box<dyn Write + Send + 'a>) -> Result<Box<dyn SealWriter + 'a>> {
+        Ok(Box::new(ChunkWriter::new(
+            Encode::new(self.lang.clone()),
+            w,
+        )))
+    }
+
+    fn reveal_read<'a>(&self, r: Box<dyn Read + Send + 'a>) -> Result<Box<dyn Read + Send + 'a>> {
+        Ok(Box::new(ChunkReader::new(
+            Decode::new(self.lang.clone()),
+            r,
+        )))
+    }
+}
+
+impl WrapTransport for FteBuilder {
+    fn wrapper(&self) -> Result<Wrapper> {
+        Ok(self.wrapper_for(Role::Sealer))
+    }
+
+    fn unwrapper(&self) -> Result<Wrapper> {
+        Ok(self.wrapper_for(Role::Revealer))
+    }
+
+    /// A whole string for every [`Language::capacity`] - 1 bytes, or part of that.
+    fn overhead(&self, payload_len: usize) -> OverheadEstimate {
+        let (len, per_string) = (self.lang.string_len(), payload_per_string(&self.lang));
+        OverheadEstimate {
+            wire_len: payload_len.div_ceil(per_string) * len,
+            fixed: 0,
+            per_byte: len as f64 / per_string as f64,
+        }
+    }
+}
+
+/// Makes the FTE transforms for one side of a connection, see [`FteBuilder::factory`].
+#[derive(Clone, Debug)]
+pub struct FteFactory {
+    role: Role,
+    lang: Arc<Language>,
+}
+
+impl<'a, R, W> TransformFactory<'a, R, W> for FteFactory
+where
+    R: AsyncRead + Unpin + ?Sized + 'a,
+    W: AsyncWrite + Unpin + ?Sized + 'a,
+{
+    fn make(&self) -> Box<dyn BufferTransform<'a, R, W> + Unpin + Send + Sync + 'a> {
+        match self.role {
+            Role::Sealer => Box::new(Chunked::new(Encode::new(self.lang.clone()))),
+            Role::Revealer => Box::new(Chunked::new(Decode::new(self.lang.clone()))),
+        }
+    }
+
+    /// Takes the same options as [`FteBuilder`], so each direction can use its own language.
+    fn configure(&mut self, args: &str) -> Result<()> {
+        if args.trim().is_empty() {
+            return Ok(());
+        }
+        let (regex, len) = parse_options(args)?;
+        self.lang = Arc::new(Language::new(regex, len)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn config() -> Result<()> {
+        let b = FteBuilder::default().with_config("len=40;regex=[0-9;]+")?;
+        assert_eq!(b.lang.string_len(), 40);
+        assert!(FteBuilder::default().with_config("len=forty").is_err());
+        assert!(FteBuilder::default().with_config("size=40").is_err());
+        assert!(FteBuilder::default().with_config("regex=a").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wire_matches_the_grammar() -> Result<()> {
+        let builder = FteBuilder::default();
+        let (c, mut wire) = tokio::io::duplex(4096);
+        let mut client = builder.wrapper()?.wrap(c);
+        client.write_all(&[0xff; 300]).await?;
+        client.flush().await?;
+        drop(client);
+
+        let mut seen = vec![];
+        wire.read_to_end(&mut seen).await?;
+        assert_eq!(seen.len() % DEFAULT_LEN, 0);
+        assert!(seen.iter().all(|&b| b == b' ' || b.is_ascii_lowercase()));
+
+        let (s, mut peer) = tokio::io::duplex(4096);
+        let mut server = builder.unwrapper()?.wrap(s);
+        peer.write_all(&seen).await?;
+        let mut out = [0u8; 300];
+        server.read_exact(&mut out).await?;
+        assert_eq!(out, [0xff; 300]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn directions_take_their_own_language() -> Result<()> {
+        let digits = "len=40;regex=[0-9]+";
+        let builder = FteBuilder::default();
+        let client = builder.wrapper()?.with_seal_config(digits)?;
+        assert_eq!((client.seal_config(), client.reveal_config()), (digits, ""));
+        assert!(builder.wrapper()?.with_seal_config("size=40").is_err());
+
+        let (c, mut wire) = tokio::io::duplex(4096);
+        let mut client = client.wrap(c);
+        client.write_all(b"upstream").await?;
+        client.flush().await?;
+        drop(client);
+        let mut seen = vec![];
+        wire.read_to_end(&mut seen).await?;
+        assert_eq!(seen.len() % 40, 0);
+        assert!(seen.iter().all(u8::is_ascii_digit));
+
+        let (s, mut peer) = tokio::io::duplex(4096);
+        let mut server = builder.unwrapper()?.with_reveal_config(digits)?.wrap(s);
+        peer.write_all(&seen).await?;
+        let mut out = [0u8; 8];
+        server.read_exact(&mut out).await?;
+        assert_eq!(&out, b"upstream");
+        Ok(())
+    }
+}
@@ -1,12 +1,16 @@
 // use std::io::{self, Read, Result, Write};
 
-use crate::sync::constructions::stream::StreamHandler;
-use crate::Result;
-use crate::{Configurable, Named};
+use crate::codec::hex;
+use crate::pt::transform::{Chunked, TransformFactory};
+use crate::sync::{ChunkReader, ChunkWriter, SealWriter, SyncTransport};
+use crate::{BufferTransform, OverheadEstimate, Result};
+use crate::{Configurable, Named, Role};
 
-use hex::{decode_to_slice, encode_to_slice, encode_upper};
+use tokio::io::{AsyncRead, AsyncWrite};
 
-use std::io::{BufWriter, Error, ErrorKind, Read, Write};
+use ::hex::{decode_to_slice, encode_to_slice, encode_upper};
+
+use std::io::{Error, Read, Write};
 use std::str::FromStr;
 
 pub const NAME: &str = "hex";
@@ -29,10 +33,7 @@ impl FromStr for Config {
         match s {
             "upper" => Ok(Config { case: Case::Upper }),
             "lower" => Ok(Config { case: Case::Lower }),
-            _ => Err(Error::new(
-                ErrorKind::Other,
-                format!("Bad config, unknown case: {}", s),
-            )),
+            _ => Err(Error::other(format!("Bad config, unknown case: {}", s))),
         }
     }
 }
@@ -57,30 +58,23 @@ impl HexEncoder {
         }
     }
 
-    pub fn stream_encode_fn() -> Result<Box<dyn crate::sync::constructions::stream::StreamHandler>>
-    {
-        // let _h = Self::new();
-        crate::sync::constructions::stream::from_transform(|r, mut w| {
-            // Ok(h.encode(r, w)?)
-            let mut buf = [0_u8; 1024];
-            let nr = r.read(&mut buf)?;
-            Ok(w.write(&buf[..nr])?)
-        })
-    }
-
     pub fn encode<T: AsRef<[u8]>>(&self, data: T, out: &mut [u8]) -> Result<usize> {
-        let l: usize;
+        let data = data.as_ref();
+        let l = data.len() * 2;
+        if out.len() < l {
+            return Err(
+                Error::other(format!("output buffer too small: {} < {}", out.len(), l)).into(),
+            );
+        }
 
         match self.config.case {
             Case::Upper => {
-                encode_to_slice(data.as_ref(), out)
-                    .map_err(|e| Error::new(ErrorKind::Other, format!("encode error: {e}")))?;
-                l = out.len()
+                let s = encode_upper(data);
+                out[..l].copy_from_slice(s.as_bytes());
             }
             Case::Lower => {
-                let s = encode_upper(data.as_ref());
-                l = s.len();
-                _ = BufWriter::new(out).write(s.as_bytes())?;
+                encode_to_slice(data, &mut out[..l])
+                    .map_err(|e| Error::other(format!("encode error: {e}")))?;
             }
         }
         Ok(l)
@@ -89,15 +83,13 @@ impl HexEncoder {
     pub fn decode<T: AsRef<[u8]>>(&self, data: T, out: &mut [u8]) -> Result<()> {
         let l = data.as_ref().len() / 2;
         if out.len() < l {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!("output buffer too small: {} < {}", out.len(), l),
-            )
-            .into());
+            return Err(
+                Error::other(format!("output buffer too small: {} < {}", out.len(), l)).into(),
+            );
         }
 
         decode_to_slice(data.as_ref(), &mut out[..l])
-            .map_err(|e| Error::new(ErrorKind::Other, format!("decode error: {e}")))?;
+            .map_err(|e| Error::other(format!("decode error: {e}")))?;
         Ok(())
     }
 }
@@ -108,24 +100,57 @@ impl Default for HexEncoder {
     }
 }
 
-impl From<HexEncoder> for Box<dyn StreamHandler> {
-    fn from(h: HexEncoder) -> Self {
-        let _h = h;
-        Box::new(move |r: &mut dyn Read, w: &mut dyn Write| -> Result<u64> {
-            let mut buf = [0_u8; 1024];
-            let mut out = [0_u8; 1024];
-            let mut total = 0_u64;
-            loop {
-                let nr = r.read(&mut buf)?;
-                if nr == 0 {
-                    break;
-                }
-                let nw = _h.encode(&buf[..nr], &mut out)?;
-                w.write_all(&out[..nw])?;
-                total += nw as u64;
-            }
-            Ok(total)
-        })
+impl SyncTransport for HexEncoder {
+    fn seal_write<'a>(&self, w: Box<dyn Write + Send + 'a>) -> Result<Box<dyn SealWriter + 'a>> {
+        let encode = hex::Encode {
+            upper: self.config.case == Case::Upper,
+        };
+        Ok(Box::new(ChunkWriter::new(encode, w)))
+    }
+
+    fn reveal_read<'a>(&self, r: Box<dyn Read + Send + 'a>) -> Result<Box<dyn Read + Send + 'a>> {
+        Ok(Box::new(ChunkReader::new(hex::Decode::default(), r)))
+    }
+}
+
+impl HexEncoder {
+    /// Per-connection hex transforms for `role`: encoding when sealing, decoding when revealing.
+    pub fn factory(&self, role: Role) -> HexFactory {
+        HexFactory {
+            case: self.config.case,
+            role,
+        }
+    }
+
+    /// Two characters per byte.
+    pub fn overhead(&self, payload_len: usize) -> OverheadEstimate {
+        OverheadEstimate {
+            wire_len: payload_len * 2,
+            fixed: 0,
+            per_byte: 2.0,
+        }
+    }
+}
+
+/// Makes the hex transforms for one side of a connection, see [`HexEncoder::factory`].
+#[derive(Clone, Copy, Debug)]
+pub struct HexFactory {
+    case: Case,
+    role: Role,
+}
+
+impl<'a, R, W> TransformFactory<'a, R, W> for HexFactory
+where
+    R: AsyncRead + Unpin + ?Sized + 'a,
+    W: AsyncWrite + Unpin + ?Sized + 'a,
+{
+    fn make(&self) -> Box<dyn BufferTransform<'a, R, W> + Unpin + Send + Sync + 'a> {
+        match self.role {
+            Role::Sealer => Box::new(Chunked::new(hex::Encode {
+                upper: self.case == Case::Upper,
+            })),
+            Role::Revealer => Box::new(Chunked::new(hex::Decode::default())),
+        }
     }
 }
 
@@ -161,4 +186,31 @@ mod test {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn factory_transforms() -> Result<()> {
+        use crate::conversion::from_factories;
+        use crate::Transport;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let h = HexEncoder::new().with_config("lower")?;
+        let t = from_factories(h.factory(Role::Revealer), h.factory(Role::Sealer));
+        for _ in 0..2 {
+            let (a, mut wire) = tokio::io::duplex(64);
+            let mut s = t.wrap(a)?;
+            s.write_all(b"hi").await?;
+            s.flush().await?;
+            let mut buf = [0_u8; 4];
+            wire.read_exact(&mut buf).await?;
+            assert_eq!(&buf, b"6869");
+
+            // split across writes on a half byte
+            wire.write_all(b"6").await?;
+            wire.write_all(b"f").await?;
+            let mut buf = [0_u8; 1];
+            s.read_exact(&mut buf).await?;
+            assert_eq!(&buf, b"o");
+        }
+        Ok(())
+    }
 }
@@ -1,12 +1,9 @@
 use crate::pt::copy::*;
-use crate::pt::copy_buffer::CopyBuffer;
 
 use async_trait::async_trait;
-use futures::{future::poll_fn, ready};
+use futures::future::poll_fn;
 use tokio::io::{AsyncRead, AsyncWrite};
 
-use std::task::Poll;
-
 use super::{transfer_one_direction, Http};
 
 #[async_trait]
@@ -24,19 +21,21 @@ where
         A: AsyncRead + AsyncWrite + Unpin,
         B: AsyncRead + AsyncWrite + Unpin,
     {
-        let mut a_to_b = TransferState::Running(CopyBuffer::new());
-        let mut b_to_a = TransferState::Running(CopyBuffer::new());
-        poll_fn(|cx| {
-            let a_to_b = transfer_one_direction(cx, &mut a_to_b, a, b)?;
-            let b_to_a = transfer_one_direction(cx, &mut b_to_a, b, a)?;
-
-            // It is not a problem if ready! returns early because transfer_one_direction for the
-            // other direction will keep returning TransferState::Done(count) in future calls to poll
-            let a_to_b = ready!(a_to_b);
-            let b_to_a = ready!(b_to_a);
+        self.copy_bidirectional_with(a, b, HalfClosePolicy::default())
+            .await
+    }
 
-            Poll::Ready(Ok((a_to_b, b_to_a)))
-        })
-        .await
+    async fn copy_bidirectional_with<'a, 'b>(
+        &self,
+        a: &'a mut A,
+        b: &'b mut B,
+        policy: HalfClosePolicy,
+    ) -> std::result::Result<(u64, u64), std::io::Error>
+    where
+        A: AsyncRead + AsyncWrite + Unpin,
+        B: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut copy = DuplexCopy::new(policy);
+        poll_fn(|cx| copy.poll_copy(cx, a, b, transfer_one_direction, transfer_one_direction)).await
     }
 }
@@ -122,7 +122,7 @@ mod test {
     ///
     #[tokio::test]
     async fn wrap_transport() {
-        let (sealer, revealer) = Http::default().wrapper().unwrap();
+        let (sealer, revealer) = Http::default().wrapper().unwrap().into_parts();
         let (mut client, mut server) = tokio::net::UnixStream::pair().unwrap();
 
         let server_task = tokio::spawn(async move {
@@ -1,4 +1,4 @@
-use crate::{pt::wrap::*, Result};
+use crate::{pt::wrap::*, Named, Result, Role};
 use tokio::io::{AsyncRead, AsyncWrite};
 
 use super::Http;
@@ -22,21 +22,18 @@ impl Reveal for Http {
 }
 
 impl WrapTransport for Http {
-    fn wrapper(
-        &self,
-    ) -> Result<(
-        Box<dyn Seal + Unpin + Send + Sync>,
-        Box<dyn Reveal + Unpin + Send + Sync>,
-    )> {
-        Ok((Box::new(*self), Box::new(*self)))
+    fn wrapper(&self) -> Result<Wrapper> {
+        let w = Wrapper::new(self.name(), Role::Sealer, Box::new(*self), Box::new(*self));
+        Ok(w)
     }
 
-    fn unwrapper(
-        &self,
-    ) -> Result<(
-        Box<dyn Seal + Unpin + Send + Sync>,
-        Box<dyn Reveal + Unpin + Send + Sync>,
-    )> {
-        Ok((Box::new(*self), Box::new(*self)))
+    fn unwrapper(&self) -> Result<Wrapper> {
+        let w = Wrapper::new(
+            self.name(),
+            Role::Revealer,
+            Box::new(*self),
+            Box::new(*self),
+        );
+        Ok(w)
     }
 }
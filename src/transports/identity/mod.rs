@@ -1,6 +1,7 @@
 use crate::pt::copy::*;
 use crate::{
-    Configurable, Named, Result, Role, Stream, Transport, TransportBuilder, TransportInstance,
+    Capabilities, Configurable, Named, Result, Role, Stream, Transport, TransportBuilder,
+    TransportInstance,
 };
 
 use futures::ready;
@@ -12,6 +13,7 @@ use std::task::{Context, Poll};
 
 mod duplex;
 mod simplex;
+mod sync;
 mod wrap;
 
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
@@ -21,6 +23,10 @@ impl TransportBuilder for Identity {
     fn build(&self, _r: &Role) -> Result<TransportInstance> {
         Ok(TransportInstance::new(Box::new(Identity::new())))
     }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::STREAM | Capabilities::PASSTHROUGH
+    }
 }
 
 impl Identity {
@@ -48,6 +54,10 @@ where
     fn wrap(&self, a: A) -> Result<Box<dyn Stream + 'a>> {
         Ok(Box::new(a))
     }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::STREAM | Capabilities::PASSTHROUGH
+    }
 }
 
 fn transfer_one_direction<A, B>(
@@ -106,6 +116,24 @@ mod test {
         assert_eq!(down, 1024 * 1024);
     }
 
+    #[tokio::test]
+    async fn duplex_over_borrowed_trait_objects() -> Result<()> {
+        let (mut a, mut client) = tokio::io::duplex(64);
+        let (mut b, mut server) = tokio::io::duplex(64);
+        let a: &mut dyn crate::Stream = &mut a;
+        let b: &mut dyn crate::Stream = &mut b;
+
+        client.write_all(b"ping").await?;
+        client.shutdown().await?;
+        server.shutdown().await?;
+        let (up, down) = Identity::new().copy_bidirectional(a, b).await?;
+        assert_eq!((up, down), (4, 0));
+        let mut out = vec![];
+        server.read_to_end(&mut out).await?;
+        assert_eq!(out, b"ping");
+        Ok(())
+    }
+
     #[tokio::test]
     async fn duplex() {
         init_subscriber();
@@ -137,7 +165,7 @@ mod test {
     ///
     #[tokio::test]
     async fn wrap_transport() {
-        let (sealer, revealer) = Identity::default().wrapper().unwrap();
+        let (sealer, revealer) = Identity::default().wrapper().unwrap().into_parts();
         let (mut client, mut server) = tokio::net::UnixStream::pair().unwrap();
 
         let server_task = tokio::spawn(async move {
@@ -20,4 +20,8 @@ where
     ) -> Poll<io::Result<u64>> {
         transfer_one_direction(cx, state, r, w)
     }
+
+    fn is_passthrough(&self) -> bool {
+        true
+    }
 }
@@ -0,0 +1,15 @@
+use super::Identity;
+use crate::sync::{SealWriter, SyncTransport};
+use crate::Result;
+
+use std::io::{Read, Write};
+
+impl SyncTransport for Identity {
+    fn seal_write<'a>(&self, w: Box<dyn Write + Send + 'a>) -> Result<Box<dyn SealWriter + 'a>> {
+        Ok(Box::new(w))
+    }
+
+    fn reveal_read<'a>(&self, r: Box<dyn Read + Send + 'a>) -> Result<Box<dyn Read + Send + 'a>> {
+        Ok(r)
+    }
+}
@@ -1,6 +1,6 @@
 use super::Identity;
 use crate::pt::wrap::*;
-use crate::Result;
+use crate::{Named, Result, Role};
 
 use tokio::io::{AsyncRead, AsyncWrite};
 
@@ -23,21 +23,18 @@ impl Reveal for Identity {
 }
 
 impl WrapTransport for Identity {
-    fn wrapper(
-        &self,
-    ) -> Result<(
-        Box<dyn Seal + Unpin + Send + Sync>,
-        Box<dyn Reveal + Unpin + Send + Sync>,
-    )> {
-        Ok((Box::new(*self), Box::new(*self)))
+    fn wrapper(&self) -> Result<Wrapper> {
+        let w = Wrapper::new(self.name(), Role::Sealer, Box::new(*self), Box::new(*self));
+        Ok(w)
     }
 
-    fn unwrapper(
-        &self,
-    ) -> Result<(
-        Box<dyn Seal + Unpin + Send + Sync>,
-        Box<dyn Reveal + Unpin + Send + Sync>,
-    )> {
-        Ok((Box::new(*self), Box::new(*self)))
+    fn unwrapper(&self) -> Result<Wrapper> {
+        let w = Wrapper::new(
+            self.name(),
+            Role::Revealer,
+            Box::new(*self),
+            Box::new(*self),
+        );
+        Ok(w)
     }
 }
@@ -1,14 +1,29 @@
+#[cfg(feature = "codecs")]
 pub mod base64;
+#[cfg(feature = "crypto")]
 pub mod ecdh_ed25519;
+#[cfg(feature = "fte")]
+pub mod fte;
+#[cfg(feature = "codecs")]
 pub mod hex_encoder;
+#[cfg(feature = "http")]
 pub mod http;
+#[cfg(feature = "tls")]
 pub mod prefix_tls_rec_frag;
+#[cfg(feature = "quic")]
+pub mod quic;
 pub mod reverse;
+#[cfg(feature = "codecs")]
 pub mod ss_format;
 
 pub mod identity;
 
-use crate::{pt::wrap::WrapTransport, stream::Stream, Error, Result, Transport};
+#[cfg(any(feature = "codecs", feature = "fte"))]
+use crate::pt::{conversion::from_wrapping, wrap::WrapTransport};
+use crate::{
+    stream::Stream, Capabilities, Error, OverheadEstimate, Result, Role, Transport, Wrapping,
+};
+#[cfg(feature = "codecs")]
 use base64::Base64Builder;
 
 use tokio::io::{AsyncRead, AsyncWrite};
@@ -23,7 +38,12 @@ pub enum Transports {
     // PrefixTlsRecFrag,
     // SsFormat,
     // EcdhEd25519,
+    #[cfg(feature = "codecs")]
     Base64,
+    #[cfg(feature = "fte")]
+    Fte,
+    #[cfg(feature = "tutorial")]
+    Rot13,
     // Other(Box<dyn TransportBuilder>),
     // OtherStreamHandler(Box<dyn StreamHandler>),
 }
@@ -36,30 +56,85 @@ impl FromStr for Transports {
             "" | "identity" => Ok(Transports::Identity),
             "reverse" => Ok(Transports::Reverse),
             // "hex" => Ok(Transports::HexEncoder),
+            #[cfg(feature = "codecs")]
             "base64" => Ok(Transports::Base64),
-            _ => Err(std::io::Error::new(std::io::ErrorKind::Other, "not implemented yet").into()),
+            #[cfg(feature = "fte")]
+            fte::NAME => Ok(Transports::Fte),
+            #[cfg(feature = "tutorial")]
+            crate::tutorial::NAME => Ok(Transports::Rot13),
+            _ => Err(std::io::Error::other("not implemented yet").into()),
         }
     }
 }
 
 impl Transports {
-    pub fn build<'a, A>(&self) -> Box<dyn Transport<'a, A> + 'a>
+    /// Capabilities of the transport that [`Transports::build`] will produce, available before
+    /// the transport is constructed so that configurations can be validated up front.
+    pub fn capabilities(&self) -> Capabilities {
+        match self {
+            Transports::Identity => Capabilities::STREAM | Capabilities::PASSTHROUGH,
+            Transports::Reverse => Capabilities::STREAM,
+            #[cfg(feature = "codecs")]
+            Transports::Base64 => Capabilities::STREAM,
+            #[cfg(feature = "fte")]
+            Transports::Fte => Capabilities::STREAM,
+            #[cfg(feature = "tutorial")]
+            Transports::Rot13 => Capabilities::STREAM,
+        }
+    }
+
+    /// What the transport [`Transports::build`] will produce adds to a payload of
+    /// `payload_len` bytes.
+    pub fn overhead(&self, payload_len: usize) -> OverheadEstimate {
+        match self {
+            #[cfg(feature = "codecs")]
+            Transports::Base64 => Base64Builder::default().overhead(payload_len),
+            #[cfg(feature = "fte")]
+            Transports::Fte => fte::FteBuilder::default().overhead(payload_len),
+            _ => OverheadEstimate::passthrough(payload_len),
+        }
+    }
+
+    /// The transport as a [`Wrapping`], for transports built on
+    /// [`WrapTransport`](crate::WrapTransport). Turn it into a transport for either side with
+    /// [`dyn_from_wrapping`](crate::dyn_from_wrapping).
+    pub fn wrapping(&self) -> Option<Box<dyn Wrapping + Send + Sync>> {
+        match self {
+            Transports::Identity => Some(Box::new(identity::Identity::new())),
+            Transports::Reverse => None,
+            #[cfg(feature = "codecs")]
+            Transports::Base64 => Some(Box::<Base64Builder>::default()),
+            #[cfg(feature = "fte")]
+            Transports::Fte => Some(Box::<fte::FteBuilder>::default()),
+            #[cfg(feature = "tutorial")]
+            Transports::Rot13 => Some(Box::new(crate::tutorial::Rot13Transport::new())),
+        }
+    }
+
+    /// The transport for the `role` side of a connection.
+    #[cfg_attr(not(any(feature = "codecs", feature = "fte")), allow(unused_variables))]
+    pub fn build<'a, A>(&self, role: &Role) -> Box<dyn Transport<'a, A> + 'a>
     where
         A: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'a,
     {
         match self {
             Transports::Identity => Box::new(identity::Identity::new()),
             Transports::Reverse => Box::new(reverse::Reverse::new()),
-            Transports::Base64 => {
-                let wt: Box<dyn WrapTransport> = Box::<Base64Builder>::default();
-                Box::new(wt)
-            } // Transports::HexEncoder => Box::new(hex_encoder::HexEncoder::new()),
+            #[cfg(feature = "codecs")]
+            Transports::Base64 => Box::new(from_wrapping(Base64Builder::default(), *role)),
+            // Transports::HexEncoder => Box::new(hex_encoder::HexEncoder::new()),
+            #[cfg(feature = "fte")]
+            Transports::Fte => Box::new(from_wrapping(fte::FteBuilder::default(), *role)),
+            #[cfg(feature = "tutorial")]
+            Transports::Rot13 => Box::new(crate::tutorial::Rot13Transport::new()),
         }
     }
 }
 
+#[allow(dead_code)]
 struct NullTransport {}
 
+#[allow(dead_code)]
 impl NullTransport {
     fn new() -> Self {
         Self {}
@@ -83,7 +158,59 @@ where
 
 #[cfg(test)]
 mod test {
-    use crate::Result;
+    use super::Transports;
+    use crate::test_utils::snapshot::{assert_snapshot, wire_prefix, DEFAULT_LEN, PLAINTEXT};
+    use crate::{Result, Role};
+
+    use std::str::FromStr;
+
+    #[tokio::test]
+    async fn wrapping_transports_round_trip() -> Result<()> {
+        use crate::dyn_from_wrapping;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        for name in ["identity", "reverse", "base64", "fte", "rot13"] {
+            let Ok(t) = Transports::from_str(name) else {
+                continue; // feature disabled
+            };
+            let (Some(client), Some(server)) = (t.wrapping(), t.wrapping()) else {
+                assert_eq!(name, "reverse");
+                continue;
+            };
+            let client = dyn_from_wrapping(client, Role::Sealer);
+            let server = dyn_from_wrapping(server, Role::Revealer);
+
+            let (a, b) = tokio::io::duplex(64 * 1024);
+            let mut a = client.wrap_boxed(Box::new(a)).await?;
+            let mut b = server.wrap_boxed(Box::new(b)).await?;
+            a.write_all(PLAINTEXT).await?;
+            a.shutdown().await?;
+            let mut out = vec![0u8; PLAINTEXT.len()];
+            b.read_exact(&mut out).await?;
+            assert_eq!(out, PLAINTEXT, "{name}");
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wire_formats_match_snapshots() -> Result<()> {
+        let names = [
+            "identity",
+            "reverse",
+            #[cfg(feature = "codecs")]
+            "base64",
+            #[cfg(feature = "fte")]
+            super::fte::NAME,
+            #[cfg(feature = "tutorial")]
+            crate::tutorial::NAME,
+        ];
+        for name in names {
+            let transport = Transports::from_str(name)?.build(&Role::Sealer);
+            let wire = wire_prefix(&*transport, PLAINTEXT, DEFAULT_LEN).await?;
+            assert_snapshot(name, &wire);
+        }
+        Ok(())
+    }
 
     #[test]
     fn transports_interface() -> Result<()> {
@@ -0,0 +1,411 @@
+//! QUIC carrier. Dials and accepts QUIC connections and maps a single bidirectional QUIC
+//! stream to [`Stream`](crate::Stream) so that other transports can be layered on top.
+//!
+//! QUIC streams are opened lazily, so the server does not see a new stream until the client
+//! has written to it. Protocols carried over this transport must have the client speak first.
+//!
+//! A client that has connected to a server before can send its first bytes as 0-RTT data,
+//! with [`QuicClient::connect_with_early_data`]. 0-RTT data can be replayed by an observer, so
+//! it must not be anything the server would act on twice.
+
+use crate::rand::Rng;
+use crate::stream::AddrInfo;
+use crate::{Capabilities, Error, Result};
+
+use pin_project::pin_project;
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use quinn::{Connection, Endpoint, RecvStream, SendStream};
+use rand::RngCore;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// ALPN protocol identifier negotiated by both ends.
+pub const ALPN: &[u8] = b"ptrs-quic";
+
+/// Properties of the streams produced by this transport.
+pub const CAPABILITIES: Capabilities = Capabilities::STREAM
+    .union(Capabilities::NEEDS_HANDSHAKE)
+    .union(Capabilities::ZERO_RTT);
+
+/// PKCS#8 wrapping of a bare Ed25519 private key (RFC 8410), followed by the 32 key bytes.
+const ED25519_PKCS8_PREFIX: [u8; 16] = [
+    0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+];
+
+/// Generate a self-signed certificate and key for `names`. The generator's copy of the key
+/// is wiped; the caller's can be with [`Zeroize`](zeroize::Zeroize) once rustls has it.
+pub fn self_signed(
+    names: impl Into<Vec<String>>,
+) -> Result<(CertificateDer<'static>, PrivateKeyDer<'static>)> {
+    self_signed_with(names, &mut Rng::from_entropy())
+}
+
+/// [`self_signed`] with the key drawn from `rng`. The key is Ed25519, whose signatures are
+/// deterministic, so an RNG seeded through [`SEED_KEY`](crate::rand::SEED_KEY) gives the same
+/// certificate every time.
+pub fn self_signed_with(
+    names: impl Into<Vec<String>>,
+    rng: &mut Rng,
+) -> Result<(CertificateDer<'static>, PrivateKeyDer<'static>)> {
+    let mut secret = zeroize::Zeroizing::new([0u8; 32]);
+    rng.fill_bytes(&mut *secret);
+    let key = PrivatePkcs8KeyDer::from([&ED25519_PKCS8_PREFIX[..], &secret[..]].concat());
+    let mut signing_key = rcgen::KeyPair::try_from(&key).map_err(Error::new)?;
+    let cert = rcgen::CertificateParams::new(names)
+        .and_then(|params| params.self_signed(&signing_key))
+        .map_err(Error::new);
+    zeroize::Zeroize::zeroize(&mut signing_key);
+    Ok((cert?.into(), key.into()))
+}
+
+/// What the TLS handshake behind a [`QuicStream`] negotiated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TlsInfo {
+    /// Always TLS 1.3, the only version QUIC allows.
+    pub version: &'static str,
+    pub alpn: Option<Vec<u8>>,
+    /// The SNI the client sent. Only known on the server side.
+    pub server_name: Option<String>,
+}
+
+/// One bidirectional QUIC stream. Keeps its connection open for as long as it is alive.
+#[pin_project]
+pub struct QuicStream {
+    #[pin]
+    send: SendStream,
+    #[pin]
+    recv: RecvStream,
+    conn: Connection,
+    early_data_accepted: bool,
+}
+
+impl QuicStream {
+    async fn open(conn: Connection) -> Result<Self> {
+        let (send, recv) = conn.open_bi().await.map_err(Error::new)?;
+        Ok(Self {
+            send,
+            recv,
+            conn,
+            early_data_accepted: false,
+        })
+    }
+
+    /// Address of the peer.
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.conn.remote_address()
+    }
+
+    /// Whether the stream's first bytes went out as 0-RTT data and the server took them.
+    pub fn early_data_accepted(&self) -> bool {
+        self.early_data_accepted
+    }
+
+    /// Parameters of the connection's TLS handshake, for logging.
+    pub fn tls_info(&self) -> TlsInfo {
+        let data = self
+            .conn
+            .handshake_data()
+            .and_then(|d| d.downcast::<quinn::crypto::rustls::HandshakeData>().ok());
+        TlsInfo {
+            version: "TLSv1.3",
+            alpn: data.as_ref().and_then(|d| d.protocol.clone()),
+            server_name: data.and_then(|d| d.server_name),
+        }
+    }
+
+    /// `len` bytes of keying material exported from the connection's TLS session (RFC 5705,
+    /// RFC 8446 section 7.5). Both ends get the same bytes for the same `label` and `context`,
+    /// so a layer above can bind its authentication to this connection.
+    pub fn export_keying_material(
+        &self,
+        label: &[u8],
+        context: &[u8],
+        len: usize,
+    ) -> Result<Vec<u8>> {
+        let mut out = vec![0_u8; len];
+        self.conn
+            .export_keying_material(&mut out, label, context)
+            .map_err(|_| Error::new("failed to export keying material"))?;
+        Ok(out)
+    }
+}
+
+/// The local address is not known to the stream, only to its endpoint.
+impl AddrInfo for QuicStream {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        Some(self.remote_addr())
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+}
+
+impl AsyncRead for QuicStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        self.project().recv.poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for QuicStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        AsyncWrite::poll_write(self.project().send, cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        AsyncWrite::poll_flush(self.project().send, cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        AsyncWrite::poll_shutdown(self.project().send, cx)
+    }
+}
+
+/// Accepts QUIC connections, yielding the first bidirectional stream of each.
+pub struct QuicServer {
+    endpoint: Endpoint,
+}
+
+impl QuicServer {
+    pub fn bind(
+        addr: SocketAddr,
+        cert: CertificateDer<'static>,
+        key: PrivateKeyDer<'static>,
+    ) -> Result<Self> {
+        let mut crypto = rustls::ServerConfig::builder_with_provider(provider())
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(Error::new)?
+            .with_no_client_auth()
+            .with_single_cert(vec![cert], key)
+            .map_err(Error::new)?;
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        // QUIC allows no other limit
+        crypto.max_early_data_size = u32::MAX;
+
+        let crypto = QuicServerConfig::try_from(crypto).map_err(Error::new)?;
+        let config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+        let endpoint = Endpoint::server(config, addr)?;
+        Ok(Self { endpoint })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.endpoint.local_addr()?)
+    }
+
+    /// Wait for the next connection and the first stream the client opens on it.
+    pub async fn accept(&self) -> Result<QuicStream> {
+        let incoming = self
+            .endpoint
+            .accept()
+            .await
+            .ok_or_else(|| Error::new("quic endpoint closed"))?;
+        let conn = incoming.await.map_err(Error::new)?;
+        let (send, recv) = conn.accept_bi().await.map_err(Error::new)?;
+        Ok(QuicStream {
+            send,
+            recv,
+            conn,
+            early_data_accepted: false,
+        })
+    }
+
+    /// Stop accepting connections and close the ones that are open.
+    pub fn close(&self) {
+        self.endpoint.close(0_u32.into(), b"");
+    }
+}
+
+/// Dials QUIC servers whose certificate is known ahead of time (e.g. distributed in a bridge
+/// line), opening one bidirectional stream per connection.
+pub struct QuicClient {
+    endpoint: Endpoint,
+}
+
+impl QuicClient {
+    /// Create a client that trusts only `server_cert`.
+    pub fn new(server_cert: CertificateDer<'static>) -> Result<Self> {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(server_cert).map_err(Error::new)?;
+        let mut crypto = rustls::ClientConfig::builder_with_provider(provider())
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(Error::new)?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        crypto.alpn_protocols = vec![ALPN.to_vec()];
+        crypto.enable_early_data = true;
+
+        let crypto = QuicClientConfig::try_from(crypto).map_err(Error::new)?;
+        let mut endpoint = Endpoint::client((Ipv6Addr::UNSPECIFIED, 0).into())
+            .or_else(|_| Endpoint::client((Ipv4Addr::UNSPECIFIED, 0).into()))?;
+        endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(crypto)));
+        Ok(Self { endpoint })
+    }
+
+    /// Connect to `addr`, checking its certificate against `server_name`.
+    pub async fn connect(&self, addr: SocketAddr, server_name: &str) -> Result<QuicStream> {
+        let conn = self
+            .endpoint
+            .connect(addr, server_name)
+            .map_err(Error::new)?
+            .await
+            .map_err(Error::new)?;
+        QuicStream::open(conn).await
+    }
+
+    /// Like [`QuicClient::connect`], but sends `early` as soon as possible: as 0-RTT data in
+    /// the first flight if this client holds a session ticket from an earlier connection to the
+    /// server, after the handshake otherwise or if the server turns the 0-RTT data down.
+    pub async fn connect_with_early_data(
+        &self,
+        addr: SocketAddr,
+        server_name: &str,
+        early: &[u8],
+    ) -> Result<QuicStream> {
+        let connecting = self
+            .endpoint
+            .connect(addr, server_name)
+            .map_err(Error::new)?;
+        let (conn, accepted) = match connecting.into_0rtt() {
+            Ok(zero_rtt) => zero_rtt,
+            Err(connecting) => {
+                let conn = connecting.await.map_err(Error::new)?;
+                let mut stream = QuicStream::open(conn).await?;
+                stream.write_all(early).await?;
+                return Ok(stream);
+            }
+        };
+
+        let mut stream = QuicStream::open(conn.clone()).await?;
+        let sent = stream.send.write_all(early).await;
+        if accepted.await && sent.is_ok() {
+            stream.early_data_accepted = true;
+            return Ok(stream);
+        }
+        // the server never saw that stream, so send again on one over the full connection
+        let mut stream = QuicStream::open(conn).await?;
+        stream.write_all(early).await?;
+        Ok(stream)
+    }
+}
+
+fn provider() -> Arc<rustls::crypto::CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn round_trip() -> Result<()> {
+        let (cert, key) = self_signed(vec!["bridge.example".into()])?;
+        let server = QuicServer::bind("127.0.0.1:0".parse().unwrap(), cert.clone(), key)?;
+        let addr = server.local_addr()?;
+
+        let srv = tokio::spawn(async move {
+            let mut s = server.accept().await?;
+            let mut buf = [0_u8; 5];
+            s.read_exact(&mut buf).await?;
+            s.write_all(&buf).await?;
+            s.shutdown().await?;
+            let mut rest = vec![];
+            s.read_to_end(&mut rest).await?;
+            assert_eq!(s.tls_info().server_name.as_deref(), Some("bridge.example"));
+            s.export_keying_material(b"EXPORTER-ptrs-test", b"", 32)
+        });
+
+        let client = QuicClient::new(cert)?;
+        let mut c = client.connect(addr, "bridge.example").await?;
+        assert_eq!(c.remote_addr().port(), addr.port());
+        let info = c.tls_info();
+        assert_eq!(info.alpn.as_deref(), Some(ALPN));
+        assert_eq!(info.server_name, None);
+        c.write_all(b"hello").await?;
+        let mut buf = [0_u8; 5];
+        c.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"hello");
+        let ekm = c.export_keying_material(b"EXPORTER-ptrs-test", b"", 32)?;
+        assert_ne!(ekm, c.export_keying_material(b"EXPORTER-other", b"", 32)?);
+        c.shutdown().await?;
+        assert_eq!(srv.await.unwrap()?, ekm);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resumed_connections_send_early_data() -> Result<()> {
+        let (cert, key) = self_signed(vec!["bridge.example".into()])?;
+        let server = QuicServer::bind("127.0.0.1:0".parse().unwrap(), cert.clone(), key)?;
+        let addr = server.local_addr()?;
+        tokio::spawn(async move {
+            while let Ok(mut s) = server.accept().await {
+                tokio::spawn(async move {
+                    let mut buf = [0_u8; 5];
+                    s.read_exact(&mut buf).await?;
+                    s.write_all(&buf).await?;
+                    s.shutdown().await?;
+                    // hold the connection open until the client is done with it
+                    s.read_to_end(&mut vec![]).await
+                });
+            }
+        });
+
+        let client = QuicClient::new(cert)?;
+        for (early, resumed) in [(b"first", false), (b"again", true)] {
+            let mut c = client
+                .connect_with_early_data(addr, "bridge.example", early)
+                .await?;
+            assert_eq!(c.early_data_accepted(), resumed);
+            let mut echo = [0_u8; 5];
+            c.read_exact(&mut echo).await?;
+            assert_eq!(&echo, early);
+            c.shutdown().await?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_cert() -> Result<()> {
+        let (cert, key) = self_signed(vec!["bridge.example".into()])?;
+        let server = QuicServer::bind("127.0.0.1:0".parse().unwrap(), cert, key)?;
+        let addr = server.local_addr()?;
+        tokio::spawn(async move { server.accept().await });
+
+        let (other, _) = self_signed(vec!["bridge.example".into()])?;
+        let client = QuicClient::new(other)?;
+        assert!(client.connect(addr, "bridge.example").await.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn seeded_certs_repeat() -> Result<()> {
+        let seeded = || {
+            self_signed_with(
+                vec!["bridge.example".into()],
+                &mut Rng::from_args("test-seed=7")?,
+            )
+        };
+        let (cert_a, key_a) = seeded()?;
+        let (cert_b, key_b) = seeded()?;
+        assert_eq!(cert_a, cert_b);
+        assert_eq!(key_a.secret_der(), key_b.secret_der());
+
+        let (cert_c, _) = self_signed(vec!["bridge.example".into()])?;
+        assert_ne!(cert_a, cert_c);
+        Ok(())
+    }
+}
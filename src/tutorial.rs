@@ -0,0 +1,238 @@
+//! # Writing a transport
+//!
+//! A walk through adding a transport to `ptrs`, using ROT13 as the (deliberately useless)
+//! obfuscation. Each step is real code from this module, and the examples below run as doc
+//! tests, so the tutorial breaks when the APIs it relies on change. An end to end version over
+//! TCP is in `examples/custom_transport.rs`:
+//!
+//! ```sh
+//! cargo run --example custom_transport --features tutorial
+//! ```
+//!
+//! ## 1. The transform
+//!
+//! Most transports boil down to a transform over bytes. [`ChunkTransform`] is the simplest
+//! form: it is handed whatever the stream produced and appends its output. It only needs
+//! `core` and `alloc`, so the same code can be shared with other runtimes.
+//!
+//! ```
+//! use ptrs::codec::apply;
+//! use ptrs::tutorial::Rot13;
+//!
+//! assert_eq!(apply(&mut Rot13, b"Hello, world!").unwrap(), b"Uryyb, jbeyq!");
+//! ```
+//!
+//! ## 2. The transport
+//!
+//! [`Chunked`] turns a [`ChunkTransform`] into a [`BufferTransform`], and [`SealWith`] /
+//! [`RevealWith`] take a factory that makes one per connection. [`WrapTransport`] hands out a
+//! [`Wrapper`] for each side; ROT13 is its own inverse so both sides use the same transform.
+//! Implementing [`Transport`] on top of that is what lets everything else in the crate use it.
+//!
+//! ```
+//! use ptrs::tutorial::Rot13Transport;
+//! use ptrs::Transport;
+//! use tokio::io::{AsyncReadExt, AsyncWriteExt};
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> ptrs::Result<()> {
+//! let (client, mut wire) = tokio::io::duplex(1024);
+//! let mut client = Rot13Transport::new().wrap(client)?;
+//! client.write_all(b"attack at dawn").await?;
+//! client.flush().await?;
+//!
+//! let mut seen = [0u8; 14];
+//! wire.read_exact(&mut seen).await?;
+//! assert_eq!(&seen, b"nggnpx ng qnja");
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! ## 3. The builder
+//!
+//! [`TransportBuilder`] is how configuration reaches a transport and how the two sides are
+//! kept apart: [`TransportBuilder::client`] and [`TransportBuilder::server`] return distinct
+//! types. [`Named`] gives the name used on the command line and in `SMETHOD` lines, and
+//! [`Configurable`] parses the transport's arguments.
+//!
+//! ```
+//! use ptrs::tutorial::Rot13Transport;
+//! use ptrs::{Configurable, Named, TransportBuilder};
+//!
+//! let builder = Rot13Transport::new().with_config("").unwrap();
+//! assert_eq!(builder.name(), "rot13");
+//! assert!(builder.client().is_ok());
+//! assert!(Rot13Transport::new().with_config("rotate=12").is_err());
+//! ```
+//!
+//! ## 4. The registry
+//!
+//! [`Transports`] maps names to the built in transports. The tutorial transport is listed
+//! there when the `tutorial` feature is enabled, which is also how the proxy binary finds it:
+//! `proxy server -t rot13 ...` and `proxy client -t rot13 ...`.
+//!
+//! ```
+//! use ptrs::transports::Transports;
+//! use ptrs::Capabilities;
+//!
+//! let t: Transports = "rot13".parse().unwrap();
+//! assert_eq!(t.capabilities(), Capabilities::STREAM);
+//! ```
+//!
+//! ## 5. Managed mode
+//!
+//! Run as a managed transport, the server reports where it is listening with an `SMETHOD`
+//! line on stdout. [`smethod_line`] writes one, and the [`parser`](crate::parser) used by
+//! [`Manager`](crate::manager::Manager) reads it back.
+//!
+//! ```
+//! use ptrs::parser::{parse_line, PtLine};
+//! use ptrs::tutorial::{smethod_line, Rot13Transport};
+//!
+//! let addr = "127.0.0.1:4000".parse().unwrap();
+//! let line = smethod_line(&Rot13Transport::new(), addr);
+//! assert_eq!(line, "SMETHOD rot13 127.0.0.1:4000");
+//! assert!(matches!(parse_line(&line).unwrap(), PtLine::Smethod { transport, .. } if transport == "rot13"));
+//! ```
+//!
+//! [`ChunkTransform`]: crate::codec::ChunkTransform
+//! [`Chunked`]: crate::transform::Chunked
+//! [`BufferTransform`]: crate::BufferTransform
+//! [`SealWith`]: crate::wrap::SealWith
+//! [`RevealWith`]: crate::wrap::RevealWith
+//! [`WrapTransport`]: crate::WrapTransport
+//! [`Wrapper`]: crate::wrap::Wrapper
+//! [`Transport`]: crate::Transport
+//! [`TransportBuilder`]: crate::TransportBuilder
+//! [`TransportBuilder::client`]: crate::TransportBuilder::client
+//! [`TransportBuilder::server`]: crate::TransportBuilder::server
+//! [`Named`]: crate::Named
+//! [`Configurable`]: crate::Configurable
+//! [`Transports`]: crate::transports::Transports
+
+use crate::{
+    codec::{self, ChunkTransform},
+    pt::transform::Chunked,
+    stream::Stream,
+    wrap::{RevealWith, SealWith, WrapTransport, Wrapper},
+    Configurable, Error, Named, Result, Role, Transport, TransportBuilder, TransportInstance,
+};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use std::net::SocketAddr;
+
+pub const NAME: &str = "rot13";
+
+/// Rotates ASCII letters 13 places, leaving everything else alone.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Rot13;
+
+impl ChunkTransform for Rot13 {
+    fn transform(&mut self, input: &[u8], out: &mut Vec<u8>) -> codec::Result<()> {
+        out.extend(input.iter().map(|&b| match b {
+            b'a'..=b'z' => (b - b'a' + 13) % 26 + b'a',
+            b'A'..=b'Z' => (b - b'A' + 13) % 26 + b'A',
+            _ => b,
+        }));
+        Ok(())
+    }
+}
+
+/// The ROT13 transport and its builder.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Rot13Transport {}
+
+impl Rot13Transport {
+    pub fn new() -> Self {
+        Rot13Transport {}
+    }
+
+    fn wrapper_for(&self, role: Role) -> Wrapper {
+        let seal = SealWith(|| Chunked::new(Rot13));
+        let reveal = RevealWith(|| Chunked::new(Rot13));
+        Wrapper::new(NAME, role, Box::new(seal), Box::new(reveal))
+    }
+}
+
+impl Named for Rot13Transport {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+}
+
+impl Configurable for Rot13Transport {
+    /// ROT13 takes no arguments; reject any rather than silently ignoring them.
+    fn with_config(self, args: &str) -> Result<Self> {
+        match args.is_empty() {
+            true => Ok(self),
+            false => Err(Error::Config(
+                format!("{NAME} takes no arguments, got \"{args}\"").into(),
+            )),
+        }
+    }
+}
+
+impl WrapTransport for Rot13Transport {
+    fn wrapper(&self) -> Result<Wrapper> {
+        Ok(self.wrapper_for(Role::Sealer))
+    }
+
+    fn unwrapper(&self) -> Result<Wrapper> {
+        Ok(self.wrapper_for(Role::Revealer))
+    }
+}
+
+impl<'a, A> Transport<'a, A> for Rot13Transport
+where
+    A: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'a,
+{
+    fn wrap(&self, a: A) -> Result<Box<dyn Stream + 'a>> {
+        Ok(self.wrapper()?.wrap(a))
+    }
+}
+
+impl TransportBuilder for Rot13Transport {
+    fn build(&self, _r: &Role) -> Result<TransportInstance> {
+        Ok(TransportInstance::new(Box::new(*self)))
+    }
+}
+
+/// The `SMETHOD` line a managed server running `transport` on `addr` reports to its parent.
+pub fn smethod_line<T: Named>(transport: &T, addr: SocketAddr) -> String {
+    format!("SMETHOD {} {addr}", transport.name())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::codec::apply;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn rot13_is_an_involution() -> codec::Result<()> {
+        let all: Vec<u8> = (0..=255).collect();
+        let once = apply(&mut Rot13, &all)?;
+        assert_ne!(once, all);
+        assert_eq!(apply(&mut Rot13, &once)?, all);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn client_server_round_trip() -> Result<()> {
+        let builder = Rot13Transport::new();
+        let (c, s) = tokio::io::duplex(1024);
+        let mut client = builder.client()?.wrap(c)?;
+        let mut server = builder.server()?.wrap(s)?;
+
+        client
+            .write_all(b"Why did the chicken cross the road?")
+            .await?;
+        client.flush().await?;
+        let mut buf = [0u8; 35];
+        server.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"Why did the chicken cross the road?");
+        Ok(())
+    }
+}
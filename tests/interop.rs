@@ -0,0 +1,177 @@
+//! Interop tests against reference pluggable transport binaries.
+//!
+//! Enabled with `--features interop-tests`. The lyrebird binary is taken from `LYREBIRD_BIN`,
+//! or from `PATH`; tests skip (and say so) when it can't be found, so the feature can be
+//! turned on in CI images that don't ship it.
+//!
+//! Until ptrs has its own obfs4 this drives lyrebird on both sides, with ptrs acting as the
+//! parent process and SOCKS client, which pins the managed-transport wire format (environment,
+//! `CMETHOD`/`SMETHOD` lines, SOCKS argument encoding) against the reference implementation.
+
+#![cfg(feature = "interop-tests")]
+
+use ptrs::manager::{ClientConfig, Manager, ServerConfig};
+use ptrs::parser::ProxyProtocol;
+use ptrs::Result;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+
+fn lyrebird() -> Option<PathBuf> {
+    if let Some(p) = std::env::var_os("LYREBIRD_BIN") {
+        return Some(p.into());
+    }
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join("lyrebird"))
+        .find(|p| p.is_file())
+}
+
+/// Encode transport args for the SOCKS5 username/password fields, as described in the
+/// pt-spec: `k=v` pairs joined with `;`, with `;`, `=` and `\` backslash escaped, split
+/// across the two fields.
+fn socks_auth(args: &[(String, String)]) -> (Vec<u8>, Vec<u8>) {
+    let escape = |s: &str| {
+        s.replace('\\', "\\\\")
+            .replace(';', "\\;")
+            .replace('=', "\\=")
+    };
+    let joined = args
+        .iter()
+        .map(|(k, v)| format!("{}={}", escape(k), escape(v)))
+        .collect::<Vec<_>>()
+        .join(";")
+        .into_bytes();
+    let split = joined.len().min(255);
+    let (user, pass) = joined.split_at(split);
+    // the password field can't be empty
+    let pass = if pass.is_empty() { &[0_u8][..] } else { pass };
+    (user.to_vec(), pass.to_vec())
+}
+
+/// Minimal SOCKS5 CONNECT with username/password authentication.
+async fn socks5_connect(
+    proxy: SocketAddr,
+    target: SocketAddr,
+    user: &[u8],
+    pass: &[u8],
+) -> Result<TcpStream> {
+    let mut s = TcpStream::connect(proxy).await?;
+    s.write_all(&[5, 1, 2]).await?;
+    let mut reply = [0_u8; 2];
+    s.read_exact(&mut reply).await?;
+    assert_eq!(reply, [5, 2], "proxy refused username/password auth");
+
+    let mut auth = vec![1, user.len() as u8];
+    auth.extend_from_slice(user);
+    auth.push(pass.len() as u8);
+    auth.extend_from_slice(pass);
+    s.write_all(&auth).await?;
+    s.read_exact(&mut reply).await?;
+    assert_eq!(reply, [1, 0], "proxy rejected transport args");
+
+    let mut req = vec![5, 1, 0];
+    match target.ip() {
+        IpAddr::V4(ip) => {
+            req.push(1);
+            req.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            req.push(4);
+            req.extend_from_slice(&ip.octets());
+        }
+    }
+    req.extend_from_slice(&target.port().to_be_bytes());
+    s.write_all(&req).await?;
+
+    let mut head = [0_u8; 4];
+    s.read_exact(&mut head).await?;
+    assert_eq!(head[1], 0, "connect failed with SOCKS status {}", head[1]);
+    let rest = match head[3] {
+        1 => 4 + 2,
+        4 => 16 + 2,
+        _ => panic!("unexpected bound address type {}", head[3]),
+    };
+    s.read_exact(&mut vec![0_u8; rest]).await?;
+    Ok(s)
+}
+
+#[tokio::test]
+async fn lyrebird_obfs4_round_trip() -> Result<()> {
+    let Some(bin) = lyrebird() else {
+        eprintln!("skipping: lyrebird not found (set LYREBIRD_BIN)");
+        return Ok(());
+    };
+    let state = tempfile::tempdir()?;
+
+    // echo server standing in for the ORPort
+    let orport = TcpListener::bind("127.0.0.1:0").await?;
+    let or_addr = orport.local_addr()?;
+    tokio::spawn(async move {
+        while let Ok((mut s, _)) = orport.accept().await {
+            tokio::spawn(async move {
+                let (mut r, mut w) = s.split();
+                let _ = tokio::io::copy(&mut r, &mut w).await;
+            });
+        }
+    });
+
+    let server = Manager::new(&bin)
+        .state_location(state.path().join("server"))
+        .launch_server(&ServerConfig {
+            transports: vec!["obfs4".into()],
+            bindaddrs: vec![("obfs4".into(), "127.0.0.1:0".parse().unwrap())],
+            orport: Some(or_addr),
+            ..Default::default()
+        })
+        .await?;
+    assert!(server.errors.is_empty(), "{:?}", server.errors);
+    let smethod = &server.methods[0];
+    assert_eq!(smethod.transport, "obfs4");
+    assert!(smethod.args.iter().any(|(k, _)| k == "cert"));
+
+    let client = Manager::new(&bin)
+        .state_location(state.path().join("client"))
+        .launch_client(&ClientConfig {
+            transports: vec!["obfs4".into()],
+            ..Default::default()
+        })
+        .await?;
+    assert!(client.errors.is_empty(), "{:?}", client.errors);
+    let cmethod = &client.methods[0];
+    assert_eq!(cmethod.protocol, ProxyProtocol::Socks5);
+
+    let (user, pass) = socks_auth(&smethod.args);
+    let mut s = socks5_connect(cmethod.addr, smethod.addr, &user, &pass).await?;
+
+    let msg: Vec<u8> = (0..64 * 1024).map(|i| (i * 31) as u8).collect();
+    s.write_all(&msg).await?;
+    let mut echoed = vec![0_u8; msg.len()];
+    tokio::time::timeout(Duration::from_secs(30), s.read_exact(&mut echoed))
+        .await
+        .expect("timed out waiting for echo")?;
+    assert_eq!(echoed, msg);
+
+    client.process.shutdown(Duration::from_secs(5)).await?;
+    server.process.shutdown(Duration::from_secs(5)).await?;
+    Ok(())
+}
+
+#[test]
+fn socks_auth_encoding() {
+    let args = vec![
+        ("cert".to_string(), "a=b;c".to_string()),
+        ("iat-mode".to_string(), "0".to_string()),
+    ];
+    let (user, pass) = socks_auth(&args);
+    assert_eq!(user, br"cert=a\=b\;c;iat-mode=0");
+    assert_eq!(pass, [0]);
+
+    let long = vec![("k".to_string(), "v".repeat(300))];
+    let (user, pass) = socks_auth(&long);
+    assert_eq!(user.len(), 255);
+    assert_eq!(user.len() + pass.len(), 302);
+}